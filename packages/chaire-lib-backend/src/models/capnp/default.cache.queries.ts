@@ -11,7 +11,16 @@ import * as capnp from 'capnp-ts';
 import config from '../../config/server.config';
 import { fileManager } from '../../utils/filesystem/fileManager';
 import TrError from 'chaire-lib-common/lib/utils/TrError';
-import { writePackedMessageToStream, readToEndOfStream } from '../../services/json2capnp/capnpMessagesManager';
+import { readToEndOfStream } from '../../services/json2capnp/capnpMessagesManager';
+import {
+    withLockedCacheFile,
+    withCacheFilesSwap,
+    withCacheFilesOpening,
+    writeTempCacheFile,
+    writeCacheFileAtomically,
+    removeCacheFile,
+    TempCacheFile
+} from '../../services/json2capnp/capnpCacheFiles';
 import Preferences from 'chaire-lib-common/lib/config/Preferences';
 import json2CapnpRust from '../../services/json2capnp/Json2CapnpRust';
 import GenericCollection from 'chaire-lib-common/lib/utils/objects/GenericCollection';
@@ -145,60 +154,61 @@ const collectionToCache = async (params: CollectionCacheParams): Promise<any> =>
                     : collection;
         const featuresCount = features.length;
 
-        const countFiles = Math.ceil(featuresCount / maxNumberOfObjectsPerFile);
+        // an empty collection is still written, to replace the previous one:
+        const countFiles = Math.max(1, Math.ceil(featuresCount / maxNumberOfObjectsPerFile));
 
-        if (countFiles > 1) {
-            fileManager.writeFile(`${cachePath}.count`, `${countFiles}`);
-        }
+        const tempFiles: TempCacheFile[] = [];
+        const promiseProducer = async (fileIndex: number): Promise<number> => {
+            const startFeatureIndex = fileIndex * maxNumberOfObjectsPerFile;
+            const endFeatureIndex = Math.min((fileIndex + 1) * maxNumberOfObjectsPerFile, featuresCount) - 1;
+            const fileFeaturesCount = endFeatureIndex + 1 - startFeatureIndex;
+            const message = new capnp.Message();
+            const cacheCollectionMessage = message.initRoot(CacheCollection);
+            const cacheCollection = cacheCollectionMessage[`init${pluralizedCollectionName}`](fileFeaturesCount);
 
-        const promiseProducer = async (fileIndex: number): Promise<number> =>
-            new Promise((resolveWriteStream) => {
-                const startFeatureIndex = fileIndex * maxNumberOfObjectsPerFile;
-                const endFeatureIndex =
-                    (fileIndex + 1) * maxNumberOfObjectsPerFile >= featuresCount
-                        ? featuresCount - 1
-                        : (fileIndex + 1) * maxNumberOfObjectsPerFile - 1;
-                const fileFeaturesCount = endFeatureIndex + 1 - startFeatureIndex;
-                const writeStream =
-                    countFiles === 1
-                        ? fs.createWriteStream(`${absoluteCacheFilePath}`)
-                        : fs.createWriteStream(`${absoluteCacheFilePath}.${fileIndex}`);
-                const message = new capnp.Message();
-                const cacheCollectionMessage = message.initRoot(CacheCollection);
-                const cacheCollection = cacheCollectionMessage[`init${pluralizedCollectionName}`](fileFeaturesCount);
-
-                const objects: any[] = [];
-                const indexes: number[] = [];
-                for (let i = startFeatureIndex; i <= endFeatureIndex; i++) {
-                    objects.push(features[i]);
-                    indexes.push(i - startFeatureIndex);
-                }
-                writeStream.on('finish', () => {
-                    resolveWriteStream(fileIndex);
-                });
+            for (let i = startFeatureIndex; i <= endFeatureIndex; i++) {
+                capnpParser(features[i], cacheCollection.get(i - startFeatureIndex));
+            }
+            tempFiles[fileIndex] = await writeTempCacheFile(
+                countFiles === 1 ? absoluteCacheFilePath : `${absoluteCacheFilePath}.${fileIndex}`,
+                Buffer.from(message.toPackedArrayBuffer())
+            );
+            return fileIndex;
+        };
 
-                const objectSetPromises = objects.map(async (object, objectI) => {
-                    const cacheObject = cacheCollection.get(indexes[objectI]);
-                    capnpParser(object, cacheObject);
-                });
+        try {
+            // the json2capnp server takes the same lock while writing this collection:
+            await withLockedCacheFile(absoluteCacheFilePath, async () => {
+                const promiseQueue = new pQueue({ concurrency: 10 });
+                const fileWriterPromises: Promise<number>[] = [];
+                for (let fileI = 0; fileI < countFiles; fileI++) {
+                    fileWriterPromises.push(promiseQueue.add(async () => promiseProducer(fileI)));
+                }
                 try {
-                    Promise.all(objectSetPromises).then(() => {
-                        writePackedMessageToStream(writeStream, message);
-                        writeStream.end();
+                    await Promise.all(fileWriterPromises);
+
+                    // readers use the count file to choose between the single file and the numbered files:
+                    const countTempFile =
+                        countFiles > 1
+                            ? await writeTempCacheFile(`${absoluteCacheFilePath}.count`, `${countFiles}`)
+                            : undefined;
+                    if (countTempFile) {
+                        tempFiles.push(countTempFile);
+                    }
+                    // readers taking the swap lock open either the previous files or the new ones:
+                    await withCacheFilesSwap(absoluteCacheFilePath, async () => {
+                        for (const tempFile of tempFiles) {
+                            await tempFile.commit();
+                        }
+                        if (!countTempFile) {
+                            await removeCacheFile(`${absoluteCacheFilePath}.count`);
+                        }
                     });
-                } catch (error) {
-                    console.error('Error saving to cache', error);
+                } finally {
+                    await Promise.all(fileWriterPromises.map((promise) => promise.catch(() => undefined)));
+                    await Promise.all(tempFiles.map((tempFile) => tempFile.discard()));
                 }
             });
-
-        const promiseQueue = new pQueue({ concurrency: 10 });
-        const fileWriterPromises: Promise<number>[] = [];
-        for (let fileI = 0; fileI < countFiles; fileI++) {
-            fileWriterPromises.push(promiseQueue.add(async () => promiseProducer(fileI)));
-        }
-
-        try {
-            await Promise.all(fileWriterPromises);
             return cachePath;
         } catch (error) {
             console.error('error saving collection cache', error);
@@ -252,23 +262,43 @@ const collectionFromCache = async (params: CollectionCacheParams): Promise<any>
             cachePath
         });
     } else {
-        const countFiles = fileManager.fileExists(`${cachePath}.count`)
-            ? parseInt(fileManager.readFile(`${cachePath}.count`) || '1')
-            : 1;
+        // the files are all opened first, while holding the swap lock shared, so they are from the same write:
+        const fileHandles = await withCacheFilesOpening(absoluteCacheFilePath, async () => {
+            const countFiles = fileManager.fileExists(`${cachePath}.count`)
+                ? parseInt(fileManager.readFile(`${cachePath}.count`) || '1')
+                : 1;
+            if (countFiles === 1 && !fileManager.fileExists(cachePath)) {
+                return undefined;
+            }
+            const handles: fs.promises.FileHandle[] = [];
+            try {
+                for (let fileI = 0; fileI < countFiles; fileI++) {
+                    handles.push(
+                        await fs.promises.open(
+                            countFiles === 1 ? absoluteCacheFilePath : `${absoluteCacheFilePath}.${fileI}`,
+                            'r'
+                        )
+                    );
+                }
+            } catch (error) {
+                await Promise.all(handles.map((handle) => handle.close()));
+                throw error;
+            }
+            return handles;
+        });
 
-        if (countFiles === 1 && !fileManager.fileExists(cachePath)) {
+        if (fileHandles === undefined) {
             const collection = CollectionClass ? new CollectionClass([]) : [];
             return collection;
         }
+        const countFiles = fileHandles.length;
 
         const featuresByFileIndex: any[][] = [];
         const promiseProducer = async (fileIndex: number): Promise<number> =>
             new Promise((resolveReadStream) => {
                 featuresByFileIndex[fileIndex] = [];
-                const readStream =
-                    countFiles === 1
-                        ? fs.createReadStream(`${absoluteCacheFilePath}`)
-                        : fs.createReadStream(`${absoluteCacheFilePath}.${fileIndex}`);
+                // the stream closes the file once read:
+                const readStream = fileHandles[fileIndex].createReadStream();
 
                 readToEndOfStream(readStream).then((data: any) => {
                     const features: any[] = [];
@@ -362,19 +392,15 @@ const objectToCache = async (params: ObjectToCacheParams) => {
             parser
         });
     } else {
-        return new Promise((resolve) => {
-            const writeStream = fs.createWriteStream(absoluteCacheFilePath);
-            const message = new capnp.Message();
-            const cacheObject = message.initRoot(CacheObjectClass);
+        const message = new capnp.Message();
+        const cacheObject = message.initRoot(CacheObjectClass);
 
-            capnpParser(object, cacheObject);
+        capnpParser(object, cacheObject);
 
-            writePackedMessageToStream(writeStream, message);
-            writeStream.end();
-            writeStream.on('finish', () => {
-                resolve(cachePath);
-            });
-        });
+        await withLockedCacheFile(absoluteCacheFilePath, () =>
+            writeCacheFileAtomically(absoluteCacheFilePath, Buffer.from(message.toPackedArrayBuffer()))
+        );
+        return cachePath;
     }
 };
 
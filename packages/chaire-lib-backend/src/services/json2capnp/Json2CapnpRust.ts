@@ -8,6 +8,25 @@ import TrError from 'chaire-lib-common/lib/utils/TrError';
 import { Json2CapnpBase, collectionCacheParams, objectCacheParams, objectFromCacheParams } from './Json2CapnpBase';
 import json2CapnpService from '../../utils/json2capnp/Json2CapnpService';
 
+/**
+ * Error codes returned by the json2capnp server in the `errorCode` field of
+ * failed responses
+ */
+export const Json2CapnpErrorCodes = {
+    NotFound: 'NOT_FOUND',
+    InvalidPayload: 'INVALID_PAYLOAD',
+    InvalidParameter: 'INVALID_PARAMETER',
+    IoError: 'IO_ERROR',
+    CapnpDecodeError: 'CAPNP_DECODE_ERROR',
+    Conflict: 'CONFLICT',
+    ValidationFailed: 'VALIDATION_FAILED',
+    BodyTooLarge: 'BODY_TOO_LARGE',
+    ReaderLimitExceeded: 'READER_LIMIT_EXCEEDED',
+    JobPanicked: 'JOB_PANICKED',
+    UnsupportedContentEncoding: 'UNSUPPORTED_CONTENT_ENCODING',
+    ShuttingDown: 'SHUTTING_DOWN'
+} as const;
+
 /**
  * Json2Capnp service provider which saves/loads capnp data from an external
  * server, for example the json2capnp Rust server.
@@ -55,7 +74,9 @@ class Json2CapnpRust implements Json2CapnpBase {
             }
             return args.collection;
         } else {
-            console.error(`error loading ${collectionName} collection cache using json2capnp`);
+            console.error(
+                `error loading ${collectionName} collection cache using json2capnp: ${response.errorCode} ${response.error}`
+            );
             throw new TrError(`Cannot load ${collectionName} collection`, 'CAQCFC0001', 'CannotLoadCacheBecauseError');
         }
     }
@@ -82,6 +103,8 @@ class Json2CapnpRust implements Json2CapnpBase {
                     : response.data[args.cacheName];
             const object = args.newObject(attributes);
             return object;
+        } else if (response && response.errorCode === Json2CapnpErrorCodes.NotFound) {
+            return undefined;
         } else {
             console.error(
                 `error loading ${args.cacheName} object cache using json2capnp with data: ${JSON.stringify(
@@ -301,4 +301,24 @@ describe('ObjectFromCache', () => {
         expect(readCacheMock).toHaveBeenCalledWith(objectName, { ...callParams.bodyData, uuid: stubObjects[0].getId() });
         expect(object).toBeUndefined();
     });
-});
\ No newline at end of file
+
+    test('Object not found', async () => {
+        readCacheMock.mockResolvedValueOnce({
+            status: 'fail',
+            cacheName: objectName,
+            error: 'object not found in cache',
+            errorCode: 'NOT_FOUND'
+        });
+        const callParams = {
+            objectUuid: stubObjects[0].getId(),
+            newObject: (attributes) => new ObjectStub(attributes),
+            cacheName: objectName,
+            bodyData: {},
+            cachePathDirectory: 'tmp',
+            cachePath: 'myCache'
+        };
+        const object = await json2CapnpRust.objectFromCache(callParams);
+        expect(readCacheMock).toHaveBeenCalledTimes(1);
+        expect(object).toBeUndefined();
+    });
+});
@@ -0,0 +1,81 @@
+/*
+ * Copyright 2022, Polytechnique Montreal and contributors
+ *
+ * This file is licensed under the MIT License.
+ * License text available at https://opensource.org/licenses/MIT
+ */
+import fs from 'fs';
+import os from 'os';
+import path from 'path';
+
+import Preferences from 'chaire-lib-common/lib/config/Preferences';
+import {
+    lockCacheFile,
+    withLockedCacheFile,
+    withCacheFilesOpening,
+    withCacheFilesSwap,
+    writeTempCacheFile,
+    writeCacheFileAtomically
+} from '../capnpCacheFiles';
+
+const directoryPath = fs.mkdtempSync(path.join(os.tmpdir(), 'capnpCacheFiles-'));
+const cacheFilePath = path.join(directoryPath, 'odTrips.capnpbin');
+
+afterAll(() => {
+    fs.rmSync(directoryPath, { recursive: true, force: true });
+});
+
+test('Write a cache file atomically', async () => {
+    await writeCacheFileAtomically(cacheFilePath, 'previous');
+    await withLockedCacheFile(cacheFilePath, () => writeCacheFileAtomically(cacheFilePath, Buffer.from('new')));
+    expect(fs.readFileSync(cacheFilePath, 'utf8')).toEqual('new');
+    // only the cache file and its lock file remain:
+    expect(fs.readdirSync(directoryPath).sort()).toEqual(['.odTrips.capnpbin.lock', 'odTrips.capnpbin']);
+});
+
+test('A second writer waits for the lock', async () => {
+    const release = await lockCacheFile(cacheFilePath);
+    await expect(lockCacheFile(cacheFilePath, 1)).rejects.toThrow('still being written by another writer');
+    await release();
+    const releaseAgain = await lockCacheFile(cacheFilePath, 1);
+    await releaseAgain();
+});
+
+test('Files are swapped once their readers opened them', async () => {
+    const tempFile = await writeTempCacheFile(cacheFilePath, 'swapped');
+    const releaseReader = await lockCacheFile(`${cacheFilePath}.count`, 1, 'shared');
+    // readers share the swap lock, writers wait for them:
+    await withCacheFilesOpening(cacheFilePath, async () => {
+        expect(fs.readFileSync(cacheFilePath, 'utf8')).toEqual('new');
+    });
+    await expect(lockCacheFile(`${cacheFilePath}.count`, 1)).rejects.toThrow('still being written by another writer');
+    await releaseReader();
+    await withCacheFilesSwap(cacheFilePath, tempFile.commit);
+    expect(fs.readFileSync(cacheFilePath, 'utf8')).toEqual('swapped');
+});
+
+describe('Without the flock command', () => {
+    const envPath = process.env.PATH;
+
+    beforeEach(() => {
+        process.env.PATH = '';
+    });
+
+    afterEach(() => {
+        process.env.PATH = envPath;
+        Preferences.set('json2Capnp.unlockedCacheWrites', false);
+    });
+
+    test('Locks fail by default', async () => {
+        await expect(
+            withLockedCacheFile(cacheFilePath, () => writeCacheFileAtomically(cacheFilePath, 'unlocked'))
+        ).rejects.toThrow('the flock command (util-linux) is missing');
+        expect(fs.readFileSync(cacheFilePath, 'utf8')).toEqual('swapped');
+    });
+
+    test('Files are written without the lock when allowed', async () => {
+        Preferences.set('json2Capnp.unlockedCacheWrites', true);
+        await withLockedCacheFile(cacheFilePath, () => writeCacheFileAtomically(cacheFilePath, 'unlocked'));
+        expect(fs.readFileSync(cacheFilePath, 'utf8')).toEqual('unlocked');
+    });
+});
@@ -0,0 +1,208 @@
+/*
+ * Copyright 2022, Polytechnique Montreal and contributors
+ *
+ * This file is licensed under the MIT License.
+ * License text available at https://opensource.org/licenses/MIT
+ */
+/**
+ * Writes of cache files coordinated with the json2capnp server: writers of a
+ * cache file take an advisory lock (flock) on the `.{file name}.lock` file
+ * next to it, and the file is written to a temporary file renamed over the
+ * cache file, so readers never see a half written file.
+ *
+ * The files of a collection split in several files are read together: they
+ * are renamed while holding the swap lock of the collection, the lock of its
+ * `.count` file, which readers take shared while they open the files.
+ */
+import fs from 'fs';
+import path from 'path';
+import { spawn } from 'child_process';
+
+import TrError from 'chaire-lib-common/lib/utils/TrError';
+import Preferences from 'chaire-lib-common/lib/config/Preferences';
+
+const DEFAULT_LOCK_TIMEOUT_SECONDS = 60;
+
+let tempFileCounter = 0;
+let flockMissingWarned = false;
+
+const lockFilePath = (absoluteFilePath: string): string =>
+    path.join(path.dirname(absoluteFilePath), `.${path.basename(absoluteFilePath)}.lock`);
+
+/**
+ * Take the advisory lock of a cache file, waiting up to timeoutSeconds for
+ * the other writers (like the json2capnp server). Resolves with the function
+ * releasing the lock. A shared lock is only taken by readers, with other
+ * readers.
+ *
+ * Node has no flock, so the lock is held by the flock command (util-linux)
+ * while its command waits for its input to be closed. The lock is also
+ * released if this process dies. Without the flock command, the lock fails,
+ * unless the json2Capnp.unlockedCacheWrites preference allows to go on
+ * without it.
+ */
+const lockCacheFile = (
+    absoluteFilePath: string,
+    timeoutSeconds: number = DEFAULT_LOCK_TIMEOUT_SECONDS,
+    mode: 'exclusive' | 'shared' = 'exclusive'
+): Promise<() => Promise<void>> =>
+    new Promise((resolve, reject) => {
+        const lockProcess = spawn('flock', [
+            `--${mode}`,
+            '--timeout',
+            String(timeoutSeconds),
+            lockFilePath(absoluteFilePath),
+            'sh',
+            '-c',
+            'echo locked && cat > /dev/null'
+        ]);
+        let locked = false;
+
+        const release = (): Promise<void> =>
+            new Promise((resolveRelease) => {
+                if (lockProcess.exitCode !== null || lockProcess.signalCode !== null) {
+                    resolveRelease();
+                    return;
+                }
+                lockProcess.once('exit', () => resolveRelease());
+                lockProcess.stdin.end();
+            });
+
+        lockProcess.stdout.once('data', () => {
+            locked = true;
+            resolve(release);
+        });
+        lockProcess.on('error', (error: NodeJS.ErrnoException) => {
+            locked = true;
+            if (error.code !== 'ENOENT') {
+                reject(error);
+            } else if (Preferences.get('json2Capnp.unlockedCacheWrites', false) === true) {
+                if (!flockMissingWarned) {
+                    console.warn('The flock command is missing, cache files are written without the advisory lock');
+                    flockMissingWarned = true;
+                }
+                resolve(async () => undefined);
+            } else {
+                reject(
+                    new TrError(
+                        `Cannot lock cache file ${absoluteFilePath}: the flock command (util-linux) is missing. Set the json2Capnp.unlockedCacheWrites preference to write cache files without the lock`,
+                        'CAQCFL0002',
+                        'CacheFileLockUnavailable'
+                    )
+                );
+            }
+        });
+        lockProcess.on('exit', () => {
+            if (!locked) {
+                reject(
+                    new TrError(
+                        `Cache file ${absoluteFilePath} is still being written by another writer after ${timeoutSeconds} seconds`,
+                        'CAQCFL0001',
+                        'CacheFileLockedByAnotherWriter'
+                    )
+                );
+            }
+        });
+    });
+
+/**
+ * Run writeFn while holding the advisory lock of a cache file
+ */
+const withLockedCacheFile = async <T>(absoluteFilePath: string, writeFn: () => Promise<T>): Promise<T> => {
+    const release = await lockCacheFile(absoluteFilePath);
+    try {
+        return await writeFn();
+    } finally {
+        await release();
+    }
+};
+
+/**
+ * Run swapFn, renaming the files of a collection, while holding its swap lock
+ */
+const withCacheFilesSwap = async <T>(absoluteCacheFilePath: string, swapFn: () => Promise<T>): Promise<T> => {
+    const release = await lockCacheFile(`${absoluteCacheFilePath}.count`);
+    try {
+        return await swapFn();
+    } finally {
+        await release();
+    }
+};
+
+/**
+ * Run openFn, opening the files of a collection, while holding its swap lock
+ * shared, so the files opened are all from the same write. Opened files stay
+ * readable once replaced.
+ */
+const withCacheFilesOpening = async <T>(absoluteCacheFilePath: string, openFn: () => Promise<T>): Promise<T> => {
+    const release = await lockCacheFile(`${absoluteCacheFilePath}.count`, DEFAULT_LOCK_TIMEOUT_SECONDS, 'shared');
+    try {
+        return await openFn();
+    } finally {
+        await release();
+    }
+};
+
+export interface TempCacheFile {
+    /** Rename the temporary file over the cache file */
+    commit: () => Promise<void>;
+    /** Remove the temporary file, if it was not committed */
+    discard: () => Promise<void>;
+}
+
+/**
+ * Write a cache file to a temporary file in the same directory and fsync it,
+ * to be renamed over the cache file later
+ */
+const writeTempCacheFile = async (absoluteFilePath: string, data: Buffer | string): Promise<TempCacheFile> => {
+    const tempFilePath = path.join(
+        path.dirname(absoluteFilePath),
+        `.${path.basename(absoluteFilePath)}.${process.pid}.${tempFileCounter++}.tmp`
+    );
+    const discard = () => fs.promises.rm(tempFilePath, { force: true });
+    try {
+        const file = await fs.promises.open(tempFilePath, 'wx');
+        try {
+            await file.writeFile(data);
+            await file.sync();
+        } finally {
+            await file.close();
+        }
+    } catch (error) {
+        await discard();
+        throw error;
+    }
+    return { commit: () => fs.promises.rename(tempFilePath, absoluteFilePath), discard };
+};
+
+/**
+ * Write a cache file to a temporary file in the same directory, then fsync
+ * and rename it over the cache file. If the write fails, the previous cache
+ * file is left untouched.
+ */
+const writeCacheFileAtomically = async (absoluteFilePath: string, data: Buffer | string): Promise<void> => {
+    const tempFile = await writeTempCacheFile(absoluteFilePath, data);
+    try {
+        await tempFile.commit();
+    } catch (error) {
+        await tempFile.discard();
+        throw error;
+    }
+};
+
+/**
+ * Remove a cache file, if it exists
+ */
+const removeCacheFile = async (absoluteFilePath: string): Promise<void> => {
+    await fs.promises.rm(absoluteFilePath, { force: true });
+};
+
+export {
+    lockCacheFile,
+    withLockedCacheFile,
+    withCacheFilesSwap,
+    withCacheFilesOpening,
+    writeTempCacheFile,
+    writeCacheFileAtomically,
+    removeCacheFile
+};
@@ -5,6 +5,7 @@
  * License text available at https://opensource.org/licenses/MIT
  */
 import url from 'url';
+import zlib from 'zlib';
 // TODO replace this fetch-retry library with one compatible with TS
 const fetchRetry = require('@zeit/fetch-retry')(require('node-fetch'));
 
@@ -16,6 +17,20 @@ const fetch = async (url: string, opts) => {
 
 import Preferences from 'chaire-lib-common/lib/config/Preferences';
 
+// Json bodies larger than this are sent gzipped, the server decodes them
+const GZIP_BODY_MIN_LENGTH = 1024 * 1024;
+
+const jsonRequest = (jsonData: any): { headers: { [header: string]: string }; body: string | Buffer } => {
+    const body = JSON.stringify(jsonData);
+    if (body.length < GZIP_BODY_MIN_LENGTH) {
+        return { headers: { 'Content-Type': 'application/json' }, body };
+    }
+    return {
+        headers: { 'Content-Type': 'application/json', 'Content-Encoding': 'gzip' },
+        body: zlib.gzipSync(body)
+    };
+};
+
 class Json2CapnpService {
     // TODO Support the cache path directory or remove from method
     async writeCache(cacheName: string, jsonData: any, _cachePathDirectory?: string) {
@@ -23,10 +38,67 @@ class Json2CapnpService {
             const request = `${this.getUrlPrefix()}${cacheName}`;
             const response = await fetch(request, {
                 method: 'POST',
-                headers: {
-                    'Content-Type': 'application/json'
-                },
-                body: JSON.stringify(jsonData)
+                ...jsonRequest(jsonData)
+            });
+            return await response.json();
+        } catch (error) {
+            console.error(error);
+            throw error;
+        }
+    }
+
+    /**
+     * Write a collection cache in a background job on the server, then wait
+     * for the job to finish. Unlike `writeCache`, a long conversion does not
+     * keep the request open, so a retried request does not submit the work
+     * again. Returns the job status, with the `data` or the `errors` of the
+     * write.
+     *
+     * @param cacheName The collection name (`odTrips`, `paths`, ...)
+     * @param jsonData The body of the write
+     * @param pollInterval Interval between the checks of the job, in ms
+     */
+    async writeCacheAsync(cacheName: string, jsonData: any, pollInterval = 1000) {
+        const job = await this.writeCache(`${cacheName}?async=true`, jsonData);
+        if (job.status !== 'success') {
+            return job;
+        }
+        try {
+            for (;;) {
+                const response = await fetch(`${this.getUrlPrefix()}jobs/${job.data.jobId}`, {
+                    method: 'GET'
+                });
+                const jobStatus = await response.json();
+                if (jobStatus.status !== 'success' || jobStatus.data.state !== 'running') {
+                    return jobStatus;
+                }
+                await new Promise((resolve) => setTimeout(resolve, pollInterval));
+            }
+        } catch (error) {
+            console.error(error);
+            throw error;
+        }
+    }
+
+    /**
+     * Upsert and remove items of a collection cache by uuid, without sending
+     * the whole collection. The response data contains the number of
+     * `inserted`, `updated` and `removed` items.
+     *
+     * @param cacheName The collection name (`nodes`, `lines`, ...)
+     * @param patch The items to upsert, with the shape of the collection
+     * items (geojson features for feature collections), and the uuids to
+     * remove
+     */
+    async patchCache(
+        cacheName: string,
+        patch: { upsert?: any[]; remove?: string[]; [param: string]: any }
+    ) {
+        try {
+            const request = `${this.getUrlPrefix()}${cacheName}`;
+            const response = await fetch(request, {
+                method: 'PATCH',
+                ...jsonRequest(patch)
             });
             return await response.json();
         } catch (error) {
@@ -35,6 +107,90 @@ class Json2CapnpService {
         }
     }
 
+    /**
+     * Append items to a sharded collection (`odTrips`, `persons` or
+     * `households`) in a new shard, without rewriting the current ones
+     *
+     * @param cacheName The collection name
+     * @param items The items to append
+     * @param params Other body parameters, like the `data_source_uuid`
+     */
+    async appendCache(cacheName: string, items: any[], params = {}) {
+        return await this.writeCache(`${cacheName}/append`, { ...params, [cacheName]: items });
+    }
+
+    /**
+     * Upload a sharded collection (`odTrips`, `persons` or `households`) in
+     * chunks of an upload session, each converted when it is received, then
+     * replace the collection with all the chunks at once. The session is
+     * aborted if a chunk fails.
+     *
+     * @param cacheName The collection name
+     * @param chunks The items of each chunk, in order
+     * @param params Other session parameters, like the `data_source_uuid` or
+     * `append` to append the chunks instead of replacing the collection
+     */
+    async uploadCache(cacheName: string, chunks: any[][], params = {}) {
+        const session = await this.writeCache(`${cacheName}/uploads`, params);
+        if (session.status !== 'success') {
+            return session;
+        }
+        const uploadUrl = `${this.getUrlPrefix()}${cacheName}/uploads/${session.data.uploadId}`;
+        try {
+            for (let chunk = 0; chunk < chunks.length; chunk++) {
+                const response = await fetch(`${uploadUrl}/chunks/${chunk}`, {
+                    method: 'PUT',
+                    ...jsonRequest({ [cacheName]: chunks[chunk] })
+                });
+                const chunkResponse = await response.json();
+                if (chunkResponse.status !== 'success') {
+                    await fetch(uploadUrl, { method: 'DELETE' });
+                    return chunkResponse;
+                }
+            }
+            return await this.writeCache(`${cacheName}/uploads/${session.data.uploadId}/commit`, {
+                chunks: chunks.length
+            });
+        } catch (error) {
+            console.error(error);
+            throw error;
+        }
+    }
+
+    /**
+     * Write many objects of a collection at once (`lines/objects`,
+     * `nodes/objects`, ...). The response data reports the status of each
+     * object, in the same order, with the `succeeded` and `failed` counts.
+     *
+     * @param collectionName The collection name (`lines`, `nodes`, ...)
+     * @param objects The objects to write
+     * @param params Other body parameters, like the `data_source_uuid`
+     */
+    async writeCacheObjects(collectionName: string, objects: any[], params = {}) {
+        return await this.writeCache(`${collectionName}/objects`, { ...params, [collectionName]: objects });
+    }
+
+    /**
+     * Read many objects of a collection at once. The response data contains
+     * the `objects` by uuid and the uuids `missing` from the cache.
+     *
+     * @param collectionName The collection name (`lines`, `nodes`, ...)
+     * @param uuids The uuids of the objects to read
+     * @param params Other body parameters, like the `data_source_uuid`
+     */
+    async readCacheObjects(collectionName: string, uuids: string[], params = {}) {
+        return await this.writeCache(`${collectionName}/objects/read`, { ...params, uuids });
+    }
+
+    /**
+     * Read a cache. Large collections are streamed by the server: if an item
+     * fails once the response has started, the response ends with the
+     * `error` and `errorCode` of the failure, and is returned as failed.
+     *
+     * @param cacheName The collection or object name (`lines`, `line`, ...)
+     * @param params Query parameters, like the object `uuid` or the
+     * `data_source_uuid`
+     */
     async readCache(cacheName: string, params = {}) {
         try {
             const query = new url.URLSearchParams();
@@ -46,6 +202,40 @@ class Json2CapnpService {
             const response = await fetch(request, {
                 method: 'GET'
             });
+            const json = await response.json();
+            if (json && json.status === 'success' && json.errorCode !== undefined) {
+                const failure = { ...json, status: 'fail' };
+                delete failure.data;
+                return failure;
+            }
+            return json;
+        } catch (error) {
+            console.error(error);
+            throw error;
+        }
+    }
+
+    /**
+     * Delete a cache. The response data contains the `removed` cache files,
+     * relative to the project cache directory.
+     *
+     * @param cacheName The collection or object name (`lines`, `line`, ...),
+     * or `dataSources/{dataSourceUuid}/cache` for the whole cache of a data
+     * source
+     * @param params Query parameters, like the object `uuid` or the
+     * `data_source_uuid`
+     */
+    async deleteCache(cacheName: string, params = {}) {
+        try {
+            const query = new url.URLSearchParams();
+            for (const param in params) {
+                query.append(param, params[param]);
+            }
+            const request = `${this.getUrlPrefix()}${cacheName}?${query.toString()}`;
+
+            const response = await fetch(request, {
+                method: 'DELETE'
+            });
             return await response.json();
         } catch (error) {
             console.error(error);
@@ -6,6 +6,7 @@
  */
 import json2CapnpService from '../Json2CapnpService';
 import fetchMock from 'jest-fetch-mock';
+import zlib from 'zlib';
 import Preferences from 'chaire-lib-common/lib/config/Preferences';
 
 const jsonCapnpDefaultPrefs = Preferences.get('json2Capnp');
@@ -27,6 +28,18 @@ describe('Valid calls and return values', () => {
         expect(result).toEqual(jsonObject);
     });
 
+    test('Read streamed value failing after its first items', async () => {
+        fetchMock.mockOnce(JSON.stringify({
+            status: 'success',
+            cacheName: 'odTrips',
+            data: { odTrips: [{ id: 'a' }] },
+            error: 'Invalid item',
+            errorCode: 'CAPNP_DECODE_ERROR'
+        }));
+        const result = await json2CapnpService.readCache('odTrips', {});
+        expect(result).toEqual({ status: 'fail', cacheName: 'odTrips', error: 'Invalid item', errorCode: 'CAPNP_DECODE_ERROR' });
+    });
+
     test('Write value', async() => {
         const jsonObject = {
             field: 3,
@@ -37,6 +50,20 @@ describe('Valid calls and return values', () => {
         expect(fetchMock).toHaveBeenCalledTimes(1);
         expect(fetchMock).toHaveBeenCalledWith('http://localhost:2000/test', expect.objectContaining({ method: 'POST', headers: expect.anything(), body: JSON.stringify(jsonObject) }));
     });
+
+    test('Write large value', async() => {
+        const jsonObject = {
+            data: 'hello'.repeat(300000)
+        };
+        fetchMock.mockOnce('{ "status": "OK" }');
+        await json2CapnpService.writeCache('test', jsonObject);
+        expect(fetchMock).toHaveBeenCalledTimes(1);
+        expect(fetchMock).toHaveBeenCalledWith('http://localhost:2000/test', expect.objectContaining({
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json', 'Content-Encoding': 'gzip' },
+            body: zlib.gzipSync(JSON.stringify(jsonObject))
+        }));
+    });
 });
 
 describe('Valid calls, with default preferences changes', () => {
@@ -108,4 +135,113 @@ describe('Call errors', () => {
         expect(fetchMock).toHaveBeenCalledTimes(5);
         expect(fetchMock).toHaveBeenCalledWith('http://localhost:2000/test', expect.objectContaining({ method: 'POST', headers: expect.anything(), body: JSON.stringify(jsonObject) }));
     });
-});
\ No newline at end of file
+});
+describe('Cache updates, uploads and batches', () => {
+    const requests = () =>
+        fetchMock.mock.calls.map(([request, options]) => ({
+            url: request,
+            method: (options as any).method,
+            body: (options as any).body === undefined ? undefined : JSON.parse((options as any).body)
+        }));
+
+    test('Patch cache', async () => {
+        const patch = { upsert: [{ type: 'Feature', properties: { id: 'a' } }], remove: ['b'] };
+        fetchMock.mockOnce(JSON.stringify({ status: 'success', data: { inserted: 1, updated: 0, removed: 1 } }));
+        const result = await json2CapnpService.patchCache('nodes', patch);
+        expect(requests()).toEqual([{ url: 'http://localhost:2000/nodes', method: 'PATCH', body: patch }]);
+        expect(fetchMock).toHaveBeenCalledWith('http://localhost:2000/nodes', expect.objectContaining({ headers: { 'Content-Type': 'application/json' } }));
+        expect(result.data).toEqual({ inserted: 1, updated: 0, removed: 1 });
+    });
+
+    test('Patch cache with invalid items', async () => {
+        const failure = { status: 'fail', cacheName: 'nodes', errorCode: 'VALIDATION_FAILED', errors: [{ pointer: '/upsert/0/properties/integer_id', message: 'must be a positive integer' }] };
+        fetchMock.mockOnce(JSON.stringify(failure), { status: 400 });
+        const result = await json2CapnpService.patchCache('nodes', { upsert: [{}] });
+        expect(result).toEqual(failure);
+    });
+
+    test('Append to cache', async () => {
+        fetchMock.mockOnce(JSON.stringify({ status: 'success', data: { count: 3, shards: 2 } }));
+        const result = await json2CapnpService.appendCache('odTrips', [{ id: 'a' }], { data_source_uuid: 'ds' });
+        expect(requests()).toEqual([{ url: 'http://localhost:2000/odTrips/append', method: 'POST', body: { data_source_uuid: 'ds', odTrips: [{ id: 'a' }] } }]);
+        expect(result.data).toEqual({ count: 3, shards: 2 });
+    });
+
+    test('Upload cache in chunks', async () => {
+        fetchMock
+            .mockOnce(JSON.stringify({ status: 'success', data: { uploadId: 'u1' } }))
+            .mockOnce(JSON.stringify({ status: 'success', data: { uploadId: 'u1', chunk: 0, count: 2, chunks: 1 } }))
+            .mockOnce(JSON.stringify({ status: 'success', data: { uploadId: 'u1', chunk: 1, count: 1, chunks: 2 } }))
+            .mockOnce(JSON.stringify({ status: 'success', data: { count: 3, shards: 1 } }));
+        const result = await json2CapnpService.uploadCache('odTrips', [[{ id: 'a' }, { id: 'b' }], [{ id: 'c' }]], { append: true });
+        expect(requests()).toEqual([
+            { url: 'http://localhost:2000/odTrips/uploads', method: 'POST', body: { append: true } },
+            { url: 'http://localhost:2000/odTrips/uploads/u1/chunks/0', method: 'PUT', body: { odTrips: [{ id: 'a' }, { id: 'b' }] } },
+            { url: 'http://localhost:2000/odTrips/uploads/u1/chunks/1', method: 'PUT', body: { odTrips: [{ id: 'c' }] } },
+            { url: 'http://localhost:2000/odTrips/uploads/u1/commit', method: 'POST', body: { chunks: 2 } }
+        ]);
+        expect(result.data).toEqual({ count: 3, shards: 1 });
+    });
+
+    test('Upload cache aborted on a failed chunk', async () => {
+        const chunkFailure = { status: 'fail', cacheName: 'odTrips', errorCode: 'VALIDATION_FAILED', errors: [{ pointer: '/odTrips/0/id', message: 'is required' }] };
+        fetchMock
+            .mockOnce(JSON.stringify({ status: 'success', data: { uploadId: 'u1' } }))
+            .mockOnce(JSON.stringify({ status: 'success', data: { uploadId: 'u1', chunk: 0, count: 1, chunks: 1 } }))
+            .mockOnce(JSON.stringify(chunkFailure), { status: 400 })
+            .mockOnce(JSON.stringify({ status: 'success', data: { uploadId: 'u1', aborted: true } }));
+        const result = await json2CapnpService.uploadCache('odTrips', [[{ id: 'a' }], [{}], [{ id: 'c' }]]);
+        expect(requests().map(({ url, method }) => `${method} ${url}`)).toEqual([
+            'POST http://localhost:2000/odTrips/uploads',
+            'PUT http://localhost:2000/odTrips/uploads/u1/chunks/0',
+            'PUT http://localhost:2000/odTrips/uploads/u1/chunks/1',
+            'DELETE http://localhost:2000/odTrips/uploads/u1'
+        ]);
+        expect(result).toEqual(chunkFailure);
+    });
+
+    test('Upload cache without session', async () => {
+        const failure = { status: 'fail', cacheName: 'odTrips', errorCode: 'INVALID_PARAMETER', error: 'Invalid data_source_uuid' };
+        fetchMock.mockOnce(JSON.stringify(failure), { status: 400 });
+        const result = await json2CapnpService.uploadCache('odTrips', [[{ id: 'a' }]], { data_source_uuid: '..' });
+        expect(fetchMock).toHaveBeenCalledTimes(1);
+        expect(result).toEqual(failure);
+    });
+
+    test('Write cache asynchronously', async () => {
+        fetchMock
+            .mockOnce(JSON.stringify({ status: 'success', cacheName: 'odTrips', data: { jobId: 'j1' } }), { status: 202 })
+            .mockOnce(JSON.stringify({ status: 'success', jobId: 'j1', data: { id: 'j1', state: 'running', recordsProcessed: 10 } }))
+            .mockOnce(JSON.stringify({ status: 'success', jobId: 'j1', data: { id: 'j1', state: 'succeeded', recordsProcessed: 20, statusCode: 200, data: { count: 20, shards: 1 } } }));
+        const result = await json2CapnpService.writeCacheAsync('odTrips', { odTrips: [] }, 0);
+        expect(requests()).toEqual([
+            { url: 'http://localhost:2000/odTrips?async=true', method: 'POST', body: { odTrips: [] } },
+            { url: 'http://localhost:2000/jobs/j1', method: 'GET', body: undefined },
+            { url: 'http://localhost:2000/jobs/j1', method: 'GET', body: undefined }
+        ]);
+        expect(result.data.state).toEqual('succeeded');
+    });
+
+    test('Write cache asynchronously, failing to start', async () => {
+        const failure = { status: 'fail', cacheName: 'odTrips', errorCode: 'BODY_TOO_LARGE', error: 'The body is larger than the max_body_size limit' };
+        fetchMock.mockOnce(JSON.stringify(failure), { status: 413 });
+        const result = await json2CapnpService.writeCacheAsync('odTrips', { odTrips: [] }, 0);
+        expect(fetchMock).toHaveBeenCalledTimes(1);
+        expect(result).toEqual(failure);
+    });
+
+    test('Write cache objects', async () => {
+        const summary = { succeeded: 1, failed: 1, objects: [{ id: 'a', status: 'success' }, { id: 'b', status: 'fail', error: 'Invalid', errorCode: 'VALIDATION_FAILED', errors: [] }] };
+        fetchMock.mockOnce(JSON.stringify({ status: 'success', data: summary }));
+        const result = await json2CapnpService.writeCacheObjects('lines', [{ id: 'a' }, { id: 'b' }], { data_source_uuid: 'ds' });
+        expect(requests()).toEqual([{ url: 'http://localhost:2000/lines/objects', method: 'POST', body: { data_source_uuid: 'ds', lines: [{ id: 'a' }, { id: 'b' }] } }]);
+        expect(result.data).toEqual(summary);
+    });
+
+    test('Read cache objects', async () => {
+        fetchMock.mockOnce(JSON.stringify({ status: 'success', data: { objects: { a: { id: 'a' } }, missing: ['b'] } }));
+        const result = await json2CapnpService.readCacheObjects('lines', ['a', 'b']);
+        expect(requests()).toEqual([{ url: 'http://localhost:2000/lines/objects/read', method: 'POST', body: { uuids: ['a', 'b'] } }]);
+        expect(result.data).toEqual({ objects: { a: { id: 'a' } }, missing: ['b'] });
+    });
+});
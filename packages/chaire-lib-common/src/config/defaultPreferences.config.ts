@@ -197,7 +197,10 @@ const defaultPreferences: PreferencesModel = {
     json2Capnp: {
         enabled: true, // if not enabled, will use javascript to read/write capnp cache files (slower)
         host: 'http://localhost',
-        port: 2000
+        port: 2000,
+        // the javascript writer locks the cache files with the flock command (util-linux) and fails without it.
+        // Set to true to write them without the lock instead, when no other process writes the cache:
+        unlockedCacheWrites: false
     },
     osrmRouting: {
         // directoryPrefix: used as a prefix, can be overridden in .env (OSRM_DIRECTORY_PREFIX).
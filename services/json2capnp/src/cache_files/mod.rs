@@ -0,0 +1,461 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use fs2::FileExt;
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::errors::CacheError;
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Cache files currently locked by a writer of this process
+static LOCKED_PATHS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+static LOCK_RELEASED: Condvar = Condvar::new();
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What a writer does when the cache file it writes is locked by another writer
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteLockMode {
+    /// Wait for the other writers, in turn, up to the lock timeout
+    Wait,
+    /// Fail immediately with a conflict
+    Fail,
+}
+
+impl WriteLockMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WriteLockMode::Wait => "wait",
+            WriteLockMode::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WriteLock {
+    pub mode: WriteLockMode,
+    pub timeout: Duration,
+}
+
+impl WriteLock {
+
+    /// Get the write lock options from the request config (write_lock and
+    /// write_lock_timeout in seconds), waiting for 60 seconds by default
+    pub fn from_config(config: &serde_json::Value) -> WriteLock {
+        let mode = match config["write_lock"].as_str() {
+            Some("fail") => WriteLockMode::Fail,
+            _ => WriteLockMode::Wait
+        };
+        let timeout = Duration::from_secs(config["write_lock_timeout"].as_u64().unwrap_or(60));
+        WriteLock { mode, timeout }
+    }
+
+}
+
+/// Exclusive lock on a cache file, released when dropped.
+///
+/// Writers of this process are coordinated in memory, and other processes
+/// with an advisory lock (flock) on a `.{file name}.lock` file next to the
+/// cache file. The cache file itself cannot be locked since it is replaced
+/// on every write.
+pub struct CacheFileLock {
+    path: PathBuf,
+    lock_file: Option<File>,
+}
+
+impl CacheFileLock {
+
+    pub fn acquire(path: &Path, write_lock: &WriteLock) -> Result<CacheFileLock, CacheError> {
+
+        let deadline = Instant::now() + write_lock.timeout;
+
+        let mut locked_paths = LOCKED_PATHS.lock().unwrap_or_else(PoisonError::into_inner);
+        while locked_paths.iter().any(|locked_path| locked_path == path) {
+            let now = Instant::now();
+            if write_lock.mode == WriteLockMode::Fail || now >= deadline {
+                return Err(lock_conflict(path, write_lock));
+            }
+            locked_paths = LOCK_RELEASED.wait_timeout(locked_paths, deadline - now).unwrap_or_else(PoisonError::into_inner).0;
+        }
+        locked_paths.push(path.to_path_buf());
+        drop(locked_paths);
+
+        // from now on, dropping the lock releases the path:
+        let mut lock = CacheFileLock { path: path.to_path_buf(), lock_file: None };
+
+        let lock_file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(lock_file_path(path)?)?;
+        loop {
+            match lock_file.try_lock_exclusive() {
+                Ok(()) => break,
+                Err(error) if error.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                    let now = Instant::now();
+                    if write_lock.mode == WriteLockMode::Fail || now >= deadline {
+                        return Err(lock_conflict(path, write_lock));
+                    }
+                    thread::sleep(LOCK_POLL_INTERVAL.min(deadline - now));
+                },
+                Err(error) => return Err(error.into())
+            }
+        }
+        lock.lock_file = Some(lock_file);
+        Ok(lock)
+
+    }
+
+}
+
+impl Drop for CacheFileLock {
+    fn drop(&mut self) {
+        // closing the lock file releases the advisory lock, before other writers of this process are woken up:
+        drop(self.lock_file.take());
+        let mut locked_paths = LOCKED_PATHS.lock().unwrap_or_else(PoisonError::into_inner);
+        locked_paths.retain(|locked_path| locked_path != &self.path);
+        LOCK_RELEASED.notify_all();
+    }
+}
+
+/// Advisory lock (flock) on the `.{file name}.lock` file of a cache file
+/// read together with other files (eg the count file of shards), released
+/// when dropped. Readers hold it shared while they open the files, writers
+/// exclusively while they replace them, in this process and the others, so
+/// it is only held for these short operations and is waited for without
+/// timeout.
+pub struct SwapLock {
+    _lock_file: File,
+}
+
+impl SwapLock {
+
+    pub fn read(path: &Path) -> io::Result<SwapLock> {
+        let lock_file = SwapLock::open(path)?;
+        lock_file.lock_shared()?;
+        Ok(SwapLock { _lock_file: lock_file })
+    }
+
+    pub fn write(path: &Path) -> io::Result<SwapLock> {
+        let lock_file = SwapLock::open(path)?;
+        lock_file.lock_exclusive()?;
+        Ok(SwapLock { _lock_file: lock_file })
+    }
+
+    fn open(path: &Path) -> io::Result<File> {
+        OpenOptions::new().read(true).write(true).create(true).truncate(false).open(lock_file_path(path)?)
+    }
+
+}
+
+/// Remove a cache file once its other writers are done. Returns false if the
+/// file does not exist.
+pub fn remove_locked(path: &Path, write_lock: &WriteLock) -> Result<bool, CacheError> {
+    let _lock = CacheFileLock::acquire(path, write_lock)?;
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error.into())
+    }
+}
+
+/// Remove a cache directory with its content once the writers of its cache
+/// files are done, and return the removed cache files (lock files are not
+/// listed). The locks are taken in the order of the paths, and the directory
+/// is listed again while holding them until no file was added meanwhile. It
+/// is then renamed before being removed, so a writer of a new file either
+/// committed it before (and it is removed) or fails to commit it, instead of
+/// racing the removal.
+pub fn remove_directory(path: &Path, write_lock: &WriteLock) -> Result<Vec<PathBuf>, CacheError> {
+    let mut locked_files: Vec<PathBuf> = Vec::new();
+    let mut locks = Vec::new();
+    let removed_files = loop {
+        let mut files = Vec::new();
+        list_cache_files(path, &mut files)?;
+        files.sort();
+        let new_files: Vec<&PathBuf> = files.iter().filter(|file_path| !locked_files.contains(file_path)).collect();
+        if new_files.is_empty() {
+            break files;
+        }
+        for file_path in new_files {
+            locks.push(CacheFileLock::acquire(file_path, write_lock)?);
+            locked_files.push(file_path.clone());
+        }
+    };
+    let renamed_path = temp_file_path(path)?;
+    fs::rename(path, &renamed_path)?;
+    fs::remove_dir_all(&renamed_path)?;
+    Ok(removed_files)
+}
+
+fn list_cache_files(directory_path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            list_cache_files(&path, files)?;
+        } else if !is_lock_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_lock_file(path: &Path) -> bool {
+    let file_name = path.file_name().map(|file_name| file_name.to_string_lossy()).unwrap_or_default();
+    file_name.starts_with('.') && file_name.ends_with(".lock")
+}
+
+fn temp_file_path(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid cache file path {}", path.display())))?;
+    Ok(path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst)
+    )))
+}
+
+fn lock_file_path(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid cache file path {}", path.display())))?;
+    Ok(path.with_file_name(format!(".{}.lock", file_name.to_string_lossy())))
+}
+
+fn lock_conflict(path: &Path, write_lock: &WriteLock) -> CacheError {
+    match write_lock.mode {
+        WriteLockMode::Fail => CacheError::Conflict(format!("Cache file {} is being written by another writer", path.display())),
+        WriteLockMode::Wait => CacheError::Conflict(format!("Cache file {} is still being written by another writer after {} seconds", path.display(), write_lock.timeout.as_secs()))
+    }
+}
+
+/// A cache file written to a temporary file in the same directory, then
+/// fsynced and renamed over the destination on commit, so readers (like
+/// trRouting) never see a truncated or half written file. If the file is
+/// dropped without being committed, the temporary file is removed and the
+/// previous file is left untouched.
+pub struct AtomicFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    file: File,
+    committed: bool,
+    _lock: Option<CacheFileLock>,
+}
+
+impl AtomicFile {
+
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<AtomicFile> {
+        let path = path.as_ref().to_path_buf();
+        let temp_path = temp_file_path(&path)?;
+        let file = OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        Ok(AtomicFile { path, temp_path, file, committed: false, _lock: None })
+    }
+
+    /// A file already written elsewhere on the same file system (eg an
+    /// upload chunk), hard linked to the temporary file, so the source file
+    /// stays in place
+    pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(source_path: P, path: Q) -> io::Result<AtomicFile> {
+        let path = path.as_ref().to_path_buf();
+        let temp_path = temp_file_path(&path)?;
+        fs::hard_link(source_path, &temp_path)?;
+        match File::open(&temp_path) {
+            Ok(file) => Ok(AtomicFile { path, temp_path, file, committed: false, _lock: None }),
+            Err(error) => {
+                let _ = fs::remove_file(&temp_path);
+                Err(error)
+            }
+        }
+    }
+
+    /// Lock the cache file, then create it. The lock is held until the file
+    /// is committed or dropped.
+    pub fn create_locked<P: AsRef<Path>>(path: P, write_lock: &WriteLock) -> Result<AtomicFile, CacheError> {
+        let lock = CacheFileLock::acquire(path.as_ref(), write_lock)?;
+        let mut file = AtomicFile::create(path)?;
+        file._lock = Some(lock);
+        Ok(file)
+    }
+
+    pub fn file_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Flush and fsync the temporary file, then rename it to its destination
+    pub fn commit(self) -> io::Result<()> {
+        let path = self.path.clone();
+        self.commit_to(path)
+    }
+
+    /// Commit the file to another destination in the same directory (eg a
+    /// shard file, named once all the shards are written)
+    pub fn commit_to<P: AsRef<Path>>(mut self, path: P) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_all()?;
+        fs::rename(&self.temp_path, path.as_ref())?;
+        self.committed = true;
+        sync_parent_directory(path.as_ref())
+    }
+
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Make the rename durable
+#[cfg(unix)]
+fn sync_parent_directory(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => File::open(parent)?.sync_all(),
+        None => Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_directory(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::thread;
+    use pretty_assertions::{assert_eq};
+
+    fn directory_entries(path: &str) -> Vec<String> {
+        let mut entries: Vec<String> = fs::read_dir(path).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn atomic_file() {
+
+        let _ = fs::remove_dir_all("test/atomic_file");
+        fs::create_dir_all("test/atomic_file").unwrap();
+        fs::write("test/atomic_file/cache.capnpbin", "previous").unwrap();
+
+        // not committed: the previous file is untouched and the temporary file is removed
+        let mut atomic_file = AtomicFile::create("test/atomic_file/cache.capnpbin").unwrap();
+        atomic_file.write_all(b"half written").unwrap();
+        assert_eq!(fs::read_to_string("test/atomic_file/cache.capnpbin").unwrap(), "previous");
+        drop(atomic_file);
+        assert_eq!(fs::read_to_string("test/atomic_file/cache.capnpbin").unwrap(), "previous");
+        assert_eq!(directory_entries("test/atomic_file"), vec!["cache.capnpbin"]);
+
+        // committed: replaced
+        let mut atomic_file = AtomicFile::create("test/atomic_file/cache.capnpbin").unwrap();
+        atomic_file.file_mut().write_all(b"new").unwrap();
+        atomic_file.commit().unwrap();
+        assert_eq!(fs::read_to_string("test/atomic_file/cache.capnpbin").unwrap(), "new");
+        assert_eq!(directory_entries("test/atomic_file"), vec!["cache.capnpbin"]);
+
+        // hard linked and committed to another destination: the source file stays
+        let atomic_file = AtomicFile::hard_link("test/atomic_file/cache.capnpbin", "test/atomic_file/cache.capnpbin").unwrap();
+        atomic_file.commit_to("test/atomic_file/cache.capnpbin.0").unwrap();
+        assert_eq!(fs::read_to_string("test/atomic_file/cache.capnpbin.0").unwrap(), "new");
+        assert_eq!(directory_entries("test/atomic_file"), vec!["cache.capnpbin", "cache.capnpbin.0"]);
+
+    }
+
+    #[test]
+    fn cache_file_locks() {
+
+        fs::create_dir_all("test/cache_file_locks").unwrap();
+        let path = fs::canonicalize("test/cache_file_locks").unwrap().join("nodes.capnpbin");
+        let fail = WriteLock { mode: WriteLockMode::Fail, timeout: Duration::from_secs(10) };
+        let wait = WriteLock { mode: WriteLockMode::Wait, timeout: Duration::from_secs(10) };
+
+        // a second writer of this process fails or waits for the first one:
+        let atomic_file = AtomicFile::create_locked(&path, &fail).unwrap();
+        assert_eq!(AtomicFile::create_locked(&path, &fail).err().unwrap().code(), "CONFLICT");
+        let waiting_writer = {
+            let path = path.clone();
+            thread::spawn(move || AtomicFile::create_locked(&path, &wait).and_then(|file| file.commit().map_err(CacheError::from)))
+        };
+        thread::sleep(Duration::from_millis(100));
+        atomic_file.commit().unwrap();
+        waiting_writer.join().unwrap().unwrap();
+
+        // another process holds the advisory lock:
+        let other_process_lock = OpenOptions::new().write(true).create(true).truncate(false).open(lock_file_path(&path).unwrap()).unwrap();
+        other_process_lock.lock_exclusive().unwrap();
+        assert_eq!(AtomicFile::create_locked(&path, &fail).err().unwrap().code(), "CONFLICT");
+        let short_wait = WriteLock { mode: WriteLockMode::Wait, timeout: Duration::from_millis(100) };
+        assert_eq!(AtomicFile::create_locked(&path, &short_wait).err().unwrap().code(), "CONFLICT");
+        other_process_lock.unlock().unwrap();
+        AtomicFile::create_locked(&path, &fail).unwrap();
+
+        // removing waits for the writers too:
+        let atomic_file = AtomicFile::create_locked(&path, &fail).unwrap();
+        assert_eq!(remove_locked(&path, &fail).err().unwrap().code(), "CONFLICT");
+        drop(atomic_file);
+        assert!(remove_locked(&path, &fail).unwrap());
+        assert!(!remove_locked(&path, &fail).unwrap());
+
+        assert_eq!(WriteLock::from_config(&json!({})), WriteLock { mode: WriteLockMode::Wait, timeout: Duration::from_secs(60) });
+        assert_eq!(WriteLock::from_config(&json!({ "write_lock": "fail", "write_lock_timeout": 5 })), WriteLock { mode: WriteLockMode::Fail, timeout: Duration::from_secs(5) });
+
+    }
+
+    #[test]
+    fn remove_directories() {
+
+        let _ = fs::remove_dir_all("test/remove_directory");
+        fs::create_dir_all("test/remove_directory/nodes").unwrap();
+        fs::write("test/remove_directory/lines.capnpbin", "").unwrap();
+        fs::write("test/remove_directory/.lines.capnpbin.lock", "").unwrap();
+        fs::write("test/remove_directory/nodes/node_1.capnpbin", "").unwrap();
+
+        let fail = WriteLock { mode: WriteLockMode::Fail, timeout: Duration::from_secs(1) };
+        let lock = CacheFileLock::acquire(Path::new("test/remove_directory/nodes/node_1.capnpbin"), &fail).unwrap();
+        assert!(matches!(remove_directory(Path::new("test/remove_directory"), &fail), Err(CacheError::Conflict(_))));
+        assert!(Path::new("test/remove_directory/lines.capnpbin").exists());
+        drop(lock);
+
+        // files added by the writers waited for are removed too:
+        let lock = CacheFileLock::acquire(Path::new("test/remove_directory/nodes/node_1.capnpbin"), &fail).unwrap();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            fs::write("test/remove_directory/nodes/node_2.capnpbin", "").unwrap();
+            drop(lock);
+        });
+        let wait = WriteLock { mode: WriteLockMode::Wait, timeout: Duration::from_secs(5) };
+        let removed_files = remove_directory(Path::new("test/remove_directory"), &wait).unwrap();
+        writer.join().unwrap();
+        assert_eq!(removed_files, vec![
+            Path::new("test/remove_directory/lines.capnpbin"),
+            Path::new("test/remove_directory/nodes/node_1.capnpbin"),
+            Path::new("test/remove_directory/nodes/node_2.capnpbin")
+        ]);
+        assert!(!Path::new("test/remove_directory").exists());
+        assert!(fs::read_dir("test").unwrap().all(|entry| !entry.unwrap().file_name().to_string_lossy().starts_with(".remove_directory")));
+
+    }
+}
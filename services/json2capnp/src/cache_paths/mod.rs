@@ -0,0 +1,140 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use crate::errors::CacheError;
+
+/// Check that a request parameter is a valid uuid, so it can be used as a
+/// file or directory name
+pub fn validate_uuid<'a>(parameter_name: &str, value: &'a str) -> Result<&'a str, CacheError> {
+    match uuid::Uuid::parse_str(value) {
+        Ok(_) => Ok(value),
+        Err(_) => Err(CacheError::InvalidParameter(format!("Invalid {} {}: must be a valid uuid", parameter_name, value)))
+    }
+}
+
+/// Check that a custom subdirectory is a relative path that stays inside its
+/// parent directory (no root, prefix or .. components)
+pub fn validate_subdirectory<'a>(parameter_name: &str, value: &'a str) -> Result<&'a Path, CacheError> {
+    let path = Path::new(value);
+    let is_confined = path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if value.is_empty() || !is_confined {
+        return Err(CacheError::InvalidParameter(format!("Invalid {} {}: must be a relative path inside the project cache directory", parameter_name, value)));
+    }
+    Ok(path)
+}
+
+/// Get the cache directory for a request, inside the project cache directory:
+/// {project}/dataSources/{data_source_uuid}/{custom_subdirectory or subdirectory}
+pub fn cache_directory_path(
+    project_cache_directory_path: &Path,
+    data_source_uuid: Option<&str>,
+    custom_subdirectory_path: Option<&str>,
+    subdirectory: Option<&str>
+) -> Result<PathBuf, CacheError> {
+
+    let mut cache_directory_path = project_cache_directory_path.to_path_buf();
+    if let Some(data_source_uuid) = data_source_uuid {
+        cache_directory_path.push("dataSources");
+        cache_directory_path.push(validate_uuid("data_source_uuid", data_source_uuid)?);
+    }
+    match custom_subdirectory_path {
+        Some(custom_subdirectory_path) => cache_directory_path.push(validate_subdirectory("cache_directory_path", custom_subdirectory_path)?),
+        None => if let Some(subdirectory) = subdirectory {
+            cache_directory_path.push(subdirectory);
+        }
+    }
+    Ok(cache_directory_path)
+
+}
+
+/// Make sure a path resolves inside the project cache directory, following
+/// symlinks. Only the part of the path that already exists is canonicalized.
+pub fn confine(project_cache_directory_path: &Path, path: &Path) -> Result<PathBuf, CacheError> {
+
+    let project_cache_directory_path = fs::canonicalize(project_cache_directory_path)?;
+    let mut existing_path = path;
+    let mut missing_components = Vec::new();
+    let canonical_path = loop {
+        match fs::canonicalize(existing_path) {
+            Ok(canonical_path) => break canonical_path,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                match (existing_path.parent(), existing_path.file_name()) {
+                    (Some(parent), Some(file_name)) => {
+                        missing_components.push(file_name);
+                        existing_path = parent;
+                    },
+                    _ => return Err(error.into())
+                }
+            },
+            Err(error) => return Err(error.into())
+        }
+    };
+
+    if !canonical_path.starts_with(&project_cache_directory_path) {
+        return Err(CacheError::InvalidParameter(format!("Path {} is outside the project cache directory", path.display())));
+    }
+    Ok(missing_components.iter().rev().fold(canonical_path, |path, component| path.join(component)))
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+
+    #[test]
+    fn cache_directory_paths() {
+
+        let project_path = Path::new("/cache/demo");
+        let data_source_uuid = "0a5f8d2a-4c1b-4b8e-9c5e-6f1f1d2b7e3a";
+
+        assert_eq!(cache_directory_path(project_path, None, None, None).unwrap(), PathBuf::from("/cache/demo"));
+        assert_eq!(cache_directory_path(project_path, None, None, Some("nodes")).unwrap(), PathBuf::from("/cache/demo/nodes"));
+        assert_eq!(
+            cache_directory_path(project_path, Some(data_source_uuid), Some("custom/sub"), Some("nodes")).unwrap(),
+            PathBuf::from(format!("/cache/demo/dataSources/{}/custom/sub", data_source_uuid))
+        );
+
+        for data_source_uuid in &["../..", "4567-8910", "", "a/b"] {
+            let error = cache_directory_path(project_path, Some(data_source_uuid), None, None).unwrap_err();
+            assert_eq!(error.code(), "INVALID_PARAMETER");
+        }
+        for custom_subdirectory_path in &["../../etc", "/etc", "sub/../../..", ""] {
+            let error = cache_directory_path(project_path, None, Some(custom_subdirectory_path), None).unwrap_err();
+            assert_eq!(error.code(), "INVALID_PARAMETER");
+        }
+
+    }
+
+    #[test]
+    fn confined_paths() {
+
+        // the symlink target is absolute, so the directories are created for each run:
+        let directory_path = std::env::temp_dir().join(format!("json2capnp-cache-paths-{}", crate::utils::unique_id()));
+        fs::create_dir_all(directory_path.join("project")).unwrap();
+        fs::create_dir_all(directory_path.join("outside")).unwrap();
+        let project_path = fs::canonicalize(directory_path.join("project")).unwrap();
+
+        assert_eq!(confine(&project_path, &project_path.join("new/directory")).unwrap(), project_path.join("new/directory"));
+
+        #[cfg(unix)]
+        {
+            let link_path = project_path.join("link");
+            std::os::unix::fs::symlink(fs::canonicalize(directory_path.join("outside")).unwrap(), &link_path).unwrap();
+            let error = confine(&project_path, &link_path.join("nodes")).unwrap_err();
+            assert_eq!(error.code(), "INVALID_PARAMETER");
+        }
+
+        fs::remove_dir_all(&directory_path).unwrap();
+
+    }
+}
@@ -0,0 +1,231 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use crate::collections::{self, CacheCollection};
+use crate::errors::CacheError;
+use crate::shards;
+use crate::utils;
+
+/// Cache of a collection in a project or data source directory
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionSummary {
+    pub collection: String,
+    /// None for the project directory
+    pub data_source_uuid: Option<String>,
+    /// Cache files, relative to the project cache directory
+    pub files: Vec<String>,
+    /// Records count, None if the cache cannot be read
+    pub count: Option<usize>,
+    /// Total size of the files, in bytes
+    pub size: u64,
+    /// Last modification of the files, in milliseconds since the epoch
+    pub last_modified: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Readiness of a project cache directory
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryStatus {
+    pub path: String,
+    pub exists: bool,
+    pub writable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Check that a cache directory exists and that files can be created in it
+pub fn directory_status(directory_path: &Path) -> DirectoryStatus {
+    let mut status = DirectoryStatus { path: directory_path.display().to_string(), exists: directory_path.is_dir(), writable: false, error: None };
+    if !status.exists {
+        status.error = Some(String::from("The cache directory does not exist"));
+        return status;
+    }
+    let probe_path = directory_path.join(format!(".ready-{}", utils::unique_id()));
+    match File::create(&probe_path).and_then(|_| fs::remove_file(&probe_path)) {
+        Ok(()) => status.writable = true,
+        Err(error) => status.error = Some(format!("The cache directory is not writable: {}", error))
+    }
+    status
+}
+
+/// Summarize the collection caches of the project directory and of its data
+/// source directories (dataSources/{uuid})
+pub fn summarize(project_cache_directory_path: &Path, config: &serde_json::Value) -> Result<Vec<CollectionSummary>, CacheError> {
+
+    let mut directories: Vec<(Option<String>, PathBuf)> = vec![(None, project_cache_directory_path.to_path_buf())];
+    match fs::read_dir(project_cache_directory_path.join("dataSources")) {
+        Ok(entries) => {
+            let mut data_source_directories = Vec::new();
+            for entry in entries {
+                let entry = entry?;
+                // directories being removed are renamed to hidden directories:
+                if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+                    data_source_directories.push((Some(entry.file_name().to_string_lossy().into_owned()), entry.path()));
+                }
+            }
+            data_source_directories.sort();
+            directories.extend(data_source_directories);
+        },
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+        Err(error) => return Err(error.into())
+    }
+
+    let mut summaries = Vec::new();
+    for (data_source_uuid, directory_path) in directories {
+        for collection in collections::COLLECTIONS {
+            if let Some(mut summary) = summarize_collection(*collection, &directory_path, config)? {
+                summary.data_source_uuid = data_source_uuid.clone();
+                summary.files = summary.files.iter()
+                    .map(|file_name| directory_path.join(file_name).strip_prefix(project_cache_directory_path).map(|path| path.display().to_string()).unwrap_or_else(|_| file_name.clone()))
+                    .collect();
+                summaries.push(summary);
+            }
+        }
+    }
+    Ok(summaries)
+
+}
+
+/// Summarize the cache of a collection in a directory, with all its shards,
+/// None if there is none
+fn summarize_collection(collection: &dyn CacheCollection, directory_path: &Path, config: &serde_json::Value) -> Result<Option<CollectionSummary>, CacheError> {
+
+    let mut summary = CollectionSummary {
+        collection: collection.name().to_string(),
+        data_source_uuid: None,
+        files: Vec::new(),
+        count: None,
+        size: 0,
+        last_modified: 0,
+        error: None,
+    };
+    // collections split in several files by Transition have the layout of shards:
+    let file_names = match shards::shard_file_names(directory_path, collection.file_name()) {
+        Ok(file_names) => file_names,
+        Err(error) => {
+            summary.files.push(file_name(&shards::count_file_path(directory_path, collection.file_name())));
+            summary.error = Some(error.to_string());
+            return Ok(Some(summary));
+        }
+    };
+    if file_names.len() == 1 && !directory_path.join(&file_names[0]).is_file() {
+        return Ok(None);
+    }
+    summary.files.extend(file_names.iter().cloned());
+    if file_names.len() > 1 {
+        summary.files.push(file_name(&shards::count_file_path(directory_path, collection.file_name())));
+    }
+    let manifest_path = shards::manifest_path(directory_path, collection.file_name());
+    if manifest_path.is_file() {
+        summary.files.push(file_name(&manifest_path));
+    }
+
+    let count = match collection.items_stream() {
+        Some(items_stream) => shards::read_shards(directory_path, collection.file_name(), items_stream.read_items, config)
+            .map(|shards| shards.unwrap_or_default().iter().map(|shard| shard.count).sum()),
+        None => file_names.iter().map(|file_name| read_count(collection, &directory_path.join(file_name), config)).sum()
+    };
+    match count {
+        Ok(count) => summary.count = Some(count),
+        Err(error) => summary.error = Some(error.to_string())
+    }
+
+    for file_name in &summary.files {
+        match fs::metadata(directory_path.join(file_name)).and_then(|metadata| Ok((metadata.len(), metadata.modified()?))) {
+            Ok((size, modified)) => {
+                summary.size += size;
+                summary.last_modified = summary.last_modified.max(modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64);
+            },
+            // eg a shard removed by hand:
+            Err(error) => {
+                summary.error.get_or_insert_with(|| format!("{}: {}", file_name, error));
+            }
+        }
+    }
+    Ok(Some(summary))
+
+}
+
+/// Count the records of a cache file. Collections read item by
+/// item only read their items count, the others are converted to json.
+fn read_count(collection: &dyn CacheCollection, path: &Path, config: &serde_json::Value) -> Result<usize, CacheError> {
+    let mut file = File::open(path)?;
+    if let Some(read_items) = collection.read_items() {
+        return read_items(&mut file, config).map(|items| items.count());
+    }
+    let json = collection.read_collection(&mut file, config)?;
+    let items = &json[collection.name()];
+    items["features"].as_array().or_else(|| items.as_array())
+        .map(|items| items.len())
+        .ok_or_else(|| CacheError::InvalidPayload(format!("The {} cache has no {} array", collection.name(), collection.name())))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|file_name| file_name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+
+    #[test]
+    fn cache_summary() {
+
+        let _ = fs::remove_dir_all("test/summary");
+        fs::create_dir_all("test/summary/dataSources/b4d8b3ab-0f9d-4c2b-9c4b-4f7c0c1e2d3a").unwrap();
+        let project_path = fs::canonicalize("test/summary").unwrap();
+
+        let status = directory_status(&project_path);
+        assert!(status.exists && status.writable);
+        let status = directory_status(&project_path.join("missing"));
+        assert!(!status.exists && !status.writable && status.error.is_some());
+
+        fs::write("test/summary/dataSources/b4d8b3ab-0f9d-4c2b-9c4b-4f7c0c1e2d3a/nodes.capnpbin", b"not capnp").unwrap();
+        // the shard counts of the manifest are used while the shard files do not change:
+        let data_source_path = Path::new("test/summary/dataSources/b4d8b3ab-0f9d-4c2b-9c4b-4f7c0c1e2d3a");
+        fs::write(data_source_path.join("persons.capnpbin.0"), b"012").unwrap();
+        fs::write(data_source_path.join("persons.capnpbin.1"), b"01").unwrap();
+        fs::write(data_source_path.join("persons.capnpbin.count"), b"2").unwrap();
+        let shard = |file_name: &str, count: usize| {
+            let metadata = fs::metadata(data_source_path.join(file_name)).unwrap();
+            json!({ "file_name": file_name, "count": count, "size": metadata.len(), "modified": metadata.modified().unwrap().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64 })
+        };
+        fs::write(data_source_path.join("persons.manifest.json"), json!({ "shards": [shard("persons.capnpbin.0", 3), shard("persons.capnpbin.1", 2)] }).to_string()).unwrap();
+
+        let summaries = summarize(&project_path, &json!({})).unwrap();
+        let data_source_summaries: Vec<&CollectionSummary> = summaries.iter().filter(|summary| summary.data_source_uuid.is_some()).collect();
+        assert_eq!(data_source_summaries.len(), 2);
+
+        let nodes = data_source_summaries[0];
+        assert_eq!((nodes.collection.as_str(), nodes.count, nodes.size), ("nodes", None, 9));
+        assert!(nodes.error.is_some());
+
+        let persons = data_source_summaries[1];
+        assert_eq!((persons.collection.as_str(), persons.count), ("persons", Some(5)));
+        assert_eq!(persons.files, vec![
+            "dataSources/b4d8b3ab-0f9d-4c2b-9c4b-4f7c0c1e2d3a/persons.capnpbin.0",
+            "dataSources/b4d8b3ab-0f9d-4c2b-9c4b-4f7c0c1e2d3a/persons.capnpbin.1",
+            "dataSources/b4d8b3ab-0f9d-4c2b-9c4b-4f7c0c1e2d3a/persons.capnpbin.count",
+            "dataSources/b4d8b3ab-0f9d-4c2b-9c4b-4f7c0c1e2d3a/persons.manifest.json"
+        ]);
+        let now = std::time::SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        assert!(persons.last_modified > 0 && persons.last_modified <= now);
+
+    }
+}
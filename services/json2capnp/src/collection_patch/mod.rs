@@ -0,0 +1,170 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use crate::errors::CacheError;
+use crate::validation::{Validator, rebase_pointers};
+
+/// Items to upsert and uuids to remove in a cache collection, from a PATCH
+/// body: {"upsert": [...], "remove": ["uuid", ...]}. Upserted items have the
+/// shape of the collection items (geojson features for feature collections).
+pub struct CollectionPatch<'a> {
+    upsert: &'a [Value],
+    upsert_uuids: Vec<&'a str>,
+    remove: Vec<&'a str>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct PatchSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub removed: usize,
+    /// Index in the patched collection of each upserted item
+    #[serde(skip)]
+    pub upserted_indexes: Vec<usize>,
+}
+
+/// Uuid of a collection item, in properties.id for geojson features (their
+/// id is the integer id) and in id for the other items
+pub fn item_uuid(item: &Value) -> Option<&str> {
+    match item.get("properties") {
+        Some(properties) => properties["id"].as_str(),
+        None => item["id"].as_str(),
+    }
+}
+
+impl<'a> CollectionPatch<'a> {
+
+    pub fn from_json(json: &'a Value) -> Result<CollectionPatch<'a>, CacheError> {
+
+        if json["upsert"].is_null() && json["remove"].is_null() {
+            return Err(CacheError::InvalidPayload(String::from("PATCH body needs an upsert or a remove array")));
+        }
+
+        let mut validator = Validator::new();
+        let upsert = if json["upsert"].is_null() { &[] } else { validator.array(&json["upsert"], "/upsert") };
+        let upsert_uuids = upsert.iter().enumerate().map(|(i, item)| {
+            let pointer = if item.get("properties").is_some() { format!("/upsert/{}/properties", i) } else { format!("/upsert/{}", i) };
+            let object = item.get("properties").unwrap_or(item);
+            validator.fields(object, &pointer).required_string("id")
+        }).collect();
+        let remove = if json["remove"].is_null() { Vec::new() } else { validator.string_array(&json["remove"], "/remove") };
+        validator.into_result()?;
+
+        Ok(CollectionPatch { upsert, upsert_uuids, remove })
+
+    }
+
+    /// Upsert and remove items by uuid in the items of a collection. Upserted
+    /// items replace the existing item in place or are appended.
+    pub fn apply(&self, items: &mut Vec<Value>) -> PatchSummary {
+
+        let mut summary = PatchSummary::default();
+
+        let items_count = items.len();
+        items.retain(|item| !matches!(item_uuid(item), Some(uuid) if self.remove.contains(&uuid)));
+        summary.removed = items_count - items.len();
+
+        let mut indexes_by_uuid: HashMap<String, usize> = items.iter().enumerate()
+            .filter_map(|(index, item)| item_uuid(item).map(|uuid| (uuid.to_string(), index)))
+            .collect();
+
+        for (item, uuid) in self.upsert.iter().zip(&self.upsert_uuids) {
+            match indexes_by_uuid.get(*uuid) {
+                Some(&index) => {
+                    items[index] = item.clone();
+                    summary.updated += 1;
+                    summary.upserted_indexes.push(index);
+                },
+                None => {
+                    indexes_by_uuid.insert(uuid.to_string(), items.len());
+                    summary.upserted_indexes.push(items.len());
+                    items.push(item.clone());
+                    summary.inserted += 1;
+                }
+            }
+        }
+
+        summary
+
+    }
+
+}
+
+impl PatchSummary {
+
+    /// Point the validation errors of upserted items, found while writing the
+    /// patched collection at items_pointer (eg /nodes/features), to their
+    /// index in the PATCH body instead
+    pub fn upsert_errors(&self, error: CacheError, items_pointer: &str) -> CacheError {
+        self.upserted_indexes.iter().enumerate().fold(error, |error, (i, index)| {
+            rebase_pointers(error, &format!("{}/{}", items_pointer, index), &format!("/upsert/{}", i))
+        })
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::validation::FieldError;
+    use pretty_assertions::{assert_eq};
+
+    #[test]
+    fn patch() {
+
+        let json = json!({
+            "upsert": [
+                { "id": "b", "name": "B2" },
+                { "id": "d", "name": "D" }
+            ],
+            "remove": ["a", "unknown"]
+        });
+        let patch = CollectionPatch::from_json(&json).unwrap();
+
+        let mut items = vec![json!({ "id": "a" }), json!({ "id": "b", "name": "B" }), json!({ "id": "c" })];
+        let summary = patch.apply(&mut items);
+
+        assert_eq!(items, vec![json!({ "id": "b", "name": "B2" }), json!({ "id": "c" }), json!({ "id": "d", "name": "D" })]);
+        assert_eq!(summary, PatchSummary { inserted: 1, updated: 1, removed: 1, upserted_indexes: vec![0, 2] });
+
+        let error = summary.upsert_errors(CacheError::Validation(vec![
+            FieldError { pointer: String::from("/lines/2/agency_id"), message: String::from("is required") },
+            FieldError { pointer: String::from("/lines/1/agency_id"), message: String::from("is required") },
+        ]), "/lines");
+        match error {
+            CacheError::Validation(errors) => {
+                assert_eq!(errors[0].pointer, "/upsert/1/agency_id");
+                assert_eq!(errors[1].pointer, "/lines/1/agency_id");
+            },
+            _ => panic!("expected a validation error")
+        }
+
+        // features are merged by their properties id:
+        let json = json!({ "upsert": [{ "type": "Feature", "id": 3, "properties": { "id": "a", "name": "A2" } }] });
+        let mut items = vec![json!({ "type": "Feature", "id": 3, "properties": { "id": "a" } })];
+        CollectionPatch::from_json(&json).unwrap().apply(&mut items);
+        assert_eq!(items[0]["properties"]["name"], "A2");
+
+        let json = json!({ "upsert": [{ "name": "no id" }, { "type": "Feature", "properties": {} }], "remove": [1] });
+        match CollectionPatch::from_json(&json) {
+            Err(CacheError::Validation(errors)) => {
+                let pointers: Vec<&str> = errors.iter().map(|error| error.pointer.as_str()).collect();
+                assert_eq!(pointers, vec!["/upsert/0/id", "/upsert/1/properties/id", "/remove/0"]);
+            },
+            _ => panic!("expected a validation error")
+        }
+
+        assert!(matches!(CollectionPatch::from_json(&json!({})), Err(CacheError::InvalidPayload(_))));
+
+    }
+}
@@ -0,0 +1,290 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use serde_json::json;
+use std::fs::File;
+use crate::errors::CacheError;
+use crate::routers;
+use crate::validation::Fields;
+use crate::json_stream::JsonItems;
+
+/// A cache collection served by the server. Registering a collection in
+/// `COLLECTIONS` mounts its GET, POST, PATCH and DELETE routes (/{name}), and the
+/// routes of its single objects if it has any (/{object name}?uuid=, and
+/// /{name}/objects to read or write many objects at once).
+pub trait CacheCollection: Sync {
+
+    /// Name of the collection in urls and json bodies (eg lines)
+    fn name(&self) -> &'static str;
+
+    /// Cache file name, without the .capnpbin extension
+    fn file_name(&self) -> &'static str {
+        self.name()
+    }
+
+    /// Whether the collection json is a geojson FeatureCollection
+    fn is_feature_collection(&self) -> bool {
+        false
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut File, config: &serde_json::Value) -> Result<(), CacheError>;
+
+    fn read_collection(&self, file: &mut File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError>;
+
+    /// Write the collection items one by one while the body is parsed, for
+    /// collections too big to be parsed as a whole
+    fn items_stream(&self) -> Option<ItemsStream> {
+        None
+    }
+
+    /// Read the collection items one by one while the response is sent, for
+    /// collections too big to be converted as a whole: odTrips, persons,
+    /// households, paths, places, zones, nodes and lines. The other
+    /// collections (services, scenarios, agencies, ...) are small, they are
+    /// read as a whole.
+    fn read_items(&self) -> Option<ReadItemsFn> {
+        None
+    }
+
+    /// Single objects of the collection, cached in their own file
+    fn object(&self) -> Option<CacheObject> {
+        None
+    }
+
+}
+
+/// Writes the items of a collection one by one, each in its own message, and
+/// then the collection from these items
+#[derive(Clone, Copy)]
+pub struct ItemsStream {
+    pub write_item: fn(&serde_json::Value, Fields) -> Result<Vec<u8>, CacheError>,
+    pub write_items: fn(Vec<Vec<u8>>, &mut File) -> Result<(), CacheError>,
+    /// Read back a collection file, eg to count the items of a shard
+    pub read_items: ReadItemsFn,
+}
+
+/// Read the items count of a collection file and how to convert each item
+pub type ReadItemsFn = fn(&mut File, &serde_json::Value) -> Result<JsonItems, CacheError>;
+
+/// Single objects of a collection, cached in
+/// {subdirectory}/{name}_{uuid}.capnpbin
+#[derive(Clone, Copy)]
+pub struct CacheObject {
+    /// Name of the object in urls and json bodies (eg line)
+    pub name: &'static str,
+    pub subdirectory: &'static str,
+    pub write_object: fn(&str, &serde_json::Value, &serde_json::Value) -> Result<(), CacheError>,
+    pub read_object: fn(&str, &str, &serde_json::Value) -> Result<serde_json::Value, CacheError>,
+}
+
+pub static COLLECTIONS: &[&dyn CacheCollection] = &[
+    &routers::data_source_collection_router::DataSourceCollection,
+    &routers::agency_collection_router::AgencyCollection,
+    &routers::garage_collection_router::GarageCollection,
+    &routers::path_collection_router::PathCollection,
+    &routers::node_collection_router::NodeCollection,
+    &routers::household_collection_router::HouseholdCollection,
+    &routers::line_collection_router::LineCollection,
+    &routers::od_trip_collection_router::OdTripCollection,
+    &routers::person_collection_router::PersonCollection,
+    &routers::place_collection_router::PlaceCollection,
+    &routers::scenario_collection_router::ScenarioCollection,
+    &routers::service_collection_router::ServiceCollection,
+    &routers::zone_collection_router::ZoneCollection,
+    &routers::unit_collection_router::UnitCollection,
+];
+
+pub fn find(name: &str) -> Option<&'static dyn CacheCollection> {
+    COLLECTIONS.iter().copied().find(|collection| collection.name() == name)
+}
+
+pub fn find_object(name: &str) -> Option<CacheObject> {
+    COLLECTIONS.iter().filter_map(|collection| collection.object()).find(|object| object.name == name)
+}
+
+/// Route a request to the collection or object named by its url. Returns
+/// None if there is no such collection or object.
+pub fn route(request: &rouille::Request, config: &serde_json::Value, object_uuid: &str) -> Option<rouille::Response> {
+
+    let name = request.url();
+    let name = name.strip_prefix('/').unwrap_or(&name);
+
+    if let Some(collection) = find(name) {
+        let response = match request.method() {
+            "GET" => match collection.read_items() {
+                Some(read_items) => routers::read_collection_stream_route(collection.name(), collection.file_name(), collection.is_feature_collection(), config, read_items),
+                None => routers::read_collection_route(
+                    collection.name(),
+                    collection.file_name(),
+                    config,
+                    &|file, config| collection.read_collection(file, config)
+                )
+            },
+            "POST" => match collection.items_stream() {
+                Some(items_stream) => routers::write_collection_stream_route(collection.name(), collection.file_name(), config, &items_stream, false, request),
+                None => routers::write_collection_route(
+                    collection.name(),
+                    collection.file_name(),
+                    config,
+                    &|json, file, config| collection.write_collection(json, file, config),
+                    request
+                )
+            },
+            "PATCH" => match (collection.items_stream(), collection.read_items()) {
+                (Some(items_stream), Some(read_items)) => routers::patch_collection_shards_route(collection.name(), collection.file_name(), config, &items_stream, read_items, request),
+                _ => routers::patch_collection_route(
+                    collection.name(),
+                    collection.file_name(),
+                    collection.is_feature_collection(),
+                    config,
+                    &|file, config| collection.read_collection(file, config),
+                    &|json, file, config| collection.write_collection(json, file, config),
+                    request
+                )
+            },
+            "DELETE" => routers::delete_collection_route(collection.name(), collection.file_name(), config),
+            _ => method_not_allowed()
+        };
+        return Some(response);
+    }
+
+    // new shard appended to a sharded collection:
+    if let Some(collection) = name.strip_suffix("/append").and_then(find) {
+        let items_stream = collection.items_stream()?;
+        let response = match request.method() {
+            "POST" => routers::write_collection_stream_route(collection.name(), collection.file_name(), config, &items_stream, true, request),
+            _ => method_not_allowed()
+        };
+        return Some(response);
+    }
+
+    // chunked upload sessions of a sharded collection:
+    if let Some((collection, upload_path)) = name.split_once("/uploads").and_then(|(name, upload_path)| Some((find(name)?, upload_path))) {
+        let items_stream = collection.items_stream()?;
+        let segments: Vec<&str> = upload_path.split('/').skip(1).collect();
+        let response = match (request.method(), segments.as_slice()) {
+            ("POST", []) => routers::open_upload_route(collection.name(), collection.file_name(), config, request),
+            ("PUT", [upload_id, "chunks", chunk]) => routers::upload_chunk_route(collection.name(), config, &items_stream, upload_id, chunk, request),
+            ("POST", [upload_id, "commit"]) => routers::commit_upload_route(collection.name(), config, &items_stream, upload_id, request),
+            ("DELETE", [upload_id]) => routers::abort_upload_route(collection.name(), upload_id),
+            _ => method_not_allowed()
+        };
+        return Some(response);
+    }
+
+    // POST alternative to GET /{name}/objects?uuids=, for long uuids lists:
+    if let Some(collection) = name.strip_suffix("/objects/read").and_then(find) {
+        let object = collection.object()?;
+        let response = match request.method() {
+            "POST" => routers::read_objects_route(collection.name(), object.name, object.subdirectory, config, &object.read_object, request),
+            _ => method_not_allowed()
+        };
+        return Some(response);
+    }
+
+    if let Some(collection) = name.strip_suffix("/objects").and_then(find) {
+        let object = collection.object()?;
+        let response = match request.method() {
+            "GET" => routers::read_objects_route(collection.name(), object.name, object.subdirectory, config, &object.read_object, request),
+            "POST" => routers::write_objects_route(collection.name(), object.name, object.subdirectory, config, &object.write_object, request),
+            _ => method_not_allowed()
+        };
+        return Some(response);
+    }
+
+    if let Some(object) = find_object(name) {
+        let response = match request.method() {
+            "GET" => routers::read_object_route(object.name, object_uuid, object.subdirectory, config, &object.read_object),
+            "POST" => routers::write_object_route(object.name, object.subdirectory, config, &object.write_object, request),
+            "DELETE" => routers::delete_object_route(object.name, object_uuid, object.subdirectory, config),
+            _ => method_not_allowed()
+        };
+        return Some(response);
+    }
+
+    None
+
+}
+
+fn method_not_allowed() -> rouille::Response {
+    rouille::Response::empty_404().with_status_code(405)
+}
+
+/// Supported collections with their endpoints, for GET /collections
+pub fn description() -> serde_json::Value {
+
+    let collections: Vec<serde_json::Value> = COLLECTIONS.iter().map(|collection| {
+        let mut endpoints: Vec<String> = ["GET", "POST", "PATCH", "DELETE"].iter().map(|method| format!("{} /{}", method, collection.name())).collect();
+        if collection.items_stream().is_some() {
+            endpoints.push(format!("POST /{}/append", collection.name()));
+            endpoints.push(format!("POST /{}/uploads", collection.name()));
+            endpoints.push(format!("PUT /{}/uploads/{{uploadId}}/chunks/{{chunk}}", collection.name()));
+            endpoints.push(format!("POST /{}/uploads/{{uploadId}}/commit", collection.name()));
+            endpoints.push(format!("DELETE /{}/uploads/{{uploadId}}", collection.name()));
+        }
+        let mut collection_json = json!({
+            "name"     : collection.name(),
+            "fileName" : format!("{}.capnpbin", collection.file_name()),
+            "endpoints": endpoints
+        });
+        if let Some(object) = collection.object() {
+            collection_json["object"] = json!({
+                "name"     : object.name,
+                "fileName" : format!("{}/{}_{{uuid}}.capnpbin", object.subdirectory, object.name),
+                "endpoints": [
+                    format!("GET /{}?uuid={{uuid}}", object.name),
+                    format!("POST /{}", object.name),
+                    format!("DELETE /{}?uuid={{uuid}}", object.name),
+                    format!("GET /{}/objects?uuids={{uuids}}", collection.name()),
+                    format!("POST /{}/objects/read", collection.name()),
+                    format!("POST /{}/objects", collection.name())
+                ]
+            });
+        }
+        collection_json
+    }).collect();
+
+    json!({ "collections": collections })
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+    use rouille::Request;
+
+    #[test]
+    fn registry() {
+
+        let mut names: Vec<&str> = COLLECTIONS.iter().map(|collection| collection.name()).collect();
+        names.extend(COLLECTIONS.iter().filter_map(|collection| collection.object()).map(|object| object.name));
+        let count = names.len();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), count);
+
+        assert_eq!(find("lines").unwrap().file_name(), "lines");
+        assert_eq!(find_object("node").unwrap().subdirectory, "nodes");
+        assert!(find("unknown").is_none());
+
+        let description = description();
+        let lines = description["collections"].as_array().unwrap().iter().find(|collection| collection["name"] == "lines").unwrap();
+        assert_eq!(lines["endpoints"], json!(["GET /lines", "POST /lines", "PATCH /lines", "DELETE /lines"]));
+        assert_eq!(lines["object"]["endpoints"], json!(["GET /line?uuid={uuid}", "POST /line", "DELETE /line?uuid={uuid}", "GET /lines/objects?uuids={uuids}", "POST /lines/objects/read", "POST /lines/objects"]));
+
+        let config = json!({ "project_cache_directory_path": "test" });
+        let request = Request::fake_http("PUT", "/lines", vec![], vec![]);
+        assert_eq!(route(&request, &config, "").unwrap().status_code, 405);
+        let request = Request::fake_http("GET", "/unknown", vec![], vec![]);
+        assert!(route(&request, &config, "").is_none());
+
+    }
+}
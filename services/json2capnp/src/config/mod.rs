@@ -0,0 +1,472 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::cache_files::WriteLockMode;
+use crate::errors::{CacheError, TrError};
+use crate::limits::Limits;
+use crate::logging::{self, LogFormat};
+
+const ENV_PREFIX: &str = "JSON2CAPNP_";
+
+pub const USAGE: &str = "Usage: json2capnp [OPTIONS]
+
+Options:
+    --config <path>              YAML or JSON configuration file
+    --port <port>                Port to listen on (default 2000)
+    --bind <address>             Address to bind to (default 0.0.0.0)
+    --cache-dir <path>           Project cache directory
+    --project-shortname <name>   Project shortname (default \"default\"), used when
+                                 requests do not specify a project
+    --write-lock <wait|fail>     What a request does when another request or process
+                                 is writing the same cache file: wait for it
+                                 (default) or fail with a 409 conflict
+    --write-lock-timeout <secs>  Maximum time to wait for a locked cache file
+                                 (default 60)
+    --traversal-limit-words <n>  Maximum number of 8 bytes words read from a
+                                 cache file (default 8388608, 64 MiB)
+    --nesting-limit <n>          Maximum nesting depth of cache files (default 64)
+    --max-body-size <bytes>      Maximum request body size (default unlimited)
+    --shard-size <n>             Maximum number of items per shard file of the
+                                 odTrips, persons and households collections
+                                 (default 100000)
+    --shutdown-timeout <secs>    Maximum time to wait for the requests and async
+                                 jobs in progress on SIGTERM or SIGINT (default 30)
+    --log-level <filter>         Log level (error, warn, info, debug or trace) or
+                                 filter directives like warn,json2capnp=debug
+                                 (default info)
+    --log-format <text|json>     Log lines format (default text)
+    -h, --help                   Print this help
+
+Every option can also be set with a JSON2CAPNP_* environment variable
+(eg JSON2CAPNP_CACHE_DIR). Command line flags take precedence over
+environment variables, which take precedence over the configuration file.
+Additional projects can be declared in the configuration file under
+`projects`, each with its own `cache_dir`, and the limits of specific
+collections under `collections`.";
+
+/// Startup configuration of the server
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: u16,
+    pub bind: String,
+    pub cache_dir: Option<String>,
+    pub project_shortname: String,
+    pub projects: HashMap<String, ProjectConfig>,
+    pub write_lock: WriteLockMode,
+    pub write_lock_timeout: u64,
+    pub traversal_limit_words: u64,
+    pub nesting_limit: i32,
+    pub max_body_size: Option<u64>,
+    pub shard_size: usize,
+    /// Limits of specific collections, overriding the global limits
+    pub collections: HashMap<String, CollectionLimits>,
+    pub shutdown_timeout: u64,
+    pub log_level: String,
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub cache_dir: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectionLimits {
+    pub traversal_limit_words: Option<u64>,
+    pub nesting_limit: Option<i32>,
+    pub max_body_size: Option<u64>,
+    pub shard_size: Option<usize>,
+}
+
+/// Projects served by the server, with their canonical cache directories
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projects {
+    pub cache_directories: HashMap<String, PathBuf>,
+    pub default_shortname: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            port: 2000,
+            bind: String::from("0.0.0.0"),
+            cache_dir: None,
+            project_shortname: String::from("default"),
+            projects: HashMap::new(),
+            write_lock: WriteLockMode::Wait,
+            write_lock_timeout: 60,
+            traversal_limit_words: Limits::default().traversal_limit_words,
+            nesting_limit: Limits::default().nesting_limit,
+            max_body_size: None,
+            shard_size: Limits::default().shard_size,
+            collections: HashMap::new(),
+            shutdown_timeout: 30,
+            log_level: String::from("info"),
+            log_format: LogFormat::Text,
+        }
+    }
+}
+
+/// Outcome of parsing the command line
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Box<ServerConfig>),
+    Help,
+}
+
+impl ServerConfig {
+
+    /// Load the configuration from the command line arguments (without the
+    /// program name), the environment and the optional configuration file
+    pub fn load(args: &[String], env: &HashMap<String, String>) -> Result<Command, TrError> {
+
+        let mut flags: HashMap<String, String> = HashMap::new();
+        let mut args_iter = args.iter();
+        while let Some(arg) = args_iter.next() {
+            if arg == "-h" || arg == "--help" {
+                return Ok(Command::Help);
+            }
+            let flag = match arg.strip_prefix("--") {
+                Some(flag) => flag,
+                None => return Err(TrError::new(&format!("Unexpected argument '{}'", arg)))
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => {
+                    let value = args_iter.next().ok_or_else(|| TrError::new(&format!("Missing value for --{}", flag)))?;
+                    (flag.to_string(), value.to_string())
+                }
+            };
+            match name.as_str() {
+                "config" | "port" | "bind" | "cache-dir" | "project-shortname" | "write-lock" | "write-lock-timeout" | "traversal-limit-words" | "nesting-limit" | "max-body-size" | "shard-size" | "shutdown-timeout" | "log-level" | "log-format" => {
+                    flags.insert(name, value);
+                },
+                _ => return Err(TrError::new(&format!("Unknown option --{}", name)))
+            }
+        }
+
+        let config_file_path = flags.get("config").or_else(|| env.get(&env_name("config")));
+        let mut config = match config_file_path {
+            Some(config_file_path) => ServerConfig::from_file(Path::new(config_file_path))?,
+            None => ServerConfig::default()
+        };
+
+        for name in ["port", "bind", "cache-dir", "project-shortname", "write-lock", "write-lock-timeout", "traversal-limit-words", "nesting-limit", "max-body-size", "shard-size", "shutdown-timeout", "log-level", "log-format"] {
+            if let Some(value) = flags.get(name).or_else(|| env.get(&env_name(name))) {
+                config.set(name, value)?;
+            }
+        }
+
+        Ok(Command::Run(Box::new(config)))
+    }
+
+    pub fn from_file(path: &Path) -> Result<ServerConfig, TrError> {
+        let content = fs::read_to_string(path)
+            .map_err(|error| TrError::new(&format!("Cannot read config file {}: {}", path.display(), error)))?;
+        let is_json = path.extension() == Some(OsStr::new("json"));
+        let config = if is_json {
+            serde_json::from_str(&content).map_err(|error| error.to_string())
+        } else {
+            serde_yaml::from_str(&content).map_err(|error| error.to_string())
+        };
+        config.map_err(|error| TrError::new(&format!("Invalid config file {}: {}", path.display(), error)))
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), TrError> {
+        match name {
+            "port" => {
+                self.port = value.parse().map_err(|_| TrError::new(&format!("Invalid port '{}'", value)))?;
+            },
+            "bind" => self.bind = value.to_string(),
+            "cache-dir" => self.cache_dir = Some(value.to_string()),
+            "project-shortname" => self.project_shortname = value.to_string(),
+            "write-lock" => {
+                self.write_lock = match value {
+                    "wait" => WriteLockMode::Wait,
+                    "fail" => WriteLockMode::Fail,
+                    _ => return Err(TrError::new(&format!("Invalid write lock '{}', must be wait or fail", value)))
+                };
+            },
+            "write-lock-timeout" => {
+                self.write_lock_timeout = value.parse().map_err(|_| TrError::new(&format!("Invalid write lock timeout '{}'", value)))?;
+            },
+            "traversal-limit-words" => {
+                self.traversal_limit_words = value.parse().map_err(|_| TrError::new(&format!("Invalid traversal limit '{}'", value)))?;
+            },
+            "nesting-limit" => {
+                self.nesting_limit = value.parse().map_err(|_| TrError::new(&format!("Invalid nesting limit '{}'", value)))?;
+            },
+            "max-body-size" => {
+                self.max_body_size = Some(value.parse().map_err(|_| TrError::new(&format!("Invalid max body size '{}'", value)))?);
+            },
+            "shard-size" => {
+                self.shard_size = value.parse().map_err(|_| TrError::new(&format!("Invalid shard size '{}'", value)))?;
+            },
+            "shutdown-timeout" => {
+                self.shutdown_timeout = value.parse().map_err(|_| TrError::new(&format!("Invalid shutdown timeout '{}'", value)))?;
+            },
+            "log-level" => {
+                logging::filter(value)?;
+                self.log_level = value.to_string();
+            },
+            "log-format" => {
+                self.log_format = match value {
+                    "text" => LogFormat::Text,
+                    "json" => LogFormat::Json,
+                    _ => return Err(TrError::new(&format!("Invalid log format '{}', must be text or json", value)))
+                };
+            },
+            _ => return Err(TrError::new(&format!("Unknown option --{}", name)))
+        }
+        Ok(())
+    }
+
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+
+    /// Global and per collection limits, for the request config (see
+    /// Limits::from_config)
+    pub fn limits(&self) -> Result<serde_json::Value, TrError> {
+        for (collection_name, collection_limits) in &self.collections {
+            if crate::collections::find(collection_name).is_none() {
+                return Err(TrError::new(&format!("Unknown collection {} in the collections limits", collection_name)));
+            }
+            if matches!(collection_limits.nesting_limit, Some(nesting_limit) if nesting_limit <= 0) {
+                return Err(TrError::new(&format!("Invalid nesting limit of collection {}, must be positive", collection_name)));
+            }
+            if collection_limits.shard_size == Some(0) {
+                return Err(TrError::new(&format!("Invalid shard size of collection {}, must be positive", collection_name)));
+            }
+        }
+        if self.nesting_limit <= 0 {
+            return Err(TrError::new("Invalid nesting limit, must be positive"));
+        }
+        if self.shard_size == 0 {
+            return Err(TrError::new("Invalid shard size, must be positive"));
+        }
+        Ok(json!({
+            "traversal_limit_words": self.traversal_limit_words,
+            "nesting_limit"        : self.nesting_limit,
+            "max_body_size"        : self.max_body_size,
+            "shard_size"           : self.shard_size,
+            "collections"          : self.collections
+        }))
+    }
+
+    /// Resolve the cache directory of every configured project. The
+    /// `cache_dir` option declares the `project_shortname` project, which is
+    /// also the default project if it exists. Otherwise, a single configured
+    /// project is the default.
+    pub fn projects(&self) -> Result<Projects, TrError> {
+
+        let mut cache_directories_str: HashMap<&str, &str> = self.projects.iter()
+            .map(|(shortname, project)| (shortname.as_str(), project.cache_dir.as_str()))
+            .collect();
+        if let Some(cache_dir) = &self.cache_dir {
+            if let Some(other_cache_dir) = cache_directories_str.insert(&self.project_shortname, cache_dir) {
+                if other_cache_dir != cache_dir {
+                    return Err(TrError::new(&format!("Project {} is configured with two different cache directories", self.project_shortname)));
+                }
+            }
+        }
+
+        if cache_directories_str.is_empty() {
+            return Err(TrError::new("Cache directory must be set with --cache-dir, the JSON2CAPNP_CACHE_DIR environment variable or the config file (eg cargo run -- --cache-dir path/to/cache/dir)"));
+        }
+
+        let mut cache_directories = HashMap::new();
+        for (shortname, cache_dir) in cache_directories_str {
+            let path = fs::canonicalize(Path::new(cache_dir))
+                .map_err(|error| TrError::new(&format!("Cache directory {} of project {} does not exist: {}", cache_dir, shortname, error)))?;
+            cache_directories.insert(shortname.to_string(), path);
+        }
+
+        let default_shortname = if cache_directories.contains_key(&self.project_shortname) {
+            Some(self.project_shortname.clone())
+        } else if cache_directories.len() == 1 {
+            cache_directories.keys().next().cloned()
+        } else {
+            None
+        };
+
+        Ok(Projects { cache_directories, default_shortname })
+    }
+
+}
+
+impl Projects {
+
+    /// Find the cache directory of the requested project, or of the default
+    /// project if none is requested
+    pub fn resolve(&self, requested_shortname: Option<&str>) -> Result<(&str, &Path), CacheError> {
+        let shortname = match requested_shortname.or(self.default_shortname.as_deref()) {
+            Some(shortname) => shortname,
+            None => return Err(CacheError::InvalidPayload(String::from("No project specified and no default project configured")))
+        };
+        match self.cache_directories.get_key_value(shortname) {
+            Some((shortname, cache_directory)) => Ok((shortname.as_str(), cache_directory.as_path())),
+            None => Err(CacheError::NotFound(format!("Unknown project {}", shortname)))
+        }
+    }
+
+}
+
+fn env_name(flag_name: &str) -> String {
+    format!("{}{}", ENV_PREFIX, flag_name.replace('-', "_").to_uppercase())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn run_config(command: Command) -> ServerConfig {
+        match command {
+            Command::Run(config) => *config,
+            Command::Help => panic!("expected a run command")
+        }
+    }
+
+    #[test]
+    fn config_from_flags() {
+
+        let env = HashMap::new();
+
+        let config = run_config(ServerConfig::load(&args(&[]), &env).unwrap());
+        assert_eq!(config, ServerConfig::default());
+
+        let config = run_config(ServerConfig::load(&args(&["--port", "2001", "--bind=127.0.0.1", "--cache-dir", "test", "--project-shortname", "demo"]), &env).unwrap());
+        assert_eq!(config.port, 2001);
+        assert_eq!(config.bind_address(), "127.0.0.1:2001");
+        assert_eq!(config.cache_dir, Some(String::from("test")));
+        assert_eq!(config.project_shortname, "demo");
+        assert_eq!(config.write_lock, WriteLockMode::Wait);
+
+        let config = run_config(ServerConfig::load(&args(&["--write-lock", "fail", "--write-lock-timeout=5"]), &env).unwrap());
+        assert_eq!(config.write_lock, WriteLockMode::Fail);
+        assert_eq!(config.write_lock_timeout, 5);
+
+        let config = run_config(ServerConfig::load(&args(&["--traversal-limit-words", "1000", "--nesting-limit=32", "--max-body-size", "2048"]), &env).unwrap());
+        assert_eq!((config.traversal_limit_words, config.nesting_limit, config.max_body_size), (1000, 32, Some(2048)));
+        assert_eq!(config.limits().unwrap(), json!({ "traversal_limit_words": 1000, "nesting_limit": 32, "max_body_size": 2048, "shard_size": 100000, "collections": {} }));
+        assert!(ServerConfig::load(&args(&["--max-body-size", "1MB"]), &env).is_err());
+
+        assert_eq!(ServerConfig::load(&args(&["--help"]), &env).unwrap(), Command::Help);
+        assert!(ServerConfig::load(&args(&["2000"]), &env).is_err());
+        assert!(ServerConfig::load(&args(&["--port"]), &env).is_err());
+        assert!(ServerConfig::load(&args(&["--port", "abc"]), &env).is_err());
+        assert!(ServerConfig::load(&args(&["--unknown", "abc"]), &env).is_err());
+        assert!(ServerConfig::load(&args(&["--write-lock", "queue"]), &env).is_err());
+
+        let config = run_config(ServerConfig::load(&args(&["--log-level", "warn,json2capnp=debug", "--log-format=json"]), &env).unwrap());
+        assert_eq!((config.log_level.as_str(), config.log_format), ("warn,json2capnp=debug", LogFormat::Json));
+        assert!(ServerConfig::load(&args(&["--log-format", "xml"]), &env).is_err());
+
+        let config = run_config(ServerConfig::load(&args(&["--shutdown-timeout", "120"]), &env).unwrap());
+        assert_eq!(config.shutdown_timeout, 120);
+        assert!(ServerConfig::load(&args(&["--shutdown-timeout", "-1"]), &env).is_err());
+        assert!(ServerConfig::load(&args(&["--log-level", "json2capnp=loud"]), &env).is_err());
+
+    }
+
+    #[test]
+    fn config_from_file_and_env() {
+
+        let config_file_path = Path::new("test/config_test.yml");
+        fs::write(config_file_path, "port: 2002\ncache_dir: test\nproject_shortname: from_file\n").unwrap();
+        let config_file_path_str = config_file_path.to_str().unwrap();
+
+        let mut env = HashMap::new();
+        let config = run_config(ServerConfig::load(&args(&["--config", config_file_path_str]), &env).unwrap());
+        assert_eq!(config.port, 2002);
+        assert_eq!(config.bind, "0.0.0.0");
+        assert_eq!(config.cache_dir, Some(String::from("test")));
+        assert_eq!(config.project_shortname, "from_file");
+
+        // environment overrides the file, flags override the environment
+        env.insert(String::from("JSON2CAPNP_CONFIG"), config_file_path_str.to_string());
+        env.insert(String::from("JSON2CAPNP_PROJECT_SHORTNAME"), String::from("from_env"));
+        env.insert(String::from("JSON2CAPNP_PORT"), String::from("2003"));
+        let config = run_config(ServerConfig::load(&args(&["--port", "2004"]), &env).unwrap());
+        assert_eq!(config.port, 2004);
+        assert_eq!(config.project_shortname, "from_env");
+
+        let config_file_path = Path::new("test/config_test.json");
+        fs::write(config_file_path, r#"{ "bind": "127.0.0.1", "project_shortname": "json" }"#).unwrap();
+        let config = ServerConfig::from_file(config_file_path).unwrap();
+        assert_eq!(config.bind, "127.0.0.1");
+        assert_eq!(config.project_shortname, "json");
+
+        fs::write(config_file_path, r#"{ "unknown_key": true }"#).unwrap();
+        assert!(ServerConfig::from_file(config_file_path).is_err());
+
+        fs::write(config_file_path, r#"{ "max_body_size": 1024, "collections": { "odTrips": { "traversal_limit_words": 134217728 } } }"#).unwrap();
+        let config = ServerConfig::from_file(config_file_path).unwrap();
+        assert_eq!(config.limits().unwrap()["collections"]["odTrips"], json!({ "traversal_limit_words": 134217728, "nesting_limit": null, "max_body_size": null, "shard_size": null }));
+        fs::write(config_file_path, r#"{ "collections": { "unknown": { "nesting_limit": 10 } } }"#).unwrap();
+        assert!(ServerConfig::from_file(config_file_path).unwrap().limits().is_err());
+        fs::write(config_file_path, r#"{ "collections": { "odTrips": { "max_size": 10 } } }"#).unwrap();
+        assert!(ServerConfig::from_file(config_file_path).is_err());
+
+    }
+
+    #[test]
+    fn config_projects() {
+
+        fs::create_dir_all("test/projects/first").unwrap();
+        fs::create_dir_all("test/projects/second").unwrap();
+
+        let config_file_path = Path::new("test/config_projects_test.yml");
+        fs::write(config_file_path, "projects:\n  first:\n    cache_dir: test/projects/first\n  second:\n    cache_dir: test/projects/second\n").unwrap();
+
+        let config = ServerConfig::from_file(config_file_path).unwrap();
+        let projects = config.projects().unwrap();
+        assert_eq!(projects.cache_directories.len(), 2);
+        assert_eq!(projects.cache_directories["second"], fs::canonicalize("test/projects/second").unwrap());
+        assert_eq!(projects.default_shortname, None);
+
+        let mut config = config;
+        config.project_shortname = String::from("first");
+        assert_eq!(config.projects().unwrap().default_shortname, Some(String::from("first")));
+
+        // --cache-dir adds the project_shortname project
+        config.project_shortname = String::from("third");
+        config.cache_dir = Some(String::from("test"));
+        let projects = config.projects().unwrap();
+        assert_eq!(projects.cache_directories.len(), 3);
+        assert_eq!(projects.default_shortname, Some(String::from("third")));
+
+        assert_eq!(projects.resolve(None).unwrap().0, "third");
+        assert_eq!(projects.resolve(Some("first")).unwrap(), ("first", fs::canonicalize("test/projects/first").unwrap().as_path()));
+        assert!(projects.resolve(Some("unknown")).is_err());
+
+        config.cache_dir = Some(String::from("test/projects/unexisting"));
+        assert!(config.projects().is_err());
+
+        assert!(ServerConfig::default().projects().is_err());
+
+    }
+}
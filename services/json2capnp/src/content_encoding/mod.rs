@@ -0,0 +1,201 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use std::io::{self, Read};
+use crate::errors::CacheError;
+
+/// Content codings of request bodies and responses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentEncoding {
+    Identity,
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+/// Response encodings, by order of preference when the client accepts
+/// several of them with the same quality
+const RESPONSE_ENCODINGS: [ContentEncoding; 3] = [ContentEncoding::Zstd, ContentEncoding::Brotli, ContentEncoding::Gzip];
+
+impl ContentEncoding {
+
+    pub fn from_name(name: &str) -> Option<ContentEncoding> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "" | "identity"    => Some(ContentEncoding::Identity),
+            "gzip" | "x-gzip"  => Some(ContentEncoding::Gzip),
+            "br"               => Some(ContentEncoding::Brotli),
+            "zstd"             => Some(ContentEncoding::Zstd),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentEncoding::Identity => "identity",
+            ContentEncoding::Gzip     => "gzip",
+            ContentEncoding::Brotli   => "br",
+            ContentEncoding::Zstd     => "zstd",
+        }
+    }
+
+    /// Decode a body. Invalid encoded data fails with an io error wrapping an
+    /// InvalidPayload error.
+    pub fn decoder<'a, R: Read + 'a>(&self, reader: R) -> Result<Box<dyn Read + 'a>, CacheError> {
+        Ok(match self {
+            ContentEncoding::Identity => Box::new(reader),
+            ContentEncoding::Gzip     => Box::new(InvalidData { reader: flate2::read::MultiGzDecoder::new(reader), encoding: *self }),
+            ContentEncoding::Brotli   => Box::new(InvalidData { reader: brotli2::read::BrotliDecoder::new(reader), encoding: *self }),
+            ContentEncoding::Zstd     => Box::new(InvalidData { reader: zstd::stream::read::Decoder::new(reader)?, encoding: *self }),
+        })
+    }
+
+    /// Encode a response body, as it is read
+    pub fn encoder<R: Read + Send + 'static>(&self, reader: R) -> Result<Box<dyn Read + Send>, CacheError> {
+        Ok(match self {
+            ContentEncoding::Identity => Box::new(reader),
+            ContentEncoding::Gzip     => Box::new(flate2::read::GzEncoder::new(reader, flate2::Compression::fast())),
+            ContentEncoding::Brotli   => Box::new(brotli2::read::BrotliEncoder::new(reader, 4)),
+            ContentEncoding::Zstd     => Box::new(zstd::stream::read::Encoder::new(reader, 3)?),
+        })
+    }
+
+}
+
+/// Get the Content-Encoding of a request body. Several codings, applied in
+/// order, are not supported.
+pub fn request_encoding(request: &rouille::Request) -> Result<ContentEncoding, CacheError> {
+    let content_encoding = request.header("Content-Encoding").unwrap_or("");
+    ContentEncoding::from_name(content_encoding).ok_or_else(|| CacheError::UnsupportedEncoding(format!(
+        "Unsupported Content-Encoding {}, the supported encodings are gzip, br and zstd",
+        content_encoding
+    )))
+}
+
+/// Choose the response encoding from the Accept-Encoding header: the
+/// supported encoding with the highest quality, identity if none
+pub fn accepted_encoding(accept_encoding: Option<&str>) -> ContentEncoding {
+    let mut qualities: Vec<(ContentEncoding, f32)> = Vec::new();
+    let mut any_quality = None;
+    for element in accept_encoding.unwrap_or("").split(',') {
+        let mut parameters = element.split(';');
+        let name = parameters.next().unwrap_or("").trim();
+        let quality = parameters
+            .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+            .find_map(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match (name, ContentEncoding::from_name(name)) {
+            ("*", _) => any_quality = Some(quality),
+            ("", _) | (_, None) => {},
+            (_, Some(encoding)) => qualities.push((encoding, quality))
+        }
+    }
+    let quality = |encoding: ContentEncoding| qualities.iter()
+        .find(|(accepted_encoding, _)| *accepted_encoding == encoding)
+        .map(|(_, quality)| *quality)
+        .or(any_quality)
+        .unwrap_or(0.0);
+    RESPONSE_ENCODINGS.iter().copied()
+        .filter(|encoding| quality(*encoding) > 0.0)
+        .fold(None, |best: Option<ContentEncoding>, encoding| match best {
+            Some(best) if quality(best) >= quality(encoding) => Some(best),
+            _ => Some(encoding)
+        })
+        .unwrap_or(ContentEncoding::Identity)
+}
+
+/// Encode a json response with the encoding accepted by the request. Other
+/// responses, like event streams, and encoded responses are not changed.
+pub fn encode_response(request: &rouille::Request, mut response: rouille::Response) -> rouille::Response {
+
+    let is_json = response.headers.iter().any(|(key, value)| key.eq_ignore_ascii_case("Content-Type") && value.contains("json"));
+    let is_encoded = response.headers.iter().any(|(key, _)| key.eq_ignore_ascii_case("Content-Encoding"));
+    if !is_json || is_encoded {
+        return response;
+    }
+    response.headers.push(("Vary".into(), "Accept-Encoding".into()));
+    let encoding = accepted_encoding(request.header("Accept-Encoding"));
+    if encoding == ContentEncoding::Identity {
+        return response;
+    }
+
+    let body = std::mem::replace(&mut response.data, rouille::ResponseBody::empty());
+    let (body_reader, _) = body.into_reader_and_size();
+    match encoding.encoder(body_reader) {
+        Ok(encoded_body) => {
+            response.data = rouille::ResponseBody::from_reader(encoded_body);
+            response.headers.push(("Content-Encoding".into(), encoding.name().into()));
+            response
+        },
+        // the body reader is consumed, the response cannot be sent:
+        Err(error) => rouille::Response::text(error.to_string()).with_status_code(500)
+    }
+
+}
+
+/// Decoder reporting its io errors as invalid payloads, except the errors
+/// of the encoded body reader itself (eg BodyTooLarge)
+struct InvalidData<R> {
+    reader: R,
+    encoding: ContentEncoding,
+}
+
+impl<R: Read> Read for InvalidData<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf).map_err(|error| match error.get_ref() {
+            Some(inner) if inner.is::<CacheError>() => error,
+            _ => io::Error::other(CacheError::InvalidPayload(format!("Invalid {} body: {}", self.encoding.name(), error)))
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+    use std::io::Write;
+
+    #[test]
+    fn content_encoding() {
+
+        assert_eq!(accepted_encoding(None), ContentEncoding::Identity);
+        assert_eq!(accepted_encoding(Some("gzip, deflate")), ContentEncoding::Gzip);
+        assert_eq!(accepted_encoding(Some("gzip, deflate, br")), ContentEncoding::Brotli);
+        assert_eq!(accepted_encoding(Some("gzip;q=1.0, br;q=0.5, zstd;q=0.8")), ContentEncoding::Gzip);
+        assert_eq!(accepted_encoding(Some("zstd;q=0, *")), ContentEncoding::Brotli);
+        assert_eq!(accepted_encoding(Some("deflate, identity")), ContentEncoding::Identity);
+
+        let body = "{\"odTrips\": []}".repeat(100);
+        for encoding in RESPONSE_ENCODINGS {
+            let mut encoded = Vec::new();
+            encoding.encoder(io::Cursor::new(body.clone().into_bytes())).unwrap().read_to_end(&mut encoded).unwrap();
+            assert!(encoded.len() < body.len());
+            let mut decoded = String::new();
+            encoding.decoder(encoded.as_slice()).unwrap().read_to_string(&mut decoded).unwrap();
+            assert_eq!(decoded, body);
+        }
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(b"{}").unwrap();
+        let mut truncated = gzip.finish().unwrap();
+        truncated.truncate(8);
+        let error: CacheError = ContentEncoding::Gzip.decoder(truncated.as_slice()).unwrap().read_to_end(&mut Vec::new()).unwrap_err().into();
+        assert!(matches!(error, CacheError::InvalidPayload(_)));
+
+        let request = rouille::Request::fake_http("POST", "/odTrips", vec![("Content-Encoding".to_owned(), "compress".to_owned())], vec![]);
+        assert!(matches!(request_encoding(&request), Err(CacheError::UnsupportedEncoding(_))));
+
+        let request = rouille::Request::fake_http("GET", "/odTrips", vec![("Accept-Encoding".to_owned(), "gzip".to_owned())], vec![]);
+        let response = encode_response(&request, rouille::Response::json(&json!({ "status": "success" })));
+        assert!(response.headers.iter().any(|(key, value)| key == "Content-Encoding" && value == "gzip"));
+        let response = encode_response(&request, rouille::Response::text("text"));
+        assert!(!response.headers.iter().any(|(key, _)| key == "Content-Encoding"));
+
+    }
+}
@@ -8,6 +8,7 @@
 
 use std::error::Error;
 use std::fmt;
+use crate::validation::FieldError;
 
 #[derive(Debug)]
 pub struct TrError {
@@ -30,4 +31,179 @@ impl Error for TrError {
     fn description(&self) -> &str {
         &self.details
     }
-}
\ No newline at end of file
+}
+
+/// Errors returned by the cache routes. Each error has a stable code that
+/// clients can match on and is mapped to an http status code.
+#[derive(Debug)]
+pub enum CacheError {
+    NotFound(String),
+    InvalidPayload(String),
+    InvalidParameter(String),
+    Io(std::io::Error),
+    CapnpDecode(capnp::Error),
+    Conflict(String),
+    Validation(Vec<FieldError>),
+    BodyTooLarge(String),
+    ReaderLimit(String),
+    UnsupportedEncoding(String),
+    ShuttingDown(String),
+}
+
+impl CacheError {
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            CacheError::NotFound(_)         => "NOT_FOUND",
+            CacheError::InvalidPayload(_)   => "INVALID_PAYLOAD",
+            CacheError::InvalidParameter(_) => "INVALID_PARAMETER",
+            CacheError::Io(_)               => "IO_ERROR",
+            CacheError::CapnpDecode(_)      => "CAPNP_DECODE_ERROR",
+            CacheError::Conflict(_)         => "CONFLICT",
+            CacheError::Validation(_)       => "VALIDATION_FAILED",
+            CacheError::BodyTooLarge(_)     => "BODY_TOO_LARGE",
+            CacheError::ReaderLimit(_)      => "READER_LIMIT_EXCEEDED",
+            CacheError::UnsupportedEncoding(_) => "UNSUPPORTED_CONTENT_ENCODING",
+            CacheError::ShuttingDown(_)     => "SHUTTING_DOWN",
+        }
+    }
+
+    pub fn status_code(&self) -> u16 {
+        match self {
+            CacheError::NotFound(_)         => 404,
+            CacheError::InvalidPayload(_)   => 400,
+            CacheError::InvalidParameter(_) => 400,
+            CacheError::Io(_)               => 500,
+            CacheError::CapnpDecode(_)      => 500,
+            CacheError::Conflict(_)         => 409,
+            CacheError::Validation(_)       => 400,
+            CacheError::BodyTooLarge(_)     => 413,
+            CacheError::ReaderLimit(_)      => 500,
+            CacheError::UnsupportedEncoding(_) => 415,
+            CacheError::ShuttingDown(_)     => 503,
+        }
+    }
+
+    /// Errors from capnp::serialize_packed::write_message can only come from
+    /// the underlying writer
+    pub fn from_write_error(error: capnp::Error) -> CacheError {
+        CacheError::Io(std::io::Error::other(error.description))
+    }
+
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::NotFound(message)         => write!(f, "{}", message),
+            CacheError::InvalidPayload(message)   => write!(f, "{}", message),
+            CacheError::InvalidParameter(message) => write!(f, "{}", message),
+            CacheError::Io(error)                 => write!(f, "{}", error),
+            CacheError::CapnpDecode(error)        => write!(f, "{}", error),
+            CacheError::Conflict(message)         => write!(f, "{}", message),
+            CacheError::Validation(errors)        => write!(f, "Invalid payload: {} validation error(s)", errors.len()),
+            CacheError::BodyTooLarge(message)     => write!(f, "{}", message),
+            CacheError::ReaderLimit(message)      => write!(f, "{}", message),
+            CacheError::UnsupportedEncoding(message) => write!(f, "{}", message),
+            CacheError::ShuttingDown(message)     => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for CacheError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CacheError::Io(error)          => Some(error),
+            CacheError::CapnpDecode(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+/// Io errors wrapping a CacheError (eg from a body reader) are unwrapped
+impl From<std::io::Error> for CacheError {
+    fn from(error: std::io::Error) -> Self {
+        match error.get_ref() {
+            Some(inner) if inner.is::<CacheError>() => *error.into_inner().unwrap().downcast::<CacheError>().unwrap(),
+            _ => CacheError::Io(error)
+        }
+    }
+}
+
+/// Capnp reader limit errors name the limit to increase
+impl From<capnp::Error> for CacheError {
+    fn from(error: capnp::Error) -> Self {
+        let limit = if error.description.contains("read limit exceeded") || (error.description.starts_with("Message has ") && error.description.contains("too large")) {
+            "traversal_limit_words"
+        } else if error.description.contains("nesting limit exceeded") {
+            "nesting_limit"
+        } else {
+            return CacheError::CapnpDecode(error);
+        };
+        CacheError::ReaderLimit(format!("Cache file exceeds the {} reader limit, which can be increased globally or for this collection ({})", limit, error.description))
+    }
+}
+
+impl From<capnp::NotInSchema> for CacheError {
+    fn from(error: capnp::NotInSchema) -> Self {
+        CacheError::CapnpDecode(error.into())
+    }
+}
+
+/// The json data and the geobuf geographies are decoded with the cache file
+impl From<serde_json::Error> for CacheError {
+    fn from(error: serde_json::Error) -> Self {
+        CacheError::CapnpDecode(capnp::Error::failed(format!("Invalid json data in cache file: {}", error)))
+    }
+}
+
+impl From<protobuf::ProtobufError> for CacheError {
+    fn from(error: protobuf::ProtobufError) -> Self {
+        CacheError::CapnpDecode(capnp::Error::failed(format!("Invalid geography in cache file: {}", error)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::CacheError;
+    use pretty_assertions::{assert_eq};
+
+    #[test]
+    fn cache_error_codes() {
+
+        // The codes are part of the api, clients match on them
+        let errors = vec![
+            (CacheError::NotFound(String::from("not found")), "NOT_FOUND", 404),
+            (CacheError::InvalidPayload(String::from("invalid")), "INVALID_PAYLOAD", 400),
+            (CacheError::InvalidParameter(String::from("invalid")), "INVALID_PARAMETER", 400),
+            (CacheError::Io(std::io::Error::other("io")), "IO_ERROR", 500),
+            (CacheError::CapnpDecode(capnp::Error::failed(String::from("decode"))), "CAPNP_DECODE_ERROR", 500),
+            (CacheError::Conflict(String::from("conflict")), "CONFLICT", 409),
+            (CacheError::Validation(vec![]), "VALIDATION_FAILED", 400),
+            (CacheError::BodyTooLarge(String::from("too large")), "BODY_TOO_LARGE", 413),
+            (CacheError::ReaderLimit(String::from("limit")), "READER_LIMIT_EXCEEDED", 500),
+            (CacheError::UnsupportedEncoding(String::from("compress")), "UNSUPPORTED_CONTENT_ENCODING", 415),
+            (CacheError::ShuttingDown(String::from("shutting down")), "SHUTTING_DOWN", 503),
+        ];
+
+        for (error, code, status_code) in errors {
+            assert_eq!(error.code(), code);
+            assert_eq!(error.status_code(), status_code);
+        }
+
+        let error : CacheError = std::io::Error::other("disk full").into();
+        assert_eq!(error.to_string(), "disk full");
+
+        let error : CacheError = capnp::Error::failed(String::from("read limit exceeded")).into();
+        assert!(matches!(&error, CacheError::ReaderLimit(message) if message.contains("traversal_limit_words")));
+        let error : CacheError = capnp::Error::failed(String::from("nesting limit exceeded")).into();
+        assert!(matches!(&error, CacheError::ReaderLimit(message) if message.contains("nesting_limit")));
+        let error : CacheError = std::io::Error::other(CacheError::BodyTooLarge(String::from("too large"))).into();
+        assert!(matches!(error, CacheError::BodyTooLarge(_)));
+        let error : CacheError = serde_json::from_str::<serde_json::Value>("{").unwrap_err().into();
+        assert_eq!(error.code(), "CAPNP_DECODE_ERROR");
+
+    }
+}
@@ -0,0 +1,347 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::errors::CacheError;
+use crate::logging;
+use crate::shutdown;
+use crate::utils;
+
+/// Finished jobs are forgotten after this long, when another job is started
+/// or a job is looked up
+const JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// Interval between the checks of a job for its event stream
+const JOB_EVENTS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Size of the chunks of the chunked responses of the http server, which
+/// only sends full chunks until the response ends
+const EVENT_CHUNK_SIZE: usize = 8192;
+
+/// Jobs started or finished less than JOB_RETENTION ago, by job id
+static JOBS: Mutex<Option<HashMap<String, Arc<Job>>>> = Mutex::new(None);
+
+thread_local! {
+    /// Job run by the current thread, for progress reports
+    static CURRENT_JOB: RefCell<Option<Arc<Job>>> = const { RefCell::new(None) };
+}
+
+/// Request run in a background thread, with its progress and its response
+/// once finished
+pub struct Job {
+    id: String,
+    method: String,
+    url: String,
+    started: Instant,
+    records_processed: AtomicUsize,
+    /// Body of the request, until it is read by the job (see take_body)
+    body: Mutex<Option<File>>,
+    result: Mutex<Option<JobResult>>,
+}
+
+struct JobResult {
+    status_code: u16,
+    response: serde_json::Value,
+    duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// Status of a job, for GET /jobs/{id}. A finished job has the status code
+/// of its response and either its data or its errors.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub id: String,
+    pub method: String,
+    pub url: String,
+    pub state: JobState,
+    pub records_processed: usize,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<serde_json::Value>,
+}
+
+impl Job {
+
+    fn is_finished(&self) -> bool {
+        self.result.lock().unwrap_or_else(PoisonError::into_inner).is_some()
+    }
+
+    pub fn status(&self) -> JobStatus {
+        let result = self.result.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut status = JobStatus {
+            id: self.id.clone(),
+            method: self.method.clone(),
+            url: self.url.clone(),
+            state: JobState::Running,
+            records_processed: self.records_processed.load(Ordering::SeqCst),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            status_code: None,
+            data: None,
+            error: None,
+            error_code: None,
+            errors: None,
+        };
+        if let Some(result) = result.as_ref() {
+            let member = |key: &str| result.response.get(key).cloned();
+            status.state = if result.status_code < 400 { JobState::Succeeded } else { JobState::Failed };
+            status.duration_ms = result.duration.as_millis() as u64;
+            status.status_code = Some(result.status_code);
+            status.data = member("data");
+            status.error = member("error");
+            status.error_code = member("errorCode");
+            status.errors = member("errors");
+            if let Some(count) = status.data.as_ref().and_then(|data| data["count"].as_u64()) {
+                status.records_processed = count as usize;
+            }
+        }
+        status
+    }
+
+}
+
+/// Run jobs_fn on the jobs, once the jobs finished more than JOB_RETENTION
+/// ago are forgotten
+fn with_jobs<T>(jobs_fn: impl FnOnce(&mut HashMap<String, Arc<Job>>) -> T) -> T {
+    let mut jobs = JOBS.lock().unwrap_or_else(PoisonError::into_inner);
+    let jobs = jobs.get_or_insert_with(HashMap::new);
+    jobs.retain(|_, job| job.started.elapsed() < JOB_RETENTION || !job.is_finished());
+    jobs_fn(jobs)
+}
+
+/// Report the number of records processed so far by the job of the current
+/// thread, if any
+pub fn progress(records_processed: usize) {
+    CURRENT_JOB.with(|job| {
+        if let Some(job) = job.borrow().as_ref() {
+            job.records_processed.store(records_processed, Ordering::SeqCst);
+        }
+    });
+}
+
+/// Take the body of the request of the job run by the current thread, if it
+/// was not read yet. The request given to the handler of a job has an empty
+/// body.
+pub fn take_body() -> Option<File> {
+    CURRENT_JOB.with(|job| job.borrow().as_ref().and_then(|job| job.body.lock().unwrap_or_else(PoisonError::into_inner).take()))
+}
+
+/// Copy the body of a request, up to max_body_size, to a file of
+/// directory_path which is removed as soon as it is created: its space is
+/// freed once the job closes it, or if the server stops.
+fn spool_body(request: &rouille::Request, max_body_size: Option<u64>, directory_path: &Path) -> Result<Option<File>, CacheError> {
+    let request_body = match request.data() {
+        Some(request_body) => request_body,
+        None => return Ok(None)
+    };
+    let limits = crate::limits::Limits { max_body_size, ..Default::default() };
+    limits.check_content_length(request.header("Content-Length"))?;
+    fs::create_dir_all(directory_path)?;
+    let path = directory_path.join(format!(".job.{}.body", utils::unique_id()));
+    let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+    fs::remove_file(&path)?;
+    io::copy(&mut limits.limit_body(request_body), &mut file)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(Some(file))
+}
+
+/// Copy a request to run it in a background thread with handler. Returns
+/// the job id. The body is copied up to max_body_size to a file of
+/// spool_directory_path before the job starts, and read by the handler with
+/// take_body.
+pub fn start<F>(request: &rouille::Request, max_body_size: Option<u64>, spool_directory_path: &Path, handler: F) -> Result<String, CacheError>
+where F: FnOnce(&rouille::Request) -> rouille::Response + Send + 'static {
+
+    let body = spool_body(request, max_body_size, spool_directory_path)?;
+    let remote_addr: SocketAddr = *request.remote_addr();
+    let method = request.method().to_string();
+    let url = request.raw_url().to_string();
+    let headers: Vec<(String, String)> = request.headers().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+
+    let job = Arc::new(Job {
+        id: utils::unique_id(),
+        method: method.clone(),
+        url: url.clone(),
+        started: Instant::now(),
+        records_processed: AtomicUsize::new(0),
+        body: Mutex::new(body),
+        result: Mutex::new(None),
+    });
+    with_jobs(|jobs| jobs.insert(job.id.clone(), job.clone()));
+
+    // the job logs in a span inside the span of the request which started it:
+    let span = tracing::info_span!("job", job_id = job.id.as_str(), records = tracing::field::Empty);
+    // a shutdown waits for the job, like for a request:
+    let active = shutdown::Active::start();
+    let thread_job = job.clone();
+    thread::Builder::new().name(format!("job-{}", job.id)).spawn(move || {
+        let _active = active;
+        let _span = span.enter();
+        CURRENT_JOB.with(|current_job| *current_job.borrow_mut() = Some(thread_job.clone()));
+        let job_request = rouille::Request::fake_http_from(remote_addr, method, url, headers, Vec::new());
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| handler(&job_request))) {
+            Ok(response) => {
+                let (mut response_body, _) = response.data.into_reader_and_size();
+                let mut response_json = String::new();
+                let response_json = response_body.read_to_string(&mut response_json).ok()
+                    .and_then(|_| serde_json::from_str(&response_json).ok())
+                    .unwrap_or(serde_json::Value::Null);
+                JobResult { status_code: response.status_code, response: response_json, duration: thread_job.started.elapsed() }
+            },
+            Err(_) => JobResult {
+                status_code: 500,
+                response: json!({ "status": "fail", "error": "The job panicked", "errorCode": "JOB_PANICKED" }),
+                duration: thread_job.started.elapsed()
+            }
+        };
+        thread_job.body.lock().unwrap_or_else(PoisonError::into_inner).take();
+        logging::finished("job finished", result.status_code, result.duration);
+        *thread_job.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+    })?;
+
+    Ok(job.id.clone())
+
+}
+
+pub fn find(job_id: &str) -> Result<Arc<Job>, CacheError> {
+    with_jobs(|jobs| jobs.get(job_id).cloned()).ok_or_else(|| CacheError::NotFound(format!("Job {} not found", job_id)))
+}
+
+/// Server-Sent Events of a job: a progress event each time its status
+/// changes, then a done event with its final status
+pub struct JobEvents {
+    job: Arc<Job>,
+    last_status: Option<JobStatus>,
+    buffer: io::Cursor<Vec<u8>>,
+    done: bool,
+}
+
+impl JobEvents {
+    pub fn new(job: Arc<Job>) -> JobEvents {
+        JobEvents { job, last_status: None, buffer: io::Cursor::new(Vec::new()), done: false }
+    }
+}
+
+impl Read for JobEvents {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read_length = self.buffer.read(buf)?;
+            if read_length > 0 || self.done {
+                return Ok(read_length);
+            }
+            let status = self.job.status();
+            // the duration of a running job always changes, it is not an event:
+            let changed = match self.last_status.as_ref() {
+                Some(last_status) => (status.state, status.records_processed) != (last_status.state, last_status.records_processed),
+                None => true
+            };
+            if !changed {
+                thread::sleep(JOB_EVENTS_INTERVAL);
+                continue;
+            }
+            self.done = status.state != JobState::Running;
+            let event = format!("event: {}\ndata: {}\n\n", if self.done { "done" } else { "progress" }, serde_json::to_string(&status).map_err(io::Error::from)?);
+            // pad the event with a comment so it fills whole chunks and is sent at once:
+            let padding = (EVENT_CHUNK_SIZE - (event.len() + 2) % EVENT_CHUNK_SIZE) % EVENT_CHUNK_SIZE;
+            self.buffer = io::Cursor::new(format!(":{}\n{}", " ".repeat(padding), event).into_bytes());
+            self.last_status = Some(status);
+        }
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+
+    fn wait(job_id: &str) -> JobStatus {
+        let job = find(job_id).unwrap();
+        loop {
+            let status = job.status();
+            if status.state != JobState::Running {
+                return status;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn jobs() {
+
+        let spool_directory_path = Path::new("test/jobs");
+        let request = || rouille::Request::fake_http("POST", "/odTrips?async=true", vec![("Content-Type".to_owned(), "application/json".to_owned())], b"{\"odTrips\": [1, 2, 3]}".to_vec());
+        let job_id = start(&request(), None, spool_directory_path, |request| {
+            assert_eq!(request.data().unwrap().read(&mut [0; 1]).unwrap(), 0);
+            let json: serde_json::Value = serde_json::from_reader(take_body().unwrap()).unwrap();
+            progress(2);
+            rouille::Response::json(&json!({ "status": "success", "data": { "count": json["odTrips"].as_array().unwrap().len() } }))
+        }).unwrap();
+        let status = wait(&job_id);
+        assert_eq!((status.state, status.status_code, status.records_processed), (JobState::Succeeded, Some(200), 3));
+        assert_eq!((status.method.as_str(), status.url.as_str()), ("POST", "/odTrips?async=true"));
+        assert_eq!(status.data, Some(json!({ "count": 3 })));
+
+        let job_id = start(&request(), None, spool_directory_path, |_| {
+            progress(2);
+            rouille::Response::json(&json!({ "status": "fail", "error": "Invalid", "errorCode": "VALIDATION_FAILED", "errors": [] })).with_status_code(400)
+        }).unwrap();
+        let status = wait(&job_id);
+        assert_eq!((status.state, status.status_code, status.records_processed), (JobState::Failed, Some(400), 2));
+        assert_eq!((status.error_code, status.errors), (Some(json!("VALIDATION_FAILED")), Some(json!([]))));
+        let mut events = String::new();
+        JobEvents::new(find(&job_id).unwrap()).read_to_string(&mut events).unwrap();
+        assert_eq!(events.len() % EVENT_CHUNK_SIZE, 0);
+        assert!(events.trim_start_matches([':', ' ', '\n']).starts_with("event: done\ndata: {"));
+        assert!(events.ends_with("}\n\n"));
+
+        let job_id = start(&request(), None, spool_directory_path, |_| panic!("conversion failed")).unwrap();
+        assert_eq!(wait(&job_id).error_code, Some(json!("JOB_PANICKED")));
+
+        assert!(matches!(start(&request(), Some(10), spool_directory_path, |_| rouille::Response::empty_404()), Err(CacheError::BodyTooLarge(_))));
+        assert!(matches!(find("unknown"), Err(CacheError::NotFound(_))));
+
+        // finished jobs are forgotten after JOB_RETENTION, when they are looked up:
+        if let Some(started) = Instant::now().checked_sub(JOB_RETENTION) {
+            let result = JobResult { status_code: 200, response: json!({}), duration: Duration::from_secs(1) };
+            let job = Job { id: String::from("expired"), method: String::from("POST"), url: String::from("/odTrips"), started, records_processed: AtomicUsize::new(0), body: Mutex::new(None), result: Mutex::new(Some(result)) };
+            with_jobs(|jobs| jobs.insert(job.id.clone(), Arc::new(job)));
+            assert!(matches!(find("expired"), Err(CacheError::NotFound(_))));
+        }
+
+    }
+}
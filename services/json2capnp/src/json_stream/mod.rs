@@ -0,0 +1,300 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::{self, Read};
+use tracing::error;
+use crate::errors::CacheError;
+
+/// Parse a json object body without building the items of one of its arrays
+/// in memory: each item of the array (eg odTrips) is passed to item_fn as soon
+/// as it is parsed. Returns the other members of the object (eg project) and
+/// whether the array was found.
+pub fn read_items<R: Read>(reader: R, array_key: &str, item_fn: &mut dyn FnMut(usize, Value) -> Result<(), CacheError>) -> Result<(Map<String, Value>, bool), CacheError> {
+
+    let mut item_error = None;
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let result = BodySeed { array_key, item_fn, item_error: &mut item_error }.deserialize(&mut deserializer).and_then(|body| deserializer.end().map(|()| body));
+
+    match (result, item_error) {
+        (_, Some(error)) => Err(error),
+        (Ok(body), None) => Ok(body),
+        (Err(error), None) => Err(json_body_error(error))
+    }
+
+}
+
+/// Errors of the body reader (eg BodyTooLarge) are kept, the others are
+/// invalid json
+pub fn json_body_error(error: serde_json::Error) -> CacheError {
+    if error.is_io() {
+        return io::Error::from(error).into();
+    }
+    CacheError::InvalidPayload(format!("Invalid json body: {}", error))
+}
+
+struct BodySeed<'a, 'f> {
+    array_key: &'a str,
+    item_fn: &'a mut dyn FnMut(usize, Value) -> Result<(), CacheError>,
+    item_error: &'f mut Option<CacheError>,
+}
+
+impl<'de, 'a, 'f> DeserializeSeed<'de> for BodySeed<'a, 'f> {
+    type Value = (Map<String, Value>, bool);
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a, 'f> Visitor<'de> for BodySeed<'a, 'f> {
+    type Value = (Map<String, Value>, bool);
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a json object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut body = Map::new();
+        let mut array_found = false;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == self.array_key {
+                map.next_value_seed(ItemsSeed { item_fn: &mut *self.item_fn, item_error: &mut *self.item_error })?;
+                array_found = true;
+            } else {
+                body.insert(key, map.next_value()?);
+            }
+        }
+        Ok((body, array_found))
+    }
+}
+
+struct ItemsSeed<'a, 'f> {
+    item_fn: &'a mut dyn FnMut(usize, Value) -> Result<(), CacheError>,
+    item_error: &'f mut Option<CacheError>,
+}
+
+impl<'de, 'a, 'f> DeserializeSeed<'de> for ItemsSeed<'a, 'f> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a, 'f> Visitor<'de> for ItemsSeed<'a, 'f> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut i = 0;
+        while let Some(item) = seq.next_element::<Value>()? {
+            if let Err(error) = (self.item_fn)(i, item) {
+                *self.item_error = Some(error);
+                return Err(de::Error::custom("item error"));
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+}
+
+
+/// Items of a collection converted to json one by one while they are sent
+pub struct JsonItems {
+    count: usize,
+    read_item: Box<dyn FnMut(usize) -> Result<Value, CacheError> + Send>,
+}
+
+impl JsonItems {
+
+    pub fn new<F: FnMut(usize) -> Result<Value, CacheError> + Send + 'static>(count: usize, read_item: F) -> JsonItems {
+        JsonItems { count, read_item: Box::new(read_item) }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Items of several parts (eg the shards of a collection) read in order.
+    /// Each part is read when its first item is read and dropped after its
+    /// last item, so only one part is in memory at a time. The first part is
+    /// read right away, so that its errors are returned before the items are
+    /// sent.
+    pub fn concat<F: FnMut(usize) -> Result<JsonItems, CacheError> + Send + 'static>(counts: Vec<usize>, mut open_part: F) -> Result<JsonItems, CacheError> {
+        let mut open_checked_part = move |part: usize, count: usize| -> Result<JsonItems, CacheError> {
+            let items = open_part(part)?;
+            if items.count != count {
+                return Err(CacheError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("Part {} has {} items instead of {}", part, items.count, count))));
+            }
+            Ok(items)
+        };
+        let mut part = 0;
+        let mut part_start = 0;
+        let mut current = match counts.iter().position(|&count| count > 0) {
+            Some(first_part) => {
+                part = first_part;
+                Some(open_checked_part(first_part, counts[first_part])?)
+            },
+            None => None
+        };
+        Ok(JsonItems::new(counts.iter().sum(), move |i| {
+            while i >= part_start + counts[part] {
+                part_start += counts[part];
+                part += 1;
+                current = None;
+            }
+            let items = match current.as_mut() {
+                Some(items) => items,
+                None => current.insert(open_checked_part(part, counts[part])?)
+            };
+            (items.read_item)(i - part_start)
+        }))
+    }
+
+    /// Convert all the items, for updates of the whole collection
+    pub fn into_values(mut self) -> Result<Vec<Value>, CacheError> {
+        (0..self.count).map(|i| (self.read_item)(i)).collect()
+    }
+
+    /// Json text of the items array of a json object between prefix and
+    /// suffix, eg {"odTrips":[ and ]}, produced as it is read. The errors are
+    /// logged in the span of the current request.
+    pub fn into_reader(self, prefix: String, suffix: String) -> JsonItemsReader {
+        JsonItemsReader { items: self, next_item: 0, buffer: prefix.into_bytes(), position: 0, suffix: Some(suffix), span: tracing::Span::current() }
+    }
+
+}
+
+/// The status of the response is sent before the items are converted, so an
+/// error while converting an item ends the response: the items array is
+/// closed, and the error and errorCode of the error are added to the json
+/// object, eg {"status":"success","data":{"odTrips":[...]},"error":"...","errorCode":"..."}.
+/// Clients must check for an errorCode in successful streamed responses.
+pub struct JsonItemsReader {
+    items: JsonItems,
+    next_item: usize,
+    buffer: Vec<u8>,
+    position: usize,
+    suffix: Option<String>,
+    span: tracing::Span,
+}
+
+impl JsonItemsReader {
+
+    fn end_with_error(&mut self, error: CacheError) -> io::Result<()> {
+        {
+            let _span = self.span.enter();
+            error!(error_code = error.code(), "Streamed response failed after {} of {} items: {}", self.next_item, self.items.count, error);
+        }
+        self.next_item = self.items.count;
+        let suffix = self.suffix.take().unwrap_or_default();
+        let suffix = suffix.strip_suffix('}').ok_or_else(|| io::Error::other(error.to_string()))?;
+        let error_members = serde_json::to_string(&serde_json::json!({ "error": error.to_string(), "errorCode": error.code() }))?;
+        self.buffer.clear();
+        self.buffer.extend_from_slice(suffix.as_bytes());
+        self.buffer.push(b',');
+        self.buffer.extend_from_slice(&error_members.as_bytes()[1..]);
+        Ok(())
+    }
+
+}
+
+impl Read for JsonItemsReader {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            self.buffer.clear();
+            self.position = 0;
+            if self.next_item < self.items.count {
+                if self.next_item > 0 {
+                    self.buffer.push(b',');
+                }
+                match (self.items.read_item)(self.next_item) {
+                    Ok(item) => {
+                        serde_json::to_writer(&mut self.buffer, &item)?;
+                        self.next_item += 1;
+                    },
+                    Err(error) => self.end_with_error(error)?
+                }
+            } else {
+                match self.suffix.take() {
+                    Some(suffix) => self.buffer = suffix.into_bytes(),
+                    None => return Ok(0)
+                }
+            }
+        }
+        let length = buf.len().min(self.buffer.len() - self.position);
+        buf[..length].copy_from_slice(&self.buffer[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+
+    #[test]
+    fn items() {
+
+        let body = r##"{ "project": "demo", "odTrips": [{ "id": "a" }, { "id": "b" }], "data_source_uuid": null }"##;
+        let mut items = Vec::new();
+        let (members, array_found) = read_items(body.as_bytes(), "odTrips", &mut |i, item| {
+            items.push((i, item));
+            Ok(())
+        }).unwrap();
+        assert!(array_found);
+        assert_eq!(items, vec![(0, json!({ "id": "a" })), (1, json!({ "id": "b" }))]);
+        assert_eq!(Value::Object(members), json!({ "project": "demo", "data_source_uuid": null }));
+
+        let (_, array_found) = read_items(r##"{ "persons": [] }"##.as_bytes(), "odTrips", &mut |_, _| Ok(())).unwrap();
+        assert!(!array_found);
+
+        // item errors stop the parsing:
+        let result = read_items(body.as_bytes(), "odTrips", &mut |i, _| if i == 0 { Err(CacheError::Conflict(String::from("stop"))) } else { panic!("parsed after an error") });
+        assert!(matches!(result, Err(CacheError::Conflict(_))));
+
+        let mut reader = JsonItems::new(3, |i| Ok(json!({ "id": i }))).into_reader(String::from(r##"{"odTrips":["##), String::from("]}"));
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, r##"{"odTrips":[{"id":0},{"id":1},{"id":2}]}"##);
+
+        let mut reader = JsonItems::new(0, |_| panic!("no items")).into_reader(String::from("["), String::from("]"));
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "[]");
+
+        let counts = vec![0, 2, 0, 1];
+        let parts = JsonItems::concat(counts.clone(), move |part| Ok(JsonItems::new(counts[part], move |i| Ok(json!([part, i])))));
+        assert_eq!(parts.unwrap().into_values().unwrap(), vec![json!([1, 0]), json!([1, 1]), json!([3, 0])]);
+        assert!(JsonItems::concat(vec![1, 1], |_| Ok(JsonItems::new(2, |i| Ok(json!(i))))).is_err());
+        let parts = JsonItems::concat(vec![1, 1], |part| Ok(JsonItems::new(part + 1, |i| Ok(json!(i))))).unwrap();
+        assert!(parts.into_values().is_err());
+
+        // an item error ends the response with the error:
+        let mut reader = JsonItems::new(3, |i| if i == 0 { Ok(json!(0)) } else { Err(CacheError::NotFound(String::from("item"))) }).into_reader(String::from(r##"{"status":"success","data":{"odTrips":["##), String::from("]}}"));
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), json!({ "status": "success", "data": { "odTrips": [0] }, "error": "item", "errorCode": "NOT_FOUND" }));
+
+        for invalid_body in ["[]", r##"{ "odTrips": {} }"##, r##"{ "odTrips": [{}"##, r##"{} {}"##].iter() {
+            assert!(matches!(read_items(invalid_body.as_bytes(), "odTrips", &mut |_, _| Ok(())), Err(CacheError::InvalidPayload(_))));
+        }
+
+    }
+}
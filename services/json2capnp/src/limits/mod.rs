@@ -0,0 +1,154 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use capnp::message::{ReaderOptions, DEFAULT_READER_OPTIONS};
+use std::io::{self, Read};
+use crate::errors::CacheError;
+
+/// Capnp reader options, request body size and shard size of a collection
+/// and its objects
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub traversal_limit_words: u64,
+    pub nesting_limit: i32,
+    /// Maximum request body size in bytes, unlimited if None
+    pub max_body_size: Option<u64>,
+    /// Maximum number of items per shard file of the sharded collections
+    pub shard_size: usize,
+}
+
+pub const DEFAULT_SHARD_SIZE: usize = 100000;
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            traversal_limit_words: DEFAULT_READER_OPTIONS.traversal_limit_in_words.unwrap_or(usize::MAX) as u64,
+            nesting_limit: DEFAULT_READER_OPTIONS.nesting_limit,
+            max_body_size: None,
+            shard_size: DEFAULT_SHARD_SIZE,
+        }
+    }
+}
+
+impl Limits {
+
+    /// Get the limits of a collection from the request config: the global
+    /// limits, overridden by limits.collections.{collection_name}. Objects use
+    /// the limits of their collection.
+    pub fn from_config(config: &serde_json::Value, collection_name: &str) -> Limits {
+        let mut limits = Limits::default();
+        for limits_json in [&config["limits"], &config["limits"]["collections"][collection_name]] {
+            if let Some(traversal_limit_words) = limits_json["traversal_limit_words"].as_u64() {
+                limits.traversal_limit_words = traversal_limit_words;
+            }
+            if let Some(nesting_limit) = limits_json["nesting_limit"].as_i64() {
+                limits.nesting_limit = nesting_limit as i32;
+            }
+            if let Some(max_body_size) = limits_json["max_body_size"].as_u64() {
+                limits.max_body_size = Some(max_body_size);
+            }
+            if let Some(shard_size) = limits_json["shard_size"].as_u64() {
+                limits.shard_size = shard_size as usize;
+            }
+        }
+        limits
+    }
+
+    pub fn reader_options(&self) -> ReaderOptions {
+        let mut reader_options = ReaderOptions::new();
+        reader_options
+            .traversal_limit_in_words(Some(self.traversal_limit_words as usize))
+            .nesting_limit(self.nesting_limit);
+        reader_options
+    }
+
+    /// Fail early if the announced body size exceeds max_body_size
+    pub fn check_content_length(&self, content_length: Option<&str>) -> Result<(), CacheError> {
+        match (self.max_body_size, content_length.and_then(|content_length| content_length.parse::<u64>().ok())) {
+            (Some(max_body_size), Some(content_length)) if content_length > max_body_size => Err(body_too_large(max_body_size)),
+            _ => Ok(())
+        }
+    }
+
+    /// Read a request body up to max_body_size. Reading more fails with an io
+    /// error wrapping a BodyTooLarge error.
+    pub fn limit_body<R: Read>(&self, reader: R) -> LimitedBody<R> {
+        LimitedBody { reader, remaining: self.max_body_size, max_body_size: self.max_body_size.unwrap_or(0) }
+    }
+
+}
+
+fn body_too_large(max_body_size: u64) -> CacheError {
+    CacheError::BodyTooLarge(format!("Request body exceeds the max_body_size limit of {} bytes", max_body_size))
+}
+
+pub struct LimitedBody<R> {
+    reader: R,
+    remaining: Option<u64>,
+    max_body_size: u64,
+}
+
+impl<R: Read> Read for LimitedBody<R> {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => return self.reader.read(buf)
+        };
+        if remaining == 0 {
+            // the body can end exactly at the limit:
+            return match self.reader.read(&mut [0u8])? {
+                0 => Ok(0),
+                _ => Err(io::Error::other(body_too_large(self.max_body_size)))
+            };
+        }
+        let length = (buf.len() as u64).min(remaining) as usize;
+        let read_length = self.reader.read(&mut buf[..length])?;
+        self.remaining = Some(remaining - read_length as u64);
+        Ok(read_length)
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+
+    #[test]
+    fn limits() {
+
+        assert_eq!(Limits::from_config(&json!({}), "odTrips"), Limits { traversal_limit_words: 8 * 1024 * 1024, nesting_limit: 64, max_body_size: None, shard_size: 100000 });
+
+        let config = json!({
+            "limits": {
+                "traversal_limit_words": 1000,
+                "nesting_limit": 32,
+                "max_body_size": 10,
+                "collections": { "odTrips": { "traversal_limit_words": 5000, "max_body_size": null, "shard_size": 500 } }
+            }
+        });
+        assert_eq!(Limits::from_config(&config, "nodes"), Limits { traversal_limit_words: 1000, nesting_limit: 32, max_body_size: Some(10), shard_size: 100000 });
+        let limits = Limits::from_config(&config, "odTrips");
+        assert_eq!(limits, Limits { traversal_limit_words: 5000, nesting_limit: 32, max_body_size: Some(10), shard_size: 500 });
+        assert_eq!(limits.reader_options().traversal_limit_in_words, Some(5000));
+
+        assert!(limits.check_content_length(Some("10")).is_ok());
+        assert!(matches!(limits.check_content_length(Some("11")), Err(CacheError::BodyTooLarge(_))));
+        assert!(limits.check_content_length(None).is_ok());
+
+        let mut body = String::new();
+        limits.limit_body("0123456789".as_bytes()).read_to_string(&mut body).unwrap();
+        assert_eq!(body, "0123456789");
+        let error: CacheError = limits.limit_body("0123456789A".as_bytes()).read_to_string(&mut body).unwrap_err().into();
+        assert_eq!(error.to_string(), "Request body exceeds the max_body_size limit of 10 bytes");
+
+    }
+}
@@ -0,0 +1,96 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use serde::Deserialize;
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+use crate::errors::TrError;
+use crate::utils;
+
+/// Request ids received in X-Request-Id headers longer than this are replaced
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Format of the log lines written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One json object per line, with the fields of the current spans
+    Json,
+}
+
+/// Check a log level or filter directives (eg `info` or
+/// `warn,json2capnp=debug`)
+pub fn filter(log_level: &str) -> Result<EnvFilter, TrError> {
+    EnvFilter::try_new(log_level).map_err(|error| TrError::new(&format!("Invalid log level '{}': {}", log_level, error)))
+}
+
+/// Install the global logger
+pub fn init(log_level: &str, log_format: LogFormat) -> Result<(), TrError> {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter(log_level)?)
+        .with_target(false)
+        .with_ansi(io::stdout().is_terminal());
+    let result = match log_format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(false).try_init(),
+    };
+    result.map_err(|error| TrError::new(&format!("Cannot initialize the logger: {}", error)))
+}
+
+/// Id of a request: its X-Request-Id header if it is printable and not too
+/// long, a new id otherwise
+pub fn request_id(request: &rouille::Request) -> String {
+    match request.header("X-Request-Id") {
+        Some(request_id) if !request_id.is_empty() && request_id.len() <= MAX_REQUEST_ID_LENGTH && request_id.bytes().all(|byte| byte.is_ascii_graphic()) => request_id.to_string(),
+        _ => utils::unique_id()
+    }
+}
+
+/// Record the number of records read or written by the current request or job
+pub fn record_count(records: usize) {
+    tracing::Span::current().record("records", records as u64);
+}
+
+/// Log the end of a request or job, at the warn level for client errors and
+/// the error level for server errors
+pub fn finished(message: &str, status_code: u16, duration: Duration) {
+    let duration_ms = duration.as_micros() as f64 / 1000.0;
+    match status_code {
+        500..=599 => error!(status = status_code, duration_ms, "{}", message),
+        400..=499 => warn!(status = status_code, duration_ms, "{}", message),
+        _         => info!(status = status_code, duration_ms, "{}", message),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+
+    #[test]
+    fn logging() {
+
+        assert!(filter("debug").is_ok());
+        assert!(filter("warn,json2capnp=trace").is_ok());
+        assert!(filter("json2capnp=loud").is_err());
+
+        let request = |request_id: &str| rouille::Request::fake_http("GET", "/odTrips", vec![("X-Request-Id".to_owned(), request_id.to_owned())], vec![]);
+        assert_eq!(request_id(&request("a1b2-c3")), "a1b2-c3");
+        assert_ne!(request_id(&request("with space")), "with space");
+        assert_ne!(request_id(&request(&"a".repeat(129))), "a".repeat(129));
+        let request = rouille::Request::fake_http("GET", "/odTrips", vec![], vec![]);
+        assert_ne!(request_id(&request), request_id(&request));
+
+    }
+}
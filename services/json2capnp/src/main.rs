@@ -8,10 +8,11 @@
 
 use rouille::Request;
 use rouille::Response;
-use std::fs;
-use std::io;
-use std::path::Path;
+use std::collections::HashMap;
 use std::env;
+use std::process;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info};
 
 #[macro_use]
 extern crate rouille;
@@ -19,75 +20,118 @@ extern crate rouille;
 #[macro_use]
 extern crate serde_json;
 
+mod cache_files;
+mod cache_paths;
+mod cache_summary;
+mod collection_patch;
+mod collections;
+mod config;
+mod content_encoding;
 mod enum_mappings;
+mod errors;
+mod jobs;
+mod json_stream;
+mod limits;
+mod logging;
+mod metrics;
 mod my_error;
 mod routers;
+mod shards;
+mod shutdown;
+mod uploads;
 mod utils;
+mod validation;
 
 include!("./capnp/include.rs");
 
 fn main() {
 
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let env_vars: HashMap<String, String> = env::vars().collect();
 
-    let port: u16;
-    let cache_directory: Option<String>;
-
-    // TODO Handle command line arguments with --argName [argValue]
-    match args.len() {
-        1 => {
-            println!("no argument, using default port 2000");
-            port = 2000;
-            cache_directory = None;
-        },
-        2 => {
-            println!("using port in arguments: {}", &args[1]);
-            port = args[1].parse().unwrap();
-            cache_directory = None;
-        },
-        3 => {
-            println!("using port and relative path in arguments: {}, {}", &args[1], &args[2]);
-            port = args[1].parse().unwrap();
-            cache_directory = Some(args[2].parse().unwrap());
+    let server_config = match config::ServerConfig::load(&args, &env_vars) {
+        Ok(config::Command::Run(server_config)) => *server_config,
+        Ok(config::Command::Help) => {
+            println!("{}", config::USAGE);
+            return;
         },
-        _ => {
-            println!("too many arguments, using default port 2000");
-            port = 2000;
-            cache_directory = None;
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, config::USAGE);
+            process::exit(1);
         }
+    };
+
+    if let Err(error) = logging::init(&server_config.log_level, server_config.log_format) {
+        eprintln!("{}", error);
+        process::exit(1);
     }
 
-    println!("Starting json2capnp server...");
+    info!("Starting json2capnp server...");
 
-    // TODO For now, the project cache directory must be set on the command line. It can come from config when config is json or yml (#415)
-    let project_shortname = "default";
-    let project_cache_directory_path_str = cache_directory.unwrap_or_else(|| { panic!("Cache directory must be set on the command line (eg cargo run -- 2000 path/to/cache/dir)") } );
-    println!("Using {} as cache directory",
-        project_cache_directory_path_str
-    );
-             
-    let project_cache_directory_path =
-        fs::canonicalize(Path::new(&project_cache_directory_path_str)).expect("Cache directory does not exist");
-    
-    println!(
-        "Port {} | Using project {}",
-        port,
-        project_shortname
+    let projects = match server_config.projects() {
+        Ok(projects) => projects,
+        Err(error) => {
+            error!("{}", error);
+            process::exit(1);
+        }
+    };
+    for (shortname, cache_directory_path) in &projects.cache_directories {
+        info!("Using {} as cache directory for project {}", cache_directory_path.display(), shortname);
+        match uploads::remove_stale_upload_directories(cache_directory_path) {
+            Ok(removed_paths) if !removed_paths.is_empty() => info!("Removed {} stale upload directories of project {}", removed_paths.len(), shortname),
+            Ok(_) => {},
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {},
+            Err(error) => error!("Could not remove the stale uploads of project {}: {}", shortname, error)
+        }
+    }
+    let projects_cache_directory_paths: serde_json::Value = projects.cache_directories.iter()
+        .map(|(shortname, cache_directory_path)| (shortname.clone(), json!(cache_directory_path.to_str().unwrap())))
+        .collect::<serde_json::Map<String, serde_json::Value>>()
+        .into();
+
+    info!(
+        "Listening on {} | Default project {}",
+        server_config.bind_address(),
+        projects.default_shortname.as_deref().unwrap_or("none")
     );
 
-    let handle_request = move |request: &Request| -> Response {
-        
+    let limits = match server_config.limits() {
+        Ok(limits) => limits,
+        Err(error) => {
+            error!("{}", error);
+            process::exit(1);
+        }
+    };
+
+    let write_lock = server_config.write_lock.as_str();
+    let write_lock_timeout = server_config.write_lock_timeout;
+
+    let ready_cache_directory_paths = projects_cache_directory_paths.clone();
+
+    // the requested project, with the config of the request:
+    let respond = move |request: &Request, requested_project_shortname: Option<&str>| -> Response {
+
+        let (project_shortname, project_cache_directory_path) = match projects.resolve(requested_project_shortname) {
+            Ok(project) => project,
+            Err(error) => return routers::project_failed_response(requested_project_shortname, &error)
+        };
+
         // setup config:
         let mut config: serde_json::Value = json!({
             "project_cache_directory_path": project_cache_directory_path.to_str().unwrap(),
             "custom_subdirectory_path"    : json!(null),
             "project_shortname"           : json!(project_shortname),
-            "data_source_uuid"            : json!(null)
+            "project_requested"           : requested_project_shortname.is_some(),
+            "projects"                    : projects_cache_directory_paths,
+            "data_source_uuid"            : json!(null),
+            "write_lock"                  : write_lock,
+            "write_lock_timeout"          : write_lock_timeout,
+            "limits"                      : limits
         });
 
         match &request.get_param("cache_directory_path") {
             Some(cache_directory_path) => {
-                println!("request cache_directory_path {}", cache_directory_path);
+                debug!("request cache_directory_path {}", cache_directory_path);
                 config["custom_subdirectory_path"] = json!(format!("{}", cache_directory_path));
             },
             _ => {}
@@ -95,7 +139,7 @@ fn main() {
 
         match &request.get_param("data_source_uuid") {
             Some(data_source_uuid) => {
-                println!("request data_source_uuid {}", data_source_uuid);
+                debug!("request data_source_uuid {}", data_source_uuid);
                 config["data_source_uuid"] = json!(format!("{}", data_source_uuid));
             },
             _ => {}
@@ -108,53 +152,85 @@ fn main() {
             _ => String::from("").to_owned()
         };
 
-        rouille::log(&request, io::stdout(), || {
-            router!(request,
-              (GET) (/) => {
-                // When viewing the home page, we return an HTML document described below.
-                Response::text(format!("empty response"))
-              },
-
-              (POST) (/dataSources) => { routers::write_collection_route("dataSources", "dataSources", &config, &routers::data_source_collection_router::write_collection, request) },
-              (POST) (/agencies)    => { routers::write_collection_route("agencies", "agencies", &config, &routers::agency_collection_router::write_collection, request) },
-              (POST) (/garages)     => { routers::write_collection_route("garages", "garages", &config, &routers::garage_collection_router::write_collection, request) },
-              (POST) (/paths)       => { routers::write_collection_route("paths", "paths", &config, &routers::path_collection_router::write_collection, request) },
-              (POST) (/nodes)       => { routers::write_collection_route("nodes", "nodes", &config, &routers::node_collection_router::write_collection, request) },
-              (POST) (/node)        => { routers::write_object_route("node", "nodes", &config, &routers::node_router::write_object, request) },
-              (POST) (/households)  => { routers::write_collection_route("households", "households", &config, &routers::household_collection_router::write_collection, request) },
-              (POST) (/lines)       => { routers::write_collection_route("lines", "lines", &config, &routers::line_collection_router::write_collection, request) },
-              (POST) (/line)        => { routers::write_object_route("line", "lines", &config, &routers::line_router::write_object, request) },
-              (POST) (/odTrips)     => { routers::write_collection_route("odTrips", "odTrips", &config, &routers::od_trip_collection_router::write_collection, request) },
-              (POST) (/persons)     => { routers::write_collection_route("persons", "persons", &config, &routers::person_collection_router::write_collection, request) },
-              (POST) (/places)      => { routers::write_collection_route("places", "places", &config, &routers::place_collection_router::write_collection, request) },
-              (POST) (/scenarios)   => { routers::write_collection_route("scenarios", "scenarios", &config, &routers::scenario_collection_router::write_collection, request) },
-              (POST) (/services)    => { routers::write_collection_route("services", "services", &config, &routers::service_collection_router::write_collection, request) },
-              (POST) (/zones)       => { routers::write_collection_route("zones", "zones", &config, &routers::zone_collection_router::write_collection, request) },
-              (POST) (/units)       => { routers::write_collection_route("units", "units", &config, &routers::unit_collection_router::write_collection, request) },
-
-              // taxi:
-              //(POST) (/taxiPoints)  => { routers::write_route("taxiPoints", "items", &config, &routers::taxi_point_collection_router::write_collection, request) },
-              (GET) (/dataSources) => { routers::read_collection_route("dataSources", "dataSources", &config, &routers::data_source_collection_router::read_collection) },
-              (GET) (/agencies)    => { routers::read_collection_route("agencies", "agencies", &config, &routers::agency_collection_router::read_collection) },
-              (GET) (/garages)     => { routers::read_collection_route("garages", "garages", &config, &routers::garage_collection_router::read_collection) },
-              (GET) (/paths)       => { routers::read_collection_route("paths", "paths", &config, &routers::path_collection_router::read_collection) },
-              (GET) (/nodes)       => { routers::read_collection_route("nodes", "nodes", &config, &routers::node_collection_router::read_collection) },
-              (GET) (/node)        => { routers::read_object_route("node", &object_uuid, "nodes", &config, &routers::node_router::read_object) },
-              (GET) (/households)  => { routers::read_collection_route("households", "households", &config, &routers::household_collection_router::read_collection) },
-              (GET) (/lines)       => { routers::read_collection_route("lines", "lines", &config, &routers::line_collection_router::read_collection) },
-              (GET) (/line)        => { routers::read_object_route("line", &object_uuid, "lines", &config, &routers::line_router::read_object) },
-              (GET) (/odTrips)     => { routers::read_collection_route("odTrips", "odTrips", &config, &routers::od_trip_collection_router::read_collection) },
-              (GET) (/persons)     => { routers::read_collection_route("persons", "persons", &config, &routers::person_collection_router::read_collection) },
-              (GET) (/places)      => { routers::read_collection_route("places", "places", &config, &routers::place_collection_router::read_collection) },
-              (GET) (/scenarios)   => { routers::read_collection_route("scenarios", "scenarios", &config, &routers::scenario_collection_router::read_collection) },
-              (GET) (/services)    => { routers::read_collection_route("services", "services", &config, &routers::service_collection_router::read_collection) },
-              (GET) (/zones)       => { routers::read_collection_route("zones", "zones", &config, &routers::zone_collection_router::read_collection) },
-              (GET) (/units)       => { routers::read_collection_route("units", "units", &config, &routers::unit_collection_router::read_collection) },
-
-              _ => rouille::Response::empty_404()
-            )
-        })
+        // writes can run in a background job, see GET /jobs/{id}:
+        let response = if request.method() != "GET" && request.get_param("async").as_deref() == Some("true") {
+            let job_config = config.clone();
+            routers::start_job_route(&config, request, move |job_request| route(job_request, &job_config, &object_uuid))
+        } else {
+            route(request, &config, &object_uuid)
+        };
+        content_encoding::encode_response(request, response)
     };
 
-    rouille::start_server(format!("0.0.0.0:{}", port), handle_request);
+    let handle_request = move |request: &Request| -> Response {
+
+        // requests can be prefixed with /projects/{shortname}:
+        let (prefix_project_shortname, prefixed_request) = routers::remove_project_prefix(request);
+        let request = prefixed_request.as_ref().unwrap_or(request);
+        let requested_project_shortname = prefix_project_shortname.or_else(|| request.get_param("project"));
+
+        // probes, not logged and independent of the requested project:
+        match (request.method(), request.url().as_str()) {
+            ("GET", "/health") => return routers::health_route(),
+            ("GET", "/ready") => return routers::ready_route(&ready_cache_directory_paths),
+            _ => {}
+        }
+
+        let request_id = logging::request_id(request);
+        let span = tracing::info_span!(
+            "request",
+            request_id = request_id.as_str(),
+            method = request.method(),
+            url = request.raw_url(),
+            collection = metrics::collection_label(&request.url()),
+            records = tracing::field::Empty
+        );
+        let _span = span.enter();
+        let _in_flight = metrics::InFlight::start();
+        let started = Instant::now();
+
+        // once shutting down, only the jobs being drained can still be followed:
+        let _active = shutdown::Active::start();
+        let response = if shutdown::is_shutting_down() && !(request.method() == "GET" && request.url().starts_with("/jobs/")) {
+            routers::shutting_down_response()
+        } else {
+            respond(request, requested_project_shortname.as_deref())
+        };
+        let response = metrics::observe(request.method(), &request.url(), started.elapsed(), response);
+        logging::finished("request finished", response.status_code, started.elapsed());
+        response.with_unique_header("X-Request-Id", request_id)
+    };
+
+    if let Err(error) = shutdown::handle_signals(Duration::from_secs(server_config.shutdown_timeout)) {
+        error!("Cannot handle the shutdown signals: {}", error);
+        process::exit(1);
+    }
+
+    rouille::start_server(server_config.bind_address(), handle_request);
+}
+
+fn route(request: &Request, config: &serde_json::Value, object_uuid: &str) -> Response {
+    router!(request,
+      (GET) (/) => {
+        // When viewing the home page, we return an HTML document described below.
+        Response::text(format!("empty response"))
+      },
+
+      (GET) (/collections) => { Response::json(&collections::description()) },
+
+      (GET) (/cache/summary) => { routers::cache_summary_route(config) },
+
+      (GET) (/metrics) => { Response::text(metrics::render()).with_unique_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8") },
+
+      (GET) (/jobs/{job_id: String}) => { routers::job_route(&job_id) },
+
+      (GET) (/jobs/{job_id: String}/events) => { routers::job_events_route(&job_id) },
+
+      (POST) (/lines/reconcile) => { routers::reconcile_collection_route("lines", "lines", config, &routers::line_collection_router::reconcile_collection, request) },
+
+      (DELETE) (/dataSources/{data_source_uuid: String}/cache) => { routers::delete_data_source_cache_route(&data_source_uuid, config) },
+
+      // GET, POST and DELETE routes of the registered collections and their objects:
+      _ => collections::route(request, config, object_uuid).unwrap_or_else(rouille::Response::empty_404)
+    )
 }
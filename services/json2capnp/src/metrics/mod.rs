@@ -0,0 +1,270 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::collections;
+
+/// Upper bounds of the request duration histogram buckets, in seconds
+const DURATION_BUCKETS: [f64; 14] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// Routes which are not collections, used as is in the collection label
+const ROUTE_LABELS: [&str; 6] = ["collections", "jobs", "cache", "metrics", "health", "ready"];
+
+static METRICS: Mutex<Option<Metrics>> = Mutex::new(None);
+
+static IN_FLIGHT: AtomicI64 = AtomicI64::new(0);
+
+#[derive(Default)]
+struct Metrics {
+    requests: BTreeMap<(String, String, u16), u64>,
+    durations: BTreeMap<(String, String), Histogram>,
+    bytes_read: BTreeMap<String, u64>,
+    bytes_written: BTreeMap<String, u64>,
+    errors: BTreeMap<(String, String), u64>,
+    /// Records count and unix time of the last write, by collection
+    last_writes: BTreeMap<String, (usize, f64)>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Count of the durations in each bucket, not cumulative
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+fn with_metrics<T>(metrics_fn: impl FnOnce(&mut Metrics) -> T) -> T {
+    let mut metrics = METRICS.lock().unwrap_or_else(PoisonError::into_inner);
+    metrics_fn(metrics.get_or_insert_with(Metrics::default))
+}
+
+/// Collection label of a request url: the collection of its first segment
+/// (the collection of an object for object urls), the route name for the
+/// other known routes, and "other" for the rest, so that the number of
+/// labels stays bounded
+pub fn collection_label(url: &str) -> &'static str {
+    let segment = url.trim_start_matches('/').split('/').next().unwrap_or("");
+    if segment.is_empty() {
+        return "root";
+    }
+    collections::find(segment).map(|collection| collection.name())
+        .or_else(|| collections::COLLECTIONS.iter().find(|collection| matches!(collection.object(), Some(object) if object.name == segment)).map(|collection| collection.name()))
+        .or_else(|| ROUTE_LABELS.iter().copied().find(|route| *route == segment))
+        .unwrap_or("other")
+}
+
+fn method_label(method: &str) -> &'static str {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"].iter().copied().find(|label| *label == method).unwrap_or("OTHER")
+}
+
+/// Request counted as in flight until dropped
+pub struct InFlight;
+
+impl InFlight {
+    pub fn start() -> InFlight {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Count a request and its duration, until its response was returned by its
+/// route, and count the bytes of its response body as they are sent
+pub fn observe(method: &str, url: &str, duration: Duration, mut response: rouille::Response) -> rouille::Response {
+
+    let (collection, method) = (collection_label(url), method_label(method));
+    with_metrics(|metrics| {
+        *metrics.requests.entry((collection.to_string(), method.to_string(), response.status_code)).or_default() += 1;
+        let histogram = metrics.durations.entry((collection.to_string(), method.to_string())).or_default();
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    });
+
+    let body = std::mem::replace(&mut response.data, rouille::ResponseBody::empty());
+    let (body_reader, size) = body.into_reader_and_size();
+    response.data = match size {
+        // keep the content length of the bodies in memory:
+        Some(size) => {
+            let mut data = Vec::with_capacity(size);
+            match body_reader.take(size as u64).read_to_end(&mut data) {
+                Ok(_) => add_bytes_written(collection, data.len()),
+                Err(_) => data.clear()
+            }
+            rouille::ResponseBody::from_data(data)
+        },
+        None => rouille::ResponseBody::from_reader(CountingReader { reader: body_reader, count: add_bytes_written, collection })
+    };
+    response
+
+}
+
+fn add_bytes_written(collection: &str, bytes: usize) {
+    with_metrics(|metrics| *metrics.bytes_written.entry(collection.to_string()).or_default() += bytes as u64);
+}
+
+fn add_bytes_read(collection: &str, bytes: usize) {
+    with_metrics(|metrics| *metrics.bytes_read.entry(collection.to_string()).or_default() += bytes as u64);
+}
+
+/// Count the bytes of a request body as they are read
+pub fn count_body<R: Read>(url: &str, reader: R) -> CountingReader<R> {
+    CountingReader { reader, count: add_bytes_read, collection: collection_label(url) }
+}
+
+pub struct CountingReader<R> {
+    reader: R,
+    count: fn(&str, usize),
+    collection: &'static str,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_length = self.reader.read(buf)?;
+        if read_length > 0 {
+            (self.count)(self.collection, read_length);
+        }
+        Ok(read_length)
+    }
+}
+
+/// Count a failed request of a collection or object by error code
+pub fn record_error(cache_name: &str, error_code: &str) {
+    let collection = collection_label(cache_name);
+    with_metrics(|metrics| *metrics.errors.entry((collection.to_string(), error_code.to_string())).or_default() += 1);
+}
+
+/// Record the records count of a collection after a write
+pub fn record_write(collection_name: &str, count: usize) {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    with_metrics(|metrics| metrics.last_writes.insert(collection_name.to_string(), (count, time)));
+}
+
+/// Metrics in the Prometheus text format
+pub fn render() -> String {
+
+    let mut text = String::new();
+    let in_flight = IN_FLIGHT.load(Ordering::SeqCst);
+    with_metrics(|metrics| {
+        let _ = writeln!(text, "# HELP json2capnp_requests_total Requests by collection, method and status code");
+        let _ = writeln!(text, "# TYPE json2capnp_requests_total counter");
+        for ((collection, method, status), count) in &metrics.requests {
+            let _ = writeln!(text, "json2capnp_requests_total{{collection=\"{}\",method=\"{}\",status=\"{}\"}} {}", collection, method, status, count);
+        }
+
+        let _ = writeln!(text, "# HELP json2capnp_request_duration_seconds Duration of the requests until their response starts");
+        let _ = writeln!(text, "# TYPE json2capnp_request_duration_seconds histogram");
+        for ((collection, method), histogram) in &metrics.durations {
+            let labels = format!("collection=\"{}\",method=\"{}\"", collection, method);
+            let mut cumulative_count = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative_count += count;
+                let _ = writeln!(text, "json2capnp_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, cumulative_count);
+            }
+            let _ = writeln!(text, "json2capnp_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, histogram.count);
+            let _ = writeln!(text, "json2capnp_request_duration_seconds_sum{{{}}} {}", labels, histogram.sum);
+            let _ = writeln!(text, "json2capnp_request_duration_seconds_count{{{}}} {}", labels, histogram.count);
+        }
+
+        let _ = writeln!(text, "# HELP json2capnp_requests_in_flight Requests being handled");
+        let _ = writeln!(text, "# TYPE json2capnp_requests_in_flight gauge");
+        let _ = writeln!(text, "json2capnp_requests_in_flight {}", in_flight);
+
+        for (name, help, bytes) in [
+            ("json2capnp_request_body_bytes_total", "Bytes read from the request bodies, before decoding", &metrics.bytes_read),
+            ("json2capnp_response_body_bytes_total", "Bytes written to the response bodies, after encoding", &metrics.bytes_written)
+        ] {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} counter", name);
+            for (collection, count) in bytes {
+                let _ = writeln!(text, "{}{{collection=\"{}\"}} {}", name, collection, count);
+            }
+        }
+
+        let _ = writeln!(text, "# HELP json2capnp_errors_total Failed requests by collection and error code");
+        let _ = writeln!(text, "# TYPE json2capnp_errors_total counter");
+        for ((collection, code), count) in &metrics.errors {
+            let _ = writeln!(text, "json2capnp_errors_total{{collection=\"{}\",code=\"{}\"}} {}", collection, code, count);
+        }
+
+        let _ = writeln!(text, "# HELP json2capnp_last_write_records Records count of the collection after its last write");
+        let _ = writeln!(text, "# TYPE json2capnp_last_write_records gauge");
+        for (collection, (count, _)) in &metrics.last_writes {
+            let _ = writeln!(text, "json2capnp_last_write_records{{collection=\"{}\"}} {}", collection, count);
+        }
+        let _ = writeln!(text, "# HELP json2capnp_last_write_timestamp_seconds Unix time of the last write of the collection");
+        let _ = writeln!(text, "# TYPE json2capnp_last_write_timestamp_seconds gauge");
+        for (collection, (_, time)) in &metrics.last_writes {
+            let _ = writeln!(text, "json2capnp_last_write_timestamp_seconds{{collection=\"{}\"}} {}", collection, time);
+        }
+    });
+    text
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+
+    #[test]
+    fn metrics() {
+
+        assert_eq!(collection_label("/odTrips/uploads/abc/chunks/0"), "odTrips");
+        assert_eq!(collection_label("/line"), "lines");
+        assert_eq!(collection_label("/jobs/abc"), "jobs");
+        assert_eq!(collection_label("/"), "root");
+        assert_eq!(collection_label("/unknown"), "other");
+
+        // routes of unknown collections are only requested here:
+        let in_flight = InFlight::start();
+        let response = observe("POST", "/unknown", Duration::from_millis(30), rouille::Response::text("12345").with_status_code(400));
+        assert_eq!(response.data.into_reader_and_size().1, Some(5));
+        let mut response = rouille::Response::text("");
+        response.data = rouille::ResponseBody::from_reader(io::Cursor::new(b"stream".to_vec()));
+        let (mut body, size) = observe("GET", "/unknown", Duration::from_secs(400), response).data.into_reader_and_size();
+        assert_eq!(size, None);
+        body.read_to_end(&mut Vec::new()).unwrap();
+        count_body("/unknown", "123".as_bytes()).read_to_end(&mut Vec::new()).unwrap();
+        record_error("unknown", "TEST_ERROR");
+        record_write("test", 42);
+
+        let text = render();
+        drop(in_flight);
+        for line in [
+            "json2capnp_requests_total{collection=\"other\",method=\"POST\",status=\"400\"} 1",
+            "json2capnp_request_duration_seconds_bucket{collection=\"other\",method=\"POST\",le=\"0.025\"} 0",
+            "json2capnp_request_duration_seconds_bucket{collection=\"other\",method=\"POST\",le=\"0.05\"} 1",
+            "json2capnp_request_duration_seconds_bucket{collection=\"other\",method=\"GET\",le=\"300\"} 0",
+            "json2capnp_request_duration_seconds_bucket{collection=\"other\",method=\"GET\",le=\"+Inf\"} 1",
+            "json2capnp_request_body_bytes_total{collection=\"other\"} 3",
+            "json2capnp_response_body_bytes_total{collection=\"other\"} 11",
+            "json2capnp_errors_total{collection=\"other\",code=\"TEST_ERROR\"} 1",
+            "json2capnp_last_write_records{collection=\"test\"} 42",
+        ] {
+            assert!(text.lines().any(|text_line| text_line == line), "missing {}", line);
+        }
+        assert!(text.contains("json2capnp_requests_in_flight "));
+
+    }
+}
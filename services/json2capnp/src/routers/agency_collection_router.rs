@@ -9,61 +9,84 @@
 use crate::agencyCollection_capnp::agency_collection as collection;
 //use crate::my_error::MyError;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::CacheCollection;
 use serde_json;
 use std::io::BufReader;
+use crate::validation::Validator;
 use crate::utils::{ 
-    required_string, 
-    optional_string_json_null_to_empty_str as optional_string, 
     json_boolean_to_i8, 
     empty_str_to_json_null, 
     i8_to_json_boolean 
 };
 
+pub struct AgencyCollection;
+
+impl CacheCollection for AgencyCollection {
+
+    fn name(&self) -> &'static str {
+        "agencies"
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
-    let json_objects = &json["agencies"];
+    let json_objects = validator.array(&json["agencies"], "/agencies");
 
-    let count: usize = json_objects.as_array().unwrap().len();
+    let count: usize = json_objects.len();
     let collection_capnp = message.init_root::<collection::Builder>();
     let mut capnp = collection_capnp.init_agencies(count as u32);
 
-    for i in 0..count {
-        let json_data = &json_objects[i];
+    for (i, json_data) in json_objects.iter().enumerate() {
+        let mut fields = validator.fields(json_data, &format!("/agencies/{}", i));
         let mut capnp_data = capnp.reborrow().get(i as u32);
-        capnp_data.set_uuid(&required_string(json_data.get("id")));
-        capnp_data.set_simulation_uuid(&optional_string(json_data.get("simulation_id")));
-        capnp_data.set_acronym(&optional_string(json_data.get("acronym")));
-        capnp_data.set_name(&optional_string(json_data.get("name")));
-        capnp_data.set_internal_id(&optional_string(json_data.get("internal_id")));
-        capnp_data.set_color(&optional_string(json_data.get("color")));
-        capnp_data.set_description(&optional_string(json_data.get("description")));
-        capnp_data.set_data(&json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
-        capnp_data.set_is_frozen(json_boolean_to_i8(json_data.get("is_frozen").unwrap_or(&json!(null))));
-        capnp_data.set_is_enabled(json_boolean_to_i8(json_data.get("is_enabled").unwrap_or(&json!(null))));
+        capnp_data.set_uuid(fields.required_string("id"));
+        capnp_data.set_simulation_uuid(fields.optional_string("simulation_id"));
+        capnp_data.set_acronym(fields.optional_string("acronym"));
+        capnp_data.set_name(fields.optional_string("name"));
+        capnp_data.set_internal_id(fields.optional_string("internal_id"));
+        capnp_data.set_color(fields.optional_string("color"));
+        capnp_data.set_description(fields.optional_string("description"));
+        capnp_data.set_data(json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
+        capnp_data.set_is_frozen(json_boolean_to_i8(&json_data["is_frozen"]));
+        capnp_data.set_is_enabled(json_boolean_to_i8(&json_data["is_enabled"]));
     }
 
-    serialize_packed::write_message(file, &message)
+    validator.into_result()?;
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
 }
 
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "agencies").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_agencies()?.len() as usize);
 
     for capnp_object in capnp_collection.get_agencies()?.iter() {
         
-        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
+        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
         let object_json : serde_json::Value = json!({
             "id": capnp_object.get_uuid()?,
             "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
@@ -210,12 +233,9 @@ mod tests {
             &routers::agency_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["agencies"], json_compare_data["agencies"]);
 
     }
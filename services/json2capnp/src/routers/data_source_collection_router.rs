@@ -7,70 +7,84 @@
  */
 
 use crate::dataSourceCollection_capnp::data_source_collection as collection;
+use crate::dataSource_capnp::data_source;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::{CacheCollection, CacheObject};
 use serde_json;
 use std::io::BufReader;
+use crate::validation::{Validator, Fields};
 use crate::utils::{ 
-    required_string, 
-    optional_string_json_null_to_empty_str as optional_string, 
     json_boolean_to_i8, 
     empty_str_to_json_null, 
     i8_to_json_boolean 
 };
 
+pub struct DataSourceCollection;
+
+impl CacheCollection for DataSourceCollection {
+
+    fn name(&self) -> &'static str {
+        "dataSources"
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+    fn object(&self) -> Option<CacheObject> {
+        Some(CacheObject {
+            name        : "dataSource",
+            subdirectory: "dataSources",
+            write_object: crate::routers::data_source_router::write_object,
+            read_object : crate::routers::data_source_router::read_object
+        })
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
-    let json_objects = &json["dataSources"];
+    let json_objects = validator.array(&json["dataSources"], "/dataSources");
 
-    let count: usize = json_objects.as_array().unwrap().len();
+    let count: usize = json_objects.len();
     let collection_capnp = message.init_root::<collection::Builder>();
     let mut capnp = collection_capnp.init_data_sources(count as u32);
 
-    for i in 0..count {
-        let json_data = &json_objects[i];
-        let mut capnp_data = capnp.reborrow().get(i as u32);
-        capnp_data.set_uuid(&required_string(json_data.get("id")));
-        capnp_data.set_name(&optional_string(json_data.get("name")));
-        capnp_data.set_shortname(&optional_string(json_data.get("shortname")));
-        capnp_data.set_description(&optional_string(json_data.get("description")));
-        capnp_data.set_type(crate::enum_mappings::data_source_type(&json_data["type"].as_str().unwrap_or("none")));
-        capnp_data.set_data(&json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
-        capnp_data.set_is_frozen(json_boolean_to_i8(json_data.get("is_frozen").unwrap_or(&json!(null))));
+    for (i, json_data) in json_objects.iter().enumerate() {
+        let fields = validator.fields(json_data, &format!("/dataSources/{}", i));
+        write_data_source(capnp.reborrow().get(i as u32), json_data, fields);
     }
 
-    serialize_packed::write_message(file, &message)
+    validator.into_result()?;
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
 }
 
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "dataSources").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_data_sources()?.len() as usize);
 
     for capnp_object in capnp_collection.get_data_sources()?.iter() {
-        
-        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
-        let object_json : serde_json::Value = json!({
-            "id": capnp_object.get_uuid()?,
-            "shortname": empty_str_to_json_null(capnp_object.get_shortname()?),
-            "name": empty_str_to_json_null(capnp_object.get_name()?),
-            "description": empty_str_to_json_null(capnp_object.get_description()?),
-            "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
-            "type": crate::enum_mappings::data_source_type_to_str(&capnp_object.get_type()?),
-            "data": data_attributes
-        });
-        collection_json_vec.push(object_json);
-
+        collection_json_vec.push(read_data_source(capnp_object)?);
     }
     
     let collection_json = json!({
@@ -82,6 +96,36 @@ pub fn read_collection(
 }
 
 
+/// Write a data source, for the collection and the single data_source caches
+pub fn write_data_source(mut capnp_data: data_source::Builder, json_data: &serde_json::Value, mut fields: Fields) {
+    capnp_data.set_uuid(fields.required_string("id"));
+    capnp_data.set_name(fields.optional_string("name"));
+    capnp_data.set_shortname(fields.optional_string("shortname"));
+    capnp_data.set_description(fields.optional_string("description"));
+    capnp_data.set_type(crate::enum_mappings::data_source_type(json_data["type"].as_str().unwrap_or("none")));
+    capnp_data.set_data(json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
+    capnp_data.set_is_frozen(json_boolean_to_i8(&json_data["is_frozen"]));
+}
+
+/// Read a data source, for the collection and the single data_source caches
+pub fn read_data_source(capnp_object: data_source::Reader) -> Result<serde_json::Value, CacheError> {
+
+    let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
+    let object_json : serde_json::Value = json!({
+        "id": capnp_object.get_uuid()?,
+        "shortname": empty_str_to_json_null(capnp_object.get_shortname()?),
+        "name": empty_str_to_json_null(capnp_object.get_name()?),
+        "description": empty_str_to_json_null(capnp_object.get_description()?),
+        "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
+        "type": crate::enum_mappings::data_source_type_to_str(&capnp_object.get_type()?),
+        "data": data_attributes
+    });
+
+    Ok(object_json)
+
+}
+
+
 #[cfg(test)]
 mod tests {
 
@@ -192,12 +236,9 @@ mod tests {
             &routers::data_source_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["dataSources"], json_compare_data["dataSources"]);
 
     }
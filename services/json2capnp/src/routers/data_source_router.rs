@@ -0,0 +1,140 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use crate::dataSource_capnp::data_source;
+use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use serde_json;
+use std::io::BufReader;
+use crate::validation::Validator;
+use crate::routers::{write_object_file, open_object_file};
+use crate::routers::data_source_collection_router::{write_data_source, read_data_source};
+
+pub fn write_object(
+    cache_directory_path: &str,
+    json: &serde_json::Value,
+    config: &serde_json::Value,
+) -> Result<(), CacheError> {
+
+    let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
+
+    let json_object = &json["dataSource"];
+    let fields = validator.fields(json_object, "/dataSource");
+    write_data_source(message.init_root::<data_source::Builder>(), json_object, fields);
+    let object_uuid = validator.fields(json_object, "/dataSource").uuid_format("id");
+
+    validator.into_result()?;
+
+    write_object_file("dataSource", object_uuid, cache_directory_path, &message, config)
+
+}
+
+
+pub fn read_object(
+    object_uuid: &str,
+    cache_directory_path: &str,
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
+
+    let file = open_object_file("dataSource", object_uuid, cache_directory_path)?;
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "dataSources").reader_options())?;
+    let object_json    = read_data_source(message_reader.get_root::<data_source::Reader>()?)?;
+
+    Ok(json!({
+        "dataSource": object_json
+    }))
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use pretty_assertions::{assert_eq};
+    use crate::routers;
+    use std::path::{Path};
+    use std::fs;
+    use rouille::Request;
+
+    #[test]
+    fn data_source() {
+
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test")).unwrap(),
+            "project_shortname"           : "test"
+        });
+
+        let data = r##"
+            {
+                "dataSource": {
+                    "id": "2a3b4c5d-6e7f-4a8b-9c0d-1e2f3a4b5c6d",
+                    "shortname": "survey",
+                    "name": "Survey",
+                    "description": null,
+                    "type": "odTrips",
+                    "is_frozen": true,
+                    "data": { "year": 2018 }
+                }
+            }
+        "##;
+
+        let compare_data = r##"
+            {
+                "dataSource": {
+                    "id": "2a3b4c5d-6e7f-4a8b-9c0d-1e2f3a4b5c6d",
+                    "shortname": "survey",
+                    "name": "Survey",
+                    "description": null,
+                    "type": "odTrips",
+                    "is_frozen": true,
+                    "data": { "year": 2018 }
+                }
+            }
+        "##;
+
+        let json_data : serde_json::Value = serde_json::from_str(data).unwrap();
+        let json_compare_data : serde_json::Value = serde_json::from_str(compare_data).unwrap();
+
+        let request = Request::fake_http(
+            "POST",
+            "/dataSource",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            data.as_bytes().to_vec(),
+        );
+
+        let response = routers::write_object_route(
+            "dataSource",
+            "dataSources",
+            &config,
+            &routers::data_source_router::write_object,
+            &request,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let response = routers::read_object_route(
+            "dataSource",
+            json_data["dataSource"]["id"].as_str().unwrap(),
+            "dataSources",
+            &config,
+            &routers::data_source_router::read_object,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
+        assert_eq!(json_response["data"]["dataSource"], json_compare_data["dataSource"]);
+
+    }
+}
@@ -9,69 +9,80 @@
 use crate::garageCollection_capnp::garage_collection as collection;
 //use crate::my_error::MyError;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::CacheCollection;
 use serde_json;
 use std::io::BufReader;
-use geojson::GeoJson;
-use geobuf;
+use crate::validation::Validator;
 use protobuf::Message;
 
+pub struct GarageCollection;
+
+impl CacheCollection for GarageCollection {
+
+    fn name(&self) -> &'static str {
+        "garages"
+    }
+
+    fn is_feature_collection(&self) -> bool {
+        true
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
-    
-    let geojson_data : GeoJson = GeoJson::from_json_value((json["garages"]).clone()).unwrap();
-
-    match geojson_data {
-        geojson::GeoJson::FeatureCollection(feature_collection) => {
-
-            let features_count = feature_collection.features.len();
-            let collection_capnp = message.init_root::<collection::Builder>();
-            let mut capnp = collection_capnp.init_garages(features_count as u32);
-            for i in 0..features_count {
-                let feature        = &feature_collection.features[i];
-                let mut capnp_data = capnp.reborrow().get(i as u32);
-                let properties = feature.properties.as_ref().unwrap();
-                capnp_data.set_uuid(properties.get("id").unwrap().as_str().unwrap()); // required
-                capnp_data.set_id(properties.get("integer_id").unwrap().as_i64().unwrap() as i32); // required
-                capnp_data.set_agency_uuid(properties.get("agency_id").unwrap().as_str().unwrap()); // required
-                capnp_data.set_name(&crate::utils::json_null_to_empty_str(&properties.get("name").unwrap_or(&json!(null))));
-                capnp_data.set_color(&crate::utils::json_null_to_empty_str(&properties.get("color").unwrap_or(&json!(null))));
-                capnp_data.set_internal_id(&crate::utils::json_null_to_empty_str(&properties.get("internal_id").unwrap_or(&json!(null))));
-                capnp_data.set_description(&crate::utils::json_null_to_empty_str(&properties.get("description").unwrap_or(&json!(null))));
-                capnp_data.set_data(&properties.get("data").unwrap_or(&json!({})).to_string().as_str());
-                capnp_data.set_is_frozen(crate::utils::json_boolean_to_i8(&properties.get("is_frozen").unwrap_or(&json!(null))));
-                capnp_data.set_is_enabled(crate::utils::json_boolean_to_i8(&properties.get("is_enabled").unwrap_or(&json!(null))));
-
-                let geojson_json = json!({
-                    "type": "Feature",
-                    "properties": {},
-                    "geometry": feature.geometry.as_ref().unwrap()
-                });
-                let geobuf = geobuf::encode::Encoder::encode(&geojson_json, 6, 2).unwrap().write_to_bytes().unwrap();
-
-                capnp_data.set_geography(&geobuf);
-                //capnp_data.set_geography(&geojson_json["geometry"].to_string().as_str());
-
-            }
-            serialize_packed::write_message(file, &message)
-        },
-        _ => {
-            Err(capnp::Error::failed(String::from("Garages geojson is invalid, empty or not a FeatureCollection")))
-        }
+    let mut validator = Validator::new();
+
+    let features = validator.features(&json["garages"], "/garages");
+
+    let features_count = features.len();
+    let collection_capnp = message.init_root::<collection::Builder>();
+    let mut capnp = collection_capnp.init_garages(features_count as u32);
+    for (i, feature) in features.iter().enumerate() {
+        let mut capnp_data = capnp.reborrow().get(i as u32);
+        let properties = &feature["properties"];
+        let mut fields = validator.fields(properties, &format!("/garages/features/{}/properties", i));
+        capnp_data.set_uuid(fields.required_string("id"));
+        capnp_data.set_id(fields.required_i32("integer_id"));
+        capnp_data.set_agency_uuid(fields.required_string("agency_id"));
+        capnp_data.set_name(fields.optional_string("name"));
+        capnp_data.set_color(fields.optional_string("color"));
+        capnp_data.set_internal_id(fields.optional_string("internal_id"));
+        capnp_data.set_description(fields.optional_string("description"));
+        capnp_data.set_data(properties.get("data").unwrap_or(&json!({})).to_string().as_str());
+        capnp_data.set_is_frozen(crate::utils::json_boolean_to_i8(&properties["is_frozen"]));
+        capnp_data.set_is_enabled(crate::utils::json_boolean_to_i8(&properties["is_enabled"]));
+
+        let geobuf = validator.geobuf(&feature["geometry"], &format!("/garages/features/{}/geometry", i));
+        capnp_data.set_geography(&geobuf);
     }
 
+    validator.into_result()?;
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
 }
 
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "garages").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_garages()?.len() as usize);
@@ -79,7 +90,7 @@ pub fn read_collection(
     for capnp_object in capnp_collection.get_garages()?.iter() {
         
         let integer_id = capnp_object.get_id() as i32;
-        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
+        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
         let properties_json : serde_json::Value = json!({
             "id": capnp_object.get_uuid()?,
             "integer_id": integer_id,
@@ -261,12 +272,9 @@ mod tests {
             &routers::garage_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["garages"], json_compare_data);
 
     }
@@ -8,13 +8,18 @@
 
 use crate::householdCollection_capnp::household_collection as collection;
 //use crate::my_error::MyError;
+use crate::household_capnp::household;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::{CacheCollection, CacheObject, ItemsStream, ReadItemsFn};
+use crate::json_stream::JsonItems;
 use serde_json;
 use std::io::BufReader;
-use geojson::{Value as GeojsonValue};
+use crate::validation::{Validator, Fields};
 use crate::utils::{ 
-    required_string, 
-    optional_string_json_null_to_empty_str as optional_string, 
+    set_text_list,
+    set_primitive_list,
     json_boolean_to_i8, 
     empty_str_to_json_null, 
     i8_to_json_boolean,
@@ -24,144 +29,208 @@ use crate::utils::{
     json_value_or_null_to_f64_or_minus_one
 };
 
+pub struct HouseholdCollection;
+
+impl CacheCollection for HouseholdCollection {
+
+    fn name(&self) -> &'static str {
+        "households"
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+    fn read_items(&self) -> Option<ReadItemsFn> {
+        Some(read_items)
+    }
+
+    fn items_stream(&self) -> Option<ItemsStream> {
+        Some(ItemsStream {
+            write_item : write_household_item,
+            write_items: write_household_items,
+            read_items
+        })
+    }
+
+    fn object(&self) -> Option<CacheObject> {
+        Some(CacheObject {
+            name        : "household",
+            subdirectory: "households",
+            write_object: crate::routers::household_router::write_object,
+            read_object : crate::routers::household_router::read_object
+        })
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
-    let json_objects = &json["households"];
+    let json_objects = validator.array(&json["households"], "/households");
 
-    let count: usize = json_objects.as_array().unwrap().len();
+    let count: usize = json_objects.len();
     let collection_capnp = message.init_root::<collection::Builder>();
     let mut capnp = collection_capnp.init_households(count as u32);
 
-    for i in 0..count {
-        let mut json_data = json_objects[i].clone();
-
-        let geometry = geojson::Geometry::from_json_value(json_data["home_geography"].clone());
-        let geojson_value = geometry.unwrap().value;
-        let latitude;
-        let longitude;
-        match geojson_value {
-            GeojsonValue::Point(point) => {
-                longitude = (point[0] * 1000000.0).round() as i32;
-                latitude  = (point[1] * 1000000.0).round() as i32;
-            },
-            _ => {
-                longitude = -1;
-                latitude  = -1;
-            }
-        }
+    for (i, json_data) in json_objects.iter().enumerate() {
+        let fields = validator.fields(json_data, &format!("/households/{}", i));
+        write_household(capnp.reborrow().get(i as u32), json_data, fields);
+    }
 
-        let mut capnp_data = capnp.reborrow().get(i as u32);
-        capnp_data.set_uuid(&required_string(json_data.get("id")));
-        capnp_data.set_id(json_data.get("integer_id").unwrap().as_u64().unwrap() as u32); // required
-        capnp_data.set_data_source_uuid(&optional_string(json_data.get("data_source_id")));
-        capnp_data.set_size(json_value_or_null_to_i64_or_minus_one(&json_data.get("size").unwrap_or(&json!(null))) as i8);
-        capnp_data.set_internal_id(&optional_string(json_data.get("internal_id")));
-        capnp_data.set_data(&json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
-        capnp_data.set_is_frozen(json_boolean_to_i8(json_data.get("is_frozen").unwrap_or(&json!(null))));
-
-        capnp_data.set_income_level_group(crate::enum_mappings::household_income_level_group(&json_data["income_level_group"].as_str().unwrap_or("none")));
-        capnp_data.set_category(crate::enum_mappings::household_category(&json_data["category"].as_str().unwrap_or("none")));
-        capnp_data.set_income_level(json_value_or_null_to_i64_or_minus_one(&json_data.get("income_level").unwrap_or(&json!(null))) as i32);
-        capnp_data.set_car_number(json_value_or_null_to_i64_or_minus_one(&json_data.get("car_number").unwrap_or(&json!(null))) as i8);
-        capnp_data.set_expansion_factor(json_value_or_null_to_f64_or_minus_one(&json_data.get("expansion_factor").unwrap_or(&json!(null))) as f32);
-        capnp_data.set_home_latitude(latitude);
-        capnp_data.set_home_longitude(longitude);
-
-        if json_data.get("data") != None && json_data["data"].is_object() && json_data["data"].get("homeNodes") != None && json_data["data"]["homeNodes"].is_array()
-        {
-            let nodes_count = json_data["data"]["homeNodes"].as_array().unwrap().len();
-            capnp_data.reborrow().init_home_nodes_uuids(nodes_count as u32);
-            capnp_data.reborrow().init_home_nodes_travel_times(nodes_count as u32);
-            capnp_data.reborrow().init_home_nodes_distances(nodes_count as u32);
-            for j in 0..nodes_count
-            {
-                capnp_data.reborrow().get_home_nodes_uuids().unwrap().set(j as u32, json_data["data"]["homeNodes"][j].as_str().unwrap());
-                capnp_data.reborrow().get_home_nodes_travel_times().unwrap().set(j as u32, json_data["data"]["homeNodesTravelTimes"][j].as_i64().unwrap() as i16);
-                capnp_data.reborrow().get_home_nodes_distances().unwrap().set(j as u32, json_data["data"]["homeNodesDistances"][j].as_i64().unwrap() as i16);
-            }
+    validator.into_result()?;
 
-             // remove homeNodes and associates:
-            json_data["data"]["homeNodes"].take();
-            json_data["data"]["homeNodesTravelTimes"].take();
-            json_data["data"]["homeNodesDistances"].take();
-        }
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
+}
 
-    }
 
-    serialize_packed::write_message(file, &message)
+/// Write a household in its own message, to be copied in the collection by
+/// write_household_items once all the households of a streamed body are parsed
+pub fn write_household_item(json_data: &serde_json::Value, fields: Fields) -> Result<Vec<u8>, CacheError> {
+    let mut message = ::capnp::message::Builder::new_default();
+    write_household(message.init_root::<household::Builder>(), json_data, fields);
+    let mut item = Vec::new();
+    serialize_packed::write_message(&mut item, &message).map_err(CacheError::from_write_error)?;
+    Ok(item)
 }
 
+pub fn write_household_items(items: Vec<Vec<u8>>, file: &mut std::fs::File) -> Result<(), CacheError> {
+    let mut message = ::capnp::message::Builder::new_default();
+    let collection_capnp = message.init_root::<collection::Builder>();
+    let capnp = collection_capnp.init_households(items.len() as u32);
+
+    for (i, item) in items.into_iter().enumerate() {
+        let item_message = serialize_packed::read_message(item.as_slice(), ::capnp::message::ReaderOptions::new())?;
+        capnp.set_with_caveats(i as u32, item_message.get_root::<household::Reader>()?)?;
+    }
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
+}
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "households").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_households()?.len() as usize);
 
     for capnp_object in capnp_collection.get_households()?.iter() {
-        
-        let mut data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
-        let latitude  = (capnp_object.get_home_latitude() as f64)/1000000.0;
-        let longitude = (capnp_object.get_home_longitude() as f64)/1000000.0;
-
-        if capnp_object.has_home_nodes_uuids()
-        {
-            let mut home_nodes_uuids : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_home_nodes_uuids()?.len() as usize);
-            for home_node_uuid in capnp_object.get_home_nodes_uuids()?.iter() {
-                home_nodes_uuids.push(json!(home_node_uuid.unwrap()));
-            }
-            data_attributes["homeNodes"] = json!(home_nodes_uuids);
+        collection_json_vec.push(read_household(capnp_object)?);
+    }
 
-            let mut home_nodes_travel_times : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_home_nodes_travel_times()?.len() as usize);
-            for home_node_travel_time in capnp_object.get_home_nodes_travel_times()?.iter() {
-                home_nodes_travel_times.push(json!(home_node_travel_time));
-            }
-            data_attributes["homeNodesTravelTimes"] = json!(home_nodes_travel_times);
+    let collection_json = json!({
+        "households": serde_json::Value::Array(collection_json_vec)
+    });
 
-            let mut home_nodes_distances : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_home_nodes_distances()?.len() as usize);
-            for home_node_distance in capnp_object.get_home_nodes_distances()?.iter() {
-                home_nodes_distances.push(json!(home_node_distance));
-            }
-            data_attributes["homeNodesDistances"] = json!(home_nodes_distances);
-        }
+    Ok(collection_json)
 
-        let object_json : serde_json::Value = json!({
-            "id": capnp_object.get_uuid()?,
-            "integer_id": capnp_object.get_id(),
-            "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
-            "data_source_id": empty_str_to_json_null(capnp_object.get_data_source_uuid()?),
-            "size": minus_one_i64_to_null(capnp_object.get_size() as i64),
-            "income_level_group": crate::enum_mappings::household_income_level_group_to_str(&capnp_object.get_income_level_group()?),
-            "category": crate::enum_mappings::household_category_to_str(&capnp_object.get_category()?),
-            "income_level": minus_one_i64_to_null(capnp_object.get_income_level() as i64),
-            "car_number": minus_one_i64_to_null(capnp_object.get_car_number() as i64),
-            "expansion_factor": minus_one_f64_to_null(((capnp_object.get_expansion_factor() as f64)*100000.0).round() / 100000.0), // we must round to 5 decimals so we don't get numbers like 2.10000000345454 for n input value of 2.1
-            "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
-            "data": data_attributes,
-            "home_geography": {
-                "type": "Point",
-                "coordinates": [longitude, latitude]
-            }
-        });
+}
+
+
+/// Read the households one by one, for streamed responses
+pub fn read_items(
+    file: &mut std::fs::File,
+    config: &serde_json::Value,
+) -> Result<JsonItems, CacheError> {
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "households").reader_options())?;
+    let count = message_reader.get_root::<collection::Reader>()?.get_households()?.len() as usize;
 
-        collection_json_vec.push(object_json);
+    Ok(JsonItems::new(count, move |i| {
+        read_household(message_reader.get_root::<collection::Reader>()?.get_households()?.get(i as u32))
+    }))
+
+}
 
+/// Write a household, for the collection and the single household caches
+pub fn write_household(mut capnp_data: household::Builder, json_data: &serde_json::Value, mut fields: Fields) {
+    let (longitude, latitude) = fields.point("home_geography");
+
+    capnp_data.set_uuid(fields.required_string("id"));
+    capnp_data.set_id(fields.required_u32("integer_id"));
+    capnp_data.set_data_source_uuid(fields.optional_string("data_source_id"));
+    capnp_data.set_size(json_value_or_null_to_i64_or_minus_one(&json_data["size"]) as i8);
+    capnp_data.set_internal_id(fields.optional_string("internal_id"));
+    capnp_data.set_data(json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
+    capnp_data.set_is_frozen(json_boolean_to_i8(&json_data["is_frozen"]));
+
+    capnp_data.set_income_level_group(crate::enum_mappings::household_income_level_group(json_data["income_level_group"].as_str().unwrap_or("none")));
+    capnp_data.set_category(crate::enum_mappings::household_category(json_data["category"].as_str().unwrap_or("none")));
+    capnp_data.set_income_level(json_value_or_null_to_i64_or_minus_one(&json_data["income_level"]) as i32);
+    capnp_data.set_car_number(json_value_or_null_to_i64_or_minus_one(&json_data["car_number"]) as i8);
+    capnp_data.set_expansion_factor(json_value_or_null_to_f64_or_minus_one(&json_data["expansion_factor"]) as f32);
+    capnp_data.set_home_latitude(latitude);
+    capnp_data.set_home_longitude(longitude);
+
+    if let Some((nodes_uuids, travel_times, distances)) = fields.accessible_nodes("homeNodes")
+    {
+        let nodes_count = nodes_uuids.len() as u32;
+        set_text_list(capnp_data.reborrow().init_home_nodes_uuids(nodes_count), &nodes_uuids);
+        set_primitive_list(capnp_data.reborrow().init_home_nodes_travel_times(nodes_count), &travel_times);
+        set_primitive_list(capnp_data.reborrow().init_home_nodes_distances(nodes_count), &distances);
     }
+}
 
-    let collection_json = json!({
-        "households": serde_json::Value::Array(collection_json_vec)
+/// Read a household, for the collection and the single household caches
+pub fn read_household(capnp_object: household::Reader) -> Result<serde_json::Value, CacheError> {
+
+    let mut data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
+    let latitude  = (capnp_object.get_home_latitude() as f64)/1000000.0;
+    let longitude = (capnp_object.get_home_longitude() as f64)/1000000.0;
+
+    if capnp_object.has_home_nodes_uuids()
+    {
+        let mut home_nodes_uuids : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_home_nodes_uuids()?.len() as usize);
+        for home_node_uuid in capnp_object.get_home_nodes_uuids()?.iter() {
+            home_nodes_uuids.push(json!(home_node_uuid?));
+        }
+        data_attributes["homeNodes"] = json!(home_nodes_uuids);
+
+        let mut home_nodes_travel_times : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_home_nodes_travel_times()?.len() as usize);
+        for home_node_travel_time in capnp_object.get_home_nodes_travel_times()?.iter() {
+            home_nodes_travel_times.push(json!(home_node_travel_time));
+        }
+        data_attributes["homeNodesTravelTimes"] = json!(home_nodes_travel_times);
+
+        let mut home_nodes_distances : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_home_nodes_distances()?.len() as usize);
+        for home_node_distance in capnp_object.get_home_nodes_distances()?.iter() {
+            home_nodes_distances.push(json!(home_node_distance));
+        }
+        data_attributes["homeNodesDistances"] = json!(home_nodes_distances);
+    }
+
+    let object_json : serde_json::Value = json!({
+        "id": capnp_object.get_uuid()?,
+        "integer_id": capnp_object.get_id(),
+        "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
+        "data_source_id": empty_str_to_json_null(capnp_object.get_data_source_uuid()?),
+        "size": minus_one_i64_to_null(capnp_object.get_size() as i64),
+        "income_level_group": crate::enum_mappings::household_income_level_group_to_str(&capnp_object.get_income_level_group()?),
+        "category": crate::enum_mappings::household_category_to_str(&capnp_object.get_category()?),
+        "income_level": minus_one_i64_to_null(capnp_object.get_income_level() as i64),
+        "car_number": minus_one_i64_to_null(capnp_object.get_car_number() as i64),
+        "expansion_factor": minus_one_f64_to_null(((capnp_object.get_expansion_factor() as f64)*100000.0).round() / 100000.0), // we must round to 5 decimals so we don't get numbers like 2.10000000345454 for n input value of 2.1
+        "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
+        "data": data_attributes,
+        "home_geography": {
+            "type": "Point",
+            "coordinates": [longitude, latitude]
+        }
     });
 
-    Ok(collection_json)
+    Ok(object_json)
 
 }
 
@@ -181,19 +250,19 @@ mod tests {
         let config: serde_json::Value = json!({
             "project_cache_directory_path": fs::canonicalize(Path::new("test")).unwrap(),
             "project_shortname"           : "test",
-            "data_source_uuid"            : "4567-8910"
+            "data_source_uuid"            : "a4f1c2d3-5b6e-4f70-8a9b-0c1d2e3f4a5b"
         });
 
         let data = r##"
             {
-                "data_source_uuid": "4567-8910",
+                "data_source_uuid": "a4f1c2d3-5b6e-4f70-8a9b-0c1d2e3f4a5b",
                 "households": [
                     {
                         "id": "1234-1234",
                         "is_frozen": true,
                         "integer_id": 1234,
                         "internal_id": "h21",
-                        "data_source_id": "4567-8910",
+                        "data_source_id": "a4f1c2d3-5b6e-4f70-8a9b-0c1d2e3f4a5b",
                         "size": 4,
                         "income_level_group": "veryLow",
                         "category": "monoparentalFamily",
@@ -237,14 +306,14 @@ mod tests {
 
             let compare_data = r##"
             {
-                "data_source_uuid": "4567-8910",
+                "data_source_uuid": "a4f1c2d3-5b6e-4f70-8a9b-0c1d2e3f4a5b",
                 "households": [
                     {
                         "id": "1234-1234",
                         "integer_id": 1234,
                         "is_frozen": true,
                         "internal_id": "h21",
-                        "data_source_id": "4567-8910",
+                        "data_source_id": "a4f1c2d3-5b6e-4f70-8a9b-0c1d2e3f4a5b",
                         "size": 4,
                         "income_level_group": "veryLow",
                         "category": "monoparentalFamily",
@@ -331,12 +400,9 @@ mod tests {
             &routers::household_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["households"], json_compare_data["households"]);
 
     }
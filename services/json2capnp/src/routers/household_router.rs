@@ -0,0 +1,166 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use crate::household_capnp::household;
+use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use serde_json;
+use std::io::BufReader;
+use crate::validation::Validator;
+use crate::routers::{write_object_file, open_object_file};
+use crate::routers::household_collection_router::{write_household, read_household};
+
+pub fn write_object(
+    cache_directory_path: &str,
+    json: &serde_json::Value,
+    config: &serde_json::Value,
+) -> Result<(), CacheError> {
+
+    let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
+
+    let json_object = &json["household"];
+    let fields = validator.fields(json_object, "/household");
+    write_household(message.init_root::<household::Builder>(), json_object, fields);
+    let object_uuid = validator.fields(json_object, "/household").uuid_format("id");
+
+    validator.into_result()?;
+
+    write_object_file("household", object_uuid, cache_directory_path, &message, config)
+
+}
+
+
+pub fn read_object(
+    object_uuid: &str,
+    cache_directory_path: &str,
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
+
+    let file = open_object_file("household", object_uuid, cache_directory_path)?;
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "households").reader_options())?;
+    let object_json    = read_household(message_reader.get_root::<household::Reader>()?)?;
+
+    Ok(json!({
+        "household": object_json
+    }))
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use pretty_assertions::{assert_eq};
+    use crate::routers;
+    use std::path::{Path};
+    use std::fs;
+    use rouille::Request;
+
+    #[test]
+    fn household() {
+
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test")).unwrap(),
+            "project_shortname"           : "test"
+        });
+
+        let data = r##"
+            {
+                "household": {
+                    "id": "3b4c5d6e-7f8a-4b9c-8d1e-2f3a4b5c6d7e",
+                    "integer_id": 1234,
+                    "is_frozen": true,
+                    "internal_id": "h21",
+                    "data_source_id": "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f",
+                    "size": 4,
+                    "income_level_group": "veryLow",
+                    "category": "monoparentalFamily",
+                    "income_level": 235000,
+                    "car_number": 2,
+                    "expansion_factor": 23.3,
+                    "home_geography": {
+                        "type": "Point",
+                        "coordinates": [-73.45, 45.47]
+                    },
+                    "data": {
+                        "homeNodes": ["abc", "def"],
+                        "homeNodesTravelTimes": [234, 567],
+                        "homeNodesDistances": [1243, 3453]
+                    }
+                }
+            }
+        "##;
+
+        let compare_data = r##"
+            {
+                "household": {
+                    "id": "3b4c5d6e-7f8a-4b9c-8d1e-2f3a4b5c6d7e",
+                    "integer_id": 1234,
+                    "is_frozen": true,
+                    "internal_id": "h21",
+                    "data_source_id": "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f",
+                    "size": 4,
+                    "income_level_group": "veryLow",
+                    "category": "monoparentalFamily",
+                    "income_level": 235000,
+                    "car_number": 2,
+                    "expansion_factor": 23.3,
+                    "home_geography": {
+                        "type": "Point",
+                        "coordinates": [-73.45, 45.47]
+                    },
+                    "data": {
+                        "homeNodes": ["abc", "def"],
+                        "homeNodesTravelTimes": [234, 567],
+                        "homeNodesDistances": [1243, 3453]
+                    }
+                }
+            }
+        "##;
+
+        let json_data : serde_json::Value = serde_json::from_str(data).unwrap();
+        let json_compare_data : serde_json::Value = serde_json::from_str(compare_data).unwrap();
+
+        let request = Request::fake_http(
+            "POST",
+            "/household",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            data.as_bytes().to_vec(),
+        );
+
+        let response = routers::write_object_route(
+            "household",
+            "households",
+            &config,
+            &routers::household_router::write_object,
+            &request,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let response = routers::read_object_route(
+            "household",
+            json_data["household"]["id"].as_str().unwrap(),
+            "households",
+            &config,
+            &routers::household_router::read_object,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
+        assert_eq!(json_response["data"]["household"], json_compare_data["household"]);
+
+    }
+}
@@ -7,88 +7,113 @@
  */
 
 use crate::lineCollection_capnp::line_collection as collection;
+use crate::lineCollection_capnp::line;
 //use crate::my_error::MyError;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::{CacheCollection, CacheObject, ReadItemsFn};
+use crate::json_stream::JsonItems;
 use serde_json;
 use std::io::BufReader;
+use std::fs;
+use std::path::Path;
+use tracing::trace;
+use crate::routers::line_router;
+use crate::validation::Validator;
 use crate::utils::{ 
-    required_string, 
-    optional_string_json_null_to_empty_str as optional_string, 
     json_boolean_to_i8, 
     empty_str_to_json_null, 
     i8_to_json_boolean 
 };
 
+pub struct LineCollection;
+
+impl CacheCollection for LineCollection {
+
+    fn name(&self) -> &'static str {
+        "lines"
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+    fn read_items(&self) -> Option<ReadItemsFn> {
+        Some(read_items)
+    }
+
+    fn object(&self) -> Option<CacheObject> {
+        Some(CacheObject {
+            name        : "line",
+            subdirectory: "lines",
+            write_object: crate::routers::line_router::write_object,
+            read_object : crate::routers::line_router::read_object
+        })
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
-    let json_objects = &json["lines"];
+    let json_objects = validator.array(&json["lines"], "/lines");
 
-    let count: usize = json_objects.as_array().unwrap().len();
+    let count: usize = json_objects.len();
     let collection_capnp = message.init_root::<collection::Builder>();
     let mut capnp = collection_capnp.init_lines(count as u32);
 
-    for i in 0..count {
-        let json_data = &json_objects[i];
-        let uuid: &str = &required_string(json_data.get("id"));
+    for (i, json_data) in json_objects.iter().enumerate() {
+        let mut fields = validator.fields(json_data, &format!("/lines/{}", i));
+        let uuid: &str = fields.required_string("id");
 
-        println!("Writing cache for line uuid {}", uuid);
+        trace!("Writing cache for line uuid {}", uuid);
 
         let mut capnp_data = capnp.reborrow().get(i as u32);
-        capnp_data.set_uuid(&required_string(json_data.get("id")));
-        capnp_data.set_agency_uuid(&required_string(json_data.get("agency_id")));
-        capnp_data.set_shortname(&optional_string(json_data.get("shortname")));
-        capnp_data.set_longname(&optional_string(json_data.get("longname")));
-        capnp_data.set_internal_id(&optional_string(json_data.get("internal_id")));
-        capnp_data.set_category(&optional_string(json_data.get("category")));
-        capnp_data.set_mode(&required_string(json_data.get("mode")));
-        capnp_data.set_color(&optional_string(json_data.get("color")));
-        capnp_data.set_description(&optional_string(json_data.get("description")));
-        capnp_data.set_data(&json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
-        capnp_data.set_is_frozen(json_boolean_to_i8(json_data.get("is_frozen").unwrap_or(&json!(null))));
-        capnp_data.set_is_enabled(json_boolean_to_i8(json_data.get("is_enabled").unwrap_or(&json!(null))));
-        capnp_data.set_is_autonomous(json_boolean_to_i8(json_data.get("is_autonomous").unwrap_or(&json!(null))));
-        capnp_data.set_allow_same_line_transfers(json_boolean_to_i8(json_data.get("allow_same_line_transfers").unwrap_or(&json!(null))));
+        capnp_data.set_uuid(uuid);
+        capnp_data.set_agency_uuid(fields.required_string("agency_id"));
+        capnp_data.set_shortname(fields.optional_string("shortname"));
+        capnp_data.set_longname(fields.optional_string("longname"));
+        capnp_data.set_internal_id(fields.optional_string("internal_id"));
+        capnp_data.set_category(fields.optional_string("category"));
+        capnp_data.set_mode(fields.required_string("mode"));
+        capnp_data.set_color(fields.optional_string("color"));
+        capnp_data.set_description(fields.optional_string("description"));
+        capnp_data.set_data(json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
+        capnp_data.set_is_frozen(json_boolean_to_i8(&json_data["is_frozen"]));
+        capnp_data.set_is_enabled(json_boolean_to_i8(&json_data["is_enabled"]));
+        capnp_data.set_is_autonomous(json_boolean_to_i8(&json_data["is_autonomous"]));
+        capnp_data.set_allow_same_line_transfers(json_boolean_to_i8(&json_data["allow_same_line_transfers"]));
     }
 
-    serialize_packed::write_message(file, &message)
+    validator.into_result()?;
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
 }
 
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "lines").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_lines()?.len() as usize);
 
     for capnp_object in capnp_collection.get_lines()?.iter() {
-        
-        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
-        let object_json : serde_json::Value = json!({
-            "id": capnp_object.get_uuid()?,
-            "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
-            "agency_id": capnp_object.get_agency_uuid()?,
-            "shortname": capnp_object.get_shortname()?,
-            "longname": empty_str_to_json_null(capnp_object.get_longname()?),
-            "category": empty_str_to_json_null(capnp_object.get_category()?),
-            "mode": capnp_object.get_mode()?,
-            "color": empty_str_to_json_null(capnp_object.get_color()?),
-            "description": empty_str_to_json_null(capnp_object.get_description()?),
-            "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
-            "is_enabled": i8_to_json_boolean(capnp_object.get_is_enabled()),
-            "is_autonomous": i8_to_json_boolean(capnp_object.get_is_autonomous()),
-            "allow_same_line_transfers": i8_to_json_boolean(capnp_object.get_allow_same_line_transfers()),
-            "data": data_attributes
-        });
-        collection_json_vec.push(object_json);
+
+        collection_json_vec.push(read_line(capnp_object)?);
 
     }
 
@@ -101,6 +126,82 @@ pub fn read_collection(
 }
 
 
+/// Read the lines one by one, for streamed responses
+pub fn read_items(
+    file: &mut std::fs::File,
+    config: &serde_json::Value,
+) -> Result<JsonItems, CacheError> {
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "lines").reader_options())?;
+    let count = message_reader.get_root::<collection::Reader>()?.get_lines()?.len() as usize;
+
+    Ok(JsonItems::new(count, move |i| {
+        read_line(message_reader.get_root::<collection::Reader>()?.get_lines()?.get(i as u32))
+    }))
+
+}
+
+/// Read a line of the collection, without its schedules
+pub fn read_line(capnp_object: line::Reader) -> Result<serde_json::Value, CacheError> {
+
+    let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
+    Ok(json!({
+        "id": capnp_object.get_uuid()?,
+        "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
+        "agency_id": capnp_object.get_agency_uuid()?,
+        "shortname": capnp_object.get_shortname()?,
+        "longname": empty_str_to_json_null(capnp_object.get_longname()?),
+        "category": empty_str_to_json_null(capnp_object.get_category()?),
+        "mode": capnp_object.get_mode()?,
+        "color": empty_str_to_json_null(capnp_object.get_color()?),
+        "description": empty_str_to_json_null(capnp_object.get_description()?),
+        "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
+        "is_enabled": i8_to_json_boolean(capnp_object.get_is_enabled()),
+        "is_autonomous": i8_to_json_boolean(capnp_object.get_is_autonomous()),
+        "allow_same_line_transfers": i8_to_json_boolean(capnp_object.get_allow_same_line_transfers()),
+        "data": data_attributes
+    }))
+
+}
+
+
+/// Rebuild the line collection from the line object files in the lines
+/// subdirectory, sorted by uuid. Returns the number of lines.
+pub fn reconcile_collection(
+    directory_path: &Path,
+    file: &mut std::fs::File,
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
+
+    let lines_directory_path = directory_path.join("lines");
+    let mut lines_uuids: Vec<String> = match fs::read_dir(&lines_directory_path) {
+        Ok(entries) => entries.filter_map(|entry| {
+            let file_name = entry.ok()?.file_name().into_string().ok()?;
+            let line_uuid = file_name.strip_prefix("line_")?.strip_suffix(".capnpbin")?;
+            Some(line_uuid.to_string())
+        }).collect(),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(error) => return Err(CacheError::Io(error))
+    };
+    lines_uuids.sort_unstable();
+
+    let lines_directory_path = lines_directory_path.to_str().unwrap_or("");
+    let mut lines_json = Vec::with_capacity(lines_uuids.len());
+    for line_uuid in lines_uuids.iter() {
+        let mut line_json = line_router::read_object(line_uuid, lines_directory_path, config)?["line"].take();
+        if let Some(line_object) = line_json.as_object_mut() {
+            line_object.remove("scheduleByServiceId");
+        }
+        lines_json.push(line_json);
+    }
+
+    let count = lines_json.len();
+    write_collection(&json!({ "lines": lines_json }), file, config)?;
+    Ok(json!({ "lines": count }))
+
+}
+
+
 #[cfg(test)]
 mod tests {
 
@@ -244,15 +345,102 @@ mod tests {
             &routers::line_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["lines"], json_compare_data["lines"]);
 
     }
+
+    #[test]
+    fn line_collection_sync() {
+
+        let _ = fs::remove_dir_all("test/projects/line_sync");
+        fs::create_dir_all("test/projects/line_sync").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/line_sync")).unwrap(),
+            "project_shortname"           : "line_sync"
+        });
+        let agency_uuid = "0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d";
+        let lines_uuids = ["1a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d", "2a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d"];
+
+        let post = |url: &str, body: serde_json::Value| {
+            let request = Request::fake_http(
+                "POST",
+                url,
+                vec![(
+                    "Content-Type".to_owned(),
+                    "application/json; charset=utf-8".to_owned(),
+                )],
+                body.to_string().into_bytes(),
+            );
+            if url == "/line" {
+                routers::write_object_route("line", "lines", &config, &routers::line_router::write_object, &request)
+            } else {
+                routers::reconcile_collection_route("lines", "lines", &config, &routers::line_collection_router::reconcile_collection, &request)
+            }
+        };
+        let line_shortnames = || {
+            let mut file = fs::File::open("test/projects/line_sync/lines.capnpbin").unwrap();
+            let lines = routers::line_collection_router::read_collection(&mut file, &config).unwrap();
+            lines["lines"].as_array().unwrap().iter().map(|line| line["shortname"].as_str().unwrap().to_string()).collect::<Vec<String>>()
+        };
+
+        // the line collection is updated with the line, without its schedules:
+        let response = post("/line", json!({
+            "update_collection": true,
+            "line": { "id": lines_uuids[1], "agency_id": agency_uuid, "shortname": "2", "mode": "bus", "scheduleByServiceId": {} }
+        }));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(line_shortnames(), vec!["2"]);
+
+        // the mode is required to update the collection:
+        let response = post("/line", json!({
+            "update_collection": true,
+            "line": { "id": lines_uuids[0], "agency_id": agency_uuid, "shortname": "1" }
+        }));
+        assert_eq!(response.status_code, 400);
+
+        let response = post("/line", json!({
+            "line": { "id": lines_uuids[0], "agency_id": agency_uuid, "shortname": "1", "mode": "tram" }
+        }));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(line_shortnames(), vec!["2"]);
+
+        let response = post("/lines/reconcile", json!({}));
+        assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
+        assert_eq!(json_response["data"], json!({ "lines": 2 }));
+        assert_eq!(line_shortnames(), vec!["1", "2"]);
+
+        // the line file is not replaced when the line collection cannot be patched:
+        fs::write("test/projects/line_sync/lines.capnpbin", "not a capnp message").unwrap();
+        let response = post("/line", json!({
+            "update_collection": true,
+            "line": { "id": lines_uuids[1], "agency_id": agency_uuid, "shortname": "2b", "mode": "bus" }
+        }));
+        assert_ne!(response.status_code, 200);
+        let line = routers::line_router::read_object(lines_uuids[1], "test/projects/line_sync/lines", &config).unwrap();
+        assert_eq!(line["line"]["shortname"], "2");
+
+        // a line in a custom cache directory updates the line collection of this directory:
+        let response = post("/line", json!({
+            "cache_directory_path": "custom",
+            "update_collection": true,
+            "line": { "id": lines_uuids[0], "agency_id": agency_uuid, "shortname": "1c", "mode": "bus" }
+        }));
+        assert_eq!(response.status_code, 200);
+        let mut custom_config = config.clone();
+        custom_config["custom_subdirectory_path"] = json!("custom");
+        let response = routers::read_collection_route("lines", "lines", &custom_config, &routers::line_collection_router::read_collection);
+        assert_eq!(response.status_code, 200);
+        let lines = routers::tests::response_json(response)["data"]["lines"].clone();
+        assert_eq!(lines.as_array().unwrap().iter().map(|line| line["shortname"].clone()).collect::<Vec<_>>(), vec![json!("1c")]);
+        let response = routers::read_collection_stream_route("lines", "lines", false, &custom_config, routers::line_collection_router::read_items);
+        assert_eq!(routers::tests::response_json(response)["data"]["lines"], lines);
+
+    }
 }
 
 
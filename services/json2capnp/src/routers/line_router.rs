@@ -8,15 +8,18 @@
 
 use crate::line_capnp::{line};
 //use crate::my_error::MyError;
-use std::fs::File;
-use std::path::Path;
 //use std::fs;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::routers::{cache_directory, line_collection_router, prepare_object_file, open_object_file, prepare_patch_collection_file, PatchedCollectionFile};
+use crate::collection_patch::CollectionPatch;
+use std::path::Path;
 use serde_json;
 use std::io::BufReader;
+use crate::validation::{Validator, child_pointer};
 use crate::utils::{ 
-    required_string, 
-    optional_string_json_null_to_empty_str as optional_string, 
+    set_primitive_list,
     json_boolean_to_i8, 
     empty_str_to_json_null, 
     i8_to_json_boolean,
@@ -31,226 +34,180 @@ use crate::utils::{
 pub fn write_object(
     cache_directory_path: &str,
     json: &serde_json::Value,
-    _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<(), CacheError> {
 
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
     let json_object = &json["line"];
-    let object_uuid = match json_object.get("id") {
-        Some(json_object_id) => {
-            json_object_id.as_str().unwrap()
-        },
-        // TODO: deal with empty or invalid uuids here with custom error:
-        None => {
-            ""
-        }
-    };
-
-    // TODO: deal with empty or invalid uuids here with custom error:
-    /*if (object_uuid == "")
-    {
-        Err(MyError::new("line uuid is invalid or empty"))
-    }*/
-
-    let object_file_path_name = format!("{}/line_{}.capnpbin", cache_directory_path, object_uuid);
-    let path = Path::new(&object_file_path_name);
-
-    let file_check = File::create(&path);
-
-    let file = file_check.unwrap();
-
-    /* TODO: deal with error when creating object file using uuid */
+    let mut fields = validator.fields(json_object, "/line");
+    let object_uuid = fields.required_uuid("id");
+    let update_collection = json["update_collection"].as_bool().unwrap_or(false);
     
     let mut capnp_data = message.init_root::<line::Builder>();
 
-    capnp_data.set_uuid(&required_string(json_object.get("id")));
-    capnp_data.set_agency_uuid(&required_string(json_object.get("agency_id")));
-    capnp_data.set_shortname(&optional_string(json_object.get("shortname")));
-    capnp_data.set_longname(&optional_string(json_object.get("longname")));
-    capnp_data.set_internal_id(&optional_string(json_object.get("internal_id")));
-    capnp_data.set_category(&optional_string(json_object.get("category")));
-    capnp_data.set_mode(&optional_string(json_object.get("mode")));
-    capnp_data.set_color(&optional_string(json_object.get("color")));
-    capnp_data.set_description(&optional_string(json_object.get("description")));
-    capnp_data.set_data(&json_object.get("data").unwrap_or(&json!({})).to_string().as_str());
-    capnp_data.set_is_frozen(json_boolean_to_i8(json_object.get("is_frozen").unwrap_or(&json!(null))));
-    capnp_data.set_is_enabled(json_boolean_to_i8(json_object.get("is_enabled").unwrap_or(&json!(null))));
-    capnp_data.set_is_autonomous(json_boolean_to_i8(json_object.get("is_autonomous").unwrap_or(&json!(null))));
-    capnp_data.set_allow_same_line_transfers(json_boolean_to_i8(json_object.get("allow_same_line_transfers").unwrap_or(&json!(null))));
-
-    let schedules_json : std::collections::HashMap<String, serde_json::Value> = serde_json::from_str(&json_object.get("scheduleByServiceId").unwrap_or(&json!({})).to_string().as_str()).unwrap();
-    let count_schedules : usize = schedules_json.keys().len();
+    capnp_data.set_uuid(object_uuid);
+    capnp_data.set_agency_uuid(fields.required_string("agency_id"));
+    capnp_data.set_shortname(fields.optional_string("shortname"));
+    capnp_data.set_longname(fields.optional_string("longname"));
+    capnp_data.set_internal_id(fields.optional_string("internal_id"));
+    capnp_data.set_category(fields.optional_string("category"));
+    // the mode is required in the line collection:
+    capnp_data.set_mode(if update_collection { fields.required_string("mode") } else { fields.optional_string("mode") });
+    capnp_data.set_color(fields.optional_string("color"));
+    capnp_data.set_description(fields.optional_string("description"));
+    capnp_data.set_data(json_object.get("data").unwrap_or(&json!({})).to_string().as_str());
+    capnp_data.set_is_frozen(json_boolean_to_i8(&json_object["is_frozen"]));
+    capnp_data.set_is_enabled(json_boolean_to_i8(&json_object["is_enabled"]));
+    capnp_data.set_is_autonomous(json_boolean_to_i8(&json_object["is_autonomous"]));
+    capnp_data.set_allow_same_line_transfers(json_boolean_to_i8(&json_object["allow_same_line_transfers"]));
+
+    let empty_schedules = serde_json::Map::new();
+    let schedules_json = match &json_object["scheduleByServiceId"] {
+        serde_json::Value::Null => &empty_schedules,
+        serde_json::Value::Object(schedules) => schedules,
+        _ => {
+            validator.error("/line/scheduleByServiceId", "must be an object");
+            &empty_schedules
+        }
+    };
+    let count_schedules : usize = schedules_json.len();
 
     let mut capnp_schedules = capnp_data.reborrow().init_schedules(count_schedules as u32);
 
-    let mut i : u32 = 0;
-    for (_ /* service_id */, json_data) in &schedules_json
+    for (i, (service_id, json_data)) in schedules_json.iter().enumerate()
     {
+        let schedule_pointer = child_pointer("/line/scheduleByServiceId", service_id);
+        let mut fields = validator.fields(json_data, &schedule_pointer);
 
         let mut capnp_schedule_data = capnp_schedules.reborrow().get(i as u32);
         
-        capnp_schedule_data.set_uuid(&required_string(json_data.get("id")));
-        capnp_schedule_data.set_service_uuid(&required_string(json_data.get("service_id")));
-        capnp_schedule_data.set_periods_group_shortname(&optional_string(json_data.get("periods_group_shortname")));
-        capnp_schedule_data.set_allow_seconds_based_schedules(json_boolean_to_i8(json_data.get("allow_seconds_based_schedules").unwrap_or(&json!(null))));
-        capnp_schedule_data.set_is_frozen(json_boolean_to_i8(json_data.get("is_frozen").unwrap_or(&json!(null))));
+        capnp_schedule_data.set_uuid(fields.required_string("id"));
+        capnp_schedule_data.set_service_uuid(fields.required_string("service_id"));
+        capnp_schedule_data.set_periods_group_shortname(fields.optional_string("periods_group_shortname"));
+        capnp_schedule_data.set_allow_seconds_based_schedules(json_boolean_to_i8(&json_data["allow_seconds_based_schedules"]));
+        capnp_schedule_data.set_is_frozen(json_boolean_to_i8(&json_data["is_frozen"]));
   
-        let count_periods: usize = json_data["periods"].as_array().unwrap().len();
-        let mut capnp_periods    = capnp_schedule_data.init_periods(count_periods as u32);
-        let periods_json         = &json_data["periods"];
+        let periods_json         = validator.array(&json_data["periods"], &format!("{}/periods", schedule_pointer));
+        let mut capnp_periods    = capnp_schedule_data.init_periods(periods_json.len() as u32);
     
-        for j in 0..count_periods
+        for (j, json_data) in periods_json.iter().enumerate()
         {
-            let json_data             = &periods_json[j];
+            let period_pointer        = format!("{}/periods/{}", schedule_pointer, j);
+            let mut fields            = validator.fields(json_data, &period_pointer);
             let mut capnp_period_data = capnp_periods.reborrow().get(j as u32);
-            let mut custom_start_at_seconds : i32 = -1;
-            let custom_start_at_str = json_data.get("custom_start_at_str").unwrap_or(&json!(null));
-            if !custom_start_at_str.is_null()
-            {
-                let custom_start_at_seconds_option = time_str_to_seconds_since_midnight(custom_start_at_str.as_str().unwrap());
-                if custom_start_at_seconds_option != None {
-                    custom_start_at_seconds = custom_start_at_seconds_option.unwrap() as i32;
-                }
-            }
-            let mut custom_end_at_seconds : i32 = -1;
-            let custom_end_at_str = json_data.get("custom_end_at_str").unwrap_or(&json!(null));
-            if !custom_end_at_str.is_null()
-            {
-                let custom_end_at_seconds_option = time_str_to_seconds_since_midnight(custom_end_at_str.as_str().unwrap());
-                if custom_end_at_seconds_option != None {
-                    custom_end_at_seconds = custom_end_at_seconds_option.unwrap() as i32;
-                }
-            }
+            let custom_start_at_seconds = time_str_to_seconds_since_midnight(fields.optional_string("custom_start_at_str")).map_or(-1, |seconds| seconds as i32);
+            let custom_end_at_seconds = time_str_to_seconds_since_midnight(fields.optional_string("custom_end_at_str")).map_or(-1, |seconds| seconds as i32);
 
-            capnp_period_data.set_period_shortname(&optional_string(json_data.get("period_shortname")));
-            capnp_period_data.set_outbound_path_uuid(&optional_string(json_data.get("outbound_path_id")));
-            capnp_period_data.set_inbound_path_uuid(&optional_string(json_data.get("inbound_path_id")));
+            capnp_period_data.set_period_shortname(fields.optional_string("period_shortname"));
+            capnp_period_data.set_outbound_path_uuid(fields.optional_string("outbound_path_id"));
+            capnp_period_data.set_inbound_path_uuid(fields.optional_string("inbound_path_id"));
             capnp_period_data.set_custom_start_at_seconds(custom_start_at_seconds);
             capnp_period_data.set_custom_end_at_seconds(custom_end_at_seconds);
-            capnp_period_data.set_start_at_seconds((json_value_or_null_to_f64_or_minus_one(&json_data.get("start_at_hour").unwrap()) * 3600.0) as i32);
-            capnp_period_data.set_end_at_seconds((json_value_or_null_to_f64_or_minus_one(&json_data.get("end_at_hour").unwrap()) * 3600.0) as i32);
-            capnp_period_data.set_interval_seconds(json_value_or_null_to_i64_or_minus_one(&json_data.get("interval_seconds").unwrap_or(&json!(null))) as i16);
-            capnp_period_data.set_number_of_units(json_value_or_null_to_i64_or_minus_one(&json_data.get("number_of_units").unwrap_or(&json!(null))) as i16);
-            capnp_period_data.set_is_frozen(json_boolean_to_i8(json_data.get("is_frozen").unwrap_or(&json!(null))));
-            capnp_period_data.set_uuid(&optional_string(json_data.get("id"))); // period.id is required in the db. However, in the genetic algorithm, we don't need it.
-
-            let count_trips: usize = json_data.get("trips").unwrap_or(&json!([])).as_array().unwrap().len();
-            let mut capnp_trips    = capnp_period_data.init_trips(count_trips as u32);
-            let trips_json         = &json_data["trips"];
-
-            for k in 0..count_trips
+            capnp_period_data.set_start_at_seconds((json_value_or_null_to_f64_or_minus_one(&json_data["start_at_hour"]) * 3600.0) as i32);
+            capnp_period_data.set_end_at_seconds((json_value_or_null_to_f64_or_minus_one(&json_data["end_at_hour"]) * 3600.0) as i32);
+            capnp_period_data.set_interval_seconds(json_value_or_null_to_i64_or_minus_one(&json_data["interval_seconds"]) as i16);
+            capnp_period_data.set_number_of_units(json_value_or_null_to_i64_or_minus_one(&json_data["number_of_units"]) as i16);
+            capnp_period_data.set_is_frozen(json_boolean_to_i8(&json_data["is_frozen"]));
+            capnp_period_data.set_uuid(fields.optional_string("id")); // period.id is required in the db. However, in the genetic algorithm, we don't need it.
+
+            let trips_json = if json_data["trips"].is_null() { &[] } else { validator.array(&json_data["trips"], &format!("{}/trips", period_pointer)) };
+            let mut capnp_trips    = capnp_period_data.init_trips(trips_json.len() as u32);
+
+            for (k, json_data) in trips_json.iter().enumerate()
             {
-                let json_data           = &trips_json[k];
+                let mut fields          = validator.fields(json_data, &format!("{}/trips/{}", period_pointer, k));
                 let mut capnp_trip_data = capnp_trips.reborrow().get(k as u32);
 
-                capnp_trip_data.set_uuid(&required_string(json_data.get("id")));
-                capnp_trip_data.set_path_uuid(&required_string(json_data.get("path_id")));
-                capnp_trip_data.set_departure_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data.get("departure_time_seconds").unwrap_or(&json!(null))) as i32);
-                capnp_trip_data.set_arrival_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data.get("arrival_time_seconds").unwrap_or(&json!(null))) as i32);
-                capnp_trip_data.set_block_uuid(&optional_string(json_data.get("block_id")));
-                capnp_trip_data.set_total_capacity(json_value_or_null_to_i64_or_minus_one(&json_data.get("total_capacity").unwrap_or(&json!(null))) as i16);
-                capnp_trip_data.set_seated_capacity(json_value_or_null_to_i64_or_minus_one(&json_data.get("seated_capacity").unwrap_or(&json!(null))) as i16);
-                capnp_trip_data.set_is_frozen(json_boolean_to_i8(json_data.get("is_frozen").unwrap_or(&json!(null))));
-
-                let nodes_arrival_time_seconds = json_data.get("node_arrival_times_seconds").unwrap().as_array();
-                if nodes_arrival_time_seconds != None {
-                    let nodes_arrival_time_seconds = nodes_arrival_time_seconds.unwrap();
-                    let nodes_count: usize = nodes_arrival_time_seconds.len();
-
-                    capnp_trip_data.reborrow().init_node_arrival_times_seconds(nodes_count as u32);
-                    for l in 0..nodes_count
-                    {
-                        capnp_trip_data.reborrow().get_node_arrival_times_seconds().unwrap().set(l as u32, json_value_or_null_to_i64_or_minus_one(&nodes_arrival_time_seconds[l]) as i32);
-                    }
-                }
-                else
-                {
-                    capnp_trip_data.reborrow().init_node_arrival_times_seconds(0);
-                }
+                capnp_trip_data.set_uuid(fields.required_string("id"));
+                capnp_trip_data.set_path_uuid(fields.required_string("path_id"));
+                capnp_trip_data.set_departure_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data["departure_time_seconds"]) as i32);
+                capnp_trip_data.set_arrival_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data["arrival_time_seconds"]) as i32);
+                capnp_trip_data.set_block_uuid(fields.optional_string("block_id"));
+                capnp_trip_data.set_total_capacity(json_value_or_null_to_i64_or_minus_one(&json_data["total_capacity"]) as i16);
+                capnp_trip_data.set_seated_capacity(json_value_or_null_to_i64_or_minus_one(&json_data["seated_capacity"]) as i16);
+                capnp_trip_data.set_is_frozen(json_boolean_to_i8(&json_data["is_frozen"]));
 
-                let nodes_departure_time_seconds = json_data.get("node_departure_times_seconds").unwrap().as_array();
-                if nodes_departure_time_seconds != None {
-                    let nodes_departure_time_seconds = nodes_departure_time_seconds.unwrap();
-                    let nodes_count: usize = nodes_departure_time_seconds.len();
+                let nodes_arrival_time_seconds: Vec<i32> = json_data["node_arrival_times_seconds"].as_array().map_or(vec![], |values| values.iter().map(|value| json_value_or_null_to_i64_or_minus_one(value) as i32).collect());
+                set_primitive_list(capnp_trip_data.reborrow().init_node_arrival_times_seconds(nodes_arrival_time_seconds.len() as u32), &nodes_arrival_time_seconds);
 
-                    capnp_trip_data.reborrow().init_node_departure_times_seconds(nodes_count as u32);
-                    for l in 0..nodes_count
-                    {
-                        capnp_trip_data.reborrow().get_node_departure_times_seconds().unwrap().set(l as u32, json_value_or_null_to_i64_or_minus_one(&nodes_departure_time_seconds[l]) as i32);
-                    }
-                }
-                else
-                {
-                    capnp_trip_data.reborrow().init_node_departure_times_seconds(0);
-                }
+                let nodes_departure_time_seconds: Vec<i32> = json_data["node_departure_times_seconds"].as_array().map_or(vec![], |values| values.iter().map(|value| json_value_or_null_to_i64_or_minus_one(value) as i32).collect());
+                set_primitive_list(capnp_trip_data.reborrow().init_node_departure_times_seconds(nodes_departure_time_seconds.len() as u32), &nodes_departure_time_seconds);
 
-                let nodes_can_board = json_data.get("nodes_can_board").unwrap().as_array();
-                if nodes_can_board != None {
-                    let nodes_can_board = nodes_can_board.unwrap();
-                    let nodes_count: usize = nodes_can_board.len();
-
-                    capnp_trip_data.reborrow().init_nodes_can_board(nodes_count as u32);
-                    for l in 0..nodes_count
-                    {
-                        capnp_trip_data.reborrow().get_nodes_can_board().unwrap().set(l as u32, crate::utils::json_boolean_to_i8(&nodes_can_board[l]));
-                    }
-                }
-                else
-                {
-                    capnp_trip_data.reborrow().init_nodes_can_board(0);
-                }
+                let nodes_can_board: Vec<i8> = json_data["nodes_can_board"].as_array().map_or(vec![], |values| values.iter().map(json_boolean_to_i8).collect());
+                set_primitive_list(capnp_trip_data.reborrow().init_nodes_can_board(nodes_can_board.len() as u32), &nodes_can_board);
 
-                let nodes_can_unboard = json_data.get("nodes_can_unboard").unwrap().as_array();
-                if nodes_can_unboard != None {
-                    let nodes_can_unboard = nodes_can_unboard.unwrap();
-                    let nodes_count: usize = nodes_can_unboard.len();
-
-                    capnp_trip_data.reborrow().init_nodes_can_unboard(nodes_count as u32);
-                    for l in 0..nodes_count
-                    {
-                        capnp_trip_data.reborrow().get_nodes_can_unboard().unwrap().set(l as u32, crate::utils::json_boolean_to_i8(&nodes_can_unboard[l]));
-                    }
-                }
-                else
-                {
-                    capnp_trip_data.reborrow().init_nodes_can_unboard(0);
-                }
+                let nodes_can_unboard: Vec<i8> = json_data["nodes_can_unboard"].as_array().map_or(vec![], |values| values.iter().map(json_boolean_to_i8).collect());
+                set_primitive_list(capnp_trip_data.reborrow().init_nodes_can_unboard(nodes_can_unboard.len() as u32), &nodes_can_unboard);
 
             }
 
         }
-        i += 1;
 
     }
 
-    serialize_packed::write_message(file, &message)
+    validator.into_result()?;
+
+    let object_file = prepare_object_file("line", object_uuid, cache_directory_path, &message, config)?;
+
+    // patch the line collection before replacing the line file, so a failed patch leaves both files untouched:
+    if update_collection {
+        let collection_directory_path = cache_directory(
+            config,
+            json.get("cache_directory_path").unwrap_or(&serde_json::Value::Null),
+            json.get("data_source_uuid").unwrap_or(&serde_json::Value::Null),
+            None
+        )?;
+        let collection_file = update_line_collection(json_object, &collection_directory_path, config)?;
+        object_file.commit()?;
+        collection_file.commit()?;
+    } else {
+        object_file.commit()?;
+    }
+
+    Ok(())
+
+}
+
+/// Upsert the line in the line collection of collection_directory_path (the
+/// cache directory of POST /lines for the same request parameters), without
+/// its schedules. The patched collection is returned uncommitted.
+fn update_line_collection(json_object: &serde_json::Value, collection_directory_path: &Path, config: &serde_json::Value) -> Result<PatchedCollectionFile, CacheError> {
+
+    let mut line_json = json_object.clone();
+    if let Some(line_object) = line_json.as_object_mut() {
+        line_object.remove("scheduleByServiceId");
+    }
+    let patch_json = json!({ "upsert": [line_json] });
+    let patch = CollectionPatch::from_json(&patch_json)?;
+
+    let collection_path = collection_directory_path.join("lines.capnpbin");
+    prepare_patch_collection_file(
+        "lines",
+        &collection_path,
+        false,
+        &patch,
+        config,
+        &line_collection_router::read_collection,
+        &line_collection_router::write_collection
+    )
 
 }
 
 
 pub fn read_object(
-    object_uuid: &String,
+    object_uuid: &str,
     cache_directory_path: &str,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let object_file_path_name = format!("{}/line_{}.capnpbin", cache_directory_path, object_uuid);
-    let path = Path::new(&object_file_path_name);
-    let file = File::open(&path);
-    
-    let file = match file {
-        Ok(f) => f,
-        Err(e) => {
-            println!("Error opening line file {}: {}", object_file_path_name, e);
-            return Err(capnp::Error::failed(String::from("Cannot read line file")));
-        }
-    };
+    let file = open_object_file("line", object_uuid, cache_directory_path)?;
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "lines").reader_options())?;
     let capnp_object = message_reader.get_root::<line::Reader>()?;
     
-    let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
+    let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
     let mut schedules   : serde_json::Value = json!({});
 
     if capnp_object.has_schedules()
@@ -718,12 +675,9 @@ mod tests {
             &routers::line_router::read_object,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["line"], json_compare_data["line"]);
 
         // Test non-existing line
@@ -735,13 +689,10 @@ mod tests {
             &routers::line_router::read_object,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
+        assert_eq!(response.status_code, 404);
 
-        // TODO Return code should be 404 Not found
-        assert_eq!(response.status_code, 200);
+        let json_response = routers::tests::response_json(response);
+        assert_eq!(json_response["errorCode"], "NOT_FOUND");
         assert!(json_response["data"].is_null());
 
     }
@@ -7,11 +7,31 @@
  */
 
 use rouille;
+use capnp::serialize_packed;
 use serde_json::json;
-use std::error::Error;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::io::{BufReader, Read};
+use std::thread;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{debug, warn};
+use crate::errors::CacheError;
+use crate::cache_paths;
+use crate::cache_files::{self, AtomicFile, WriteLock};
+use crate::collection_patch::{CollectionPatch, PatchSummary};
+use crate::collections::{ItemsStream, ReadItemsFn};
+use crate::json_stream;
+use crate::limits::{Limits, LimitedBody};
+use crate::shards::{self, ShardFilesWriter, ShardManifest, ShardsWriter};
+use crate::uploads;
+use crate::jobs::{self, JobEvents};
+use crate::content_encoding::{self, ContentEncoding};
+use crate::cache_summary;
+use crate::logging;
+use crate::metrics;
+use crate::shutdown;
+use crate::validation::{Validator, rebase_pointers};
 
 pub mod od_trip_collection_router;
 pub mod node_router;
@@ -29,41 +49,172 @@ pub mod unit_collection_router;
 pub mod service_collection_router;
 pub mod scenario_collection_router;
 pub mod zone_collection_router;
+pub mod zone_router;
+pub mod place_router;
+pub mod person_router;
+pub mod household_router;
+pub mod od_trip_router;
+pub mod data_source_router;
 
 pub mod taxi_point_collection_router;
 
-fn failed_response(cache_name: &str, error: &dyn Error) -> rouille::Response {
+fn error_json(mut json: serde_json::Value, error: &CacheError) -> serde_json::Value {
 
-    let json = json!({
-        "status"   : "fail",
-        "cacheName": cache_name,
-        "error"    : error.to_string()
-    });
+    json["status"]    = json!("fail");
+    json["error"]     = json!(error.to_string());
+    json["errorCode"] = json!(error.code());
+    metrics::record_error(json["cacheName"].as_str().unwrap_or(""), error.code());
+    if let CacheError::Validation(errors) = error {
+        json["errors"] = json!(errors);
+    }
+    json
+
+}
 
-    let data = serde_json::to_string(&json).unwrap();
+fn error_response(json: serde_json::Value, error: &CacheError) -> rouille::Response {
+
+    let json = error_json(json, error);
 
     rouille::Response {
-        status_code: 200,
+        status_code: error.status_code(),
         headers    : vec![("Content-Type".into(), "application/json; charset=utf-8".into())],
-        data       : rouille::ResponseBody::from_data(data),
+        data       : rouille::ResponseBody::from_string(json.to_string()),
         upgrade    : None
     }
 
 }
 
-fn success_response(cache_name: &str, json_data: Option<&serde_json::Value>) -> rouille::Response {
+fn failed_response(cache_name: &str, error: &CacheError) -> rouille::Response {
+    error_response(json!({ "cacheName": cache_name }), error)
+}
+
+pub fn project_failed_response(requested_project_shortname: Option<&str>, error: &CacheError) -> rouille::Response {
+    error_response(json!({ "project": requested_project_shortname }), error)
+}
+
+/// Response to the requests received once the server is shutting down
+pub fn shutting_down_response() -> rouille::Response {
+    error_response(json!({}), &CacheError::ShuttingDown(String::from("The server is shutting down")))
+}
+
+fn json_body(request: &rouille::Request, limits: &Limits) -> Result<serde_json::Value, CacheError> {
+    let body_reader = json_body_reader(request, limits)?;
+    serde_json::from_reader(BufReader::new(body_reader)).map_err(json_stream::json_body_error)
+}
+
+/// Get the body of a json request, to be parsed as it is read, decoded
+/// according to its Content-Encoding and limited to the max_body_size of the
+/// collection. The body of the request of a job is read from its file (see
+/// jobs::take_body).
+fn json_body_reader<'a>(request: &'a rouille::Request, limits: &Limits) -> Result<LimitedBody<Box<dyn Read + 'a>>, CacheError> {
+    if !matches!(request.header("Content-Type"), Some(content_type) if content_type.starts_with("application/json")) {
+        return Err(CacheError::InvalidPayload(String::from("Invalid json body: the request didn't have a JSON content type")));
+    }
+    let encoding = content_encoding::request_encoding(request)?;
+    // the decoded size of an encoded body is only known once read:
+    if encoding == ContentEncoding::Identity {
+        limits.check_content_length(request.header("Content-Length"))?;
+    }
+    let body_reader: Box<dyn Read + 'a> = match jobs::take_body() {
+        Some(job_body) => Box::new(job_body),
+        None => Box::new(request.data().ok_or_else(|| CacheError::InvalidPayload(String::from("Invalid json body: the body was already read")))?)
+    };
+    Ok(limits.limit_body(encoding.decoder(metrics::count_body(&request.url(), body_reader))?))
+}
+
+/// Remove the /projects/{shortname} prefix from the request url, if any, and
+/// return the project shortname with the unprefixed request
+pub fn remove_project_prefix(request: &rouille::Request) -> (Option<String>, Option<rouille::Request>) {
+
+    let url = request.url();
+    let project_shortname = match url.strip_prefix("/projects/") {
+        Some(prefixed_url) => prefixed_url.split('/').next().unwrap_or(""),
+        None => return (None, None)
+    };
+    if project_shortname.is_empty() {
+        return (None, None);
+    }
+
+    let prefix = format!("/projects/{}", project_shortname);
+    match request.remove_prefix(&prefix) {
+        Some(unprefixed_request) => (Some(project_shortname.to_string()), Some(unprefixed_request)),
+        None => (None, None)
+    }
+
+}
+
+/// Apply the project requested in the json body, if any, to the config. The
+/// body project must match the project requested in the url, if any.
+fn body_project_config(config: &serde_json::Value, json: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+
+    let mut config = config.clone();
+    let body_project_shortname = match json.get("project").and_then(|project| project.as_str()) {
+        Some(body_project_shortname) => body_project_shortname,
+        None => return Ok(config)
+    };
+    if config["project_shortname"].as_str() == Some(body_project_shortname) {
+        return Ok(config);
+    }
+    if config["project_requested"].as_bool() == Some(true) {
+        return Err(CacheError::Conflict(format!("Project {} in body does not match project {} in url", body_project_shortname, config["project_shortname"].as_str().unwrap_or(""))));
+    }
+
+    let project_cache_directory_path = match config["projects"].get(body_project_shortname) {
+        Some(project_cache_directory_path) => project_cache_directory_path.clone(),
+        None => return Err(CacheError::NotFound(format!("Unknown project {}", body_project_shortname)))
+    };
+    config["project_cache_directory_path"] = project_cache_directory_path;
+    config["project_shortname"] = json!(body_project_shortname);
+    Ok(config)
+
+}
+
+/// Check that the project requested in the json body, if any, is the project
+/// of the url (or the default project), for the requests using the project
+/// before their body is parsed
+fn check_body_project(config: &serde_json::Value, json: &serde_json::Value) -> Result<(), CacheError> {
+    match json.get("project").and_then(|project| project.as_str()) {
+        Some(body_project_shortname) if config["project_shortname"].as_str() != Some(body_project_shortname) => {
+            Err(CacheError::Conflict(format!("Project {} in body must be requested in the url (project parameter or /projects/{} prefix) for streamed writes", body_project_shortname, body_project_shortname)))
+        },
+        _ => Ok(())
+    }
+}
+
+fn string_parameter<'a>(parameter_name: &str, value: &'a serde_json::Value) -> Result<Option<&'a str>, CacheError> {
+    match value {
+        serde_json::Value::Null => Ok(None),
+        serde_json::Value::String(value) => Ok(Some(value)),
+        _ => Err(CacheError::InvalidParameter(format!("Invalid {}: must be a string", parameter_name)))
+    }
+}
+
+/// Get the cache directory from the cache_directory_path and data_source_uuid
+/// parameters, confined to the project cache directory
+fn cache_directory(config: &serde_json::Value, custom_subdirectory_path: &serde_json::Value, data_source_uuid: &serde_json::Value, subdirectory: Option<&str>) -> Result<PathBuf, CacheError> {
+
+    let project_cache_directory_path = Path::new(config["project_cache_directory_path"].as_str().unwrap_or(""));
+    let cache_directory_path = cache_paths::cache_directory_path(
+        project_cache_directory_path,
+        string_parameter("data_source_uuid", data_source_uuid)?,
+        string_parameter("cache_directory_path", custom_subdirectory_path)?,
+        subdirectory
+    )?;
+    cache_paths::confine(project_cache_directory_path, &cache_directory_path)
+
+}
+
+/// The data is moved into the response, so a collection read as a whole is
+/// not copied
+fn success_response(cache_name: &str, json_data: Option<serde_json::Value>) -> rouille::Response {
     
     let mut json = json!({
         "status"   : "success",
         "cacheName": cache_name
     });
 
-    match json_data {
-        Some(json_value) => {
-            json["data"] = json_value.clone();
-        }
-
-        _ => ()
+    if let Some(json_value) = json_data {
+        json["data"] = json_value;
     }
 
     rouille::Response {
@@ -75,235 +226,1790 @@ fn success_response(cache_name: &str, json_data: Option<&serde_json::Value>) ->
 
 }
 
-pub fn write_collection_route(collection_name: &str, cache_file_name: &str, config: &serde_json::Value, write_fn: &dyn Fn(&serde_json::Value, &mut std::fs::File, &serde_json::Value) -> ::std::result::Result<(), capnp::Error>, request: &rouille::Request) -> rouille::Response {
+fn object_file_path(object_name: &str, object_uuid: &str, cache_directory_path: &str) -> PathBuf {
+    Path::new(cache_directory_path).join(format!("{}_{}.capnpbin", object_name, object_uuid))
+}
+
+/// Write the cache file of a single object: {cache_directory_path}/{object_name}_{object_uuid}.capnpbin
+pub fn write_object_file<A: capnp::message::Allocator>(object_name: &str, object_uuid: &str, cache_directory_path: &str, message: &capnp::message::Builder<A>, config: &serde_json::Value) -> Result<(), CacheError> {
+    prepare_object_file(object_name, object_uuid, cache_directory_path, message, config)?.commit()?;
+    Ok(())
+}
+
+/// Lock the cache file of a single object and write it to its temporary
+/// file, which replaces the cache file once committed
+pub fn prepare_object_file<A: capnp::message::Allocator>(object_name: &str, object_uuid: &str, cache_directory_path: &str, message: &capnp::message::Builder<A>, config: &serde_json::Value) -> Result<AtomicFile, CacheError> {
+
+    let path = object_file_path(object_name, object_uuid, cache_directory_path);
+    let mut file = AtomicFile::create_locked(&path, &WriteLock::from_config(config))?;
+    serialize_packed::write_message(file.file_mut(), message).map_err(CacheError::from_write_error)?;
+    Ok(file)
+
+}
+
+/// Open the cache file of a single object
+pub fn open_object_file(object_name: &str, object_uuid: &str, cache_directory_path: &str) -> Result<File, CacheError> {
+
+    match File::open(object_file_path(object_name, object_uuid, cache_directory_path)) {
+        Ok(file) => Ok(file),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            Err(CacheError::NotFound(format!("{} {} not found in cache", object_name, object_uuid)))
+        },
+        Err(error) => Err(CacheError::Io(error))
+    }
+
+}
+
+pub fn write_collection_route(collection_name: &str, cache_file_name: &str, config: &serde_json::Value, write_fn: &dyn Fn(&serde_json::Value, &mut std::fs::File, &serde_json::Value) -> Result<(), CacheError>, request: &rouille::Request) -> rouille::Response {
 
-    let json : serde_json::Value   = try_or_400!(rouille::input::json_input(request));
+    let json : serde_json::Value   = match json_body(request, &Limits::from_config(config, collection_name)) {
+        Ok(json) => json,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let config = match body_project_config(config, &json) {
+        Ok(config) => config,
+        Err(error) => return project_failed_response(json["project"].as_str(), &error)
+    };
+    let config = &config;
     let json_cache_directory_path  = json.get("cache_directory_path").unwrap_or(&serde_json::Value::Null);
     let json_data_source_uuid      = json.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
 
-    let cache_directory_path;
+    let directory_path = match cache_directory(config, json_cache_directory_path, json_data_source_uuid, None) {
+        Ok(directory_path) => directory_path,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    debug!("cache_directory_path: {}", directory_path.display());
 
-    if json_cache_directory_path.is_null() // no custom path
-    {
-        if !json_data_source_uuid.is_null()
-        {
-            cache_directory_path = format!("{}/dataSources/{}", config["project_cache_directory_path"].as_str().unwrap(), json_data_source_uuid.as_str().unwrap());
-        }
-        else
-        {
-            cache_directory_path = format!("{}", config["project_cache_directory_path"].as_str().unwrap());
-        }
-        println!("cache_directory_path: {}", cache_directory_path);
+    if let Err(error) = fs::create_dir_all(&directory_path) {
+        return failed_response(collection_name, &CacheError::Io(error));
+    }
+    let absolute_path = directory_path.join(format!("{}.capnpbin", cache_file_name));
+
+    // lock the cache file against concurrent writers and write to a temporary file,
+    // the previous cache file is only replaced if the conversion succeeds:
+    let file = AtomicFile::create_locked(&absolute_path, &WriteLock::from_config(config));
+
+    match file {
+        Ok(mut file) => match write_fn(&json, file.file_mut(), config).and_then(|()| file.commit().map_err(CacheError::from)) {
+            Err(error) => failed_response(collection_name, &error),
+            Ok(()) => {
+                let items = &json[collection_name];
+                if let Some(items) = items["features"].as_array().or_else(|| items.as_array()) {
+                    jobs::progress(items.len());
+                    metrics::record_write(collection_name, items.len());
+                    logging::record_count(items.len());
+                }
+                success_response(collection_name, None)
+            }
+        },
+        Err(error) => failed_response(collection_name, &error)
+    }
+
+}
+
+/// Upsert and remove items by uuid in a cache collection
+pub fn patch_collection_route(collection_name: &str, cache_file_name: &str, feature_collection: bool, config: &serde_json::Value, read_fn: &dyn Fn(&mut std::fs::File, &serde_json::Value) -> Result<serde_json::Value, CacheError>, write_fn: &dyn Fn(&serde_json::Value, &mut std::fs::File, &serde_json::Value) -> Result<(), CacheError>, request: &rouille::Request) -> rouille::Response {
+
+    let json : serde_json::Value   = match json_body(request, &Limits::from_config(config, collection_name)) {
+        Ok(json) => json,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let config = match body_project_config(config, &json) {
+        Ok(config) => config,
+        Err(error) => return project_failed_response(json["project"].as_str(), &error)
+    };
+    let config = &config;
+    let patch = match CollectionPatch::from_json(&json) {
+        Ok(patch) => patch,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let json_cache_directory_path  = json.get("cache_directory_path").unwrap_or(&serde_json::Value::Null);
+    let json_data_source_uuid      = json.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+
+    let directory_path = match cache_directory(config, json_cache_directory_path, json_data_source_uuid, None) {
+        Ok(directory_path) => directory_path,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    if let Err(error) = fs::create_dir_all(&directory_path) {
+        return failed_response(collection_name, &CacheError::Io(error));
+    }
+    let absolute_path = directory_path.join(format!("{}.capnpbin", cache_file_name));
+
+    match patch_collection_file(collection_name, &absolute_path, feature_collection, &patch, config, read_fn, write_fn) {
+        Err(error) => failed_response(collection_name, &error),
+        Ok(summary) => success_response(collection_name, Some(json!(summary)))
+    }
+
+}
+
+/// Apply a patch to a collection cache file, which is created if it does not
+/// exist yet. The file stays locked from reading the current collection to
+/// replacing it.
+pub fn patch_collection_file(collection_name: &str, path: &Path, feature_collection: bool, patch: &CollectionPatch, config: &serde_json::Value, read_fn: &dyn Fn(&mut std::fs::File, &serde_json::Value) -> Result<serde_json::Value, CacheError>, write_fn: &dyn Fn(&serde_json::Value, &mut std::fs::File, &serde_json::Value) -> Result<(), CacheError>) -> Result<PatchSummary, CacheError> {
+    prepare_patch_collection_file(collection_name, path, feature_collection, patch, config, read_fn, write_fn)?.commit()
+}
+
+/// A patched collection cache file, written to its temporary file and still
+/// locked, which replaces the cache file once committed
+pub struct PatchedCollectionFile {
+    file: AtomicFile,
+    summary: PatchSummary,
+}
+
+impl PatchedCollectionFile {
+    pub fn commit(self) -> Result<PatchSummary, CacheError> {
+        self.file.commit()?;
+        Ok(self.summary)
+    }
+}
+
+/// Lock a collection cache file and write the patched collection to its
+/// temporary file, without replacing the cache file yet
+pub fn prepare_patch_collection_file(collection_name: &str, path: &Path, feature_collection: bool, patch: &CollectionPatch, config: &serde_json::Value, read_fn: &dyn Fn(&mut std::fs::File, &serde_json::Value) -> Result<serde_json::Value, CacheError>, write_fn: &dyn Fn(&serde_json::Value, &mut std::fs::File, &serde_json::Value) -> Result<(), CacheError>) -> Result<PatchedCollectionFile, CacheError> {
+
+    let mut file = AtomicFile::create_locked(path, &WriteLock::from_config(config))?;
+
+    let mut collection_json = match File::open(path) {
+        Ok(mut current_file) => read_fn(&mut current_file, config)?,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            if feature_collection {
+                json!({ collection_name: { "type": "FeatureCollection", "features": [] } })
+            } else {
+                json!({ collection_name: [] })
+            }
+        },
+        Err(error) => return Err(CacheError::Io(error))
+    };
+
+    let items_pointer = if feature_collection { format!("/{}/features", collection_name) } else { format!("/{}", collection_name) };
+    let summary = match collection_json.pointer_mut(&items_pointer).and_then(|items| items.as_array_mut()) {
+        Some(items) => patch.apply(items),
+        None => return Err(CacheError::InvalidPayload(format!("{} cache has no {} items", collection_name, items_pointer)))
+    };
+
+    match write_fn(&collection_json, file.file_mut(), config) {
+        Err(error) => Err(summary.upsert_errors(error, &items_pointer)),
+        Ok(()) => Ok(PatchedCollectionFile { file, summary })
+    }
+
+}
+
+/// Rebuild a collection cache file from the cache files of its objects
+pub fn reconcile_collection_route(collection_name: &str, cache_file_name: &str, config: &serde_json::Value, reconcile_fn: &dyn Fn(&Path, &mut std::fs::File, &serde_json::Value) -> Result<serde_json::Value, CacheError>, request: &rouille::Request) -> rouille::Response {
+
+    let json : serde_json::Value   = match json_body(request, &Limits::from_config(config, collection_name)) {
+        Ok(json) => json,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let config = match body_project_config(config, &json) {
+        Ok(config) => config,
+        Err(error) => return project_failed_response(json["project"].as_str(), &error)
+    };
+    let config = &config;
+    let json_cache_directory_path  = json.get("cache_directory_path").unwrap_or(&serde_json::Value::Null);
+    let json_data_source_uuid      = json.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+
+    let directory_path = match cache_directory(config, json_cache_directory_path, json_data_source_uuid, None) {
+        Ok(directory_path) => directory_path,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    if let Err(error) = fs::create_dir_all(&directory_path) {
+        return failed_response(collection_name, &CacheError::Io(error));
+    }
+    let absolute_path = directory_path.join(format!("{}.capnpbin", cache_file_name));
+
+    let file = AtomicFile::create_locked(&absolute_path, &WriteLock::from_config(config));
+    match file {
+        Ok(mut file) => match reconcile_fn(&directory_path, file.file_mut(), config).and_then(|json_value| file.commit().map(|()| json_value).map_err(CacheError::from)) {
+            Err(error) => failed_response(collection_name, &error),
+            Ok(json_value) => success_response(collection_name, Some(json_value))
+        },
+        Err(error) => failed_response(collection_name, &error)
+    }
+
+}
+
+/// Parse a body with the items of a collection, converting each item as
+/// soon as it is parsed and writing the packed items in shard files. Returns
+/// the other members of the body and the validation errors of the items.
+fn items_body(collection_name: &str, config: &serde_json::Value, items_stream: &ItemsStream, shard_files: &mut ShardFilesWriter, request: &rouille::Request) -> Result<(serde_json::Value, Validator), CacheError> {
+
+    let mut validator = Validator::new();
+    let items_pointer = format!("/{}", collection_name);
+    let body_reader = json_body_reader(request, &Limits::from_config(config, collection_name))?;
+    let (body, array_found) = json_stream::read_items(BufReader::new(body_reader), collection_name, &mut |i, item_json| {
+        let fields = validator.fields(&item_json, &format!("{}/{}", items_pointer, i));
+        shard_files.push((items_stream.write_item)(&item_json, fields)?)?;
+        jobs::progress(i + 1);
+        Ok(())
+    })?;
+    if !array_found {
+        validator.error(&items_pointer, "must be an array");
+    }
+    Ok((serde_json::Value::Object(body), validator))
+
+}
+
+/// Write a collection while its body is parsed, without building the whole
+/// json body in memory: each item is converted as soon as it is parsed, and
+/// written in a shard file of the .uploads directory of the project every
+/// shard_size items, since the cache directory is only known once the body is
+/// parsed. The shard files then replace the current shards (see shards) or
+/// are appended to them. The project is the project of the url: a different
+/// project in the body is rejected, so the shard files stay in the project
+/// directory (they are copied if the cache directory is on another file
+/// system).
+pub fn write_collection_stream_route(collection_name: &str, cache_file_name: &str, config: &serde_json::Value, items_stream: &ItemsStream, append: bool, request: &rouille::Request) -> rouille::Response {
+
+    let staging_directory_path = match cache_directory(config, &serde_json::Value::Null, &serde_json::Value::Null, None)
+        .and_then(|project_directory_path| Ok(uploads::create_upload_directory(&project_directory_path)?)) {
+        Ok((_, staging_directory_path)) => staging_directory_path,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let response = write_staged_collection(collection_name, cache_file_name, config, items_stream, append, &staging_directory_path, request);
+    let _ = fs::remove_dir_all(&staging_directory_path);
+    response
+
+}
+
+fn write_staged_collection(collection_name: &str, cache_file_name: &str, config: &serde_json::Value, items_stream: &ItemsStream, append: bool, staging_directory_path: &Path, request: &rouille::Request) -> rouille::Response {
+
+    let mut shard_files = ShardFilesWriter::new(staging_directory_path, cache_file_name, Limits::from_config(config, collection_name).shard_size, items_stream);
+    let (json, shards, validator) = match items_body(collection_name, config, items_stream, &mut shard_files, request)
+        .and_then(|(json, validator)| Ok((json, shard_files.finish()?, validator))) {
+        Ok(body) => body,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+
+    if let Err(error) = check_body_project(config, &json) {
+        return project_failed_response(json["project"].as_str(), &error);
+    }
+    let json_cache_directory_path  = json.get("cache_directory_path").unwrap_or(&serde_json::Value::Null);
+    let json_data_source_uuid      = json.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+
+    let directory_path = match cache_directory(config, json_cache_directory_path, json_data_source_uuid, None) {
+        Ok(directory_path) => directory_path,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    if let Err(error) = fs::create_dir_all(&directory_path) {
+        return failed_response(collection_name, &CacheError::Io(error));
     }
-    else
-    {
-        if !json_data_source_uuid.is_null()
-        {
-            cache_directory_path = format!("{}/dataSources/{}/{}", config["project_cache_directory_path"].as_str().unwrap(), json_data_source_uuid.as_str().unwrap(), json_cache_directory_path.as_str().unwrap(), );
+
+    let write_shards = || -> Result<ShardManifest, CacheError> {
+        validator.into_result()?;
+        let mut writer = ShardsWriter::lock(&directory_path, cache_file_name, items_stream, &WriteLock::from_config(config))?;
+        if append {
+            writer.keep_current_shards(config)?;
+        }
+        for shard in &shards {
+            writer.add_file(&staging_directory_path.join(&shard.file_name), shard.count)?;
         }
-        else
-        {
-            cache_directory_path = format!("{}/{}", config["project_cache_directory_path"].as_str().unwrap(), json_cache_directory_path.as_str().unwrap());
+        writer.commit()
+    };
+    match write_shards() {
+        Err(error) => failed_response(collection_name, &error),
+        Ok(manifest) => {
+            metrics::record_write(collection_name, manifest.count());
+            logging::record_count(manifest.count());
+            success_response(collection_name, Some(json!({ "count": manifest.count(), "shards": manifest.shards.len() })))
         }
-        println!("cache_directory_path_custom: {}", cache_directory_path);
     }
 
+}
 
-    let directory_path = Path::new(&cache_directory_path);
-    let absolute_directory_path = String::from(directory_path.to_str().unwrap());
-    let create_directories = fs::create_dir_all(&absolute_directory_path);
-    match create_directories {
-        Ok(()) => {},
-        Err(error) => {
-            println!("{}", error);
-        }
+/// Upsert and remove items by uuid in a sharded collection. All the shards
+/// are rewritten.
+pub fn patch_collection_shards_route(collection_name: &str, cache_file_name: &str, config: &serde_json::Value, items_stream: &ItemsStream, read_items: ReadItemsFn, request: &rouille::Request) -> rouille::Response {
+
+    let json : serde_json::Value   = match json_body(request, &Limits::from_config(config, collection_name)) {
+        Ok(json) => json,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let config = match body_project_config(config, &json) {
+        Ok(config) => config,
+        Err(error) => return project_failed_response(json["project"].as_str(), &error)
+    };
+    let config = &config;
+    let patch = match CollectionPatch::from_json(&json) {
+        Ok(patch) => patch,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let json_cache_directory_path  = json.get("cache_directory_path").unwrap_or(&serde_json::Value::Null);
+    let json_data_source_uuid      = json.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+
+    let directory_path = match cache_directory(config, json_cache_directory_path, json_data_source_uuid, None) {
+        Ok(directory_path) => directory_path,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    if let Err(error) = fs::create_dir_all(&directory_path) {
+        return failed_response(collection_name, &CacheError::Io(error));
     }
 
-    let collection_file_path_name;
-    
-    if cache_file_name == "households" || cache_file_name == "persons" || cache_file_name == "odTrips" {
-        collection_file_path_name = format!("{}/{}.capnpbin", absolute_directory_path, cache_file_name);
-        println!("{}", collection_file_path_name);
-    } else {
-        collection_file_path_name = format!("{}/{}.capnpbin", absolute_directory_path, cache_file_name);
+    let patch_shards = || -> Result<PatchSummary, CacheError> {
+        let mut writer = ShardsWriter::lock(&directory_path, cache_file_name, items_stream, &WriteLock::from_config(config))?;
+        let mut items = match shards::read_items(&directory_path, cache_file_name, read_items, config)? {
+            Some(items) => items.into_values()?,
+            None => Vec::new()
+        };
+        let summary = patch.apply(&mut items);
+
+        let items_pointer = format!("/{}", collection_name);
+        let mut validator = Validator::new();
+        let items = items.iter().enumerate()
+            .map(|(i, item)| (items_stream.write_item)(item, validator.fields(item, &format!("{}/{}", items_pointer, i))))
+            .collect::<Result<Vec<Vec<u8>>, CacheError>>();
+        validator.into_result()
+            .and(items)
+            .and_then(|items| writer.write(items, Limits::from_config(config, collection_name).shard_size))
+            .map_err(|error| summary.upsert_errors(error, &items_pointer))?;
+        let count = writer.commit()?.count();
+        metrics::record_write(collection_name, count);
+        logging::record_count(count);
+        Ok(summary)
+    };
+
+    match patch_shards() {
+        Err(error) => failed_response(collection_name, &error),
+        Ok(summary) => success_response(collection_name, Some(json!(summary)))
     }
-    let absolute_path = Path::new(&collection_file_path_name);
-    //println!("collection_file_path_name: {}", collection_file_path_name);
-    let file = File::create(&absolute_path);
 
-    if file.is_ok()
-    {
-        match &write_fn(&json, &mut file.unwrap(), config) {
-            Err(error) => return failed_response(collection_name, error),
-            Ok(()) => return success_response(collection_name, None)
+}
+
+/// Open a chunked upload session of a sharded collection, in the cache
+/// directory requested by the body. Chunks are then uploaded to
+/// /{collection}/uploads/{upload_id}/chunks/{chunk}.
+pub fn open_upload_route(collection_name: &str, cache_file_name: &str, config: &serde_json::Value, request: &rouille::Request) -> rouille::Response {
+
+    let json : serde_json::Value   = match json_body(request, &Limits::from_config(config, collection_name)) {
+        Ok(json) => json,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let config = match body_project_config(config, &json) {
+        Ok(config) => config,
+        Err(error) => return project_failed_response(json["project"].as_str(), &error)
+    };
+    let config = &config;
+    let json_cache_directory_path  = json.get("cache_directory_path").unwrap_or(&serde_json::Value::Null);
+    let json_data_source_uuid      = json.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+
+    let mut validator = Validator::new();
+    let append = match &json["append"] {
+        serde_json::Value::Null => false,
+        serde_json::Value::Bool(append) => *append,
+        _ => {
+            validator.error("/append", "must be a boolean");
+            false
         }
-    }
-    else
-    {
-        failed_response(collection_name, &file.unwrap_err())
+    };
+    let upload_id = validator.into_result()
+        .and_then(|()| cache_directory(config, json_cache_directory_path, json_data_source_uuid, None))
+        .and_then(|directory_path| uploads::open(collection_name, cache_file_name, &directory_path, append));
+    match upload_id {
+        Err(error) => failed_response(collection_name, &error),
+        Ok(upload_id) => success_response(collection_name, Some(json!({ "uploadId": upload_id })))
     }
 
 }
 
-pub fn read_collection_route(collection_name: &str, cache_file_name: &str, config: &serde_json::Value, read_fn: &dyn Fn(&mut std::fs::File, &serde_json::Value) -> ::std::result::Result<serde_json::Value, capnp::Error>) -> rouille::Response {
+/// Validate and convert a chunk of an upload session as soon as it is
+/// received. A chunk with the same number replaces the previous one, so
+/// failed chunks can be uploaded again.
+pub fn upload_chunk_route(collection_name: &str, config: &serde_json::Value, items_stream: &ItemsStream, upload_id: &str, chunk: &str, request: &rouille::Request) -> rouille::Response {
 
-    let custom_subdirectory_path  = config.get("custom_subdirectory_path").unwrap_or(&serde_json::Value::Null);
-    let data_source_uuid          = config.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+    let chunk = match chunk.parse::<usize>() {
+        Ok(chunk) => chunk,
+        Err(_) => return failed_response(collection_name, &CacheError::InvalidParameter(format!("Invalid chunk number {}", chunk)))
+    };
+    let shard_size = Limits::from_config(config, collection_name).shard_size;
+    let summary = uploads::chunk_writer(collection_name, upload_id, chunk, shard_size, items_stream)
+        .and_then(|mut shard_files| {
+            let (_, validator) = items_body(collection_name, config, items_stream, &mut shard_files, request)?;
+            validator.into_result()?;
+            uploads::add_chunk(collection_name, upload_id, chunk, shard_files)
+        });
+    match summary {
+        Err(error) => failed_response(collection_name, &error),
+        Ok(summary) => success_response(collection_name, Some(json!(summary)))
+    }
+
+}
 
-    let cache_directory_path;
+/// Replace the collection (or append to it) with the chunks of an upload
+/// session. The body can set the expected number of chunks: {"chunks": 3}.
+pub fn commit_upload_route(collection_name: &str, config: &serde_json::Value, items_stream: &ItemsStream, upload_id: &str, request: &rouille::Request) -> rouille::Response {
 
-    if custom_subdirectory_path.is_null() // no custom path
-    {
-        if !data_source_uuid.is_null()
-        {
-            cache_directory_path = format!("{}/dataSources/{}", config["project_cache_directory_path"].as_str().unwrap(), data_source_uuid.as_str().unwrap());
-        }
-        else
-        {
-            cache_directory_path = format!("{}", config["project_cache_directory_path"].as_str().unwrap());
+    let json : serde_json::Value   = match json_body(request, &Limits::from_config(config, collection_name)) {
+        Ok(json) => json,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+
+    let mut validator = Validator::new();
+    let expected_chunks = match &json["chunks"] {
+        serde_json::Value::Null => None,
+        chunks => Some(validator.unsigned_integer(chunks, "/chunks") as usize)
+    };
+    let manifest = validator.into_result()
+        .and_then(|()| uploads::commit(collection_name, upload_id, expected_chunks, items_stream, config));
+    match manifest {
+        Err(error) => failed_response(collection_name, &error),
+        Ok(manifest) => {
+            metrics::record_write(collection_name, manifest.count());
+            logging::record_count(manifest.count());
+            success_response(collection_name, Some(json!({ "count": manifest.count(), "shards": manifest.shards.len() })))
         }
-        //println!("cache_directory_path: {}", cache_directory_path);
     }
-    else
-    {
-        if !data_source_uuid.is_null()
-        {
-            cache_directory_path = format!("{}/dataSources/{}/{}", config["project_cache_directory_path"].as_str().unwrap(), data_source_uuid.as_str().unwrap(), custom_subdirectory_path.as_str().unwrap());
+
+}
+
+pub fn abort_upload_route(collection_name: &str, upload_id: &str) -> rouille::Response {
+
+    match uploads::abort(collection_name, upload_id) {
+        Err(error) => failed_response(collection_name, &error),
+        Ok(()) => success_response(collection_name, Some(json!({ "uploadId": upload_id, "aborted": true })))
+    }
+
+}
+
+/// Run a write request in a background job and respond with its id at once.
+/// The job status is then polled at /jobs/{job_id}, or followed as
+/// Server-Sent Events at /jobs/{job_id}/events.
+pub fn start_job_route<F>(config: &serde_json::Value, request: &rouille::Request, handler: F) -> rouille::Response
+where F: FnOnce(&rouille::Request) -> rouille::Response + Send + 'static {
+
+    let collection_name = request.url().trim_start_matches('/').split('/').next().unwrap_or("").to_string();
+    let limits = Limits::from_config(config, &collection_name);
+    let spool_directory_path = Path::new(config["project_cache_directory_path"].as_str().unwrap_or(""));
+    match jobs::start(request, limits.max_body_size, spool_directory_path, handler) {
+        Err(error) => failed_response(&collection_name, &error),
+        Ok(job_id) => rouille::Response {
+            status_code: 202,
+            headers    : vec![
+                ("Content-Type".into(), "application/json; charset=utf-8".into()),
+                ("Location".into(), format!("/jobs/{}", job_id).into())
+            ],
+            data       : rouille::ResponseBody::from_string(json!({ "status": "success", "cacheName": collection_name, "data": { "jobId": job_id } }).to_string()),
+            upgrade    : None
         }
-        else
-        {
-            cache_directory_path = format!("{}/{}", config["project_cache_directory_path"].as_str().unwrap(), custom_subdirectory_path.as_str().unwrap());
+    }
+
+}
+
+pub fn job_route(job_id: &str) -> rouille::Response {
+
+    match jobs::find(job_id) {
+        Err(error) => error_response(json!({ "jobId": job_id }), &error),
+        Ok(job) => rouille::Response::json(&json!({ "status": "success", "jobId": job_id, "data": job.status() }))
+    }
+
+}
+
+pub fn job_events_route(job_id: &str) -> rouille::Response {
+
+    match jobs::find(job_id) {
+        Err(error) => error_response(json!({ "jobId": job_id }), &error),
+        Ok(job) => rouille::Response {
+            status_code: 200,
+            headers    : vec![
+                ("Content-Type".into(), "text/event-stream".into()),
+                ("Cache-Control".into(), "no-cache".into())
+            ],
+            data       : rouille::ResponseBody::from_reader(JobEvents::new(job)),
+            upgrade    : None
         }
-        //println!("cache_directory_path_custom: {}", cache_directory_path);
     }
 
+}
+
+/// Open the cache file of a collection, in the cache directory requested by
+/// the query parameters
+fn open_collection_file(collection_name: &str, cache_file_name: &str, config: &serde_json::Value) -> Result<File, CacheError> {
+
+    let custom_subdirectory_path  = config.get("custom_subdirectory_path").unwrap_or(&serde_json::Value::Null);
+    let data_source_uuid          = config.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+
+    let directory_path = cache_directory(config, custom_subdirectory_path, data_source_uuid, None)?;
 
-    let directory_path = Path::new(&cache_directory_path);
-    let absolute_directory_path = String::from(directory_path.to_str().unwrap());
-    let create_directories = fs::create_dir_all(&absolute_directory_path);
+    let create_directories = fs::create_dir_all(&directory_path);
     match create_directories {
         Ok(()) => {},
         Err(error) => {
-            println!("{}", error);
+            warn!("Cannot create the cache directory {}: {}", directory_path.display(), error);
         }
     }
 
-    let collection_file_path_name;
-    if cache_file_name == "households" || cache_file_name == "persons" || cache_file_name == "odTrips" {
-        collection_file_path_name = format!("{}/{}.capnpbin", absolute_directory_path, cache_file_name);
-    } else {
-        collection_file_path_name = format!("{}/{}.capnpbin", absolute_directory_path, cache_file_name);
-    }
-    let path = Path::new(&collection_file_path_name);
-    //println!("collection_file_path_name: {}", collection_file_path_name);
-    let absolute_path = String::from(path.to_str().unwrap());
-    let file = File::open(&absolute_path);
-
-    if file.is_ok()
-    {
-        match &read_fn(&mut file.unwrap(), config) {
-            Err(error) => return failed_response(collection_name, error),
-            Ok(json_value) => return success_response(collection_name, Some(&json_value))
-        }
+    match File::open(directory_path.join(format!("{}.capnpbin", cache_file_name))) {
+        Ok(file) => Ok(file),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            Err(CacheError::NotFound(format!("{} cache not found", collection_name)))
+        },
+        Err(error) => Err(CacheError::Io(error))
     }
-    else
-    {
-        failed_response(collection_name, &file.unwrap_err())
+
+}
+
+pub fn read_collection_route(collection_name: &str, cache_file_name: &str, config: &serde_json::Value, read_fn: &dyn Fn(&mut std::fs::File, &serde_json::Value) -> Result<serde_json::Value, CacheError>) -> rouille::Response {
+
+    match open_collection_file(collection_name, cache_file_name, config).and_then(|mut file| read_fn(&mut file, config)) {
+        Err(error) => failed_response(collection_name, &error),
+        Ok(json_value) => success_response(collection_name, Some(json_value))
+    }
+
+}
+
+/// Read a collection while the response is sent: the items are converted to
+/// json one by one as the response body is written. The shards of sharded
+/// collections are merged.
+pub fn read_collection_stream_route(collection_name: &str, cache_file_name: &str, feature_collection: bool, config: &serde_json::Value, read_items: ReadItemsFn) -> rouille::Response {
+
+    let custom_subdirectory_path  = config.get("custom_subdirectory_path").unwrap_or(&serde_json::Value::Null);
+    let data_source_uuid          = config.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+
+    let items = cache_directory(config, custom_subdirectory_path, data_source_uuid, None)
+        .and_then(|directory_path| shards::read_items(&directory_path, cache_file_name, read_items, config))
+        .and_then(|items| items.ok_or_else(|| CacheError::NotFound(format!("{} cache not found", collection_name))));
+    let items = match items {
+        Ok(items) => items,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    logging::record_count(items.count());
+
+    let envelope = json!({
+        "status"   : "success",
+        "cacheName": collection_name,
+        "data"     : { collection_name: if feature_collection { json!({ "type": "FeatureCollection", "features": [] }) } else { json!([]) } }
+    }).to_string();
+    // split the envelope around the empty items array:
+    let items_position = envelope.rfind("[]").unwrap_or(envelope.len());
+    let (prefix, suffix) = (envelope[..items_position + 1].to_string(), envelope[items_position + 1..].to_string());
+
+    rouille::Response {
+        status_code: 200,
+        headers    : vec![("Content-Type".into(), "application/json; charset=utf-8".into())],
+        data       : rouille::ResponseBody::from_reader(items.into_reader(prefix, suffix)),
+        upgrade    : None
     }
 
 }
 
 
-pub fn write_object_route(collection_name: &str, subdirectory: &str, config: &serde_json::Value, write_fn: &dyn Fn(&str, &serde_json::Value, &serde_json::Value) -> ::std::result::Result<(), capnp::Error>, request: &rouille::Request) -> rouille::Response {
+pub fn write_object_route(collection_name: &str, subdirectory: &str, config: &serde_json::Value, write_fn: &dyn Fn(&str, &serde_json::Value, &serde_json::Value) -> Result<(), CacheError>, request: &rouille::Request) -> rouille::Response {
 
-    let json : serde_json::Value   = try_or_400!(rouille::input::json_input(request));
+    let json : serde_json::Value   = match json_body(request, &Limits::from_config(config, subdirectory)) {
+        Ok(json) => json,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let config = match body_project_config(config, &json) {
+        Ok(config) => config,
+        Err(error) => return project_failed_response(json["project"].as_str(), &error)
+    };
+    let config = &config;
     let json_cache_directory_path  = json.get("cache_directory_path").unwrap_or(&serde_json::Value::Null);
     let json_data_source_uuid      = json.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
 
-    let cache_directory_path;
+    let path = match cache_directory(config, json_cache_directory_path, json_data_source_uuid, Some(subdirectory)) {
+        Ok(path) => path,
+        Err(error) => return failed_response(collection_name, &error)
+    };
 
-    if json_cache_directory_path.is_null() // no custom path
-    {
-        if !json_data_source_uuid.is_null()
-        {
-            cache_directory_path = format!("{}/dataSources/{}/{}", config["project_cache_directory_path"].as_str().unwrap(), json_data_source_uuid.as_str().unwrap(), subdirectory);
-        }
-        else
-        {
-            cache_directory_path = format!("{}/{}", config["project_cache_directory_path"].as_str().unwrap(), subdirectory);
-        }
-        //println!("cache_directory_path: {}", cache_directory_path);
+    if let Err(error) = fs::create_dir_all(&path) {
+        return failed_response(collection_name, &CacheError::Io(error));
+    }
+    
+    let absolute_path = String::from(path.to_str().unwrap_or(""));
+    match &write_fn(&absolute_path.as_str(), &json, config) {
+        Err(error) => failed_response(collection_name, error),
+        Ok(()) => success_response(collection_name, None)
+    }
+
+}
+
+/// Map the items on all the available threads, each thread taking the next
+/// item until there are none left. The results are in the items order.
+fn parallel_map<T: Sync, R: Send>(items: &[T], map_fn: &(dyn Fn(usize, &T) -> R + Sync)) -> Vec<R> {
+
+    let next_index = AtomicUsize::new(0);
+    let threads_count = thread::available_parallelism().map_or(1, |count| count.get()).min(items.len());
+    let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+        let threads: Vec<_> = (0..threads_count).map(|_| scope.spawn(|| {
+            let mut results = Vec::new();
+            loop {
+                let i = next_index.fetch_add(1, Ordering::Relaxed);
+                match items.get(i) {
+                    Some(item) => results.push((i, map_fn(i, item))),
+                    None => return results
+                }
+            }
+        })).collect();
+        threads.into_iter().flat_map(|thread| thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))).collect()
+    });
+    results.sort_unstable_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+
+}
+
+/// Write many objects at once, converted in parallel. The body has the
+/// objects in an array named after the collection (eg {"lines": [...]}) and
+/// the response reports the success or failure of each object.
+pub fn write_objects_route(collection_name: &str, object_name: &str, subdirectory: &str, config: &serde_json::Value, write_fn: &(dyn Fn(&str, &serde_json::Value, &serde_json::Value) -> Result<(), CacheError> + Sync), request: &rouille::Request) -> rouille::Response {
+
+    let json : serde_json::Value   = match json_body(request, &Limits::from_config(config, collection_name)) {
+        Ok(json) => json,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let config = match body_project_config(config, &json) {
+        Ok(config) => config,
+        Err(error) => return project_failed_response(json["project"].as_str(), &error)
+    };
+    let config = &config;
+    let mut validator = Validator::new();
+    let objects_json = validator.array(&json[collection_name], &format!("/{}", collection_name));
+    if let Err(error) = validator.into_result() {
+        return failed_response(collection_name, &error);
+    }
+    let json_cache_directory_path  = json.get("cache_directory_path").unwrap_or(&serde_json::Value::Null);
+    let json_data_source_uuid      = json.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+
+    let path = match cache_directory(config, json_cache_directory_path, json_data_source_uuid, Some(subdirectory)) {
+        Ok(path) => path,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    if let Err(error) = fs::create_dir_all(&path) {
+        return failed_response(collection_name, &CacheError::Io(error));
     }
-    else
-    {
-        if !json_data_source_uuid.is_null()
-        {
-            cache_directory_path = format!("{}/dataSources/{}/{}", config["project_cache_directory_path"].as_str().unwrap(), json_data_source_uuid.as_str().unwrap(), json_cache_directory_path.as_str().unwrap(), );
+    let absolute_path = path.to_str().unwrap_or("");
+
+    let write_object = |i: usize, object_json: &serde_json::Value| {
+        let object_uuid = object_json.get("id").cloned().unwrap_or(serde_json::Value::Null);
+        match write_fn(absolute_path, &json!({ object_name: object_json }), config) {
+            Ok(()) => json!({ "id": object_uuid, "status": "success" }),
+            Err(error) => {
+                let error = rebase_pointers(error, &format!("/{}", object_name), &format!("/{}/{}", collection_name, i));
+                error_json(json!({ "id": object_uuid }), &error)
+            }
         }
-        else
-        {
-            cache_directory_path = format!("{}/{}", config["project_cache_directory_path"].as_str().unwrap(), json_cache_directory_path.as_str().unwrap());
+    };
+
+    let results = parallel_map(objects_json, &write_object);
+
+    let failed_count = results.iter().filter(|result| result["status"] != "success").count();
+    success_response(collection_name, Some(json!({
+        "succeeded": results.len() - failed_count,
+        "failed"   : failed_count,
+        "objects"  : results
+    })))
+
+}
+
+/// Read many objects at once, converted in parallel. The uuids are in the
+/// uuids query parameter (?uuids=a,b,c) for GET requests and in a uuids array
+/// in the body for POST requests. The objects are returned by uuid, and the
+/// uuids of the objects not in the cache are listed in missing.
+pub fn read_objects_route(collection_name: &str, object_name: &str, subdirectory: &str, config: &serde_json::Value, read_fn: &(dyn Fn(&str, &str, &serde_json::Value) -> Result<serde_json::Value, CacheError> + Sync), request: &rouille::Request) -> rouille::Response {
+
+    let json : serde_json::Value = if request.method() == "POST" {
+        match json_body(request, &Limits::from_config(config, collection_name)) {
+            Ok(json) => json,
+            Err(error) => return failed_response(collection_name, &error)
+        }
+    } else {
+        json!({ "uuids": request.get_param("uuids").unwrap_or_default().split(',').filter(|uuid| !uuid.is_empty()).collect::<Vec<&str>>() })
+    };
+    let mut config = match body_project_config(config, &json) {
+        Ok(config) => config,
+        Err(error) => return project_failed_response(json["project"].as_str(), &error)
+    };
+    for (config_key, body_key) in [("custom_subdirectory_path", "cache_directory_path"), ("data_source_uuid", "data_source_uuid")].iter() {
+        if let Some(value) = json.get(*body_key) {
+            config[*config_key] = value.clone();
         }
-        //println!("cache_directory_path_custom: {}", cache_directory_path);
     }
+    let config = &config;
 
-    let path = Path::new(&cache_directory_path);
-    let create_directories = fs::create_dir_all(&path);
-    match create_directories {
-        Ok(()) => {},
-        Err(error) => {
-            println!("{}", error);
+    let mut validator = Validator::new();
+    let objects_uuids = validator.string_array(&json["uuids"], "/uuids");
+    if let Err(error) = validator.into_result().and_then(|()| objects_uuids.iter().try_for_each(|uuid| cache_paths::validate_uuid("uuids", uuid).map(|_| ()))) {
+        return failed_response(collection_name, &error);
+    }
+
+    let custom_subdirectory_path  = config.get("custom_subdirectory_path").unwrap_or(&serde_json::Value::Null);
+    let data_source_uuid          = config.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+    let path = match cache_directory(config, custom_subdirectory_path, data_source_uuid, Some(subdirectory)) {
+        Ok(path) => path,
+        Err(error) => return failed_response(collection_name, &error)
+    };
+    let absolute_path = path.to_str().unwrap_or("");
+
+    let results = parallel_map(&objects_uuids, &|_, object_uuid| read_fn(object_uuid, absolute_path, config));
+
+    let mut objects = serde_json::Map::new();
+    let mut missing_uuids = Vec::new();
+    for (object_uuid, result) in objects_uuids.iter().zip(results) {
+        match result {
+            Ok(mut object_json) => {
+                objects.insert(object_uuid.to_string(), object_json[object_name].take());
+            },
+            Err(CacheError::NotFound(_)) => missing_uuids.push(*object_uuid),
+            Err(error) => return failed_response(collection_name, &error)
         }
     }
-    
-    let absolute_path = String::from(path.to_str().unwrap());
-    match &write_fn(&absolute_path.as_str(), &json, config) {
-        Err(error) => return failed_response(collection_name, error),
-        Ok(()) => return success_response(collection_name, None)
+
+    success_response(collection_name, Some(json!({
+        "objects": objects,
+        "missing": missing_uuids
+    })))
+
+}
+
+pub fn read_object_route(object_name: &str, object_uuid: &str, subdirectory: &str, config: &serde_json::Value, read_fn: &dyn Fn(&str, &str, &serde_json::Value) -> Result<serde_json::Value, CacheError>) -> rouille::Response {
+
+    let custom_subdirectory_path  = config.get("custom_subdirectory_path").unwrap_or(&serde_json::Value::Null);
+    let data_source_uuid          = config.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+
+    let path = match cache_paths::validate_uuid("uuid", object_uuid).and_then(|_| cache_directory(config, custom_subdirectory_path, data_source_uuid, Some(subdirectory))) {
+        Ok(path) => path,
+        Err(error) => return failed_response(object_name, &error)
+    };
+    let absolute_path = String::from(path.to_str().unwrap_or(""));
+    debug!("absolute_path: {}", absolute_path);
+
+    match read_fn(object_uuid, &absolute_path.as_str(), config) {
+        Err(error) => failed_response(object_name, &error),
+        Ok(json_value) => success_response(object_name, Some(json_value))
+    }
+
+}
+
+/// Respond with the removed cache files, relative to the project cache directory
+fn removed_response(cache_name: &str, config: &serde_json::Value, removed_paths: &[PathBuf]) -> rouille::Response {
+
+    let project_cache_directory_path = Path::new(config["project_cache_directory_path"].as_str().unwrap_or(""));
+    let removed: Vec<String> = removed_paths.iter()
+        .map(|path| path.strip_prefix(project_cache_directory_path).unwrap_or(path).to_string_lossy().into_owned())
+        .collect();
+    success_response(cache_name, Some(json!({ "removed": removed })))
+
+}
+
+fn delete_cache_file(cache_name: &str, path: &Path, config: &serde_json::Value) -> rouille::Response {
+
+    match cache_files::remove_locked(path, &WriteLock::from_config(config)) {
+        Ok(true) => removed_response(cache_name, config, &[path.to_path_buf()]),
+        Ok(false) => failed_response(cache_name, &CacheError::NotFound(format!("{} cache not found", cache_name))),
+        Err(error) => failed_response(cache_name, &error)
     }
 
 }
 
-pub fn read_object_route(object_name: &str, object_uuid: &String, subdirectory: &str, config: &serde_json::Value, read_fn: &dyn Fn(&String, &str, &serde_json::Value) -> ::std::result::Result<serde_json::Value, capnp::Error>) -> rouille::Response {
+pub fn delete_collection_route(collection_name: &str, cache_file_name: &str, config: &serde_json::Value) -> rouille::Response {
 
     let custom_subdirectory_path  = config.get("custom_subdirectory_path").unwrap_or(&serde_json::Value::Null);
     let data_source_uuid          = config.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
 
-    let cache_directory_path;
+    let directory_path = match cache_directory(config, custom_subdirectory_path, data_source_uuid, None) {
+        Ok(directory_path) => directory_path,
+        Err(error) => return failed_response(collection_name, &error)
+    };
 
+    if !shards::manifest_path(&directory_path, cache_file_name).exists() && !shards::count_file_path(&directory_path, cache_file_name).exists() {
+        return delete_cache_file(collection_name, &directory_path.join(format!("{}.capnpbin", cache_file_name)), config);
+    }
+    match shards::remove(&directory_path, cache_file_name, &WriteLock::from_config(config)) {
+        Ok(removed_paths) => removed_response(collection_name, config, &removed_paths),
+        Err(error) => failed_response(collection_name, &error)
+    }
 
-    if custom_subdirectory_path.is_null() // no custom path
-    {
-        if !data_source_uuid.is_null()
-        {
-            cache_directory_path = format!("{}/dataSources/{}/{}", config["project_cache_directory_path"].as_str().unwrap(), data_source_uuid.as_str().unwrap(), subdirectory);
-        }
-        else
-        {
-            cache_directory_path = format!("{}/{}", config["project_cache_directory_path"].as_str().unwrap(), subdirectory);
-        }
-        //println!("cache_directory_path: {}", cache_directory_path);
+}
+
+pub fn delete_object_route(object_name: &str, object_uuid: &str, subdirectory: &str, config: &serde_json::Value) -> rouille::Response {
+
+    let custom_subdirectory_path  = config.get("custom_subdirectory_path").unwrap_or(&serde_json::Value::Null);
+    let data_source_uuid          = config.get("data_source_uuid").unwrap_or(&serde_json::Value::Null);
+
+    let directory_path = match cache_paths::validate_uuid("uuid", object_uuid).and_then(|_| cache_directory(config, custom_subdirectory_path, data_source_uuid, Some(subdirectory))) {
+        Ok(directory_path) => directory_path,
+        Err(error) => return failed_response(object_name, &error)
+    };
+
+    delete_cache_file(object_name, &directory_path.join(format!("{}_{}.capnpbin", object_name, object_uuid)), config)
+
+}
+
+/// Delete the whole cache of a data source: {project}/dataSources/{data_source_uuid}
+pub fn delete_data_source_cache_route(data_source_uuid: &str, config: &serde_json::Value) -> rouille::Response {
+
+    let directory_path = match cache_directory(config, &serde_json::Value::Null, &json!(data_source_uuid), None) {
+        Ok(directory_path) => directory_path,
+        Err(error) => return failed_response("dataSources", &error)
+    };
+
+    match cache_files::remove_directory(&directory_path, &WriteLock::from_config(config)) {
+        Ok(removed_paths) => removed_response("dataSources", config, &removed_paths),
+        Err(CacheError::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+            failed_response("dataSources", &CacheError::NotFound(format!("Cache of data source {} not found", data_source_uuid)))
+        },
+        Err(error) => failed_response("dataSources", &error)
     }
-    else
-    {
-        if !data_source_uuid.is_null()
-        {
-            cache_directory_path = format!("{}/dataSources/{}/{}", config["project_cache_directory_path"].as_str().unwrap(), data_source_uuid.as_str().unwrap(), custom_subdirectory_path.as_str().unwrap());
-        }
-        else
-        {
-            cache_directory_path = format!("{}/{}", config["project_cache_directory_path"].as_str().unwrap(), custom_subdirectory_path.as_str().unwrap());
+
+}
+
+/// The process is alive
+pub fn health_route() -> rouille::Response {
+    rouille::Response::json(&json!({ "status": "success", "data": { "alive": true, "version": env!("CARGO_PKG_VERSION") } }))
+}
+
+/// The cache directories of the projects, by shortname, exist and are
+/// writable
+pub fn ready_route(projects_cache_directory_paths: &serde_json::Value) -> rouille::Response {
+
+    let projects: serde_json::Map<String, serde_json::Value> = projects_cache_directory_paths.as_object().into_iter().flatten()
+        .filter_map(|(shortname, cache_directory_path)| cache_directory_path.as_str().map(|path| (shortname.clone(), json!(cache_summary::directory_status(Path::new(path))))))
+        .collect();
+    let shutting_down = shutdown::is_shutting_down();
+    let ready = !shutting_down && !projects.is_empty() && projects.values().all(|status| status["writable"] == json!(true));
+    let json = json!({ "status": if ready { "success" } else { "fail" }, "data": { "ready": ready, "shuttingDown": shutting_down, "projects": projects } });
+    rouille::Response::json(&json).with_status_code(if ready { 200 } else { 503 })
+
+}
+
+/// Collection caches of the project and of its data sources, with their
+/// records count, size and last modification
+pub fn cache_summary_route(config: &serde_json::Value) -> rouille::Response {
+
+    let project_cache_directory_path = Path::new(config["project_cache_directory_path"].as_str().unwrap_or(""));
+    match cache_summary::summarize(project_cache_directory_path, config) {
+        Err(error) => project_failed_response(config["project_shortname"].as_str(), &error),
+        Ok(collections) => rouille::Response::json(&json!({
+            "status" : "success",
+            "project": config["project_shortname"],
+            "data"   : { "collections": collections }
+        }))
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use pretty_assertions::{assert_eq};
+    use crate::routers;
+    use std::path::{Path};
+    use std::fs;
+    use rouille::Request;
+    use crate::collections::CacheCollection;
+    use crate::routers::od_trip_collection_router::OdTripCollection;
+    use crate::routers::path_collection_router::PathCollection;
+    use crate::routers::person_collection_router::PersonCollection;
+    use crate::shards;
+    use crate::collections;
+    use crate::content_encoding;
+
+    /// Json body of a response
+    pub fn response_json(response: rouille::Response) -> serde_json::Value {
+        let (mut res_data, _) = response.data.into_reader_and_size();
+        let mut buffer = String::new();
+        res_data.read_to_string(&mut buffer).unwrap();
+        serde_json::from_str(buffer.as_str()).unwrap()
+    }
+
+    #[test]
+    fn project_prefix() {
+
+        let request = Request::fake_http("GET", "/projects/demo/nodes?data_source_uuid=abc", vec![], vec![]);
+        let (project_shortname, unprefixed_request) = routers::remove_project_prefix(&request);
+        assert_eq!(project_shortname, Some(String::from("demo")));
+        let unprefixed_request = unprefixed_request.unwrap();
+        assert_eq!(unprefixed_request.url(), "/nodes");
+        assert_eq!(unprefixed_request.get_param("data_source_uuid"), Some(String::from("abc")));
+
+        let request = Request::fake_http("GET", "/nodes", vec![], vec![]);
+        let (project_shortname, unprefixed_request) = routers::remove_project_prefix(&request);
+        assert_eq!(project_shortname, None);
+        assert!(unprefixed_request.is_none());
+
+    }
+
+    #[test]
+    fn failed_responses() {
+
+        fs::create_dir_all("test/projects/empty").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/empty")).unwrap(),
+            "project_shortname"           : "empty"
+        });
+
+        let response = routers::read_collection_route(
+            "agencies",
+            "agencies",
+            &config,
+            &routers::agency_collection_router::read_collection,
+        );
+        assert_eq!(response.status_code, 404);
+
+        let json_response = response_json(response);
+        assert_eq!(json_response["status"], "fail");
+        assert_eq!(json_response["cacheName"], "agencies");
+        assert_eq!(json_response["errorCode"], "NOT_FOUND");
+
+        let request = Request::fake_http(
+            "POST",
+            "/agencies",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            "{ not json".as_bytes().to_vec(),
+        );
+        let response = routers::write_collection_route(
+            "agencies",
+            "agencies",
+            &config,
+            &routers::agency_collection_router::write_collection,
+            &request,
+        );
+        assert_eq!(response.status_code, 400);
+
+        let request = Request::fake_http(
+            "POST",
+            "/nodes",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            r##"{ "nodes": { "type": "Feature", "geometry": null, "properties": {} } }"##.as_bytes().to_vec(),
+        );
+        let response = routers::write_collection_route(
+            "nodes",
+            "nodes",
+            &config,
+            &routers::node_collection_router::write_collection,
+            &request,
+        );
+        assert_eq!(response.status_code, 400);
+
+    }
+
+    #[test]
+    fn body_project() {
+
+        fs::create_dir_all("test/projects/body_project").unwrap();
+        let default_project_path = fs::canonicalize(Path::new("test")).unwrap();
+        let body_project_path = fs::canonicalize(Path::new("test/projects/body_project")).unwrap();
+
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": default_project_path,
+            "project_shortname"           : "test",
+            "project_requested"           : false,
+            "projects"                    : {
+                "test": default_project_path,
+                "body_project": body_project_path
+            }
+        });
+
+        let data = r##"
+            {
+                "project": "body_project",
+                "agencies": []
+            }
+        "##;
+
+        let request = Request::fake_http(
+            "POST",
+            "/agencies",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            data.as_bytes().to_vec(),
+        );
+
+        let _ = fs::remove_file(body_project_path.join("agencies.capnpbin"));
+        let response = routers::write_collection_route(
+            "agencies",
+            "agencies",
+            &config,
+            &routers::agency_collection_router::write_collection,
+            &request,
+        );
+        assert_eq!(response.status_code, 200);
+        assert!(body_project_path.join("agencies.capnpbin").exists());
+
+        // unknown body project
+        let request = Request::fake_http(
+            "POST",
+            "/agencies",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            r##"{ "project": "unknown", "agencies": [] }"##.as_bytes().to_vec(),
+        );
+        let response = routers::write_collection_route(
+            "agencies",
+            "agencies",
+            &config,
+            &routers::agency_collection_router::write_collection,
+            &request,
+        );
+        assert_eq!(response.status_code, 404);
+
+        // body project different from the project requested in the url
+        let mut config = config;
+        config["project_requested"] = json!(true);
+        let response = routers::write_collection_route(
+            "agencies",
+            "agencies",
+            &config,
+            &routers::agency_collection_router::write_collection,
+            &Request::fake_http(
+                "POST",
+                "/agencies",
+                vec![(
+                    "Content-Type".to_owned(),
+                    "application/json; charset=utf-8".to_owned(),
+                )],
+                data.as_bytes().to_vec(),
+            ),
+        );
+        assert_eq!(response.status_code, 409);
+
+    }
+
+    #[test]
+    fn validation_errors() {
+
+        fs::create_dir_all("test/projects/validation").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/validation")).unwrap(),
+            "project_shortname"           : "validation"
+        });
+
+        let data = r##"
+            {
+                "odTrips": [
+                    {
+                        "id": "8e8a9a8c-4f4c-4c36-9d6c-bb5cb5d5c4a1",
+                        "integer_id": 1,
+                        "origin_geography": { "type": "Point", "coordinates": [-73.5, 45.5] },
+                        "destination_geography": { "type": "Point", "coordinates": [-73.6, 45.6] }
+                    },
+                    {
+                        "integer_id": "2",
+                        "origin_geography": null,
+                        "destination_geography": { "type": "Point", "coordinates": [-73.6, 45.6] },
+                        "data": { "originNodes": ["a", 12], "originNodesTravelTimes": [10, 20], "originNodesDistances": [100, 200] }
+                    }
+                ]
+            }
+        "##;
+
+        let request = Request::fake_http(
+            "POST",
+            "/odTrips",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            data.as_bytes().to_vec(),
+        );
+        let response = routers::write_collection_route(
+            "odTrips",
+            "odTrips",
+            &config,
+            &routers::od_trip_collection_router::write_collection,
+            &request,
+        );
+        assert_eq!(response.status_code, 400);
+
+        let json_response = response_json(response);
+        assert_eq!(json_response["errorCode"], "VALIDATION_FAILED");
+        let pointers: Vec<&str> = json_response["errors"].as_array().unwrap().iter().map(|error| error["pointer"].as_str().unwrap()).collect();
+        assert_eq!(pointers, vec![
+            "/odTrips/1/origin_geography",
+            "/odTrips/1/id",
+            "/odTrips/1/integer_id",
+            "/odTrips/1/data/originNodes/1"
+        ]);
+
+        // invalid nested object
+        let request = Request::fake_http(
+            "POST",
+            "/line",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            r##"{ "line": { "id": "3b2c1d0e-9f8a-4b7c-8d6e-5f4a3b2c1d0e", "agency_id": "b", "scheduleByServiceId": { "s/1": { "id": "c", "periods": {} } } } }"##.as_bytes().to_vec(),
+        );
+        let response = routers::write_object_route(
+            "line",
+            "lines",
+            &config,
+            &routers::line_router::write_object,
+            &request,
+        );
+        assert_eq!(response.status_code, 400);
+
+        let json_response = response_json(response);
+        assert_eq!(json_response["errors"], json!([
+            { "pointer": "/line/scheduleByServiceId/s~11/service_id", "message": "is required" },
+            { "pointer": "/line/scheduleByServiceId/s~11/periods", "message": "must be an array" }
+        ]));
+        assert!(!Path::new("test/projects/validation/lines/line_3b2c1d0e-9f8a-4b7c-8d6e-5f4a3b2c1d0e.capnpbin").exists());
+
+    }
+
+    #[test]
+    fn path_traversal() {
+
+        fs::create_dir_all("test/projects/traversal").unwrap();
+        let mut config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/traversal")).unwrap(),
+            "project_shortname"           : "traversal"
+        });
+
+        for body in &[
+            r##"{ "cache_directory_path": "../../escaped", "agencies": [] }"##,
+            r##"{ "cache_directory_path": "/tmp/escaped", "agencies": [] }"##,
+            r##"{ "data_source_uuid": "../../escaped", "agencies": [] }"##,
+            r##"{ "data_source_uuid": 1234, "agencies": [] }"##
+        ] {
+            let request = Request::fake_http(
+                "POST",
+                "/agencies",
+                vec![(
+                    "Content-Type".to_owned(),
+                    "application/json; charset=utf-8".to_owned(),
+                )],
+                body.as_bytes().to_vec(),
+            );
+            let response = routers::write_collection_route(
+                "agencies",
+                "agencies",
+                &config,
+                &routers::agency_collection_router::write_collection,
+                &request,
+            );
+            assert_eq!(response.status_code, 400);
         }
-        //println!("cache_directory_path_custom: {}", cache_directory_path);
+        assert!(!Path::new("test/escaped").exists());
+
+        let response = routers::read_object_route(
+            "node",
+            &String::from("../../nodes"),
+            "nodes",
+            &config,
+            &routers::node_router::read_object,
+        );
+        assert_eq!(response.status_code, 400);
+
+        let json_response = response_json(response);
+        assert_eq!(json_response["errorCode"], "INVALID_PARAMETER");
+
+        config["custom_subdirectory_path"] = json!("../empty");
+        let response = routers::read_collection_route(
+            "agencies",
+            "agencies",
+            &config,
+            &routers::agency_collection_router::read_collection,
+        );
+        assert_eq!(response.status_code, 400);
+
+    }
+
+    #[test]
+    fn failed_write_keeps_previous_cache() {
+
+        fs::create_dir_all("test/projects/atomic").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/atomic")).unwrap(),
+            "project_shortname"           : "atomic"
+        });
+
+        let write = |body: &str| {
+            let request = Request::fake_http(
+                "POST",
+                "/agencies",
+                vec![(
+                    "Content-Type".to_owned(),
+                    "application/json; charset=utf-8".to_owned(),
+                )],
+                body.as_bytes().to_vec(),
+            );
+            routers::write_collection_route(
+                "agencies",
+                "agencies",
+                &config,
+                &routers::agency_collection_router::write_collection,
+                &request,
+            ).status_code
+        };
+
+        assert_eq!(write(r##"{ "agencies": [{ "id": "c0ffee00-1111-4222-8333-444455556666", "acronym": "AG" }] }"##), 200);
+        let previous_cache = fs::read("test/projects/atomic/agencies.capnpbin").unwrap();
+
+        assert_eq!(write(r##"{ "agencies": [{ "id": "c0ffee00-1111-4222-8333-444455556666" }, { "acronym": 12 }] }"##), 400);
+        assert_eq!(fs::read("test/projects/atomic/agencies.capnpbin").unwrap(), previous_cache);
+
+        let mut files: Vec<String> = fs::read_dir("test/projects/atomic").unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        files.sort();
+        assert_eq!(files, vec![".agencies.capnpbin.lock", "agencies.capnpbin"]);
+
+    }
+
+    #[test]
+    fn delete_routes() {
+
+        let _ = fs::remove_dir_all("test/projects/delete");
+        fs::create_dir_all("test/projects/delete").unwrap();
+        let data_source_uuid = "0d6c5b4a-3f2e-4d1c-8b0a-9f8e7d6c5b4a";
+        let node_uuid = "6f5e4d3c-2b1a-4098-8f7e-6d5c4b3a2910";
+        let mut config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/delete")).unwrap(),
+            "project_shortname"           : "delete",
+            "data_source_uuid"            : data_source_uuid
+        });
+        let data_source_directory = format!("test/projects/delete/dataSources/{}", data_source_uuid);
+        fs::create_dir_all(format!("{}/nodes", data_source_directory)).unwrap();
+        fs::write(format!("{}/agencies.capnpbin", data_source_directory), "").unwrap();
+        fs::write(format!("{}/nodes/node_{}.capnpbin", data_source_directory, node_uuid), "").unwrap();
+
+        let response = routers::delete_object_route("node", node_uuid, "nodes", &config);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response_json(response)["data"]["removed"], json!([format!("dataSources/{}/nodes/node_{}.capnpbin", data_source_uuid, node_uuid)]));
+        assert_eq!(routers::delete_object_route("node", node_uuid, "nodes", &config).status_code, 404);
+        assert_eq!(routers::delete_object_route("node", "../agencies", "nodes", &config).status_code, 400);
+
+        fs::write(format!("{}/lines.capnpbin", data_source_directory), "").unwrap();
+        let response = routers::delete_collection_route("lines", "lines", &config);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response_json(response)["data"]["removed"], json!([format!("dataSources/{}/lines.capnpbin", data_source_uuid)]));
+        assert_eq!(routers::delete_collection_route("lines", "lines", &config).status_code, 404);
+
+        config["data_source_uuid"] = json!(null);
+        assert_eq!(routers::delete_data_source_cache_route("../..", &config).status_code, 400);
+        let response = routers::delete_data_source_cache_route(data_source_uuid, &config);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response_json(response)["data"]["removed"], json!([format!("dataSources/{}/agencies.capnpbin", data_source_uuid)]));
+        assert!(!Path::new(&data_source_directory).exists());
+        assert_eq!(routers::delete_data_source_cache_route(data_source_uuid, &config).status_code, 404);
+
+    }
+
+    #[test]
+    fn patch_route() {
+
+        let _ = fs::remove_dir_all("test/projects/patch");
+        fs::create_dir_all("test/projects/patch").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/patch")).unwrap(),
+            "project_shortname"           : "patch"
+        });
+
+        let node = |uuid: &str, integer_id: u32, name: &str| json!({
+            "type": "Feature",
+            "id": integer_id,
+            "geometry": { "type": "Point", "coordinates": [-73.5, 45.5] },
+            "properties": { "id": uuid, "integer_id": integer_id, "name": name }
+        });
+        let patch = |body: serde_json::Value| {
+            let request = Request::fake_http(
+                "PATCH",
+                "/nodes",
+                vec![(
+                    "Content-Type".to_owned(),
+                    "application/json; charset=utf-8".to_owned(),
+                )],
+                body.to_string().into_bytes(),
+            );
+            let response = routers::patch_collection_route(
+                "nodes",
+                "nodes",
+                true,
+                &config,
+                &routers::node_collection_router::read_collection,
+                &routers::node_collection_router::write_collection,
+                &request,
+            );
+            let status_code = response.status_code;
+            (status_code, response_json(response))
+        };
+        let node_names = || {
+            let mut file = fs::File::open("test/projects/patch/nodes.capnpbin").unwrap();
+            let nodes = routers::node_collection_router::read_collection(&mut file, &config).unwrap();
+            nodes["nodes"]["features"].as_array().unwrap().iter().map(|feature| feature["properties"]["name"].as_str().unwrap().to_string()).collect::<Vec<String>>()
+        };
+
+        let uuids = ["11111111-1111-4111-8111-111111111111", "22222222-2222-4222-8222-222222222222", "33333333-3333-4333-8333-333333333333"];
+
+        // the collection is created by the first patch:
+        let (status_code, json) = patch(json!({ "upsert": [node(uuids[0], 1, "A"), node(uuids[1], 2, "B")] }));
+        assert_eq!(status_code, 200);
+        assert_eq!(json["data"], json!({ "inserted": 2, "updated": 0, "removed": 0 }));
+
+        let (status_code, json) = patch(json!({ "upsert": [node(uuids[2], 3, "C"), node(uuids[0], 1, "A2")], "remove": [uuids[1]] }));
+        assert_eq!(status_code, 200);
+        assert_eq!(json["data"], json!({ "inserted": 1, "updated": 1, "removed": 1 }));
+        assert_eq!(node_names(), vec!["A2", "C"]);
+
+        // invalid upserted items are reported at their index in the body and the cache is kept:
+        let mut invalid_node = node(uuids[1], 2, "B");
+        invalid_node["properties"]["integer_id"] = json!("two");
+        let (status_code, json) = patch(json!({ "upsert": [node(uuids[0], 1, "A3"), invalid_node] }));
+        assert_eq!(status_code, 400);
+        assert_eq!(json["errors"], json!([{ "pointer": "/upsert/1/properties/integer_id", "message": "must be a positive integer" }]));
+        assert_eq!(node_names(), vec!["A2", "C"]);
+
+        let (status_code, json) = patch(json!({ "remove": "all" }));
+        assert_eq!(status_code, 400);
+        assert_eq!(json["errors"][0]["pointer"], "/remove");
+
+    }
+
+    #[test]
+    fn batch_objects() {
+
+        let _ = fs::remove_dir_all("test/projects/objects");
+        fs::create_dir_all("test/projects/objects").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/objects")).unwrap(),
+            "project_shortname"           : "objects"
+        });
+
+        let nodes_uuids: Vec<String> = (0..20).map(|i| format!("{:08x}-1111-4111-8111-111111111111", i)).collect();
+        let mut nodes: Vec<serde_json::Value> = nodes_uuids.iter().enumerate().map(|(i, uuid)| json!({
+            "id": uuid,
+            "integer_id": i,
+            "geography": { "type": "Point", "coordinates": [-73.5, 45.5] }
+        })).collect();
+        nodes[7]["geography"] = json!(null);
+        nodes[12]["id"] = json!("not a uuid");
+
+        let request = Request::fake_http(
+            "POST",
+            "/nodes/objects",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            json!({ "nodes": nodes }).to_string().into_bytes(),
+        );
+        let response = routers::write_objects_route("nodes", "node", "nodes", &config, &routers::node_router::write_object, &request);
+        assert_eq!(response.status_code, 200);
+
+        let json_response = response_json(response);
+
+        assert_eq!(json_response["data"]["succeeded"], 18);
+        assert_eq!(json_response["data"]["failed"], 2);
+        let objects = json_response["data"]["objects"].as_array().unwrap();
+        assert_eq!(objects.len(), 20);
+        assert_eq!(objects[0], json!({ "id": nodes_uuids[0], "status": "success" }));
+        assert_eq!(objects[7]["status"], "fail");
+        assert_eq!(objects[7]["errors"][0]["pointer"], "/nodes/7/geography");
+        assert_eq!(objects[12]["id"], "not a uuid");
+        assert_eq!(objects[12]["errors"][0]["pointer"], "/nodes/12/id");
+
+        assert!(Path::new(&format!("test/projects/objects/nodes/node_{}.capnpbin", nodes_uuids[19])).exists());
+        assert!(!Path::new(&format!("test/projects/objects/nodes/node_{}.capnpbin", nodes_uuids[7])).exists());
+
+        let read = |request: Request| {
+            let response = routers::read_objects_route("nodes", "node", "nodes", &config, &routers::node_router::read_object, &request);
+            let status_code = response.status_code;
+            (status_code, response_json(response))
+        };
+
+        let (status_code, json_response) = read(Request::fake_http("GET", format!("/nodes/objects?uuids={},{},{}", nodes_uuids[3], nodes_uuids[7], nodes_uuids[19]), vec![], vec![]));
+        assert_eq!(status_code, 200);
+        let objects = json_response["data"]["objects"].as_object().unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[&nodes_uuids[3]]["integer_id"], 3);
+        assert_eq!(objects[&nodes_uuids[19]]["id"], nodes_uuids[19]);
+        assert_eq!(json_response["data"]["missing"], json!([nodes_uuids[7]]));
+
+        let (status_code, json_response) = read(Request::fake_http(
+            "POST",
+            "/nodes/objects/read",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            json!({ "uuids": nodes_uuids }).to_string().into_bytes(),
+        ));
+        assert_eq!(status_code, 200);
+        assert_eq!(json_response["data"]["objects"].as_object().unwrap().len(), 18);
+        assert_eq!(json_response["data"]["missing"], json!([nodes_uuids[7], nodes_uuids[12]]));
+
+        let (status_code, json_response) = read(Request::fake_http("GET", "/nodes/objects?uuids=../nodes", vec![], vec![]));
+        assert_eq!(status_code, 400);
+        assert_eq!(json_response["errorCode"], "INVALID_PARAMETER");
+
+    }
+
+    #[test]
+    fn streamed_collection() {
+
+        let _ = fs::remove_dir_all("test/projects/streamed");
+        fs::create_dir_all("test/projects/streamed/dom").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/streamed")).unwrap(),
+            "project_shortname"           : "streamed"
+        });
+        let items_stream = OdTripCollection.items_stream().unwrap();
+
+        let od_trips: Vec<serde_json::Value> = (0..50).map(|i| json!({
+            "id": format!("{:08x}-4f4c-4c36-9d6c-bb5cb5d5c4a1", i),
+            "integer_id": i,
+            "mode": "walking",
+            "origin_geography": { "type": "Point", "coordinates": [-73.5, 45.5] },
+            "destination_geography": { "type": "Point", "coordinates": [-73.6, 45.6] },
+            "data": { "originNodes": ["a", "b"], "originNodesTravelTimes": [10, 20], "originNodesDistances": [100, 200] }
+        })).collect();
+        let request = |body: String| Request::fake_http(
+            "POST",
+            "/odTrips",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            body.into_bytes(),
+        );
+
+        // the body parameters can come after the items:
+        let body = format!(r##"{{ "odTrips": {}, "cache_directory_path": "stream" }}"##, json!(od_trips));
+        let response = routers::write_collection_stream_route("odTrips", "odTrips", &config, &items_stream, false, &request(body));
+        assert_eq!(response.status_code, 200);
+        let response = routers::write_collection_route("odTrips", "odTrips", &config, &routers::od_trip_collection_router::write_collection, &request(json!({ "odTrips": od_trips, "cache_directory_path": "dom" }).to_string()));
+        assert_eq!(response.status_code, 200);
+
+        let streamed_od_trips = shards::read_items(Path::new("test/projects/streamed/stream"), "odTrips", OdTripCollection.read_items().unwrap(), &config).unwrap().unwrap().into_values().unwrap();
+        assert_eq!(streamed_od_trips.len(), 50);
+        let mut file = fs::File::open("test/projects/streamed/dom/odTrips.capnpbin").unwrap();
+        assert_eq!(json!({ "odTrips": streamed_od_trips }), routers::od_trip_collection_router::read_collection(&mut file, &config).unwrap());
+
+        // streamed reads are the same as the whole collection reads:
+        let mut read_config = config.clone();
+        read_config["custom_subdirectory_path"] = json!("stream");
+        let streamed_response = routers::read_collection_stream_route("odTrips", "odTrips", false, &read_config, OdTripCollection.read_items().unwrap());
+        assert_eq!(streamed_response.status_code, 200);
+        let mut dom_config = config.clone();
+        dom_config["custom_subdirectory_path"] = json!("dom");
+        assert_eq!(response_json(streamed_response), response_json(routers::read_collection_route("odTrips", "odTrips", &dom_config, &routers::od_trip_collection_router::read_collection)));
+        let response = routers::write_collection_route("paths", "paths", &config, &routers::path_collection_router::write_collection, &request(json!({ "paths": { "type": "FeatureCollection", "features": [] }, "cache_directory_path": "stream" }).to_string()));
+        assert_eq!(response.status_code, 200);
+        let streamed_response = routers::read_collection_stream_route("paths", "paths", true, &read_config, PathCollection.read_items().unwrap());
+        assert_eq!(response_json(streamed_response), json!({ "status": "success", "cacheName": "paths", "data": { "paths": { "type": "FeatureCollection", "features": [] } } }));
+        assert_eq!(routers::read_collection_stream_route("persons", "persons", false, &read_config, PersonCollection.read_items().unwrap()).status_code, 404);
+
+        let body = r##"{ "odTrips": [{ "integer_id": "2", "origin_geography": null, "destination_geography": { "type": "Point", "coordinates": [-73.6, 45.6] } }] }"##;
+        let response = routers::write_collection_stream_route("odTrips", "odTrips", &config, &items_stream, false, &request(body.to_string()));
+        assert_eq!(response.status_code, 400);
+        let json_response = response_json(response);
+        let pointers: Vec<&str> = json_response["errors"].as_array().unwrap().iter().map(|error| error["pointer"].as_str().unwrap()).collect();
+        assert_eq!(pointers, vec!["/odTrips/0/origin_geography", "/odTrips/0/id", "/odTrips/0/integer_id"]);
+
+        let response = routers::write_collection_stream_route("odTrips", "odTrips", &config, &items_stream, false, &request(String::from(r##"{ "persons": [] }"##)));
+        assert_eq!(response.status_code, 400);
+        let response = routers::write_collection_stream_route("odTrips", "odTrips", &config, &items_stream, false, &request(String::from(r##"{ "odTrips": [{}"##)));
+        assert_eq!(response.status_code, 400);
+
+        // the items are staged in the project of the url, which the body cannot change:
+        let body = format!(r##"{{ "odTrips": {}, "project": "other", "cache_directory_path": "other" }}"##, json!(od_trips));
+        let response = routers::write_collection_stream_route("odTrips", "odTrips", &config, &items_stream, false, &request(body));
+        assert_eq!(response.status_code, 409);
+        assert!(!Path::new("test/projects/streamed/other").exists());
+        let body = format!(r##"{{ "odTrips": {}, "project": "streamed", "cache_directory_path": "stream" }}"##, json!(od_trips[..1]));
+        let response = routers::write_collection_stream_route("odTrips", "odTrips", &config, &items_stream, false, &request(body));
+        assert_eq!(response.status_code, 200);
+
+    }
+
+    #[test]
+    fn limits() {
+
+        let _ = fs::remove_dir_all("test/projects/limits");
+        fs::create_dir_all("test/projects/limits").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/limits")).unwrap(),
+            "project_shortname"           : "limits",
+            "limits"                      : { "max_body_size": 100, "collections": { "odTrips": { "max_body_size": 100000, "traversal_limit_words": 10 } } }
+        });
+        let request = |body: String| Request::fake_http(
+            "POST",
+            "/odTrips",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            body.into_bytes(),
+        );
+
+        let od_trips: Vec<serde_json::Value> = (0..5).map(|i| json!({
+            "id": format!("{:08x}-4f4c-4c36-9d6c-bb5cb5d5c4a1", i),
+            "integer_id": i,
+            "origin_geography": { "type": "Point", "coordinates": [-73.5, 45.5] },
+            "destination_geography": { "type": "Point", "coordinates": [-73.6, 45.6] }
+        })).collect();
+        let body = json!({ "odTrips": od_trips }).to_string();
+        let response = routers::write_collection_stream_route("odTrips", "odTrips", &config, &OdTripCollection.items_stream().unwrap(), false, &request(body.clone()));
+        assert_eq!(response.status_code, 200);
+
+        // the global max_body_size applies to the other collections:
+        let response = routers::write_collection_route("paths", "paths", &config, &routers::path_collection_router::write_collection, &request(format!(r##"{{ "paths": {{ "type": "FeatureCollection", "features": [] }}, "padding": "{}" }}"##, "x".repeat(100))));
+        assert_eq!(response.status_code, 413);
+        assert_eq!(response_json(response)["errorCode"], "BODY_TOO_LARGE");
+        let mut small_body_config = config.clone();
+        small_body_config["limits"]["collections"]["odTrips"]["max_body_size"] = json!(100);
+        let response = routers::write_collection_stream_route("odTrips", "odTrips", &small_body_config, &OdTripCollection.items_stream().unwrap(), false, &request(body));
+        assert_eq!(response.status_code, 413);
+
+        // reads exceeding the traversal limit name it:
+        let response = routers::read_collection_stream_route("odTrips", "odTrips", false, &config, OdTripCollection.read_items().unwrap());
+        assert_eq!(response.status_code, 500);
+        let json_response = response_json(response);
+        assert_eq!(json_response["errorCode"], "READER_LIMIT_EXCEEDED");
+        assert!(json_response["error"].as_str().unwrap().contains("traversal_limit_words"));
+        small_body_config["limits"]["collections"]["odTrips"]["traversal_limit_words"] = json!(100000);
+        assert_eq!(routers::read_collection_stream_route("odTrips", "odTrips", false, &small_body_config, OdTripCollection.read_items().unwrap()).status_code, 200);
+
+    }
+
+    #[test]
+    fn sharded_collection() {
+
+        let _ = fs::remove_dir_all("test/projects/sharded");
+        fs::create_dir_all("test/projects/sharded").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/sharded")).unwrap(),
+            "project_shortname"           : "sharded",
+            "limits"                      : { "collections": { "odTrips": { "shard_size": 2 } } }
+        });
+        let od_trips_body = |ids: std::ops::Range<usize>| json!({ "odTrips": ids.map(|i| json!({
+            "id": format!("{:08x}-4f4c-4c36-9d6c-bb5cb5d5c4a1", i),
+            "integer_id": i,
+            "origin_geography": { "type": "Point", "coordinates": [-73.5, 45.5] },
+            "destination_geography": { "type": "Point", "coordinates": [-73.6, 45.6] }
+        })).collect::<Vec<serde_json::Value>>() }).to_string();
+        let request = |method: &str, url: &str, body: String| Request::fake_http(
+            method,
+            url,
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            body.into_bytes(),
+        );
+        let read_integer_ids = || -> Vec<serde_json::Value> {
+            let response = collections::route(&Request::fake_http("GET", "/odTrips", vec![], vec![]), &config, "").unwrap();
+            response_json(response)["data"]["odTrips"].as_array().unwrap().iter().map(|od_trip| od_trip["integer_id"].clone()).collect()
+        };
+
+        let response = collections::route(&request("POST", "/odTrips", od_trips_body(0..5)), &config, "").unwrap();
+        assert_eq!(response_json(response)["data"], json!({ "count": 5, "shards": 3 }));
+        assert_eq!(read_integer_ids(), (0..5).map(|i| json!(i)).collect::<Vec<_>>());
+
+        let response = collections::route(&request("POST", "/odTrips/append", od_trips_body(5..6)), &config, "").unwrap();
+        assert_eq!(response_json(response)["data"], json!({ "count": 6, "shards": 4 }));
+        assert_eq!(read_integer_ids(), (0..6).map(|i| json!(i)).collect::<Vec<_>>());
+        assert!(collections::route(&request("POST", "/paths/append", String::from("{}")), &config, "").is_none());
+
+        let patch = json!({
+            "upsert": [{ "id": "00000001-4f4c-4c36-9d6c-bb5cb5d5c4a1", "integer_id": 10, "origin_geography": { "type": "Point", "coordinates": [-73.5, 45.5] }, "destination_geography": { "type": "Point", "coordinates": [-73.6, 45.6] } }],
+            "remove": ["00000000-4f4c-4c36-9d6c-bb5cb5d5c4a1"]
+        });
+        let response = collections::route(&request("PATCH", "/odTrips", patch.to_string()), &config, "").unwrap();
+        assert_eq!(response_json(response)["data"], json!({ "inserted": 0, "updated": 1, "removed": 1 }));
+        assert_eq!(read_integer_ids(), vec![json!(10), json!(2), json!(3), json!(4), json!(5)]);
+        let response = collections::route(&request("PATCH", "/odTrips", json!({ "upsert": [{ "id": "00000002-4f4c-4c36-9d6c-bb5cb5d5c4a1" }] }).to_string()), &config, "").unwrap();
+        assert_eq!(response.status_code, 400);
+        assert!(response_json(response)["errors"].as_array().unwrap().iter().all(|error| error["pointer"].as_str().unwrap().starts_with("/upsert/0/")));
+
+        let response = collections::route(&Request::fake_http("DELETE", "/odTrips", vec![], vec![]), &config, "").unwrap();
+        assert_eq!(response_json(response)["data"]["removed"], json!(["odTrips.capnpbin.0", "odTrips.capnpbin.1", "odTrips.capnpbin.2", "odTrips.capnpbin.count", "odTrips.manifest.json"]));
+        assert_eq!(collections::route(&Request::fake_http("GET", "/odTrips", vec![], vec![]), &config, "").unwrap().status_code, 404);
+
+    }
+
+    #[test]
+    fn chunked_upload() {
+
+        let _ = fs::remove_dir_all("test/projects/uploads");
+        fs::create_dir_all("test/projects/uploads").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/uploads")).unwrap(),
+            "project_shortname"           : "uploads",
+            "limits"                      : { "collections": { "persons": { "shard_size": 2 } } }
+        });
+        let persons_body = |ids: std::ops::Range<usize>| json!({ "persons": ids.map(|i| json!({
+            "id": format!("{:08x}-4f4c-4c36-9d6c-bb5cb5d5c4a1", i),
+            "integer_id": i
+        })).collect::<Vec<serde_json::Value>>() }).to_string();
+        let request = |method: &str, url: &str, body: String| Request::fake_http(
+            method,
+            url,
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            body.into_bytes(),
+        );
+        let route = |request: Request| collections::route(&request, &config, "").unwrap();
+
+        let upload_id = response_json(route(request("POST", "/persons/uploads", String::from("{}"))))["data"]["uploadId"].as_str().unwrap().to_string();
+        let chunk_url = |chunk: &str| format!("/persons/uploads/{}/chunks/{}", upload_id, chunk);
+
+        let response = route(request("PUT", &chunk_url("1"), persons_body(3..4)));
+        assert_eq!(response_json(response)["data"], json!({ "uploadId": upload_id, "chunk": 1, "count": 1, "chunks": 1 }));
+        let response = route(request("PUT", &chunk_url("1"), json!({ "persons": [{ "id": "not a uuid" }] }).to_string()));
+        assert_eq!(response.status_code, 400);
+        assert_eq!(route(request("PUT", &chunk_url("first"), persons_body(0..3))).status_code, 400);
+
+        let response = route(request("POST", &format!("/persons/uploads/{}/commit", upload_id), String::from("{}")));
+        assert_eq!(response.status_code, 400);
+        assert_eq!(response_json(response)["errorCode"], "INVALID_PAYLOAD");
+
+        // chunk 0 uploaded twice, the second one replaces the first:
+        route(request("PUT", &chunk_url("0"), persons_body(10..15)));
+        let response = route(request("PUT", &chunk_url("0"), persons_body(0..3)));
+        assert_eq!(response_json(response)["data"], json!({ "uploadId": upload_id, "chunk": 0, "count": 3, "chunks": 2 }));
+        assert_eq!(route(request("POST", &format!("/persons/uploads/{}/commit", upload_id), json!({ "chunks": 3 }).to_string())).status_code, 400);
+        assert_eq!(route(Request::fake_http("GET", "/persons", vec![], vec![])).status_code, 404);
+
+        let response = route(request("POST", &format!("/persons/uploads/{}/commit", upload_id), json!({ "chunks": 2 }).to_string()));
+        assert_eq!(response_json(response)["data"], json!({ "count": 4, "shards": 3 }));
+        let persons = response_json(route(Request::fake_http("GET", "/persons", vec![], vec![])))["data"]["persons"].clone();
+        assert_eq!(persons.as_array().unwrap().iter().map(|person| person["integer_id"].clone()).collect::<Vec<_>>(), (0..4).map(|i| json!(i)).collect::<Vec<_>>());
+        assert!(!Path::new(&format!("test/projects/uploads/.uploads/{}", upload_id)).exists());
+        assert_eq!(route(request("PUT", &chunk_url("2"), persons_body(4..5))).status_code, 404);
+
+        let upload_id = response_json(route(request("POST", "/persons/uploads", json!({ "append": true }).to_string())))["data"]["uploadId"].as_str().unwrap().to_string();
+        route(request("PUT", &format!("/persons/uploads/{}/chunks/0", upload_id), persons_body(4..5)));
+        let response = route(Request::fake_http("DELETE", format!("/persons/uploads/{}", upload_id), vec![], vec![]));
+        assert_eq!(response.status_code, 200);
+        assert!(!Path::new(&format!("test/projects/uploads/.uploads/{}", upload_id)).exists());
+        assert_eq!(route(Request::fake_http("DELETE", format!("/persons/uploads/{}", upload_id), vec![], vec![])).status_code, 404);
+        assert_eq!(route(Request::fake_http("DELETE", "/odTrips/uploads/unknown", vec![], vec![])).status_code, 404);
+        assert!(collections::route(&request("POST", "/nodes/uploads", String::from("{}")), &config, "").is_none());
+
+    }
+
+    #[test]
+    fn async_job() {
+
+        let _ = fs::remove_dir_all("test/projects/jobs");
+        fs::create_dir_all("test/projects/jobs").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/jobs")).unwrap(),
+            "project_shortname"           : "jobs"
+        });
+        let body = json!({ "persons": (0..3).map(|i| json!({
+            "id": format!("{:08x}-4f4c-4c36-9d6c-bb5cb5d5c4a1", i),
+            "integer_id": i
+        })).collect::<Vec<serde_json::Value>>() }).to_string();
+
+        let request = Request::fake_http("POST", "/persons?async=true", vec![("Content-Type".to_owned(), "application/json".to_owned())], body.into_bytes());
+        let job_config = config.clone();
+        let response = routers::start_job_route(&config, &request, move |job_request| collections::route(job_request, &job_config, "").unwrap());
+        assert_eq!(response.status_code, 202);
+        let job_id = response_json(response)["data"]["jobId"].as_str().unwrap().to_string();
+
+        let status = loop {
+            let status = response_json(routers::job_route(&job_id))["data"].clone();
+            if status["state"] != "running" {
+                break status;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert_eq!(status["state"], "succeeded");
+        assert_eq!((status["statusCode"].clone(), status["recordsProcessed"].clone()), (json!(200), json!(3)));
+        assert_eq!(status["data"], json!({ "count": 3, "shards": 1 }));
+        assert_eq!(response_json(collections::route(&Request::fake_http("GET", "/persons", vec![], vec![]), &config, "").unwrap())["data"]["persons"].as_array().unwrap().len(), 3);
+
+        assert_eq!(routers::job_events_route(&job_id).headers[0].1, "text/event-stream");
+
+        // the job start response is encoded like the other json responses:
+        let request = Request::fake_http("POST", "/persons?async=true", vec![("Content-Type".to_owned(), "application/json".to_owned()), ("Accept-Encoding".to_owned(), "gzip".to_owned())], br#"{ "persons": [] }"#.to_vec());
+        let job_config = config.clone();
+        let response = content_encoding::encode_response(&request, routers::start_job_route(&config, &request, move |job_request| collections::route(job_request, &job_config, "").unwrap()));
+        assert_eq!(response.status_code, 202);
+        assert!(response.headers.iter().any(|(key, value)| key == "Content-Encoding" && value == "gzip"));
+        assert!(response.headers.iter().any(|(key, _)| key == "Location"));
+        let response = routers::job_route("unknown");
+        assert_eq!(response.status_code, 404);
+        assert_eq!(response_json(response)["errorCode"], "NOT_FOUND");
+
     }
 
-    let path = Path::new(&cache_directory_path);
-    let absolute_path = String::from(path.to_str().unwrap());
-    println!("absolute_path: {}", absolute_path);
+    #[test]
+    fn encoded_body() {
+
+        use std::io::{Read, Write};
+
+        let _ = fs::remove_dir_all("test/projects/encoded");
+        fs::create_dir_all("test/projects/encoded").unwrap();
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test/projects/encoded")).unwrap(),
+            "project_shortname"           : "encoded",
+            "limits"                      : { "max_body_size": 1000 }
+        });
+        let body = json!({ "persons": (0..20).map(|i| json!({
+            "id": format!("{:08x}-4f4c-4c36-9d6c-bb5cb5d5c4a1", i),
+            "integer_id": i
+        })).collect::<Vec<serde_json::Value>>() }).to_string();
+        let gzip = |body: &str| {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(body.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        };
+        let request = |content_encoding: &str, body: Vec<u8>| Request::fake_http(
+            "POST",
+            "/persons",
+            vec![
+                ("Content-Type".to_owned(), "application/json".to_owned()),
+                ("Content-Encoding".to_owned(), content_encoding.to_owned())
+            ],
+            body
+        );
+
+        // the limit applies to the decoded body:
+        assert!(gzip(&body).len() < 1000 && body.len() > 1000);
+        assert_eq!(collections::route(&request("gzip", gzip(&body)), &config, "").unwrap().status_code, 413);
+        let config = json!({ "project_cache_directory_path": config["project_cache_directory_path"], "project_shortname": "encoded" });
+        assert_eq!(collections::route(&request("gzip", gzip(&body)), &config, "").unwrap().status_code, 200);
+        assert_eq!(collections::route(&request("gzip", body.clone().into_bytes()), &config, "").unwrap().status_code, 400);
+        assert_eq!(collections::route(&request("compress", body.into_bytes()), &config, "").unwrap().status_code, 415);
+
+        let read_request = Request::fake_http("GET", "/persons", vec![("Accept-Encoding".to_owned(), "gzip".to_owned())], vec![]);
+        let response = content_encoding::encode_response(&read_request, collections::route(&read_request, &config, "").unwrap());
+        let (response_body, _) = response.data.into_reader_and_size();
+        let mut persons = String::new();
+        flate2::read::GzDecoder::new(response_body).read_to_string(&mut persons).unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&persons).unwrap()["data"]["persons"].as_array().unwrap().len(), 20);
 
-    match &read_fn(object_uuid, &absolute_path.as_str(), config) {
-        Err(error) => return failed_response(object_name, error),
-        Ok(json_value) => return success_response(object_name, Some(&json_value))
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn probes() {
+
+        let _ = fs::remove_dir_all("test/projects/probes");
+        fs::create_dir_all("test/projects/probes").unwrap();
+        let project_path = fs::canonicalize(Path::new("test/projects/probes")).unwrap();
+
+        assert_eq!(routers::health_route().status_code, 200);
+        assert_eq!(routers::ready_route(&json!({ "probes": project_path })).status_code, 200);
+        assert_eq!(routers::ready_route(&json!({ "probes": project_path, "missing": project_path.join("missing") })).status_code, 503);
+
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": project_path,
+            "project_shortname"           : "probes"
+        });
+        let body = json!({ "persons": [{ "id": "00000000-4f4c-4c36-9d6c-bb5cb5d5c4a1", "integer_id": 1 }], "data_source_uuid": "b4d8b3ab-0f9d-4c2b-9c4b-4f7c0c1e2d3a" });
+        let request = Request::fake_http("POST", "/persons", vec![("Content-Type".to_owned(), "application/json".to_owned())], body.to_string().into_bytes());
+        assert_eq!(collections::route(&request, &config, "").unwrap().status_code, 200);
+
+        let summary = response_json(routers::cache_summary_route(&config));
+        let collections = summary["data"]["collections"].as_array().unwrap();
+        assert_eq!(collections.len(), 1);
+        assert_eq!((&collections[0]["collection"], &collections[0]["dataSourceUuid"], &collections[0]["count"]), (&json!("persons"), &json!("b4d8b3ab-0f9d-4c2b-9c4b-4f7c0c1e2d3a"), &json!(1)));
+
+    }
+}
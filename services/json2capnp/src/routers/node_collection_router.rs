@@ -7,16 +7,18 @@
  */
 
 use crate::nodeCollection_capnp::node_collection as collection;
+use crate::nodeCollection_capnp::node;
 //use crate::my_error::MyError;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::{CacheCollection, CacheObject, ReadItemsFn};
+use crate::json_stream::JsonItems;
 use serde_json;
 use std::io::BufReader;
-use geojson::GeoJson;
-use geojson::{Value as GeojsonValue};
+use crate::validation::Validator;
 
 use crate::utils::{
-    required_string,
-    optional_string_json_null_to_empty_str as optional_string,
     json_boolean_to_i8,
     empty_str_to_json_null,
     i8_to_json_boolean,
@@ -24,117 +26,107 @@ use crate::utils::{
     minus_one_i64_to_null
 };
 
+pub struct NodeCollection;
+
+impl CacheCollection for NodeCollection {
+
+    fn name(&self) -> &'static str {
+        "nodes"
+    }
+
+    fn is_feature_collection(&self) -> bool {
+        true
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+    fn read_items(&self) -> Option<ReadItemsFn> {
+        Some(read_items)
+    }
+
+    fn object(&self) -> Option<CacheObject> {
+        Some(CacheObject {
+            name        : "node",
+            subdirectory: "nodes",
+            write_object: crate::routers::node_router::write_object,
+            read_object : crate::routers::node_router::read_object
+        })
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
-    
-    let geojson_data : GeoJson = GeoJson::from_json_value((json["nodes"]).clone()).unwrap();
-
-    match geojson_data {
-        geojson::GeoJson::FeatureCollection(feature_collection) => {
-
-            let features_count = feature_collection.features.len();
-            let collection_capnp = message.init_root::<collection::Builder>();
-            let mut capnp = collection_capnp.init_nodes(features_count as u32);
-            for i in 0..features_count {
-                let feature        = &feature_collection.features[i];
-                let mut capnp_data = capnp.reborrow().get(i as u32);
-                let mut properties = feature.properties.clone().unwrap();
-
-                let geojson_value = feature.geometry.to_owned().unwrap().value;
-                let latitude;
-                let longitude;
-                match geojson_value {
-                    GeojsonValue::Point(point) => {
-                        longitude = (point[0] * 1000000.0).round() as i32;
-                        latitude  = (point[1] * 1000000.0).round() as i32;
-                    },
-                    _ => {
-                        longitude = -1;
-                        latitude  = -1;
-                    }
-                }
-
-                capnp_data.set_uuid(&required_string(properties.get("id"))); // required
-                capnp_data.set_id(properties.get("integer_id").unwrap().as_u64().unwrap() as u32); // required
-                capnp_data.set_station_uuid(optional_string(properties.get("station_id")));
-                capnp_data.set_internal_id(optional_string(properties.get("internal_id")));
-                capnp_data.set_code(optional_string(properties.get("code")));
-                capnp_data.set_name(optional_string(properties.get("name")));
-                capnp_data.set_color(optional_string(properties.get("color")));
-                capnp_data.set_description(optional_string(properties.get("description")));
-                capnp_data.set_routing_radius_meters(json_value_or_null_to_i64_or_minus_one(&properties.get("routing_radius_meters").unwrap_or(&json!(null))) as i16);
-                capnp_data.set_default_dwell_time_seconds(json_value_or_null_to_i64_or_minus_one(&properties.get("default_dwell_time_seconds").unwrap_or(&json!(null))) as i16);
-
-                if properties.get("data") != None && properties["data"].is_object() && properties["data"].get("transferableNodes") != None && properties["data"]["transferableNodes"].is_object() // remove transferable nodes data from collection
-                {
-                    properties["data"]["transferableNodes"].take();
-                }
+    let mut validator = Validator::new();
+
+    let features = validator.features(&json["nodes"], "/nodes");
+
+    let features_count = features.len();
+    let collection_capnp = message.init_root::<collection::Builder>();
+    let mut capnp = collection_capnp.init_nodes(features_count as u32);
+    for (i, feature) in features.iter().enumerate() {
+        let mut capnp_data = capnp.reborrow().get(i as u32);
+
+        let mut fields = validator.fields(feature, &format!("/nodes/features/{}", i));
+        let (longitude, latitude) = fields.point("geometry");
+
+        let properties = &feature["properties"];
+        let mut fields = validator.fields(properties, &format!("/nodes/features/{}/properties", i));
+
+        capnp_data.set_uuid(fields.required_string("id"));
+        capnp_data.set_id(fields.required_u32("integer_id"));
+        capnp_data.set_station_uuid(fields.optional_string("station_id"));
+        capnp_data.set_internal_id(fields.optional_string("internal_id"));
+        capnp_data.set_code(fields.optional_string("code"));
+        capnp_data.set_name(fields.optional_string("name"));
+        capnp_data.set_color(fields.optional_string("color"));
+        capnp_data.set_description(fields.optional_string("description"));
+        capnp_data.set_routing_radius_meters(json_value_or_null_to_i64_or_minus_one(&properties["routing_radius_meters"]) as i16);
+        capnp_data.set_default_dwell_time_seconds(json_value_or_null_to_i64_or_minus_one(&properties["default_dwell_time_seconds"]) as i16);
+
+        let mut data = properties.get("data").cloned().unwrap_or_else(|| json!({}));
+        if data["transferableNodes"].is_object() // remove transferable nodes data from collection
+        {
+            data["transferableNodes"].take();
+        }
 
-                capnp_data.set_data(&properties.get("data").unwrap_or(&json!({})).to_string().as_str());
-                capnp_data.set_is_frozen(json_boolean_to_i8(&properties.get("is_frozen").unwrap_or(&json!(null))));
-                capnp_data.set_is_enabled(json_boolean_to_i8(&properties.get("is_enabled").unwrap_or(&json!(null))));
-                capnp_data.set_latitude(latitude);
-                capnp_data.set_longitude(longitude);
+        capnp_data.set_data(data.to_string().as_str());
+        capnp_data.set_is_frozen(json_boolean_to_i8(&properties["is_frozen"]));
+        capnp_data.set_is_enabled(json_boolean_to_i8(&properties["is_enabled"]));
+        capnp_data.set_latitude(latitude);
+        capnp_data.set_longitude(longitude);
 
-            }
-            serialize_packed::write_message(file, &message)
-        },
-        _ => {
-            Err(capnp::Error::failed(String::from("Nodes geojson is invalid, empty or not a FeatureCollection")))
-        }
     }
 
+    validator.into_result()?;
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
 }
 
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "nodes").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_nodes()?.len() as usize);
 
     for capnp_object in capnp_collection.get_nodes()?.iter() {
-        
-        let integer_id = capnp_object.get_id() as u32;
-        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
-        let latitude  = (capnp_object.get_latitude() as f64)/1000000.0;
-        let longitude = (capnp_object.get_longitude() as f64)/1000000.0;
-        let properties_json : serde_json::Value = json!({
-            "id": capnp_object.get_uuid()?,
-            "integer_id": integer_id,
-            "station_id": empty_str_to_json_null(capnp_object.get_station_uuid()?),
-            "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
-            "code": empty_str_to_json_null(capnp_object.get_code()?),
-            "name": empty_str_to_json_null(capnp_object.get_name()?),
-            "color": empty_str_to_json_null(capnp_object.get_color()?),
-            "description": empty_str_to_json_null(capnp_object.get_description()?),
-            "routing_radius_meters": minus_one_i64_to_null(capnp_object.get_routing_radius_meters() as i64),
-            "default_dwell_time_seconds": minus_one_i64_to_null(capnp_object.get_default_dwell_time_seconds() as i64),
-            "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
-            "is_enabled": i8_to_json_boolean(capnp_object.get_is_enabled()),
-            "data": data_attributes
-        });
-
-        let mut geojson = json!({
-            "type": "Feature",
-            "geometry": {
-                "type": "Point",
-                "coordinates": [longitude, latitude]
-            }
-        });
 
-        geojson["id"]         = json!(integer_id);
-        geojson["properties"] = properties_json;
-
-        collection_json_vec.push(geojson);
+        collection_json_vec.push(read_node_feature(capnp_object)?);
 
     }
 
@@ -148,6 +140,57 @@ pub fn read_collection(
 }
 
 
+/// Read the nodes one by one, for streamed responses
+pub fn read_items(
+    file: &mut std::fs::File,
+    config: &serde_json::Value,
+) -> Result<JsonItems, CacheError> {
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "nodes").reader_options())?;
+    let count = message_reader.get_root::<collection::Reader>()?.get_nodes()?.len() as usize;
+
+    Ok(JsonItems::new(count, move |i| {
+        read_node_feature(message_reader.get_root::<collection::Reader>()?.get_nodes()?.get(i as u32))
+    }))
+
+}
+
+/// Read a node as a geojson feature, for the collection
+pub fn read_node_feature(capnp_object: node::Reader) -> Result<serde_json::Value, CacheError> {
+
+    let integer_id = capnp_object.get_id() as u32;
+    let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
+    let latitude  = (capnp_object.get_latitude() as f64)/1000000.0;
+    let longitude = (capnp_object.get_longitude() as f64)/1000000.0;
+    let properties_json : serde_json::Value = json!({
+        "id": capnp_object.get_uuid()?,
+        "integer_id": integer_id,
+        "station_id": empty_str_to_json_null(capnp_object.get_station_uuid()?),
+        "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
+        "code": empty_str_to_json_null(capnp_object.get_code()?),
+        "name": empty_str_to_json_null(capnp_object.get_name()?),
+        "color": empty_str_to_json_null(capnp_object.get_color()?),
+        "description": empty_str_to_json_null(capnp_object.get_description()?),
+        "routing_radius_meters": minus_one_i64_to_null(capnp_object.get_routing_radius_meters() as i64),
+        "default_dwell_time_seconds": minus_one_i64_to_null(capnp_object.get_default_dwell_time_seconds() as i64),
+        "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
+        "is_enabled": i8_to_json_boolean(capnp_object.get_is_enabled()),
+        "data": data_attributes
+    });
+
+    Ok(json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "Point",
+            "coordinates": [longitude, latitude]
+        },
+        "id": integer_id,
+        "properties": properties_json
+    }))
+
+}
+
+
 #[cfg(test)]
 mod tests {
 
@@ -263,14 +306,15 @@ mod tests {
             &routers::node_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["nodes"], json_compare_data);
 
+        let response = routers::read_collection_stream_route("nodes", "nodes", true, &config, routers::node_collection_router::read_items);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(routers::tests::response_json(response)["data"]["nodes"], json_compare_data);
+
 
 
 
@@ -356,12 +400,9 @@ mod tests {
             &routers::node_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["nodes"], json_compare_data);
 
     }
@@ -8,16 +8,17 @@
 
 use crate::node_capnp::{node};
 //use crate::my_error::MyError;
-use std::fs::File;
-use std::path::Path;
 //use std::fs;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::routers::{write_object_file, open_object_file};
 use serde_json;
 use std::io::BufReader;
-use geojson::{Value as GeojsonValue};
+use crate::validation::Validator;
 use crate::utils::{ 
-    required_string, 
-    optional_string_json_null_to_empty_str as optional_string, 
+    set_text_list,
+    set_primitive_list,
     json_boolean_to_i8, 
     empty_str_to_json_null, 
     i8_to_json_boolean,
@@ -28,122 +29,73 @@ use crate::utils::{
 pub fn write_object(
     cache_directory_path: &str,
     json: &serde_json::Value,
-    _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<(), CacheError> {
 
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
-    let mut json_object = json["node"].clone();
-    let object_uuid = match json_object.get("id") {
-        Some(json_object_id) => {
-            json_object_id.as_str().unwrap()
-        },
-        // TODO: deal with empty or invalid uuids here with custom error:
-        None => {
-            ""
-        }
-    };
-
-    // TODO: deal with empty or invalid uuids here with custom error:
-    /*if (object_uuid == "")
-    {
-        Err(MyError::new("node uuid is invalid or empty"))
-    }*/
-
-    let object_file_path_name = format!("{}/node_{}.capnpbin", cache_directory_path, object_uuid);
-    let path = Path::new(&object_file_path_name);
-
-    let file_check = File::create(&path);
-
-    let file = file_check.unwrap();
-
-    /* TODO: deal with error when creating object file using uuid */
+    let json_object = &json["node"];
+    let mut fields = validator.fields(json_object, "/node");
+    let object_uuid = fields.required_uuid("id");
     
     let mut capnp_data = message.init_root::<node::Builder>();
 
-    let geometry = geojson::Geometry::from_json_value(json_object["geography"].clone());
-    let geojson_value = geometry.unwrap().value;
-
-    let latitude;
-    let longitude;
-    match geojson_value {
-        GeojsonValue::Point(point) => {
-            longitude = (point[0] * 1000000.0).round() as i32;
-            latitude  = (point[1] * 1000000.0).round() as i32;
-        },
-        _ => {
-            longitude = -1;
-            latitude  = -1;
-        }
-    }
-
-    capnp_data.set_uuid(&required_string(json_object.get("id"))); // required
-    capnp_data.set_station_uuid(optional_string(json_object.get("station_id")));
-    capnp_data.set_internal_id(optional_string(json_object.get("internal_id")));
-    capnp_data.set_code(optional_string(json_object.get("code")));
-    capnp_data.set_name(optional_string(json_object.get("name")));
-    capnp_data.set_color(optional_string(json_object.get("color")));
-    capnp_data.set_description(optional_string(json_object.get("description")));
-    capnp_data.set_routing_radius_meters(json_value_or_null_to_i64_or_minus_one(&json_object.get("routing_radius_meters").unwrap_or(&json!(null))) as i16);
-    capnp_data.set_default_dwell_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_object.get("default_dwell_time_seconds").unwrap_or(&json!(null))) as i16);
-    capnp_data.set_data(&json_object.get("data").unwrap_or(&json!({})).to_string().as_str());
-    capnp_data.set_is_frozen(json_boolean_to_i8(&json_object.get("is_frozen").unwrap_or(&json!(null))));
-    capnp_data.set_is_enabled(json_boolean_to_i8(&json_object.get("is_enabled").unwrap_or(&json!(null))));
+    let (longitude, latitude) = fields.point("geography");
+
+    capnp_data.set_uuid(object_uuid);
+    capnp_data.set_station_uuid(fields.optional_string("station_id"));
+    capnp_data.set_internal_id(fields.optional_string("internal_id"));
+    capnp_data.set_code(fields.optional_string("code"));
+    capnp_data.set_name(fields.optional_string("name"));
+    capnp_data.set_color(fields.optional_string("color"));
+    capnp_data.set_description(fields.optional_string("description"));
+    capnp_data.set_routing_radius_meters(json_value_or_null_to_i64_or_minus_one(&json_object["routing_radius_meters"]) as i16);
+    capnp_data.set_default_dwell_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_object["default_dwell_time_seconds"]) as i16);
+    capnp_data.set_data(json_object.get("data").unwrap_or(&json!({})).to_string().as_str());
+    capnp_data.set_is_frozen(json_boolean_to_i8(&json_object["is_frozen"]));
+    capnp_data.set_is_enabled(json_boolean_to_i8(&json_object["is_enabled"]));
     capnp_data.set_latitude(latitude);
     capnp_data.set_longitude(longitude);
 
-    if !json_object.get("integer_id").unwrap_or(&json!(null)).is_null() { // only save transferable node if the node has an integer id, otherwise, we get null indexes
-        capnp_data.set_id(json_object.get("integer_id").unwrap().as_u64().unwrap() as u32); // required
+    if !fields.get("integer_id").is_null() { // only save transferable node if the node has an integer id, otherwise, we get null indexes
+        capnp_data.set_id(fields.required_u32("integer_id"));
 
-        if json_object.get("data") != None && json_object["data"].is_object() && json_object["data"].get("transferableNodes") != None && json_object["data"]["transferableNodes"].is_object() && json_object["data"]["transferableNodes"].get("nodesIds") != None && json_object["data"]["transferableNodes"]["nodesIds"].is_array()
+        let transferable_nodes = &json_object["data"]["transferableNodes"];
+        if transferable_nodes["nodesIds"].is_array()
         {
-            let transferable_nodes_count = json_object["data"]["transferableNodes"]["nodesIds"].as_array().unwrap().len();
-            capnp_data.reborrow().init_transferable_nodes_uuids(transferable_nodes_count as u32);
-            capnp_data.reborrow().init_transferable_nodes_travel_times(transferable_nodes_count as u32);
-            capnp_data.reborrow().init_transferable_nodes_distances(transferable_nodes_count as u32);
-
-            for j in 0..transferable_nodes_count
-            {
-                capnp_data.reborrow().get_transferable_nodes_uuids().unwrap().set(j as u32, json_object["data"]["transferableNodes"]["nodesIds"][j].as_str().unwrap());
-                capnp_data.reborrow().get_transferable_nodes_travel_times().unwrap().set(j as u32, json_object["data"]["transferableNodes"]["walkingTravelTimesSeconds"][j].as_i64().unwrap_or(-1) as i16);
-                capnp_data.reborrow().get_transferable_nodes_distances().unwrap().set(j as u32, json_object["data"]["transferableNodes"]["walkingDistancesMeters"][j].as_i64().unwrap_or(-1) as i16);
-            }
-
-            // remove transferableNodes and associates:
-            json_object["data"]["transferableNodes"]["nodesIds"].take();
-            json_object["data"]["transferableNodes"]["walkingTravelTimesSeconds"].take();
-            json_object["data"]["transferableNodes"]["walkingDistancesMeters"].take();
+            let transferable_nodes_pointer = "/node/data/transferableNodes";
+            let nodes_uuids = validator.string_array(&transferable_nodes["nodesIds"], &format!("{}/nodesIds", transferable_nodes_pointer));
+            let travel_times: Vec<i16> = (0..nodes_uuids.len()).map(|j| transferable_nodes["walkingTravelTimesSeconds"][j].as_i64().unwrap_or(-1) as i16).collect();
+            let distances: Vec<i16> = (0..nodes_uuids.len()).map(|j| transferable_nodes["walkingDistancesMeters"][j].as_i64().unwrap_or(-1) as i16).collect();
+
+            let nodes_count = nodes_uuids.len() as u32;
+            set_text_list(capnp_data.reborrow().init_transferable_nodes_uuids(nodes_count), &nodes_uuids);
+            set_primitive_list(capnp_data.reborrow().init_transferable_nodes_travel_times(nodes_count), &travel_times);
+            set_primitive_list(capnp_data.reborrow().init_transferable_nodes_distances(nodes_count), &distances);
         }
     }
 
-    serialize_packed::write_message(file, &message)
+    validator.into_result()?;
+
+    write_object_file("node", object_uuid, cache_directory_path, &message, config)
 
 }
 
 
 pub fn read_object(
-    object_uuid: &String,
+    object_uuid: &str,
     cache_directory_path: &str,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let object_file_path_name = format!("{}/node_{}.capnpbin", cache_directory_path, object_uuid);
-    let path = Path::new(&object_file_path_name);
-    let file = File::open(&path);
-    
-    let file = match file {
-        Ok(f) => f,
-        Err(e) => {
-            println!("Error opening node file {}: {}", object_file_path_name, e);
-            return Err(capnp::Error::failed(String::from("Cannot read node file")));
-        }
-    };
+    let file = open_object_file("node", object_uuid, cache_directory_path)?;
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "nodes").reader_options())?;
     let capnp_object = message_reader.get_root::<node::Reader>()?;
     
     let integer_id = capnp_object.get_id() as u32;
-    let mut data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
+    let mut data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
     let latitude  = (capnp_object.get_latitude() as f64)/1000000.0;
     let longitude = (capnp_object.get_longitude() as f64)/1000000.0;
 
@@ -156,7 +108,7 @@ pub fn read_object(
         });
         let mut transferable_nodes_uuids : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_transferable_nodes_uuids()?.len() as usize);
         for transferable_node_uuid in capnp_object.get_transferable_nodes_uuids()?.iter() {
-            transferable_nodes_uuids.push(json!(transferable_node_uuid.unwrap()));
+            transferable_nodes_uuids.push(json!(transferable_node_uuid?));
         }
         data_attributes["transferableNodes"]["nodesIds"] = json!(transferable_nodes_uuids);
 
@@ -313,12 +265,9 @@ mod tests {
             &routers::node_router::read_object,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["node"], json_compare_data["node"]);
 
         // Test unexisting node
@@ -330,13 +279,10 @@ mod tests {
             &routers::node_router::read_object,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
+        assert_eq!(response.status_code, 404);
 
-        // TODO Response code should be 404 not found
-        assert_eq!(response.status_code, 200);
+        let json_response = routers::tests::response_json(response);
+        assert_eq!(json_response["errorCode"], "NOT_FOUND");
         assert!(json_response["data"].is_null());
 
     }
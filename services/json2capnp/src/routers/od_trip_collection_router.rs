@@ -8,13 +8,18 @@
 
 use crate::odTripCollection_capnp::od_trip_collection as collection;
 //use crate::my_error::MyError;
+use crate::odTrip_capnp::od_trip;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::{CacheCollection, CacheObject, ItemsStream, ReadItemsFn};
+use crate::json_stream::JsonItems;
 use serde_json;
 use std::io::BufReader;
-use geojson::{Value as GeojsonValue};
+use crate::validation::{Validator, Fields};
 use crate::utils::{ 
-    required_string, 
-    optional_string_json_null_to_empty_str as optional_string, 
+    set_text_list,
+    set_primitive_list,
     json_boolean_to_i8, 
     empty_str_to_json_null, 
     i8_to_json_boolean,
@@ -24,217 +29,256 @@ use crate::utils::{
     json_value_or_null_to_f64_or_minus_one
 };
 
+pub struct OdTripCollection;
+
+impl CacheCollection for OdTripCollection {
+
+    fn name(&self) -> &'static str {
+        "odTrips"
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+    fn read_items(&self) -> Option<ReadItemsFn> {
+        Some(read_items)
+    }
+
+    fn items_stream(&self) -> Option<ItemsStream> {
+        Some(ItemsStream {
+            write_item : write_od_trip_item,
+            write_items: write_od_trip_items,
+            read_items
+        })
+    }
+
+    fn object(&self) -> Option<CacheObject> {
+        Some(CacheObject {
+            name        : "odTrip",
+            subdirectory: "odTrips",
+            write_object: crate::routers::od_trip_router::write_object,
+            read_object : crate::routers::od_trip_router::read_object
+        })
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
-    let json_objects = &json["odTrips"];
+    let json_objects = validator.array(&json["odTrips"], "/odTrips");
 
-    let count: usize = json_objects.as_array().unwrap().len();
+    let count: usize = json_objects.len();
     let collection_capnp = message.init_root::<collection::Builder>();
     let mut capnp = collection_capnp.init_od_trips(count as u32);
 
-    for i in 0..count {
-        let mut json_data = json_objects[i].clone();
-
-        let origin_geometry = geojson::Geometry::from_json_value(json_data["origin_geography"].clone());
-        let origin_geojson_value = origin_geometry.unwrap().value;
-        let origin_latitude;
-        let origin_longitude;
-        match origin_geojson_value {
-            GeojsonValue::Point(point) => {
-                origin_longitude = (point[0] * 1000000.0).round() as i32;
-                origin_latitude  = (point[1] * 1000000.0).round() as i32;
-            },
-            _ => {
-                origin_longitude = -1;
-                origin_latitude  = -1;
-            }
-        }
+    for (i, json_data) in json_objects.iter().enumerate() {
+        let fields = validator.fields(json_data, &format!("/odTrips/{}", i));
+        write_od_trip(capnp.reborrow().get(i as u32), json_data, fields);
+    }
 
-        let destination_geometry = geojson::Geometry::from_json_value(json_data["destination_geography"].clone());
-        let destination_geojson_value = destination_geometry.unwrap().value;
-        let destination_latitude;
-        let destination_longitude;
-        match destination_geojson_value {
-            GeojsonValue::Point(point) => {
-                destination_longitude = (point[0] * 1000000.0).round() as i32;
-                destination_latitude  = (point[1] * 1000000.0).round() as i32;
-            },
-            _ => {
-                destination_longitude = -1;
-                destination_latitude  = -1;
-            }
-        }
+    validator.into_result()?;
 
-        let mut capnp_data = capnp.reborrow().get(i as u32);
-        capnp_data.set_uuid(&required_string(json_data.get("id")));
-        capnp_data.set_person_uuid(&optional_string(json_data.get("person_id")));
-        capnp_data.set_household_uuid(&optional_string(json_data.get("household_id")));
-        capnp_data.set_data_source_uuid(&optional_string(json_data.get("data_source_id")));
-        capnp_data.set_id(json_data.get("integer_id").unwrap().as_u64().unwrap() as u32); // required
-        capnp_data.set_internal_id(&optional_string(json_data.get("internal_id")));
-        capnp_data.set_data(&json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
-        capnp_data.set_is_frozen(json_boolean_to_i8(json_data.get("is_frozen").unwrap_or(&json!(null))));
-        capnp_data.set_expansion_factor(json_value_or_null_to_f64_or_minus_one(&json_data.get("expansion_factor").unwrap_or(&json!(null))) as f32);
-        capnp_data.set_departure_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data.get("departure_time_seconds").unwrap_or(&json!(null))) as i32);
-        capnp_data.set_arrival_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data.get("arrival_time_seconds").unwrap_or(&json!(null))) as i32);
-        capnp_data.set_walking_travel_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data.get("walking_travel_time_seconds").unwrap_or(&json!(null))) as i32);
-        capnp_data.set_cycling_travel_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data.get("cycling_travel_time_seconds").unwrap_or(&json!(null))) as i32);
-        capnp_data.set_driving_travel_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data.get("driving_travel_time_seconds").unwrap_or(&json!(null))) as i32);
-
-        capnp_data.set_mode(crate::enum_mappings::mode(&json_data["mode"].as_str().unwrap_or("none")));
-        capnp_data.set_origin_activity(crate::enum_mappings::activity(&json_data["origin_activity"].as_str().unwrap_or("none")));
-        capnp_data.set_destination_activity(crate::enum_mappings::activity(&json_data["destination_activity"].as_str().unwrap_or("none")));
-        capnp_data.set_origin_latitude(origin_latitude);
-        capnp_data.set_origin_longitude(origin_longitude);
-        capnp_data.set_destination_latitude(destination_latitude);
-        capnp_data.set_destination_longitude(destination_longitude);
-
-        if json_data.get("data") != None && json_data["data"].is_object() && json_data["data"].get("originNodes") != None && json_data["data"]["originNodes"].is_array()
-        {
-            let nodes_count = json_data["data"]["originNodes"].as_array().unwrap().len();
-            capnp_data.reborrow().init_origin_nodes_uuids(nodes_count as u32);
-            capnp_data.reborrow().init_origin_nodes_travel_times(nodes_count as u32);
-            capnp_data.reborrow().init_origin_nodes_distances(nodes_count as u32);
-            for j in 0..nodes_count
-            {
-                capnp_data.reborrow().get_origin_nodes_uuids().unwrap().set(j as u32, json_data["data"]["originNodes"][j].as_str().unwrap());
-                capnp_data.reborrow().get_origin_nodes_travel_times().unwrap().set(j as u32, json_data["data"]["originNodesTravelTimes"][j].as_i64().unwrap() as i16);
-                capnp_data.reborrow().get_origin_nodes_distances().unwrap().set(j as u32, json_data["data"]["originNodesDistances"][j].as_i64().unwrap() as i16);
-            }
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
+}
 
-            // remove originNodes and associates:
-            json_data["data"]["originNodes"].take();
-            json_data["data"]["originNodesTravelTimes"].take();
-            json_data["data"]["originNodesDistances"].take();
-        }
 
-        if json_data.get("data") != None && json_data["data"].is_object() && json_data["data"].get("destinationNodes") != None && json_data["data"]["destinationNodes"].is_array()
-        {
-            let nodes_count = json_data["data"]["destinationNodes"].as_array().unwrap().len();
-            capnp_data.reborrow().init_destination_nodes_uuids(nodes_count as u32);
-            capnp_data.reborrow().init_destination_nodes_travel_times(nodes_count as u32);
-            capnp_data.reborrow().init_destination_nodes_distances(nodes_count as u32);
-            for j in 0..nodes_count
-            {
-                capnp_data.reborrow().get_destination_nodes_uuids().unwrap().set(j as u32, json_data["data"]["destinationNodes"][j].as_str().unwrap());
-                capnp_data.reborrow().get_destination_nodes_travel_times().unwrap().set(j as u32, json_data["data"]["destinationNodesTravelTimes"][j].as_i64().unwrap() as i16);
-                capnp_data.reborrow().get_destination_nodes_distances().unwrap().set(j as u32, json_data["data"]["destinationNodesDistances"][j].as_i64().unwrap() as i16);
-            }
+/// Write a od trip in its own message, to be copied in the collection by
+/// write_od_trip_items once all the od trips of a streamed body are parsed
+pub fn write_od_trip_item(json_data: &serde_json::Value, fields: Fields) -> Result<Vec<u8>, CacheError> {
+    let mut message = ::capnp::message::Builder::new_default();
+    write_od_trip(message.init_root::<od_trip::Builder>(), json_data, fields);
+    let mut item = Vec::new();
+    serialize_packed::write_message(&mut item, &message).map_err(CacheError::from_write_error)?;
+    Ok(item)
+}
 
-            // remove destinationNodes and associates:
-            json_data["data"]["destinationNodes"].take();
-            json_data["data"]["destinationNodesTravelTimes"].take();
-            json_data["data"]["destinationNodesDistances"].take();
-        }
+pub fn write_od_trip_items(items: Vec<Vec<u8>>, file: &mut std::fs::File) -> Result<(), CacheError> {
+    let mut message = ::capnp::message::Builder::new_default();
+    let collection_capnp = message.init_root::<collection::Builder>();
+    let capnp = collection_capnp.init_od_trips(items.len() as u32);
 
+    for (i, item) in items.into_iter().enumerate() {
+        let item_message = serialize_packed::read_message(item.as_slice(), ::capnp::message::ReaderOptions::new())?;
+        capnp.set_with_caveats(i as u32, item_message.get_root::<od_trip::Reader>()?)?;
     }
 
-    serialize_packed::write_message(file, &message)
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
 }
 
-
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "odTrips").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_od_trips()?.len() as usize);
 
     for capnp_object in capnp_collection.get_od_trips()?.iter() {
-        
-        let mut data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
-        let origin_latitude  = (capnp_object.get_origin_latitude() as f64)/1000000.0;
-        let origin_longitude = (capnp_object.get_origin_longitude() as f64)/1000000.0;
-        let destination_latitude  = (capnp_object.get_destination_latitude() as f64)/1000000.0;
-        let destination_longitude = (capnp_object.get_destination_longitude() as f64)/1000000.0;
-
-        if capnp_object.has_origin_nodes_uuids()
-        {
-            let mut origin_nodes_uuids : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_origin_nodes_uuids()?.len() as usize);
-            for origin_node_uuid in capnp_object.get_origin_nodes_uuids()?.iter() {
-                origin_nodes_uuids.push(json!(origin_node_uuid.unwrap()));
-            }
-            data_attributes["originNodes"] = json!(origin_nodes_uuids);
+        collection_json_vec.push(read_od_trip(capnp_object)?);
+    }
 
-            let mut origin_nodes_travel_times : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_origin_nodes_travel_times()?.len() as usize);
-            for origin_node_travel_time in capnp_object.get_origin_nodes_travel_times()?.iter() {
-                origin_nodes_travel_times.push(json!(origin_node_travel_time));
-            }
-            data_attributes["originNodesTravelTimes"] = json!(origin_nodes_travel_times);
+    let collection_json = json!({
+        "odTrips": serde_json::Value::Array(collection_json_vec)
+    });
 
-            let mut origin_nodes_distances : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_origin_nodes_distances()?.len() as usize);
-            for origin_node_distance in capnp_object.get_origin_nodes_distances()?.iter() {
-                origin_nodes_distances.push(json!(origin_node_distance));
-            }
-            data_attributes["originNodesDistances"] = json!(origin_nodes_distances);
-        }
+    Ok(collection_json)
 
-        if capnp_object.has_destination_nodes_uuids()
-        {
-            let mut destination_nodes_uuids : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_destination_nodes_uuids()?.len() as usize);
-            for destination_node_uuid in capnp_object.get_destination_nodes_uuids()?.iter() {
-                destination_nodes_uuids.push(json!(destination_node_uuid.unwrap()));
-            }
-            data_attributes["destinationNodes"] = json!(destination_nodes_uuids);
+}
 
-            let mut destination_nodes_travel_times : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_destination_nodes_travel_times()?.len() as usize);
-            for destination_node_travel_time in capnp_object.get_destination_nodes_travel_times()?.iter() {
-                destination_nodes_travel_times.push(json!(destination_node_travel_time));
-            }
-            data_attributes["destinationNodesTravelTimes"] = json!(destination_nodes_travel_times);
 
-            let mut destination_nodes_distances : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_destination_nodes_distances()?.len() as usize);
-            for destination_node_distance in capnp_object.get_destination_nodes_distances()?.iter() {
-                destination_nodes_distances.push(json!(destination_node_distance));
-            }
-            data_attributes["destinationNodesDistances"] = json!(destination_nodes_distances);
+/// Read the odTrips one by one, for streamed responses
+pub fn read_items(
+    file: &mut std::fs::File,
+    config: &serde_json::Value,
+) -> Result<JsonItems, CacheError> {
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "odTrips").reader_options())?;
+    let count = message_reader.get_root::<collection::Reader>()?.get_od_trips()?.len() as usize;
+
+    Ok(JsonItems::new(count, move |i| {
+        read_od_trip(message_reader.get_root::<collection::Reader>()?.get_od_trips()?.get(i as u32))
+    }))
+
+}
+
+/// Write an od trip, for the collection and the single od_trip caches
+pub fn write_od_trip(mut capnp_data: od_trip::Builder, json_data: &serde_json::Value, mut fields: Fields) {
+    let (origin_longitude, origin_latitude) = fields.point("origin_geography");
+    let (destination_longitude, destination_latitude) = fields.point("destination_geography");
+
+    capnp_data.set_uuid(fields.required_string("id"));
+    capnp_data.set_person_uuid(fields.optional_string("person_id"));
+    capnp_data.set_household_uuid(fields.optional_string("household_id"));
+    capnp_data.set_data_source_uuid(fields.optional_string("data_source_id"));
+    capnp_data.set_id(fields.required_u32("integer_id"));
+    capnp_data.set_internal_id(fields.optional_string("internal_id"));
+    capnp_data.set_data(json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
+    capnp_data.set_is_frozen(json_boolean_to_i8(&json_data["is_frozen"]));
+    capnp_data.set_expansion_factor(json_value_or_null_to_f64_or_minus_one(&json_data["expansion_factor"]) as f32);
+    capnp_data.set_departure_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data["departure_time_seconds"]) as i32);
+    capnp_data.set_arrival_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data["arrival_time_seconds"]) as i32);
+    capnp_data.set_walking_travel_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data["walking_travel_time_seconds"]) as i32);
+    capnp_data.set_cycling_travel_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data["cycling_travel_time_seconds"]) as i32);
+    capnp_data.set_driving_travel_time_seconds(json_value_or_null_to_i64_or_minus_one(&json_data["driving_travel_time_seconds"]) as i32);
+
+    capnp_data.set_mode(crate::enum_mappings::mode(json_data["mode"].as_str().unwrap_or("none")));
+    capnp_data.set_origin_activity(crate::enum_mappings::activity(json_data["origin_activity"].as_str().unwrap_or("none")));
+    capnp_data.set_destination_activity(crate::enum_mappings::activity(json_data["destination_activity"].as_str().unwrap_or("none")));
+    capnp_data.set_origin_latitude(origin_latitude);
+    capnp_data.set_origin_longitude(origin_longitude);
+    capnp_data.set_destination_latitude(destination_latitude);
+    capnp_data.set_destination_longitude(destination_longitude);
+
+    if let Some((nodes_uuids, travel_times, distances)) = fields.accessible_nodes("originNodes")
+    {
+        let nodes_count = nodes_uuids.len() as u32;
+        set_text_list(capnp_data.reborrow().init_origin_nodes_uuids(nodes_count), &nodes_uuids);
+        set_primitive_list(capnp_data.reborrow().init_origin_nodes_travel_times(nodes_count), &travel_times);
+        set_primitive_list(capnp_data.reborrow().init_origin_nodes_distances(nodes_count), &distances);
+    }
+
+    if let Some((nodes_uuids, travel_times, distances)) = fields.accessible_nodes("destinationNodes")
+    {
+        let nodes_count = nodes_uuids.len() as u32;
+        set_text_list(capnp_data.reborrow().init_destination_nodes_uuids(nodes_count), &nodes_uuids);
+        set_primitive_list(capnp_data.reborrow().init_destination_nodes_travel_times(nodes_count), &travel_times);
+        set_primitive_list(capnp_data.reborrow().init_destination_nodes_distances(nodes_count), &distances);
+    }
+}
+
+/// Read an od trip, for the collection and the single od_trip caches
+pub fn read_od_trip(capnp_object: od_trip::Reader) -> Result<serde_json::Value, CacheError> {
+
+    let mut data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
+    let origin_latitude  = (capnp_object.get_origin_latitude() as f64)/1000000.0;
+    let origin_longitude = (capnp_object.get_origin_longitude() as f64)/1000000.0;
+    let destination_latitude  = (capnp_object.get_destination_latitude() as f64)/1000000.0;
+    let destination_longitude = (capnp_object.get_destination_longitude() as f64)/1000000.0;
+
+    if capnp_object.has_origin_nodes_uuids()
+    {
+        let mut origin_nodes_uuids : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_origin_nodes_uuids()?.len() as usize);
+        for origin_node_uuid in capnp_object.get_origin_nodes_uuids()?.iter() {
+            origin_nodes_uuids.push(json!(origin_node_uuid?));
         }
+        data_attributes["originNodes"] = json!(origin_nodes_uuids);
 
-        let object_json : serde_json::Value = json!({
-            "id": capnp_object.get_uuid()?,
-            "integer_id": capnp_object.get_id(),
-            "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
-            "person_id": empty_str_to_json_null(capnp_object.get_person_uuid()?),
-            "household_id": empty_str_to_json_null(capnp_object.get_household_uuid()?),
-            "data_source_id": empty_str_to_json_null(capnp_object.get_data_source_uuid()?),
-            "mode": crate::enum_mappings::mode_to_str(&capnp_object.get_mode()?),
-            "origin_activity": crate::enum_mappings::activity_to_str(&capnp_object.get_origin_activity()?),
-            "destination_activity": crate::enum_mappings::activity_to_str(&capnp_object.get_destination_activity()?),
-            "expansion_factor": minus_one_f64_to_null(((capnp_object.get_expansion_factor() as f64)*100000.0).round() / 100000.0), // we must round to 5 decimals so we don't get numbers like 2.10000000345454 for n input value of 2.1
-            "departure_time_seconds": minus_one_i64_to_null(capnp_object.get_departure_time_seconds() as i64),
-            "arrival_time_seconds": minus_one_i64_to_null(capnp_object.get_arrival_time_seconds() as i64),
-            "walking_travel_time_seconds": minus_one_i64_to_null(capnp_object.get_walking_travel_time_seconds() as i64),
-            "cycling_travel_time_seconds": minus_one_i64_to_null(capnp_object.get_cycling_travel_time_seconds() as i64),
-            "driving_travel_time_seconds": minus_one_i64_to_null(capnp_object.get_driving_travel_time_seconds() as i64),
-            "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
-            "data": data_attributes,
-            "origin_geography": {
-                "type": "Point",
-                "coordinates": [origin_longitude, origin_latitude]
-            },
-            "destination_geography": {
-                "type": "Point",
-                "coordinates": [destination_longitude, destination_latitude]
-            }
-        });
+        let mut origin_nodes_travel_times : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_origin_nodes_travel_times()?.len() as usize);
+        for origin_node_travel_time in capnp_object.get_origin_nodes_travel_times()?.iter() {
+            origin_nodes_travel_times.push(json!(origin_node_travel_time));
+        }
+        data_attributes["originNodesTravelTimes"] = json!(origin_nodes_travel_times);
 
-        collection_json_vec.push(object_json);
+        let mut origin_nodes_distances : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_origin_nodes_distances()?.len() as usize);
+        for origin_node_distance in capnp_object.get_origin_nodes_distances()?.iter() {
+            origin_nodes_distances.push(json!(origin_node_distance));
+        }
+        data_attributes["originNodesDistances"] = json!(origin_nodes_distances);
+    }
+
+    if capnp_object.has_destination_nodes_uuids()
+    {
+        let mut destination_nodes_uuids : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_destination_nodes_uuids()?.len() as usize);
+        for destination_node_uuid in capnp_object.get_destination_nodes_uuids()?.iter() {
+            destination_nodes_uuids.push(json!(destination_node_uuid?));
+        }
+        data_attributes["destinationNodes"] = json!(destination_nodes_uuids);
 
+        let mut destination_nodes_travel_times : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_destination_nodes_travel_times()?.len() as usize);
+        for destination_node_travel_time in capnp_object.get_destination_nodes_travel_times()?.iter() {
+            destination_nodes_travel_times.push(json!(destination_node_travel_time));
+        }
+        data_attributes["destinationNodesTravelTimes"] = json!(destination_nodes_travel_times);
+
+        let mut destination_nodes_distances : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_destination_nodes_distances()?.len() as usize);
+        for destination_node_distance in capnp_object.get_destination_nodes_distances()?.iter() {
+            destination_nodes_distances.push(json!(destination_node_distance));
+        }
+        data_attributes["destinationNodesDistances"] = json!(destination_nodes_distances);
     }
 
-    let collection_json = json!({
-        "odTrips": serde_json::Value::Array(collection_json_vec)
+    let object_json : serde_json::Value = json!({
+        "id": capnp_object.get_uuid()?,
+        "integer_id": capnp_object.get_id(),
+        "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
+        "person_id": empty_str_to_json_null(capnp_object.get_person_uuid()?),
+        "household_id": empty_str_to_json_null(capnp_object.get_household_uuid()?),
+        "data_source_id": empty_str_to_json_null(capnp_object.get_data_source_uuid()?),
+        "mode": crate::enum_mappings::mode_to_str(&capnp_object.get_mode()?),
+        "origin_activity": crate::enum_mappings::activity_to_str(&capnp_object.get_origin_activity()?),
+        "destination_activity": crate::enum_mappings::activity_to_str(&capnp_object.get_destination_activity()?),
+        "expansion_factor": minus_one_f64_to_null(((capnp_object.get_expansion_factor() as f64)*100000.0).round() / 100000.0), // we must round to 5 decimals so we don't get numbers like 2.10000000345454 for n input value of 2.1
+        "departure_time_seconds": minus_one_i64_to_null(capnp_object.get_departure_time_seconds() as i64),
+        "arrival_time_seconds": minus_one_i64_to_null(capnp_object.get_arrival_time_seconds() as i64),
+        "walking_travel_time_seconds": minus_one_i64_to_null(capnp_object.get_walking_travel_time_seconds() as i64),
+        "cycling_travel_time_seconds": minus_one_i64_to_null(capnp_object.get_cycling_travel_time_seconds() as i64),
+        "driving_travel_time_seconds": minus_one_i64_to_null(capnp_object.get_driving_travel_time_seconds() as i64),
+        "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
+        "data": data_attributes,
+        "origin_geography": {
+            "type": "Point",
+            "coordinates": [origin_longitude, origin_latitude]
+        },
+        "destination_geography": {
+            "type": "Point",
+            "coordinates": [destination_longitude, destination_latitude]
+        }
     });
 
-    Ok(collection_json)
+    Ok(object_json)
 
 }
 
@@ -254,12 +298,12 @@ mod tests {
         let config: serde_json::Value = json!({
             "project_cache_directory_path": fs::canonicalize(Path::new("test")).unwrap(),
             "project_shortname"           : "test",
-            "data_source_uuid"            : "1234-5678"
+            "data_source_uuid"            : "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f"
         });
 
         let data = r##"
             {
-                "data_source_uuid": "1234-5678",
+                "data_source_uuid": "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f",
                 "odTrips": [
                     {
                         "id": "1234-1234",
@@ -412,12 +456,9 @@ mod tests {
             &routers::od_trip_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["odTrips"], json_compare_data["odTrips"]);
 
     }
@@ -0,0 +1,184 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use crate::odTrip_capnp::od_trip;
+use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use serde_json;
+use std::io::BufReader;
+use crate::validation::Validator;
+use crate::routers::{write_object_file, open_object_file};
+use crate::routers::od_trip_collection_router::{write_od_trip, read_od_trip};
+
+pub fn write_object(
+    cache_directory_path: &str,
+    json: &serde_json::Value,
+    config: &serde_json::Value,
+) -> Result<(), CacheError> {
+
+    let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
+
+    let json_object = &json["odTrip"];
+    let fields = validator.fields(json_object, "/odTrip");
+    write_od_trip(message.init_root::<od_trip::Builder>(), json_object, fields);
+    let object_uuid = validator.fields(json_object, "/odTrip").uuid_format("id");
+
+    validator.into_result()?;
+
+    write_object_file("odTrip", object_uuid, cache_directory_path, &message, config)
+
+}
+
+
+pub fn read_object(
+    object_uuid: &str,
+    cache_directory_path: &str,
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
+
+    let file = open_object_file("odTrip", object_uuid, cache_directory_path)?;
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "odTrips").reader_options())?;
+    let object_json    = read_od_trip(message_reader.get_root::<od_trip::Reader>()?)?;
+
+    Ok(json!({
+        "odTrip": object_json
+    }))
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use pretty_assertions::{assert_eq};
+    use crate::routers;
+    use std::path::{Path};
+    use std::fs;
+    use rouille::Request;
+
+    #[test]
+    fn od_trip() {
+
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test")).unwrap(),
+            "project_shortname"           : "test"
+        });
+
+        let data = r##"
+            {
+                "odTrip": {
+                    "id": "6e7f8a9b-0c1d-4e2f-9a3b-4c5d6e7f8a9b",
+                    "integer_id": 23,
+                    "is_frozen": false,
+                    "internal_id": "t21",
+                    "data_source_id": "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f",
+                    "person_id": "5d6e7f8a-9b0c-4d1e-8f2a-3b4c5d6e7f8a",
+                    "household_id": "3b4c5d6e-7f8a-4b9c-8d1e-2f3a4b5c6d7e",
+                    "departure_time_seconds": 54412,
+                    "arrival_time_seconds": 54612,
+                    "origin_activity": "workUsual",
+                    "destination_activity": "home",
+                    "mode": "transit",
+                    "expansion_factor": 23.1,
+                    "walking_travel_time_seconds": 2345,
+                    "cycling_travel_time_seconds": null,
+                    "driving_travel_time_seconds": 988,
+                    "origin_geography": {
+                        "type": "Point",
+                        "coordinates": [-73.45, 45.47]
+                    },
+                    "destination_geography": {
+                        "type": "Point",
+                        "coordinates": [-73.100115, 45.102445]
+                    },
+                    "data": {
+                        "originNodes": ["abc", "def"],
+                        "originNodesTravelTimes": [234, 567],
+                        "originNodesDistances": [1243, 3453]
+                    }
+                }
+            }
+        "##;
+
+        let compare_data = r##"
+            {
+                "odTrip": {
+                    "id": "6e7f8a9b-0c1d-4e2f-9a3b-4c5d6e7f8a9b",
+                    "integer_id": 23,
+                    "is_frozen": false,
+                    "internal_id": "t21",
+                    "data_source_id": "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f",
+                    "person_id": "5d6e7f8a-9b0c-4d1e-8f2a-3b4c5d6e7f8a",
+                    "household_id": "3b4c5d6e-7f8a-4b9c-8d1e-2f3a4b5c6d7e",
+                    "departure_time_seconds": 54412,
+                    "arrival_time_seconds": 54612,
+                    "origin_activity": "workUsual",
+                    "destination_activity": "home",
+                    "mode": "transit",
+                    "expansion_factor": 23.1,
+                    "walking_travel_time_seconds": 2345,
+                    "cycling_travel_time_seconds": null,
+                    "driving_travel_time_seconds": 988,
+                    "origin_geography": {
+                        "type": "Point",
+                        "coordinates": [-73.45, 45.47]
+                    },
+                    "destination_geography": {
+                        "type": "Point",
+                        "coordinates": [-73.100115, 45.102445]
+                    },
+                    "data": {
+                        "originNodes": ["abc", "def"],
+                        "originNodesTravelTimes": [234, 567],
+                        "originNodesDistances": [1243, 3453]
+                    }
+                }
+            }
+        "##;
+
+        let json_data : serde_json::Value = serde_json::from_str(data).unwrap();
+        let json_compare_data : serde_json::Value = serde_json::from_str(compare_data).unwrap();
+
+        let request = Request::fake_http(
+            "POST",
+            "/odTrip",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            data.as_bytes().to_vec(),
+        );
+
+        let response = routers::write_object_route(
+            "odTrip",
+            "odTrips",
+            &config,
+            &routers::od_trip_router::write_object,
+            &request,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let response = routers::read_object_route(
+            "odTrip",
+            json_data["odTrip"]["id"].as_str().unwrap(),
+            "odTrips",
+            &config,
+            &routers::od_trip_router::read_object,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
+        assert_eq!(json_response["data"]["odTrip"], json_compare_data["odTrip"]);
+
+    }
+}
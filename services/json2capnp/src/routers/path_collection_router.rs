@@ -7,180 +7,178 @@
  */
 
 use crate::pathCollection_capnp::path_collection as collection;
+use crate::pathCollection_capnp::path;
 //use crate::my_error::MyError;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::{CacheCollection, ReadItemsFn};
+use crate::json_stream::JsonItems;
 use serde_json;
 use std::io::BufReader;
-use geojson::GeoJson;
-use geobuf;
+use crate::validation::Validator;
 use protobuf::Message;
+use crate::utils::{ set_text_list, set_primitive_list };
 
-pub fn write_collection(
-    json: &serde_json::Value,
-    file: &mut std::fs::File,
-    _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
-    let mut message = ::capnp::message::Builder::new_default();
-    
-    let geojson_data : GeoJson = GeoJson::from_json_value((json["paths"]).clone()).unwrap();
-
-    match geojson_data {
-        geojson::GeoJson::FeatureCollection(feature_collection) => {
-
-            let features_count = feature_collection.features.len();
-            let collection_capnp = message.init_root::<collection::Builder>();
-            let mut capnp = collection_capnp.init_paths(features_count as u32);
-            for i in 0..features_count {
-                let feature        = &feature_collection.features[i];
-                let mut capnp_data = capnp.reborrow().get(i as u32);
-                let properties = feature.properties.as_ref().unwrap();
-                println!("path_id {}", properties.get("id").unwrap().as_str().unwrap());
-                capnp_data.set_uuid(properties.get("id").unwrap().as_str().unwrap()); // required
-                capnp_data.set_id(properties.get("integer_id").unwrap().as_i64().unwrap() as i32); // required
-                capnp_data.set_line_uuid(properties.get("line_id").unwrap().as_str().unwrap()); // required
-                capnp_data.set_direction(&crate::utils::json_null_to_empty_str(&properties.get("direction").unwrap_or(&json!(null))));
-                capnp_data.set_name(&crate::utils::json_null_to_empty_str(&properties.get("name").unwrap_or(&json!(null))));
-                capnp_data.set_internal_id(&crate::utils::json_null_to_empty_str(&properties.get("internal_id").unwrap_or(&json!(null))));
-                capnp_data.set_description(&crate::utils::json_null_to_empty_str(&properties.get("description").unwrap_or(&json!(null))));
-                capnp_data.set_data(&properties.get("data").unwrap_or(&json!({})).to_string().as_str());
-                capnp_data.set_is_frozen(crate::utils::json_boolean_to_i8(&properties.get("is_frozen").unwrap_or(&json!(null))));
-                capnp_data.set_is_enabled(crate::utils::json_boolean_to_i8(&properties.get("is_enabled").unwrap_or(&json!(null))));
-
-                if properties.get("nodes") != None
-                {
-                    let nodes_optional = properties.get("nodes").unwrap().as_array();
-                    let nodes = nodes_optional.unwrap();
-                    let nodes_count: usize = nodes.len();
-
-                    capnp_data.reborrow().init_nodes_uuids(nodes_count as u32);
-                    for j in 0..nodes_count
-                    {
-                        capnp_data.reborrow().get_nodes_uuids().unwrap().set(j as u32, nodes[j].as_str().unwrap());
-                    }
-                }
-                else
-                {
-                    capnp_data.reborrow().init_nodes_uuids(0);
-                }
+pub struct PathCollection;
 
-                if properties.get("stops") != None
-                {
-                    let stops_optional = properties.get("stops").unwrap().as_array();
-                    let stops = stops_optional.unwrap();
-                    let stops_count: usize = stops.len();
-                    capnp_data.reborrow().init_stops_uuids(stops_count as u32);
-                    for j in 0..stops_count
-                    {
-                        capnp_data.reborrow().get_stops_uuids().unwrap().set(j as u32, stops[j].as_str().unwrap());
-                    }
-                }
-                else
-                {
-                    capnp_data.reborrow().init_stops_uuids(0);
-                }
+impl CacheCollection for PathCollection {
 
-                if properties.get("segments") != None
-                {
-                    let segments_optional = properties.get("segments").unwrap().as_array();
-                    let segments = segments_optional.unwrap();
-                    let segments_count: usize = segments.len();
-                    capnp_data.reborrow().init_segments(segments_count as u32);
-                    for j in 0..segments_count
-                    {
-                        capnp_data.reborrow().get_segments().unwrap().set(j as u32, segments[j].as_i64().unwrap() as i32);
-                    }
-                }
-                else
-                {
-                    capnp_data.reborrow().init_segments(0);
-                }
+    fn name(&self) -> &'static str {
+        "paths"
+    }
 
-                let geojson_json = json!({
-                    "type": "Feature",
-                    "properties": {},
-                    "geometry": feature.geometry.as_ref().unwrap()
-                });
-                let geobuf = geobuf::encode::Encoder::encode(&geojson_json, 6, 2).unwrap().write_to_bytes().unwrap();
+    fn is_feature_collection(&self) -> bool {
+        true
+    }
 
-                capnp_data.set_geography(&geobuf);
-                //capnp_data.set_geography(&geojson_json["geometry"].to_string().as_str());
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
 
-            }
-            serialize_packed::write_message(file, &message)
-        },
-        _ => {
-            Err(capnp::Error::failed(String::from("Paths geojson is invalid, empty or not a FeatureCollection")))
-        }
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+    fn read_items(&self) -> Option<ReadItemsFn> {
+        Some(read_items)
     }
 
 }
 
+pub fn write_collection(
+    json: &serde_json::Value,
+    file: &mut std::fs::File,
+    _: &serde_json::Value,
+) -> Result<(), CacheError> {
+    let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
+
+    let features = validator.features(&json["paths"], "/paths");
+
+    let features_count = features.len();
+    let collection_capnp = message.init_root::<collection::Builder>();
+    let mut capnp = collection_capnp.init_paths(features_count as u32);
+    for (i, feature) in features.iter().enumerate() {
+        let mut capnp_data = capnp.reborrow().get(i as u32);
+        let properties = &feature["properties"];
+        let mut fields = validator.fields(properties, &format!("/paths/features/{}/properties", i));
+        let uuid = fields.required_string("id");
+        capnp_data.set_uuid(uuid);
+        capnp_data.set_id(fields.required_i32("integer_id"));
+        capnp_data.set_line_uuid(fields.required_string("line_id"));
+        capnp_data.set_direction(fields.optional_string("direction"));
+        capnp_data.set_name(fields.optional_string("name"));
+        capnp_data.set_internal_id(fields.optional_string("internal_id"));
+        capnp_data.set_description(fields.optional_string("description"));
+        capnp_data.set_data(properties.get("data").unwrap_or(&json!({})).to_string().as_str());
+        capnp_data.set_is_frozen(crate::utils::json_boolean_to_i8(&properties["is_frozen"]));
+        capnp_data.set_is_enabled(crate::utils::json_boolean_to_i8(&properties["is_enabled"]));
+
+        let nodes = fields.optional_string_array("nodes").unwrap_or_default();
+        set_text_list(capnp_data.reborrow().init_nodes_uuids(nodes.len() as u32), &nodes);
+
+        let stops = fields.optional_string_array("stops").unwrap_or_default();
+        set_text_list(capnp_data.reborrow().init_stops_uuids(stops.len() as u32), &stops);
+
+        let segments: Vec<i32> = fields.optional_i32_array("segments").unwrap_or_default();
+        set_primitive_list(capnp_data.reborrow().init_segments(segments.len() as u32), &segments);
+
+        let geobuf = validator.geobuf(&feature["geometry"], &format!("/paths/features/{}/geometry", i));
+        capnp_data.set_geography(&geobuf);
+    }
+
+    validator.into_result()?;
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
+}
+
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "paths").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_paths()?.len() as usize);
 
     for capnp_object in capnp_collection.get_paths()?.iter() {
-        
-        let integer_id = capnp_object.get_id() as i32;
-        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
-        let mut properties_json : serde_json::Value = json!({
-            "id": capnp_object.get_uuid()?,
-            "integer_id": integer_id,
-            "line_id": capnp_object.get_line_uuid()?,
-            "internal_id": crate::utils::empty_str_to_json_null(capnp_object.get_internal_id()?),
-            "direction": crate::utils::empty_str_to_json_null(capnp_object.get_direction()?),
-            "name": crate::utils::empty_str_to_json_null(capnp_object.get_name()?),
-            "description": crate::utils::empty_str_to_json_null(capnp_object.get_description()?),
-            "is_frozen": crate::utils::i8_to_json_boolean(capnp_object.get_is_frozen()),
-            "is_enabled": crate::utils::i8_to_json_boolean(capnp_object.get_is_enabled()),
-            "data": data_attributes
-        });
+        collection_json_vec.push(read_path_feature(capnp_object)?);
+    }
 
-        let mut nodes_uuids_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_nodes_uuids()?.len() as usize);
-        for node_uuid in capnp_object.get_nodes_uuids()?.iter() {
-            nodes_uuids_vec.push(json!(node_uuid.unwrap()));
+    Ok(json!({
+        "paths": {
+            "type": "FeatureCollection",
+            "features": serde_json::Value::Array(collection_json_vec)
         }
-        properties_json["nodes"] = json!(nodes_uuids_vec);
+    }))
 
-        let mut stops_uuids_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_stops_uuids()?.len() as usize);
-        for stop_uuid in capnp_object.get_stops_uuids()?.iter() {
-            stops_uuids_vec.push(json!(stop_uuid.unwrap()));
-        }
-        properties_json["stops"] = json!(stops_uuids_vec);
+}
 
-        let mut segments_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_segments()?.len() as usize);
-        for segment in capnp_object.get_segments()?.iter() {
-            segments_vec.push(json!(segment));
-        }
-        properties_json["segments"] = json!(segments_vec);
+/// Read the paths one by one, for streamed responses
+pub fn read_items(
+    file: &mut std::fs::File,
+    config: &serde_json::Value,
+) -> Result<JsonItems, CacheError> {
 
-        let mut geobuf_data = geobuf::geobuf_pb::Data::new();
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "paths").reader_options())?;
+    let count = message_reader.get_root::<collection::Reader>()?.get_paths()?.len() as usize;
 
-        geobuf_data.merge_from_bytes(&capnp_object.get_geography().unwrap()).unwrap();
-        let mut geojson : serde_json::Value = geobuf::decode::Decoder::decode(&geobuf_data).unwrap_or(json!({
-            "geometry": null
-        }));
+    Ok(JsonItems::new(count, move |i| {
+        read_path_feature(message_reader.get_root::<collection::Reader>()?.get_paths()?.get(i as u32))
+    }))
 
-        geojson["id"] = json!(integer_id);
-        geojson["properties"] = properties_json;
+}
 
-        collection_json_vec.push(geojson);
+/// Read a path as a geojson feature, for the collection
+pub fn read_path_feature(capnp_object: path::Reader) -> Result<serde_json::Value, CacheError> {
+
+    let integer_id = capnp_object.get_id() as i32;
+    let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
+    let mut properties_json : serde_json::Value = json!({
+        "id": capnp_object.get_uuid()?,
+        "integer_id": integer_id,
+        "line_id": capnp_object.get_line_uuid()?,
+        "internal_id": crate::utils::empty_str_to_json_null(capnp_object.get_internal_id()?),
+        "direction": crate::utils::empty_str_to_json_null(capnp_object.get_direction()?),
+        "name": crate::utils::empty_str_to_json_null(capnp_object.get_name()?),
+        "description": crate::utils::empty_str_to_json_null(capnp_object.get_description()?),
+        "is_frozen": crate::utils::i8_to_json_boolean(capnp_object.get_is_frozen()),
+        "is_enabled": crate::utils::i8_to_json_boolean(capnp_object.get_is_enabled()),
+        "data": data_attributes
+    });
+
+    let mut nodes_uuids_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_nodes_uuids()?.len() as usize);
+    for node_uuid in capnp_object.get_nodes_uuids()?.iter() {
+        nodes_uuids_vec.push(json!(node_uuid?));
+    }
+    properties_json["nodes"] = json!(nodes_uuids_vec);
 
+    let mut stops_uuids_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_stops_uuids()?.len() as usize);
+    for stop_uuid in capnp_object.get_stops_uuids()?.iter() {
+        stops_uuids_vec.push(json!(stop_uuid?));
     }
+    properties_json["stops"] = json!(stops_uuids_vec);
 
-    Ok(json!({
-        "paths": {
-            "type": "FeatureCollection",
-            "features": serde_json::Value::Array(collection_json_vec)
-        }
-    }))
+    let mut segments_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_segments()?.len() as usize);
+    for segment in capnp_object.get_segments()?.iter() {
+        segments_vec.push(json!(segment));
+    }
+    properties_json["segments"] = json!(segments_vec);
+
+    let mut geobuf_data = geobuf::geobuf_pb::Data::new();
+
+    geobuf_data.merge_from_bytes(capnp_object.get_geography()?)?;
+    let mut geojson : serde_json::Value = geobuf::decode::Decoder::decode(&geobuf_data).unwrap_or(json!({
+        "geometry": null
+    }));
+
+    geojson["id"] = json!(integer_id);
+    geojson["properties"] = properties_json;
+
+    Ok(geojson)
 
 }
 
@@ -409,13 +407,29 @@ mod tests {
             &routers::path_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["paths"], json_compare_data);
 
     }
+
+    #[test]
+    fn invalid_path_feature() {
+
+        // invalid data in a cache file is an error, not a panic in the middle of a streamed body:
+        let mut message = capnp::message::Builder::new_default();
+        let mut capnp_path = message.init_root::<super::path::Builder>();
+        capnp_path.set_uuid("9a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d");
+        capnp_path.set_data("{");
+        let error = super::read_path_feature(capnp_path.into_reader()).unwrap_err();
+        assert_eq!(error.code(), "CAPNP_DECODE_ERROR");
+
+        capnp_path = message.get_root::<super::path::Builder>().unwrap();
+        capnp_path.set_data("{}");
+        capnp_path.set_geography(&[0xff, 0xff]);
+        let error = super::read_path_feature(capnp_path.into_reader()).unwrap_err();
+        assert_eq!(error.code(), "CAPNP_DECODE_ERROR");
+
+    }
 }
@@ -0,0 +1,184 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use crate::person_capnp::person;
+use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use serde_json;
+use std::io::BufReader;
+use crate::validation::Validator;
+use crate::routers::{write_object_file, open_object_file};
+use crate::routers::person_collection_router::{write_person, read_person};
+
+pub fn write_object(
+    cache_directory_path: &str,
+    json: &serde_json::Value,
+    config: &serde_json::Value,
+) -> Result<(), CacheError> {
+
+    let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
+
+    let json_object = &json["person"];
+    let fields = validator.fields(json_object, "/person");
+    write_person(message.init_root::<person::Builder>(), json_object, fields);
+    let object_uuid = validator.fields(json_object, "/person").uuid_format("id");
+
+    validator.into_result()?;
+
+    write_object_file("person", object_uuid, cache_directory_path, &message, config)
+
+}
+
+
+pub fn read_object(
+    object_uuid: &str,
+    cache_directory_path: &str,
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
+
+    let file = open_object_file("person", object_uuid, cache_directory_path)?;
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "persons").reader_options())?;
+    let object_json    = read_person(message_reader.get_root::<person::Reader>()?)?;
+
+    Ok(json!({
+        "person": object_json
+    }))
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use pretty_assertions::{assert_eq};
+    use crate::routers;
+    use std::path::{Path};
+    use std::fs;
+    use rouille::Request;
+
+    #[test]
+    fn person() {
+
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test")).unwrap(),
+            "project_shortname"           : "test"
+        });
+
+        let data = r##"
+            {
+                "person": {
+                    "id": "5d6e7f8a-9b0c-4d1e-8f2a-3b4c5d6e7f8a",
+                    "integer_id": 123,
+                    "is_frozen": true,
+                    "internal_id": "p21",
+                    "data_source_id": "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f",
+                    "household_id": "3b4c5d6e-7f8a-4b9c-8d1e-2f3a4b5c6d7e",
+                    "driving_license_owner": true,
+                    "transit_pass_owner": false,
+                    "age": 28,
+                    "age_group": "ag2529",
+                    "gender": "female",
+                    "occupation": "fullTimeStudent",
+                    "expansion_factor": 32.787,
+                    "usual_work_place_geography": null,
+                    "usual_school_place_geography": {
+                        "type": "Point",
+                        "coordinates": [-73.100115, 45.102445]
+                    },
+                    "data": {
+                        "usualSchoolPlaceNodes": ["abcd", "defg"],
+                        "usualSchoolPlaceNodesTravelTimes": [891, 781],
+                        "usualSchoolPlaceNodesDistances": [466, 566]
+                    },
+                    "usual_work_place_walking_travel_time_seconds": null,
+                    "usual_work_place_cycling_travel_time_seconds": null,
+                    "usual_work_place_driving_travel_time_seconds": null,
+                    "usual_school_place_walking_travel_time_seconds": 515,
+                    "usual_school_place_cycling_travel_time_seconds": 616,
+                    "usual_school_place_driving_travel_time_seconds": 717
+                }
+            }
+        "##;
+
+        let compare_data = r##"
+            {
+                "person": {
+                    "id": "5d6e7f8a-9b0c-4d1e-8f2a-3b4c5d6e7f8a",
+                    "integer_id": 123,
+                    "is_frozen": true,
+                    "internal_id": "p21",
+                    "data_source_id": "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f",
+                    "household_id": "3b4c5d6e-7f8a-4b9c-8d1e-2f3a4b5c6d7e",
+                    "driving_license_owner": true,
+                    "transit_pass_owner": false,
+                    "age": 28,
+                    "age_group": "ag2529",
+                    "gender": "female",
+                    "occupation": "fullTimeStudent",
+                    "expansion_factor": 32.787,
+                    "usual_work_place_geography": null,
+                    "usual_school_place_geography": {
+                        "type": "Point",
+                        "coordinates": [-73.100115, 45.102445]
+                    },
+                    "data": {
+                        "usualSchoolPlaceNodes": ["abcd", "defg"],
+                        "usualSchoolPlaceNodesTravelTimes": [891, 781],
+                        "usualSchoolPlaceNodesDistances": [466, 566]
+                    },
+                    "usual_work_place_walking_travel_time_seconds": null,
+                    "usual_work_place_cycling_travel_time_seconds": null,
+                    "usual_work_place_driving_travel_time_seconds": null,
+                    "usual_school_place_walking_travel_time_seconds": 515,
+                    "usual_school_place_cycling_travel_time_seconds": 616,
+                    "usual_school_place_driving_travel_time_seconds": 717
+                }
+            }
+        "##;
+
+        let json_data : serde_json::Value = serde_json::from_str(data).unwrap();
+        let json_compare_data : serde_json::Value = serde_json::from_str(compare_data).unwrap();
+
+        let request = Request::fake_http(
+            "POST",
+            "/person",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            data.as_bytes().to_vec(),
+        );
+
+        let response = routers::write_object_route(
+            "person",
+            "persons",
+            &config,
+            &routers::person_router::write_object,
+            &request,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let response = routers::read_object_route(
+            "person",
+            json_data["person"]["id"].as_str().unwrap(),
+            "persons",
+            &config,
+            &routers::person_router::read_object,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
+        assert_eq!(json_response["data"]["person"], json_compare_data["person"]);
+
+    }
+}
@@ -8,169 +8,210 @@
 
 use crate::placeCollection_capnp::place_collection as collection;
 //use crate::my_error::MyError;
+use crate::place_capnp::place;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::{CacheCollection, CacheObject, ReadItemsFn};
+use crate::json_stream::JsonItems;
 use serde_json;
 use std::io::BufReader;
-use geojson::{Value as GeojsonValue};
-use geojson::GeoJson;
+use crate::validation::{Validator, Fields};
 use crate::utils::{ 
-    required_string, 
-    optional_string_json_null_to_empty_str as optional_string, 
+    set_text_list,
+    set_primitive_list,
     json_boolean_to_i8, 
     empty_str_to_json_null, 
     i8_to_json_boolean
 };
 
+pub struct PlaceCollection;
+
+impl CacheCollection for PlaceCollection {
+
+    fn name(&self) -> &'static str {
+        "places"
+    }
+
+    fn is_feature_collection(&self) -> bool {
+        true
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+    fn read_items(&self) -> Option<ReadItemsFn> {
+        Some(read_items)
+    }
+
+    fn object(&self) -> Option<CacheObject> {
+        Some(CacheObject {
+            name        : "place",
+            subdirectory: "places",
+            write_object: crate::routers::place_router::write_object,
+            read_object : crate::routers::place_router::read_object
+        })
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
-    let geojson_data : GeoJson = GeoJson::from_json_value((json["places"]).clone()).unwrap();
-
-    match geojson_data {
-        geojson::GeoJson::FeatureCollection(feature_collection) => {
+    let features = validator.features(&json["places"], "/places");
 
-            let features_count = feature_collection.features.len();
-            let collection_capnp = message.init_root::<collection::Builder>();
-            let mut capnp = collection_capnp.init_places(features_count as u32);
+    let features_count = features.len();
+    let collection_capnp = message.init_root::<collection::Builder>();
+    let mut capnp = collection_capnp.init_places(features_count as u32);
 
-            for i in 0..features_count {
-                let feature = &feature_collection.features[i];
-
-                let mut capnp_data = capnp.reborrow().get(i as u32);
-                let geojson_value = feature.geometry.to_owned().unwrap().value;
-                let latitude;
-                let longitude;
-                match geojson_value {
-                    GeojsonValue::Point(point) => {
-                        longitude = (point[0] * 1000000.0).round() as i32;
-                        latitude  = (point[1] * 1000000.0).round() as i32;
-                    },
-                    _ => {
-                        longitude = -1;
-                        latitude  = -1;
-                    }
-                }
-                let mut properties = feature.properties.clone().unwrap();
-
-                capnp_data.set_uuid(&required_string(properties.get("id")));
-                capnp_data.set_data_source_uuid(&optional_string(properties.get("data_source_id")));
-                capnp_data.set_id(properties.get("integer_id").unwrap().as_u64().unwrap() as u32); // required
-                capnp_data.set_internal_id(&optional_string(properties.get("internal_id")));
-                capnp_data.set_shortname(&optional_string(properties.get("shortname")));
-                capnp_data.set_name(&optional_string(properties.get("name")));
-                capnp_data.set_description(&optional_string(properties.get("description")));
-                capnp_data.set_data(&properties.get("data").unwrap_or(&json!({})).to_string().as_str());
-                capnp_data.set_is_frozen(json_boolean_to_i8(properties.get("is_frozen").unwrap_or(&json!(null))));
-                capnp_data.set_latitude(latitude);
-                capnp_data.set_longitude(longitude);
-            
-                if properties.get("data") != None && properties["data"].is_object() && properties["data"].get("nodes") != None && properties["data"]["nodes"].is_array()
-                {
-                    let nodes_count = properties["data"]["nodes"].as_array().unwrap().len();
-                    capnp_data.reborrow().init_nodes_uuids(nodes_count as u32);
-                    capnp_data.reborrow().init_nodes_travel_times(nodes_count as u32);
-                    capnp_data.reborrow().init_nodes_distances(nodes_count as u32);
-                    for j in 0..nodes_count
-                    {
-                        capnp_data.reborrow().get_nodes_uuids().unwrap().set(j as u32, properties["data"]["nodes"][j].as_str().unwrap());
-                        capnp_data.reborrow().get_nodes_travel_times().unwrap().set(j as u32, properties["data"]["nodesTravelTimes"][j].as_i64().unwrap() as i16);
-                        capnp_data.reborrow().get_nodes_distances().unwrap().set(j as u32, properties["data"]["nodesDistances"][j].as_i64().unwrap() as i16);
-                    }
-                
-                    // remove nodes and associates:
-                    properties["data"]["nodes"].take();
-                    properties["data"]["nodesTravelTimes"].take();
-                    properties["data"]["nodesDistances"].take();
-                }
-            }
-            
-            serialize_packed::write_message(file, &message)
-        },
-        _ => {
-            Err(capnp::Error::failed(String::from("Zones geojson is invalid, empty or not a FeatureCollection")))
-        }
+    for (i, feature) in features.iter().enumerate() {
+        let point = validator.fields(feature, &format!("/places/features/{}", i)).point("geometry");
+        let properties = &feature["properties"];
+        let fields = validator.fields(properties, &format!("/places/features/{}/properties", i));
+        write_place(capnp.reborrow().get(i as u32), properties, fields, point);
     }
 
+    validator.into_result()?;
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
 }
 
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "places").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_places()?.len() as usize);
 
     for capnp_object in capnp_collection.get_places()?.iter() {
-        
-        let mut data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
-        
-        let latitude  = (capnp_object.get_latitude() as f64)/1000000.0;
-        let longitude = (capnp_object.get_longitude() as f64)/1000000.0;
-
-        if capnp_object.has_nodes_uuids()
-        {
-            let mut nodes_uuids : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_nodes_uuids()?.len() as usize);
-            for node_uuid in capnp_object.get_nodes_uuids()?.iter() {
-                nodes_uuids.push(json!(node_uuid.unwrap()));
-            }
-            data_attributes["nodes"] = json!(nodes_uuids);
 
-            let mut nodes_travel_times : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_nodes_travel_times()?.len() as usize);
-            for node_travel_time in capnp_object.get_nodes_travel_times()?.iter() {
-                nodes_travel_times.push(json!(node_travel_time));
-            }
-            data_attributes["nodesTravelTimes"] = json!(nodes_travel_times);
+        collection_json_vec.push(read_place_feature(capnp_object)?);
 
-            let mut nodes_distances : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_nodes_distances()?.len() as usize);
-            for node_distance in capnp_object.get_nodes_distances()?.iter() {
-                nodes_distances.push(json!(node_distance));
-            }
-            data_attributes["nodesDistances"] = json!(nodes_distances);
+    }
+
+    Ok(json!({
+        "places": {
+            "type": "FeatureCollection",
+            "features": serde_json::Value::Array(collection_json_vec)
         }
+    }))
 
-        let integer_id = capnp_object.get_id() as u32;
-
-        let properties_json : serde_json::Value = json!({
-            "id": capnp_object.get_uuid()?,
-            "integer_id": integer_id,
-            "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
-            "shortname": empty_str_to_json_null(capnp_object.get_shortname()?),
-            "name": empty_str_to_json_null(capnp_object.get_name()?),
-            "description": empty_str_to_json_null(capnp_object.get_description()?),
-            "data_source_id": empty_str_to_json_null(capnp_object.get_data_source_uuid()?),
-            "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
-            "data": data_attributes
-        });
+}
 
-        let mut geojson = json!({
-            "type": "Feature",
-            "geometry": {
-                "type": "Point",
-                "coordinates": [longitude, latitude]
-            }
-        });
 
-        geojson["id"]         = json!(integer_id);
-        geojson["properties"] = properties_json;
+/// Read the places one by one, for streamed responses
+pub fn read_items(
+    file: &mut std::fs::File,
+    config: &serde_json::Value,
+) -> Result<JsonItems, CacheError> {
 
-        collection_json_vec.push(geojson);
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "places").reader_options())?;
+    let count = message_reader.get_root::<collection::Reader>()?.get_places()?.len() as usize;
 
-    }
+    Ok(JsonItems::new(count, move |i| {
+        read_place_feature(message_reader.get_root::<collection::Reader>()?.get_places()?.get(i as u32))
+    }))
+
+}
 
+/// Read a place as a geojson feature, for the collection
+pub fn read_place_feature(capnp_object: place::Reader) -> Result<serde_json::Value, CacheError> {
+    let (properties_json, geometry_json) = read_place(capnp_object)?;
     Ok(json!({
-        "places": {
-            "type": "FeatureCollection",
-            "features": serde_json::Value::Array(collection_json_vec)
-        }
+            "type": "Feature",
+            "geometry": geometry_json,
+            "id": properties_json["integer_id"],
+            "properties": properties_json
     }))
+}
+
+/// Write a place, for the collection and the single place caches
+pub fn write_place(mut capnp_data: place::Builder, properties: &serde_json::Value, mut fields: Fields, (longitude, latitude): (i32, i32)) {
+    capnp_data.set_uuid(fields.required_string("id"));
+    capnp_data.set_data_source_uuid(fields.optional_string("data_source_id"));
+    capnp_data.set_id(fields.required_u32("integer_id"));
+    capnp_data.set_internal_id(fields.optional_string("internal_id"));
+    capnp_data.set_shortname(fields.optional_string("shortname"));
+    capnp_data.set_name(fields.optional_string("name"));
+    capnp_data.set_description(fields.optional_string("description"));
+    capnp_data.set_data(properties.get("data").unwrap_or(&json!({})).to_string().as_str());
+    capnp_data.set_is_frozen(json_boolean_to_i8(&properties["is_frozen"]));
+    capnp_data.set_latitude(latitude);
+    capnp_data.set_longitude(longitude);
+
+    if let Some((nodes_uuids, travel_times, distances)) = fields.accessible_nodes("nodes")
+    {
+        let nodes_count = nodes_uuids.len() as u32;
+        set_text_list(capnp_data.reborrow().init_nodes_uuids(nodes_count), &nodes_uuids);
+        set_primitive_list(capnp_data.reborrow().init_nodes_travel_times(nodes_count), &travel_times);
+        set_primitive_list(capnp_data.reborrow().init_nodes_distances(nodes_count), &distances);
+    }
+}
+
+/// Read a place, for the collection and the single place caches, as its
+/// properties and geojson point
+pub fn read_place(capnp_object: place::Reader) -> Result<(serde_json::Value, serde_json::Value), CacheError> {
+
+    let mut data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
+    
+    let latitude  = (capnp_object.get_latitude() as f64)/1000000.0;
+    let longitude = (capnp_object.get_longitude() as f64)/1000000.0;
+
+    if capnp_object.has_nodes_uuids()
+    {
+        let mut nodes_uuids : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_nodes_uuids()?.len() as usize);
+        for node_uuid in capnp_object.get_nodes_uuids()?.iter() {
+            nodes_uuids.push(json!(node_uuid?));
+        }
+        data_attributes["nodes"] = json!(nodes_uuids);
+
+        let mut nodes_travel_times : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_nodes_travel_times()?.len() as usize);
+        for node_travel_time in capnp_object.get_nodes_travel_times()?.iter() {
+            nodes_travel_times.push(json!(node_travel_time));
+        }
+        data_attributes["nodesTravelTimes"] = json!(nodes_travel_times);
+
+        let mut nodes_distances : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_nodes_distances()?.len() as usize);
+        for node_distance in capnp_object.get_nodes_distances()?.iter() {
+            nodes_distances.push(json!(node_distance));
+        }
+        data_attributes["nodesDistances"] = json!(nodes_distances);
+    }
+
+    let integer_id = capnp_object.get_id() as u32;
+
+    let properties_json : serde_json::Value = json!({
+        "id": capnp_object.get_uuid()?,
+        "integer_id": integer_id,
+        "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
+        "shortname": empty_str_to_json_null(capnp_object.get_shortname()?),
+        "name": empty_str_to_json_null(capnp_object.get_name()?),
+        "description": empty_str_to_json_null(capnp_object.get_description()?),
+        "data_source_id": empty_str_to_json_null(capnp_object.get_data_source_uuid()?),
+        "is_frozen": i8_to_json_boolean(capnp_object.get_is_frozen()),
+        "data": data_attributes
+    });
+
+    let geometry_json = json!({
+        "type": "Point",
+        "coordinates": [longitude, latitude]
+    });
+
+    Ok((properties_json, geometry_json))
 
 }
 
@@ -190,12 +231,12 @@ mod tests {
         let config: serde_json::Value = json!({
             "project_cache_directory_path": fs::canonicalize(Path::new("test")).unwrap(),
             "project_shortname"           : "test",
-            "data_source_uuid"            : "1234-5678"
+            "data_source_uuid"            : "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f"
         });
 
         let data = r##"
             {
-                "data_source_uuid": "1234-5678",
+                "data_source_uuid": "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f",
                 "places": {
                     "type": "FeatureCollection",
                     "features": [
@@ -323,12 +364,9 @@ mod tests {
             &routers::place_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["places"], json_compare_data);
 
     }
@@ -0,0 +1,163 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use crate::place_capnp::place;
+use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use serde_json;
+use std::io::BufReader;
+use crate::validation::Validator;
+use crate::routers::{write_object_file, open_object_file};
+use crate::routers::place_collection_router::{write_place, read_place};
+
+/// The place object has the properties of a place feature, with its point in `geography`
+pub fn write_object(
+    cache_directory_path: &str,
+    json: &serde_json::Value,
+    config: &serde_json::Value,
+) -> Result<(), CacheError> {
+
+    let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
+
+    let json_object = &json["place"];
+    let point = validator.fields(json_object, "/place").point("geography");
+    let fields = validator.fields(json_object, "/place");
+    write_place(message.init_root::<place::Builder>(), json_object, fields, point);
+    let object_uuid = validator.fields(json_object, "/place").uuid_format("id");
+
+    validator.into_result()?;
+
+    write_object_file("place", object_uuid, cache_directory_path, &message, config)
+
+}
+
+
+pub fn read_object(
+    object_uuid: &str,
+    cache_directory_path: &str,
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
+
+    let file = open_object_file("place", object_uuid, cache_directory_path)?;
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "places").reader_options())?;
+    let (mut object_json, geometry_json) = read_place(message_reader.get_root::<place::Reader>()?)?;
+    object_json["geography"] = geometry_json;
+
+    Ok(json!({
+        "place": object_json
+    }))
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use pretty_assertions::{assert_eq};
+    use crate::routers;
+    use std::path::{Path};
+    use std::fs;
+    use rouille::Request;
+
+    #[test]
+    fn place() {
+
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test")).unwrap(),
+            "project_shortname"           : "test"
+        });
+
+        let data = r##"
+            {
+                "place": {
+                    "id": "7d8e9f0a-1b2c-4d3e-8f4a-5b6c7d8e9f0a",
+                    "integer_id": 34,
+                    "internal_id": "p34",
+                    "shortname": "P34",
+                    "name": "Place 34",
+                    "description": "A place",
+                    "data_source_id": null,
+                    "is_frozen": false,
+                    "data": {
+                        "nodes": ["a", "b"],
+                        "nodesTravelTimes": [120, 240],
+                        "nodesDistances": [100, 200]
+                    },
+                    "geography": {
+                        "type": "Point",
+                        "coordinates": [-73.5, 45.5]
+                    }
+                }
+            }
+        "##;
+
+        let compare_data = r##"
+            {
+                "place": {
+                    "id": "7d8e9f0a-1b2c-4d3e-8f4a-5b6c7d8e9f0a",
+                    "integer_id": 34,
+                    "internal_id": "p34",
+                    "shortname": "P34",
+                    "name": "Place 34",
+                    "description": "A place",
+                    "data_source_id": null,
+                    "is_frozen": false,
+                    "data": {
+                        "nodes": ["a", "b"],
+                        "nodesTravelTimes": [120, 240],
+                        "nodesDistances": [100, 200]
+                    },
+                    "geography": {
+                        "type": "Point",
+                        "coordinates": [-73.5, 45.5]
+                    }
+                }
+            }
+        "##;
+
+        let json_data : serde_json::Value = serde_json::from_str(data).unwrap();
+        let json_compare_data : serde_json::Value = serde_json::from_str(compare_data).unwrap();
+
+        let request = Request::fake_http(
+            "POST",
+            "/place",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            data.as_bytes().to_vec(),
+        );
+
+        let response = routers::write_object_route(
+            "place",
+            "places",
+            &config,
+            &routers::place_router::write_object,
+            &request,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let response = routers::read_object_route(
+            "place",
+            json_data["place"]["id"].as_str().unwrap(),
+            "places",
+            &config,
+            &routers::place_router::read_object,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
+        assert_eq!(json_response["data"]["place"], json_compare_data["place"]);
+
+    }
+}
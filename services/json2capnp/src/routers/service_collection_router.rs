@@ -9,111 +9,105 @@
 use crate::serviceCollection_capnp::service_collection as collection;
 //use crate::my_error::MyError;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::CacheCollection;
 use serde_json;
 use std::io::BufReader;
+use crate::validation::Validator;
 use crate::utils::{ 
-    required_string, 
-    optional_string_json_null_to_empty_str as optional_string, 
+    set_text_list,
     json_boolean_to_i8, 
     empty_str_to_json_null, 
     i8_to_json_boolean 
 };
 
+pub struct ServiceCollection;
+
+impl CacheCollection for ServiceCollection {
+
+    fn name(&self) -> &'static str {
+        "services"
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
-    let json_objects = &json["services"];
+    let json_objects = validator.array(&json["services"], "/services");
 
-    let count: usize = json_objects.as_array().unwrap().len();
+    let count: usize = json_objects.len();
     let collection_capnp = message.init_root::<collection::Builder>();
     let mut capnp = collection_capnp.init_services(count as u32);
 
-    for i in 0..count {
-        let json_data = &json_objects[i];
+    for (i, json_data) in json_objects.iter().enumerate() {
+        let mut fields = validator.fields(json_data, &format!("/services/{}", i));
         let mut capnp_data = capnp.reborrow().get(i as u32);
-        capnp_data.set_uuid(&required_string(json_data.get("id")));
-        capnp_data.set_internal_id(&optional_string(json_data.get("internal_id")));
-        capnp_data.set_simulation_uuid(&optional_string(json_data.get("simulation_id")));
-        capnp_data.set_name(&optional_string(json_data.get("name")));
-        capnp_data.set_color(&optional_string(json_data.get("color")));
-        capnp_data.set_description(&optional_string(json_data.get("description")));
-        capnp_data.set_data(&json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
-        capnp_data.set_is_frozen(json_boolean_to_i8(json_data.get("is_frozen").unwrap_or(&json!(null))));
-        capnp_data.set_is_enabled(json_boolean_to_i8(json_data.get("is_enabled").unwrap_or(&json!(null))));
-        capnp_data.set_monday(json_boolean_to_i8(json_data.get("monday").unwrap_or(&json!(null))));
-        capnp_data.set_monday(json_boolean_to_i8(json_data.get("monday").unwrap_or(&json!(null))));
-        capnp_data.set_tuesday(json_boolean_to_i8(json_data.get("tuesday").unwrap_or(&json!(null))));
-        capnp_data.set_wednesday(json_boolean_to_i8(json_data.get("wednesday").unwrap_or(&json!(null))));
-        capnp_data.set_thursday(json_boolean_to_i8(json_data.get("thursday").unwrap_or(&json!(null))));
-        capnp_data.set_friday(json_boolean_to_i8(json_data.get("friday").unwrap_or(&json!(null))));
-        capnp_data.set_saturday(json_boolean_to_i8(json_data.get("saturday").unwrap_or(&json!(null))));
-        capnp_data.set_sunday(json_boolean_to_i8(json_data.get("sunday").unwrap_or(&json!(null))));
-        capnp_data.set_start_date(&optional_string(json_data.get("start_date")));
-        capnp_data.set_end_date(&optional_string(json_data.get("end_date")));
-
-        if json_data.get("only_dates") != None && json_data["only_dates"].is_array()
+        capnp_data.set_uuid(fields.required_string("id"));
+        capnp_data.set_internal_id(fields.optional_string("internal_id"));
+        capnp_data.set_simulation_uuid(fields.optional_string("simulation_id"));
+        capnp_data.set_name(fields.optional_string("name"));
+        capnp_data.set_color(fields.optional_string("color"));
+        capnp_data.set_description(fields.optional_string("description"));
+        capnp_data.set_data(json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
+        capnp_data.set_is_frozen(json_boolean_to_i8(&json_data["is_frozen"]));
+        capnp_data.set_is_enabled(json_boolean_to_i8(&json_data["is_enabled"]));
+        capnp_data.set_monday(json_boolean_to_i8(&json_data["monday"]));
+        capnp_data.set_monday(json_boolean_to_i8(&json_data["monday"]));
+        capnp_data.set_tuesday(json_boolean_to_i8(&json_data["tuesday"]));
+        capnp_data.set_wednesday(json_boolean_to_i8(&json_data["wednesday"]));
+        capnp_data.set_thursday(json_boolean_to_i8(&json_data["thursday"]));
+        capnp_data.set_friday(json_boolean_to_i8(&json_data["friday"]));
+        capnp_data.set_saturday(json_boolean_to_i8(&json_data["saturday"]));
+        capnp_data.set_sunday(json_boolean_to_i8(&json_data["sunday"]));
+        capnp_data.set_start_date(fields.optional_string("start_date"));
+        capnp_data.set_end_date(fields.optional_string("end_date"));
+
+        if let Some(only_dates) = fields.optional_string_array("only_dates")
         {
-            let only_dates_optional = json_data.get("only_dates").unwrap().as_array();
-            if only_dates_optional != None {
-                let only_dates = only_dates_optional.unwrap();
-                let only_dates_count: usize = only_dates.len();
-
-                capnp_data.reborrow().init_only_dates(only_dates_count as u32);
-                for j in 0..only_dates_count
-                {
-                    capnp_data.reborrow().get_only_dates().unwrap().set(j as u32, only_dates[j].as_str().unwrap());
-                }
-            }
-            else
-            {
-                capnp_data.reborrow().init_only_dates(0);
-            }
+            set_text_list(capnp_data.reborrow().init_only_dates(only_dates.len() as u32), &only_dates);
         }
 
-        if json_data.get("except_dates") != None && json_data["except_dates"].is_array()
+        if let Some(except_dates) = fields.optional_string_array("except_dates")
         {
-            let except_dates_optional = json_data.get("except_dates").unwrap().as_array();
-            if except_dates_optional != None {
-                let except_dates = except_dates_optional.unwrap();
-                let except_dates_count: usize = except_dates.len();
-
-                capnp_data.reborrow().init_except_dates(except_dates_count as u32);
-                for j in 0..except_dates_count
-                {
-                    capnp_data.reborrow().get_except_dates().unwrap().set(j as u32, except_dates[j].as_str().unwrap());
-                }
-            }
-            else
-            {
-                capnp_data.reborrow().init_except_dates(0);
-            }
+            set_text_list(capnp_data.reborrow().init_except_dates(except_dates.len() as u32), &except_dates);
         }
 
-      
-
     }
 
-    serialize_packed::write_message(file, &message)
+    validator.into_result()?;
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
 }
 
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "services").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_services()?.len() as usize);
 
     for capnp_object in capnp_collection.get_services()?.iter() {
         
-        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
+        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
         let mut object_json : serde_json::Value = json!({
             "id": capnp_object.get_uuid()?,
             "internal_id": empty_str_to_json_null(capnp_object.get_internal_id()?),
@@ -137,13 +131,13 @@ pub fn read_collection(
 
         let mut only_dates_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_only_dates()?.len() as usize);
         for only_date in capnp_object.get_only_dates()?.iter() {
-            only_dates_vec.push(json!(only_date.unwrap()));
+            only_dates_vec.push(json!(only_date?));
         }
         object_json["only_dates"] = json!(only_dates_vec);
 
         let mut except_dates_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_object.get_except_dates()?.len() as usize);
         for except_date in capnp_object.get_except_dates()?.iter() {
-            except_dates_vec.push(json!(except_date.unwrap()));
+            except_dates_vec.push(json!(except_date?));
         }
         object_json["except_dates"] = json!(except_dates_vec);
 
@@ -321,12 +315,9 @@ mod tests {
             &routers::service_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["services"], json_compare_data["services"]);
 
     }
@@ -9,11 +9,13 @@
 use crate::unitCollection_capnp::unit_collection as collection;
 //use crate::my_error::MyError;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::CacheCollection;
 use serde_json;
 use std::io::BufReader;
+use crate::validation::Validator;
 use crate::utils::{ 
-    required_string, 
-    optional_string_json_null_to_empty_str as optional_string, 
     json_boolean_to_i8, 
     empty_str_to_json_null, 
     i8_to_json_boolean,
@@ -23,63 +25,84 @@ use crate::utils::{
     minus_one_i64_to_null
 };
 
+pub struct UnitCollection;
+
+impl CacheCollection for UnitCollection {
+
+    fn name(&self) -> &'static str {
+        "units"
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
-    let json_objects = &json["units"];
+    let json_objects = validator.array(&json["units"], "/units");
 
-    let count: usize = json_objects.as_array().unwrap().len();
+    let count: usize = json_objects.len();
     let collection_capnp = message.init_root::<collection::Builder>();
     let mut capnp = collection_capnp.init_units(count as u32);
 
-    for i in 0..count {
-        let json_data = &json_objects[i];
+    for (i, json_data) in json_objects.iter().enumerate() {
+        let mut fields = validator.fields(json_data, &format!("/units/{}", i));
         let mut capnp_data = capnp.reborrow().get(i as u32);
-        capnp_data.set_uuid(&required_string(json_data.get("id"))); // required
-        capnp_data.set_id(json_data.get("integer_id").unwrap().as_i64().unwrap() as i32); // required
-        capnp_data.set_internal_id(&optional_string(json_data.get("internal_id")));
-        capnp_data.set_agency_uuid(&optional_string(json_data.get("agency_id")));
-        capnp_data.set_garage_uuid(&optional_string(json_data.get("garage_id")));
-        capnp_data.set_line_uuid(&optional_string(json_data.get("line_id")));
-        capnp_data.set_mode(&optional_string(json_data.get("mode")));
-        capnp_data.set_manufacturer(&optional_string(json_data.get("manufacturer")));
-        capnp_data.set_model(&optional_string(json_data.get("model")));
-        capnp_data.set_license_number(&optional_string(json_data.get("license_number")));
-        capnp_data.set_serial_number(&optional_string(json_data.get("serial_number")));
-        capnp_data.set_capacity_seated(json_value_or_null_to_i64_or_minus_one(&json_data.get("capacity_seated").unwrap_or(&json!(null))) as i16);
-        capnp_data.set_capacity_standing(json_value_or_null_to_i64_or_minus_one(&json_data.get("capacity_standing").unwrap_or(&json!(null))) as i16);
-        capnp_data.set_number_of_vehicles(json_value_or_null_to_i64_or_minus_one(&json_data.get("number_of_vehicles").unwrap_or(&json!(null))) as i16);
-        capnp_data.set_number_of_doors(json_value_or_null_to_i64_or_minus_one(&json_data.get("number_of_doors").unwrap_or(&json!(null))) as i16);
-        capnp_data.set_number_of_door_channels(json_value_or_null_to_i64_or_minus_one(&json_data.get("number_of_door_channels").unwrap_or(&json!(null))) as i16);
-        capnp_data.set_length_mm(json_value_or_null_to_f64_or_minus_one(&json_data.get("length_mm").unwrap_or(&json!(null))) as f32);
-        capnp_data.set_width_mm(json_value_or_null_to_f64_or_minus_one(&json_data.get("width_mm").unwrap_or(&json!(null))) as f32);
-        capnp_data.set_color(&optional_string(json_data.get("color")));
-        capnp_data.set_data(&json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
-        capnp_data.set_is_frozen(json_boolean_to_i8(json_data.get("is_frozen").unwrap_or(&json!(null))));
-        capnp_data.set_is_enabled(json_boolean_to_i8(json_data.get("is_enabled").unwrap_or(&json!(null))));
+        capnp_data.set_uuid(fields.required_string("id"));
+        capnp_data.set_id(fields.required_i32("integer_id"));
+        capnp_data.set_internal_id(fields.optional_string("internal_id"));
+        capnp_data.set_agency_uuid(fields.optional_string("agency_id"));
+        capnp_data.set_garage_uuid(fields.optional_string("garage_id"));
+        capnp_data.set_line_uuid(fields.optional_string("line_id"));
+        capnp_data.set_mode(fields.optional_string("mode"));
+        capnp_data.set_manufacturer(fields.optional_string("manufacturer"));
+        capnp_data.set_model(fields.optional_string("model"));
+        capnp_data.set_license_number(fields.optional_string("license_number"));
+        capnp_data.set_serial_number(fields.optional_string("serial_number"));
+        capnp_data.set_capacity_seated(json_value_or_null_to_i64_or_minus_one(&json_data["capacity_seated"]) as i16);
+        capnp_data.set_capacity_standing(json_value_or_null_to_i64_or_minus_one(&json_data["capacity_standing"]) as i16);
+        capnp_data.set_number_of_vehicles(json_value_or_null_to_i64_or_minus_one(&json_data["number_of_vehicles"]) as i16);
+        capnp_data.set_number_of_doors(json_value_or_null_to_i64_or_minus_one(&json_data["number_of_doors"]) as i16);
+        capnp_data.set_number_of_door_channels(json_value_or_null_to_i64_or_minus_one(&json_data["number_of_door_channels"]) as i16);
+        capnp_data.set_length_mm(json_value_or_null_to_f64_or_minus_one(&json_data["length_mm"]) as f32);
+        capnp_data.set_width_mm(json_value_or_null_to_f64_or_minus_one(&json_data["width_mm"]) as f32);
+        capnp_data.set_color(fields.optional_string("color"));
+        capnp_data.set_data(json_data.get("data").unwrap_or(&json!({})).to_string().as_str());
+        capnp_data.set_is_frozen(json_boolean_to_i8(&json_data["is_frozen"]));
+        capnp_data.set_is_enabled(json_boolean_to_i8(&json_data["is_enabled"]));
     }
 
-    serialize_packed::write_message(file, &message)
+    validator.into_result()?;
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
 }
 
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "units").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_units()?.len() as usize);
 
     for capnp_object in capnp_collection.get_units()?.iter() {
         
-        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
+        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
         let integer_id = capnp_object.get_id() as i32;
         let object_json : serde_json::Value = json!({
             "id": capnp_object.get_uuid()?,
@@ -290,12 +313,9 @@ mod tests {
             &routers::unit_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["units"], json_compare_data["units"]);
 
     }
@@ -8,104 +8,94 @@
 
 use crate::zoneCollection_capnp::zone_collection as collection;
 //use crate::my_error::MyError;
+use crate::zone_capnp::zone;
 use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use crate::collections::{CacheCollection, CacheObject, ReadItemsFn};
+use crate::json_stream::JsonItems;
 use serde_json;
 use std::io::BufReader;
-use geojson::GeoJson;
-use geobuf;
+use crate::validation::{Validator, Fields};
 use protobuf::Message;
 
+pub struct ZoneCollection;
+
+impl CacheCollection for ZoneCollection {
+
+    fn name(&self) -> &'static str {
+        "zones"
+    }
+
+    fn is_feature_collection(&self) -> bool {
+        true
+    }
+
+    fn write_collection(&self, json: &serde_json::Value, file: &mut std::fs::File, config: &serde_json::Value) -> Result<(), CacheError> {
+        write_collection(json, file, config)
+    }
+
+    fn read_collection(&self, file: &mut std::fs::File, config: &serde_json::Value) -> Result<serde_json::Value, CacheError> {
+        read_collection(file, config)
+    }
+
+    fn read_items(&self) -> Option<ReadItemsFn> {
+        Some(read_items)
+    }
+
+    fn object(&self) -> Option<CacheObject> {
+        Some(CacheObject {
+            name        : "zone",
+            subdirectory: "zones",
+            write_object: crate::routers::zone_router::write_object,
+            read_object : crate::routers::zone_router::read_object
+        })
+    }
+
+}
+
 pub fn write_collection(
     json: &serde_json::Value,
     file: &mut std::fs::File,
     _: &serde_json::Value,
-) -> ::std::result::Result<(), capnp::Error> {
+) -> Result<(), CacheError> {
     let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
 
-    let geojson_data : GeoJson = GeoJson::from_json_value((json["zones"]).clone()).unwrap();
-
-    match geojson_data {
-        geojson::GeoJson::FeatureCollection(feature_collection) => {
-
-            let features_count = feature_collection.features.len();
-            let collection_capnp = message.init_root::<collection::Builder>();
-            let mut capnp = collection_capnp.init_zones(features_count as u32);
-            for i in 0..features_count {
-                let feature        = &feature_collection.features[i];
-                let mut capnp_data = capnp.reborrow().get(i as u32);
-                let properties = feature.properties.as_ref().unwrap();
-                capnp_data.set_uuid(properties.get("id").unwrap().as_str().unwrap()); // required
-                if !properties.get("integer_id").unwrap_or(&json!(null)).is_null() { // only save integer id if not missing
-                    capnp_data.set_id(properties.get("integer_id").unwrap().as_i64().unwrap() as i32); // required
-                }
-                capnp_data.set_data_source_uuid(&crate::utils::optional_string_json_null_to_empty_str(properties.get("data_source_id")));
-                capnp_data.set_shortname(&crate::utils::json_null_to_empty_str(&properties.get("shortname").unwrap_or(&json!(null))));
-                capnp_data.set_name(&crate::utils::json_null_to_empty_str(&properties.get("name").unwrap_or(&json!(null))));
-                capnp_data.set_color(&crate::utils::json_null_to_empty_str(&properties.get("color").unwrap_or(&json!(null))));
-                capnp_data.set_internal_id(&crate::utils::json_null_to_empty_str(&properties.get("internal_id").unwrap_or(&json!(null))));
-                capnp_data.set_description(&crate::utils::json_null_to_empty_str(&properties.get("description").unwrap_or(&json!(null))));
-                capnp_data.set_data(&properties.get("data").unwrap_or(&json!({})).to_string().as_str());
-                capnp_data.set_is_frozen(crate::utils::json_boolean_to_i8(&properties.get("is_frozen").unwrap_or(&json!(null))));
-
-                let geojson_json = json!({
-                    "type": "Feature",
-                    "properties": {},
-                    "geometry": feature.geometry.as_ref().unwrap()
-                });
-                let geobuf = geobuf::encode::Encoder::encode(&geojson_json, 6, 2).unwrap().write_to_bytes().unwrap();
-
-                capnp_data.set_geography(&geobuf);
-                //capnp_data.set_geography(&geojson_json["geometry"].to_string().as_str());
+    let features = validator.features(&json["zones"], "/zones");
 
-            }
-            serialize_packed::write_message(file, &message)
-        },
-        _ => {
-            Err(capnp::Error::failed(String::from("Zones geojson is invalid, empty or not a FeatureCollection")))
-        }
+    let features_count = features.len();
+    let collection_capnp = message.init_root::<collection::Builder>();
+    let mut capnp = collection_capnp.init_zones(features_count as u32);
+    for (i, feature) in features.iter().enumerate() {
+        let mut capnp_data = capnp.reborrow().get(i as u32);
+        let properties = &feature["properties"];
+        let fields = validator.fields(properties, &format!("/zones/features/{}/properties", i));
+        write_zone(capnp_data.reborrow(), properties, fields);
+
+        let geobuf = validator.geobuf(&feature["geometry"], &format!("/zones/features/{}/geometry", i));
+        capnp_data.set_geography(&geobuf);
     }
 
+    validator.into_result()?;
+
+    serialize_packed::write_message(file, &message).map_err(CacheError::from_write_error)
 }
 
 
 pub fn read_collection(
     file: &mut std::fs::File,
-    _ : &serde_json::Value//config: &serde_json::Value,
-) -> ::std::result::Result<serde_json::Value, capnp::Error> {
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
 
-    let message_reader   = serialize_packed::read_message(BufReader::new(file), ::capnp::message::ReaderOptions::new())?;
+    let message_reader   = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "zones").reader_options())?;
     let capnp_collection = message_reader.get_root::<collection::Reader>()?;
     
     let mut collection_json_vec : Vec<serde_json::Value> = Vec::with_capacity(capnp_collection.get_zones()?.len() as usize);
 
     for capnp_object in capnp_collection.get_zones()?.iter() {
-        
-        let integer_id = capnp_object.get_id() as i32;
-        let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?).unwrap();
-        let properties_json : serde_json::Value = json!({
-            "id": capnp_object.get_uuid()?,
-            "integer_id": integer_id,
-            "data_source_id": crate::utils::empty_str_to_json_null(capnp_object.get_data_source_uuid()?),
-            "internal_id": crate::utils::empty_str_to_json_null(capnp_object.get_internal_id()?),
-            "shortname": crate::utils::empty_str_to_json_null(capnp_object.get_shortname()?),
-            "name": crate::utils::empty_str_to_json_null(capnp_object.get_name()?),
-            "description": crate::utils::empty_str_to_json_null(capnp_object.get_description()?),
-            "color": crate::utils::empty_str_to_json_null(capnp_object.get_color()?),
-            "is_frozen": crate::utils::i8_to_json_boolean(capnp_object.get_is_frozen()),
-            "data": data_attributes
-        });
 
-        let mut geobuf_data = geobuf::geobuf_pb::Data::new();
-
-        geobuf_data.merge_from_bytes(&capnp_object.get_geography().unwrap()).unwrap();
-        let mut geojson : serde_json::Value = geobuf::decode::Decoder::decode(&geobuf_data).unwrap_or(json!({
-            "geometry": null
-        }));
-
-        geojson["id"] = json!(integer_id);
-        geojson["properties"] = properties_json;
-
-        collection_json_vec.push(geojson);
+        collection_json_vec.push(read_zone_feature(capnp_object)?);
 
     }
 
@@ -119,6 +109,80 @@ pub fn read_collection(
 }
 
 
+/// Read the zones one by one, for streamed responses
+pub fn read_items(
+    file: &mut std::fs::File,
+    config: &serde_json::Value,
+) -> Result<JsonItems, CacheError> {
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "zones").reader_options())?;
+    let count = message_reader.get_root::<collection::Reader>()?.get_zones()?.len() as usize;
+
+    Ok(JsonItems::new(count, move |i| {
+        read_zone_feature(message_reader.get_root::<collection::Reader>()?.get_zones()?.get(i as u32))
+    }))
+
+}
+
+/// Read a zone as a geojson feature, for the collection
+pub fn read_zone_feature(capnp_object: zone::Reader) -> Result<serde_json::Value, CacheError> {
+    let (properties_json, geometry_json) = read_zone(capnp_object)?;
+    Ok(json!({
+            "type": "Feature",
+            "id": properties_json["integer_id"],
+            "geometry": geometry_json,
+            "properties": properties_json
+    }))
+}
+
+/// Write the properties of a zone, for the collection and the single zone
+/// caches. The geography is set by the caller.
+pub fn write_zone(mut capnp_data: zone::Builder, properties: &serde_json::Value, mut fields: Fields) {
+    capnp_data.set_uuid(fields.required_string("id"));
+    if !fields.get("integer_id").is_null() { // only save integer id if not missing
+        capnp_data.set_id(fields.required_i32("integer_id"));
+    }
+    capnp_data.set_data_source_uuid(fields.optional_string("data_source_id"));
+    capnp_data.set_shortname(fields.optional_string("shortname"));
+    capnp_data.set_name(fields.optional_string("name"));
+    capnp_data.set_color(fields.optional_string("color"));
+    capnp_data.set_internal_id(fields.optional_string("internal_id"));
+    capnp_data.set_description(fields.optional_string("description"));
+    capnp_data.set_data(properties.get("data").unwrap_or(&json!({})).to_string().as_str());
+    capnp_data.set_is_frozen(crate::utils::json_boolean_to_i8(&properties["is_frozen"]));
+}
+
+/// Read a zone, for the collection and the single zone caches, as its
+/// properties and geojson geometry
+pub fn read_zone(capnp_object: zone::Reader) -> Result<(serde_json::Value, serde_json::Value), CacheError> {
+
+    let integer_id = capnp_object.get_id() as i32;
+    let data_attributes : serde_json::Value = serde_json::from_str(capnp_object.get_data()?)?;
+    let properties_json : serde_json::Value = json!({
+        "id": capnp_object.get_uuid()?,
+        "integer_id": integer_id,
+        "data_source_id": crate::utils::empty_str_to_json_null(capnp_object.get_data_source_uuid()?),
+        "internal_id": crate::utils::empty_str_to_json_null(capnp_object.get_internal_id()?),
+        "shortname": crate::utils::empty_str_to_json_null(capnp_object.get_shortname()?),
+        "name": crate::utils::empty_str_to_json_null(capnp_object.get_name()?),
+        "description": crate::utils::empty_str_to_json_null(capnp_object.get_description()?),
+        "color": crate::utils::empty_str_to_json_null(capnp_object.get_color()?),
+        "is_frozen": crate::utils::i8_to_json_boolean(capnp_object.get_is_frozen()),
+        "data": data_attributes
+    });
+
+    let mut geobuf_data = geobuf::geobuf_pb::Data::new();
+
+    geobuf_data.merge_from_bytes(capnp_object.get_geography()?)?;
+    let geojson : serde_json::Value = geobuf::decode::Decoder::decode(&geobuf_data).unwrap_or(json!({
+        "geometry": null
+    }));
+
+    Ok((properties_json, geojson["geometry"].clone()))
+
+}
+
+
 #[cfg(test)]
 mod tests {
 
@@ -263,12 +327,9 @@ mod tests {
             &routers::zone_collection_router::read_collection,
         );
 
-        let (mut res_data, _) = response.data.into_reader_and_size();
-        let mut buffer = String::new();
-        res_data.read_to_string(&mut buffer).unwrap();
-        let json_response : serde_json::Value = serde_json::from_str(buffer.as_str()).unwrap();
-
         assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
         assert_eq!(json_response["data"]["zones"], json_compare_data);
 
     }
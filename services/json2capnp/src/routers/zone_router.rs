@@ -0,0 +1,159 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use crate::zone_capnp::zone;
+use capnp::serialize_packed;
+use crate::errors::CacheError;
+use crate::limits::Limits;
+use serde_json;
+use std::io::BufReader;
+use crate::validation::Validator;
+use crate::routers::{write_object_file, open_object_file};
+use crate::routers::zone_collection_router::{write_zone, read_zone};
+
+/// The zone object has the properties of a zone feature, with its geometry in `geography`
+pub fn write_object(
+    cache_directory_path: &str,
+    json: &serde_json::Value,
+    config: &serde_json::Value,
+) -> Result<(), CacheError> {
+
+    let mut message = ::capnp::message::Builder::new_default();
+    let mut validator = Validator::new();
+
+    let json_object = &json["zone"];
+    let mut capnp_data = message.init_root::<zone::Builder>();
+    let fields = validator.fields(json_object, "/zone");
+    write_zone(capnp_data.reborrow(), json_object, fields);
+    let geobuf = validator.geobuf(&json_object["geography"], "/zone/geography");
+    capnp_data.set_geography(&geobuf);
+    let object_uuid = validator.fields(json_object, "/zone").uuid_format("id");
+
+    validator.into_result()?;
+
+    write_object_file("zone", object_uuid, cache_directory_path, &message, config)
+
+}
+
+
+pub fn read_object(
+    object_uuid: &str,
+    cache_directory_path: &str,
+    config: &serde_json::Value,
+) -> Result<serde_json::Value, CacheError> {
+
+    let file = open_object_file("zone", object_uuid, cache_directory_path)?;
+
+    let message_reader = serialize_packed::read_message(BufReader::new(file), Limits::from_config(config, "zones").reader_options())?;
+    let (mut object_json, geometry_json) = read_zone(message_reader.get_root::<zone::Reader>()?)?;
+    object_json["geography"] = geometry_json;
+
+    Ok(json!({
+        "zone": object_json
+    }))
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use pretty_assertions::{assert_eq};
+    use crate::routers;
+    use std::path::{Path};
+    use std::fs;
+    use rouille::Request;
+
+    #[test]
+    fn zone() {
+
+        let config: serde_json::Value = json!({
+            "project_cache_directory_path": fs::canonicalize(Path::new("test")).unwrap(),
+            "project_shortname"           : "test"
+        });
+
+        let data = r##"
+            {
+                "zone": {
+                    "id": "4c1e2b7a-9d3f-4e8a-b5c6-1f2e3d4c5b6a",
+                    "integer_id": 12,
+                    "internal_id": "z12",
+                    "shortname": "Z12",
+                    "name": "Zone 12",
+                    "color": "#ff0000",
+                    "description": null,
+                    "data_source_id": "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f",
+                    "is_frozen": true,
+                    "data": { "foo": "bar" },
+                    "geography": {
+                        "type": "Polygon",
+                        "coordinates": [[[-73.0, 45.0], [-73.1, 45.0], [-73.1, 45.1], [-73.0, 45.0]]]
+                    }
+                }
+            }
+        "##;
+
+        let compare_data = r##"
+            {
+                "zone": {
+                    "id": "4c1e2b7a-9d3f-4e8a-b5c6-1f2e3d4c5b6a",
+                    "integer_id": 12,
+                    "internal_id": "z12",
+                    "shortname": "Z12",
+                    "name": "Zone 12",
+                    "color": "#ff0000",
+                    "description": null,
+                    "data_source_id": "c5d6e7f8-0a1b-4c2d-9e3f-4a5b6c7d8e9f",
+                    "is_frozen": true,
+                    "data": { "foo": "bar" },
+                    "geography": {
+                        "type": "Polygon",
+                        "coordinates": [[[-73.0, 45.0], [-73.1, 45.0], [-73.1, 45.1], [-73.0, 45.0]]]
+                    }
+                }
+            }
+        "##;
+
+        let json_data : serde_json::Value = serde_json::from_str(data).unwrap();
+        let json_compare_data : serde_json::Value = serde_json::from_str(compare_data).unwrap();
+
+        let request = Request::fake_http(
+            "POST",
+            "/zone",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            data.as_bytes().to_vec(),
+        );
+
+        let response = routers::write_object_route(
+            "zone",
+            "zones",
+            &config,
+            &routers::zone_router::write_object,
+            &request,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let response = routers::read_object_route(
+            "zone",
+            json_data["zone"]["id"].as_str().unwrap(),
+            "zones",
+            &config,
+            &routers::zone_router::read_object,
+        );
+
+        assert_eq!(response.status_code, 200);
+
+        let json_response = routers::tests::response_json(response);
+        assert_eq!(json_response["data"]["zone"], json_compare_data["zone"]);
+
+    }
+}
@@ -0,0 +1,625 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use crate::cache_files::{AtomicFile, CacheFileLock, SwapLock, WriteLock};
+use crate::collections::{ItemsStream, ReadItemsFn};
+use crate::errors::CacheError;
+use crate::json_stream::JsonItems;
+
+/// Item counts of the shards of a collection, in
+/// {cache_file_name}.manifest.json. The shards use the layout of the
+/// collection cache files split by Transition and read by trRouting: a single
+/// shard is the {cache_file_name}.capnpbin file, several shards are the
+/// numbered {cache_file_name}.capnpbin.{i} files, with their number in the
+/// {cache_file_name}.capnpbin.count file. Each shard is a collection cache
+/// file with up to shard_size items.
+///
+/// Other writers do not update the manifest: the count of a shard is only
+/// used if the size and modification time of its file did not change, the
+/// shard is read to count its items otherwise.
+///
+/// The shard files, the count file and the manifest are replaced one by one,
+/// while holding the swap lock of the collection: an exclusive flock on
+/// .{cache_file_name}.capnpbin.count.lock (see SwapLock). Readers holding it
+/// shared while they open the count file and the shard files open a
+/// consistent set of shards; opened shard files stay readable once replaced.
+/// Readers without the lock (trRouting) can see a mix of old and new shards
+/// while a writer replaces them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShardManifest {
+    pub shards: Vec<Shard>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Shard {
+    pub file_name: String,
+    pub count: usize,
+    pub size: u64,
+    /// Modification time of the file, in nanoseconds since the epoch
+    pub modified: u64,
+}
+
+pub fn manifest_path(directory_path: &Path, cache_file_name: &str) -> PathBuf {
+    directory_path.join(format!("{}.manifest.json", cache_file_name))
+}
+
+/// Cache file of a collection with a single shard, locked by the writers of
+/// the collection
+pub fn cache_file_path(directory_path: &Path, cache_file_name: &str) -> PathBuf {
+    directory_path.join(format!("{}.capnpbin", cache_file_name))
+}
+
+pub fn count_file_path(directory_path: &Path, cache_file_name: &str) -> PathBuf {
+    directory_path.join(format!("{}.capnpbin.count", cache_file_name))
+}
+
+/// Swap lock of the shards of a collection, see ShardManifest
+fn lock_swaps(directory_path: &Path, cache_file_name: &str, exclusive: bool) -> io::Result<SwapLock> {
+    let count_file_path = count_file_path(directory_path, cache_file_name);
+    if exclusive {
+        SwapLock::write(&count_file_path)
+    } else {
+        SwapLock::read(&count_file_path)
+    }
+}
+
+fn shard_file_name(cache_file_name: &str, shard: usize, shards_count: usize) -> String {
+    if shards_count == 1 {
+        format!("{}.capnpbin", cache_file_name)
+    } else {
+        format!("{}.capnpbin.{}", cache_file_name, shard)
+    }
+}
+
+impl ShardManifest {
+
+    /// Read the manifest of a collection, empty if it is missing or invalid
+    pub fn read(directory_path: &Path, cache_file_name: &str) -> ShardManifest {
+        fs::read(manifest_path(directory_path, cache_file_name)).ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn count(&self) -> usize {
+        self.shards.iter().map(|shard| shard.count).sum()
+    }
+
+}
+
+/// Number of shards of a collection, from its count file: 1 without count
+/// file
+fn read_shards_count(directory_path: &Path, cache_file_name: &str) -> Result<usize, CacheError> {
+    let path = count_file_path(directory_path, cache_file_name);
+    match fs::read_to_string(&path) {
+        Ok(content) => content.trim().parse::<usize>().ok().filter(|&shards_count| shards_count > 0)
+            .ok_or_else(|| CacheError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid shards count in {}", path.display())))),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(1),
+        Err(error) => Err(error.into())
+    }
+}
+
+/// File names of the shards of a collection, from its count file
+pub fn shard_file_names(directory_path: &Path, cache_file_name: &str) -> Result<Vec<String>, CacheError> {
+    let shards_count = read_shards_count(directory_path, cache_file_name)?;
+    Ok((0..shards_count).map(|shard| shard_file_name(cache_file_name, shard, shards_count)).collect())
+}
+
+/// Open all the shard files of a collection at once, None if it has no
+/// cache
+fn open_shards(directory_path: &Path, cache_file_name: &str) -> Result<Option<Vec<(String, File)>>, CacheError> {
+    let _swap = lock_swaps(directory_path, cache_file_name, false)?;
+    let shards_count = read_shards_count(directory_path, cache_file_name)?;
+    let mut files = Vec::with_capacity(shards_count);
+    for shard in 0..shards_count {
+        let file_name = shard_file_name(cache_file_name, shard, shards_count);
+        match File::open(directory_path.join(&file_name)) {
+            Ok(file) => files.push((file_name, file)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound && shards_count == 1 => return Ok(None),
+            Err(error) => return Err(error.into())
+        }
+    }
+    Ok(Some(files))
+}
+
+fn file_version(metadata: &fs::Metadata) -> io::Result<(u64, u64)> {
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map_or(0, |modified| modified.as_nanos() as u64);
+    Ok((metadata.len(), modified))
+}
+
+/// Count the items of opened shards with the manifest, or by reading the
+/// shards changed since it was written
+fn count_shards(directory_path: &Path, cache_file_name: &str, files: &mut [(String, File)], read_items: ReadItemsFn, config: &serde_json::Value) -> Result<Vec<Shard>, CacheError> {
+    let manifest = ShardManifest::read(directory_path, cache_file_name);
+    files.iter_mut().map(|(file_name, file)| {
+        let (size, modified) = file_version(&file.metadata()?)?;
+        let count = match manifest.shards.iter().find(|shard| &shard.file_name == file_name && shard.size == size && shard.modified == modified) {
+            Some(shard) => shard.count,
+            None => {
+                let count = read_items(file, config)?.count();
+                file.seek(SeekFrom::Start(0))?;
+                count
+            }
+        };
+        Ok(Shard { file_name: file_name.clone(), count, size, modified })
+    }).collect()
+}
+
+/// The shards of a collection with their item counts, None if it has no
+/// cache
+pub fn read_shards(directory_path: &Path, cache_file_name: &str, read_items: ReadItemsFn, config: &serde_json::Value) -> Result<Option<Vec<Shard>>, CacheError> {
+    match open_shards(directory_path, cache_file_name)? {
+        Some(mut files) => count_shards(directory_path, cache_file_name, &mut files, read_items, config).map(Some),
+        None => Ok(None)
+    }
+}
+
+/// Read the items of a collection, merging its shards. The shard files are
+/// all opened first, so the items are those of the shards at that time even
+/// if a writer replaces them while they are read. Returns None if the
+/// collection has no cache.
+pub fn read_items(directory_path: &Path, cache_file_name: &str, read_items: ReadItemsFn, config: &serde_json::Value) -> Result<Option<JsonItems>, CacheError> {
+
+    let mut files = match open_shards(directory_path, cache_file_name)? {
+        Some(files) => files,
+        None => return Ok(None)
+    };
+    if files.len() == 1 {
+        return read_items(&mut files[0].1, config).map(Some);
+    }
+
+    let counts = count_shards(directory_path, cache_file_name, &mut files, read_items, config)?.iter().map(|shard| shard.count).collect();
+    let mut files: Vec<Option<File>> = files.into_iter().map(|(_, file)| Some(file)).collect();
+    let config = config.clone();
+    JsonItems::concat(counts, move |shard| match files[shard].take() {
+        Some(mut file) => read_items(&mut file, &config),
+        None => Err(CacheError::Io(io::Error::other(format!("Shard {} was already read", shard))))
+    }).map(Some)
+
+}
+
+/// Writer of new shards, holding the lock of the collection from reading the
+/// current shards to replacing them. New shards are written to temporary
+/// files and replace the current shards, or are appended to them, on commit.
+/// If the writer is dropped without being committed, the new shards are
+/// removed.
+pub struct ShardsWriter {
+    directory_path: PathBuf,
+    cache_file_name: String,
+    items_stream: ItemsStream,
+    kept_shards: Vec<Shard>,
+    new_shards: Vec<(AtomicFile, usize)>,
+    _lock: CacheFileLock,
+}
+
+impl ShardsWriter {
+
+    /// Lock the collection against the other writers, which lock its
+    /// {cache_file_name}.capnpbin file
+    pub fn lock(directory_path: &Path, cache_file_name: &str, items_stream: &ItemsStream, write_lock: &WriteLock) -> Result<ShardsWriter, CacheError> {
+        let lock = CacheFileLock::acquire(&cache_file_path(directory_path, cache_file_name), write_lock)?;
+        Ok(ShardsWriter {
+            directory_path: directory_path.to_path_buf(),
+            cache_file_name: cache_file_name.to_string(),
+            items_stream: *items_stream,
+            kept_shards: Vec::new(),
+            new_shards: Vec::new(),
+            _lock: lock,
+        })
+    }
+
+    /// Keep the current shards, the new shards are appended to them
+    pub fn keep_current_shards(&mut self, config: &serde_json::Value) -> Result<(), CacheError> {
+        let shards = read_shards(&self.directory_path, &self.cache_file_name, self.items_stream.read_items, config)?.unwrap_or_default();
+        self.kept_shards = shards.into_iter().filter(|shard| shard.count > 0).collect();
+        Ok(())
+    }
+
+    /// Write packed items (see ItemsStream) in new shards of up to shard_size
+    /// items
+    pub fn write(&mut self, items: Vec<Vec<u8>>, shard_size: usize) -> Result<(), CacheError> {
+        let mut items = items.into_iter().peekable();
+        while items.peek().is_some() {
+            let shard_items: Vec<Vec<u8>> = items.by_ref().take(shard_size.max(1)).collect();
+            self.write_shard(shard_items)?;
+        }
+        Ok(())
+    }
+
+    fn write_shard(&mut self, items: Vec<Vec<u8>>) -> Result<(), CacheError> {
+        let count = items.len();
+        let mut file = AtomicFile::create(cache_file_path(&self.directory_path, &self.cache_file_name))?;
+        (self.items_stream.write_items)(items, file.file_mut())?;
+        self.new_shards.push((file, count));
+        Ok(())
+    }
+
+    /// Add a shard file written elsewhere (eg an upload chunk) as a new
+    /// shard. The file is hard linked, so it stays in place if the writer is
+    /// not committed. A file on another file system is copied.
+    pub fn add_file(&mut self, path: &Path, count: usize) -> Result<(), CacheError> {
+        let shard_path = cache_file_path(&self.directory_path, &self.cache_file_name);
+        let file = match AtomicFile::hard_link(path, &shard_path) {
+            Err(error) if error.kind() == io::ErrorKind::CrossesDevices => {
+                let mut file = AtomicFile::create(&shard_path)?;
+                io::copy(&mut File::open(path)?, file.file_mut())?;
+                file
+            },
+            file => file?
+        };
+        self.new_shards.push((file, count));
+        Ok(())
+    }
+
+    /// Replace the shard files, then the count file, remove the shard files
+    /// left from the previous shards and update the manifest, while holding
+    /// the swap lock. A single kept shard becomes the first numbered shard
+    /// when shards are appended to it.
+    pub fn commit(mut self) -> Result<ShardManifest, CacheError> {
+
+        if self.kept_shards.is_empty() && self.new_shards.is_empty() {
+            self.write_shard(Vec::new())?;
+        }
+        let directory_path = self.directory_path.clone();
+        let cache_file_name = self.cache_file_name.clone();
+        let shards_count = self.kept_shards.len() + self.new_shards.len();
+
+        let mut counts = Vec::with_capacity(shards_count);
+        let mut files = Vec::new();
+        for (shard, kept_shard) in self.kept_shards.iter().enumerate() {
+            let file_name = shard_file_name(&cache_file_name, shard, shards_count);
+            if file_name != kept_shard.file_name {
+                files.push((file_name.clone(), AtomicFile::hard_link(directory_path.join(&kept_shard.file_name), directory_path.join(&file_name))?));
+            }
+            counts.push(kept_shard.count);
+        }
+        for (shard, (file, count)) in self.new_shards.drain(..).enumerate() {
+            files.push((shard_file_name(&cache_file_name, self.kept_shards.len() + shard, shards_count), file));
+            counts.push(count);
+        }
+        let count_file = if shards_count > 1 {
+            let mut count_file = AtomicFile::create(count_file_path(&directory_path, &cache_file_name))?;
+            write!(count_file, "{}", shards_count)?;
+            Some(count_file)
+        } else {
+            None
+        };
+        let previous_shards_count = read_shards_count(&directory_path, &cache_file_name).unwrap_or(1);
+
+        let _swap = lock_swaps(&directory_path, &cache_file_name, true)?;
+        for (file_name, file) in files {
+            file.commit_to(directory_path.join(file_name))?;
+        }
+        match count_file {
+            Some(count_file) => {
+                count_file.commit()?;
+                remove_if_exists(&cache_file_path(&directory_path, &cache_file_name))?;
+            },
+            None => {
+                remove_if_exists(&count_file_path(&directory_path, &cache_file_name))?;
+            }
+        }
+        if previous_shards_count > 1 {
+            let first_removed_shard = if shards_count > 1 { shards_count } else { 0 };
+            for shard in first_removed_shard..previous_shards_count {
+                remove_if_exists(&directory_path.join(shard_file_name(&cache_file_name, shard, previous_shards_count)))?;
+            }
+        }
+
+        let shards = counts.into_iter().enumerate().map(|(shard, count)| {
+            let file_name = shard_file_name(&cache_file_name, shard, shards_count);
+            let (size, modified) = file_version(&fs::metadata(directory_path.join(&file_name))?)?;
+            Ok(Shard { file_name, count, size, modified })
+        }).collect::<Result<Vec<Shard>, CacheError>>()?;
+        let manifest = ShardManifest { shards };
+        let mut manifest_file = AtomicFile::create(manifest_path(&directory_path, &cache_file_name))?;
+        serde_json::to_writer(manifest_file.file_mut(), &manifest).map_err(io::Error::from)?;
+        manifest_file.commit()?;
+        Ok(manifest)
+
+    }
+
+}
+
+/// Returns whether the file existed
+fn remove_if_exists(path: &Path) -> io::Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error)
+    }
+}
+
+/// Shard files written while the items of a body are converted: every
+/// shard_size packed items (see ItemsStream) are written to a new shard file
+/// in directory_path, named {file_prefix}.{i}.capnpbin, so only the items of
+/// one shard are kept in memory. If the writer is dropped before being
+/// finished, its shard files are removed.
+pub struct ShardFilesWriter {
+    directory_path: PathBuf,
+    file_prefix: String,
+    shard_size: usize,
+    items_stream: ItemsStream,
+    items: Vec<Vec<u8>>,
+    shards: Vec<Shard>,
+    finished: bool,
+}
+
+impl ShardFilesWriter {
+
+    pub fn new(directory_path: &Path, file_prefix: &str, shard_size: usize, items_stream: &ItemsStream) -> ShardFilesWriter {
+        ShardFilesWriter {
+            directory_path: directory_path.to_path_buf(),
+            file_prefix: file_prefix.to_string(),
+            shard_size: shard_size.max(1),
+            items_stream: *items_stream,
+            items: Vec::new(),
+            shards: Vec::new(),
+            finished: false,
+        }
+    }
+
+    pub fn push(&mut self, item: Vec<u8>) -> Result<(), CacheError> {
+        self.items.push(item);
+        if self.items.len() >= self.shard_size {
+            self.write_shard()?;
+        }
+        Ok(())
+    }
+
+    pub fn directory_path(&self) -> &Path {
+        &self.directory_path
+    }
+
+    fn write_shard(&mut self) -> Result<(), CacheError> {
+        let items = std::mem::take(&mut self.items);
+        let count = items.len();
+        let file_name = format!("{}.{}.capnpbin", self.file_prefix, self.shards.len());
+        let path = self.directory_path.join(&file_name);
+        let mut file = AtomicFile::create(&path)?;
+        (self.items_stream.write_items)(items, file.file_mut())?;
+        file.commit()?;
+        let (size, modified) = file_version(&fs::metadata(&path)?)?;
+        self.shards.push(Shard { file_name, count, size, modified });
+        Ok(())
+    }
+
+    /// Write the last shard and return the shard files
+    pub fn finish(mut self) -> Result<Vec<Shard>, CacheError> {
+        if !self.items.is_empty() {
+            self.write_shard()?;
+        }
+        self.finished = true;
+        Ok(std::mem::take(&mut self.shards))
+    }
+
+}
+
+impl Drop for ShardFilesWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            for shard in &self.shards {
+                let _ = fs::remove_file(self.directory_path.join(&shard.file_name));
+            }
+        }
+    }
+}
+
+/// Remove the shards of a collection, with its count file and its manifest.
+/// Returns the removed files.
+pub fn remove(directory_path: &Path, cache_file_name: &str, write_lock: &WriteLock) -> Result<Vec<PathBuf>, CacheError> {
+    let _lock = CacheFileLock::acquire(&cache_file_path(directory_path, cache_file_name), write_lock)?;
+    let _swap = lock_swaps(directory_path, cache_file_name, true)?;
+    let shards_count = read_shards_count(directory_path, cache_file_name).unwrap_or(1);
+    let mut paths: Vec<PathBuf> = if shards_count > 1 {
+        (0..shards_count).map(|shard| directory_path.join(shard_file_name(cache_file_name, shard, shards_count))).collect()
+    } else {
+        Vec::new()
+    };
+    paths.push(cache_file_path(directory_path, cache_file_name));
+    paths.push(count_file_path(directory_path, cache_file_name));
+    paths.push(manifest_path(directory_path, cache_file_name));
+    let mut removed_paths = Vec::new();
+    for path in paths {
+        if remove_if_exists(&path)? {
+            removed_paths.push(path);
+        }
+    }
+    Ok(removed_paths)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::cache_files::WriteLockMode;
+    use crate::collections::CacheCollection;
+    use crate::routers::od_trip_collection_router::OdTripCollection;
+    use crate::validation::Validator;
+    use pretty_assertions::{assert_eq};
+    use std::fs::OpenOptions;
+    use std::thread;
+    use std::time::Duration;
+
+    fn od_trip_items(ids: std::ops::Range<usize>) -> Vec<Vec<u8>> {
+        let items_stream = OdTripCollection.items_stream().unwrap();
+        let mut validator = Validator::new();
+        ids.map(|i| {
+            let od_trip = json!({
+                "id": format!("{:08x}-4f4c-4c36-9d6c-bb5cb5d5c4a1", i),
+                "integer_id": i,
+                "origin_geography": { "type": "Point", "coordinates": [-73.5, 45.5] },
+                "destination_geography": { "type": "Point", "coordinates": [-73.6, 45.6] }
+            });
+            (items_stream.write_item)(&od_trip, validator.fields(&od_trip, "")).unwrap()
+        }).collect()
+    }
+
+    fn integer_ids(items: JsonItems) -> Vec<serde_json::Value> {
+        items.into_values().unwrap().iter().map(|od_trip| od_trip["integer_id"].clone()).collect()
+    }
+
+    fn read_integer_ids(directory_path: &Path) -> Vec<serde_json::Value> {
+        integer_ids(read_items(directory_path, "odTrips", OdTripCollection.read_items().unwrap(), &json!({})).unwrap().unwrap())
+    }
+
+    fn cache_files(directory_path: &Path) -> Vec<String> {
+        let mut file_names: Vec<String> = fs::read_dir(directory_path).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|file_name| !file_name.starts_with('.'))
+            .collect();
+        file_names.sort();
+        file_names
+    }
+
+    #[test]
+    fn shards() {
+
+        let _ = fs::remove_dir_all("test/shards");
+        fs::create_dir_all("test/shards").unwrap();
+        let directory_path = Path::new("test/shards");
+        let items_stream = OdTripCollection.items_stream().unwrap();
+        let write_lock = WriteLock { mode: WriteLockMode::Fail, timeout: Duration::from_secs(1) };
+        let config = json!({});
+
+        // single cache files are read as a single shard:
+        let mut file = File::create(directory_path.join("odTrips.capnpbin")).unwrap();
+        (items_stream.write_items)(od_trip_items(0..2), &mut file).unwrap();
+        assert_eq!(read_integer_ids(directory_path), vec![json!(0), json!(1)]);
+
+        // several shards are numbered, with their count in the count file:
+        let mut writer = ShardsWriter::lock(directory_path, "odTrips", &items_stream, &write_lock).unwrap();
+        writer.write(od_trip_items(0..5), 2).unwrap();
+        let manifest = writer.commit().unwrap();
+        assert_eq!(manifest.shards.iter().map(|shard| (shard.file_name.as_str(), shard.count)).collect::<Vec<_>>(), vec![("odTrips.capnpbin.0", 2), ("odTrips.capnpbin.1", 2), ("odTrips.capnpbin.2", 1)]);
+        assert_eq!(cache_files(directory_path), vec!["odTrips.capnpbin.0", "odTrips.capnpbin.1", "odTrips.capnpbin.2", "odTrips.capnpbin.count", "odTrips.manifest.json"]);
+        assert_eq!(fs::read_to_string(directory_path.join("odTrips.capnpbin.count")).unwrap(), "3");
+        assert_eq!(read_integer_ids(directory_path), (0..5).map(|i| json!(i)).collect::<Vec<_>>());
+
+        // appending adds a shard without rewriting the others:
+        let modified = fs::metadata(directory_path.join("odTrips.capnpbin.0")).unwrap().modified().unwrap();
+        let mut writer = ShardsWriter::lock(directory_path, "odTrips", &items_stream, &write_lock).unwrap();
+        writer.keep_current_shards(&config).unwrap();
+        writer.write(od_trip_items(5..6), 2).unwrap();
+        assert_eq!(writer.commit().unwrap().count(), 6);
+        assert_eq!(fs::metadata(directory_path.join("odTrips.capnpbin.0")).unwrap().modified().unwrap(), modified);
+        assert_eq!(fs::read_to_string(directory_path.join("odTrips.capnpbin.count")).unwrap(), "4");
+        assert_eq!(read_integer_ids(directory_path), (0..6).map(|i| json!(i)).collect::<Vec<_>>());
+
+        // shards changed by other writers, which do not update the manifest, are counted again:
+        let mut file = File::create(directory_path.join("odTrips.capnpbin.3")).unwrap();
+        (items_stream.write_items)(od_trip_items(5..8), &mut file).unwrap();
+        let shards = read_shards(directory_path, "odTrips", items_stream.read_items, &config).unwrap().unwrap();
+        assert_eq!(shards.iter().map(|shard| shard.count).collect::<Vec<_>>(), vec![2, 2, 1, 3]);
+        assert_eq!(read_integer_ids(directory_path), (0..8).map(|i| json!(i)).collect::<Vec<_>>());
+
+        // uncommitted shards are removed:
+        let mut writer = ShardsWriter::lock(directory_path, "odTrips", &items_stream, &write_lock).unwrap();
+        writer.write(od_trip_items(0..1), 2).unwrap();
+        drop(writer);
+        assert_eq!(fs::read_dir(directory_path).unwrap().count(), 8);
+
+        // the shards opened by a reader stay readable while they are replaced by a single shard:
+        let items = read_items(directory_path, "odTrips", items_stream.read_items, &config).unwrap().unwrap();
+        let mut writer = ShardsWriter::lock(directory_path, "odTrips", &items_stream, &write_lock).unwrap();
+        writer.write(od_trip_items(10..11), 2).unwrap();
+        writer.commit().unwrap();
+        assert_eq!(integer_ids(items), (0..8).map(|i| json!(i)).collect::<Vec<_>>());
+        assert_eq!(cache_files(directory_path), vec!["odTrips.capnpbin", "odTrips.manifest.json"]);
+        assert_eq!(read_integer_ids(directory_path), vec![json!(10)]);
+
+        // a single shard becomes the first numbered shard when shards are appended to it:
+        let mut writer = ShardsWriter::lock(directory_path, "odTrips", &items_stream, &write_lock).unwrap();
+        writer.keep_current_shards(&config).unwrap();
+        writer.write(od_trip_items(11..12), 2).unwrap();
+        writer.commit().unwrap();
+        assert_eq!(cache_files(directory_path), vec!["odTrips.capnpbin.0", "odTrips.capnpbin.1", "odTrips.capnpbin.count", "odTrips.manifest.json"]);
+        assert_eq!(read_integer_ids(directory_path), vec![json!(10), json!(11)]);
+
+        // an empty collection is a single empty shard:
+        let writer = ShardsWriter::lock(directory_path, "odTrips", &items_stream, &write_lock).unwrap();
+        assert_eq!(writer.commit().unwrap().count(), 0);
+        assert_eq!(read_integer_ids(directory_path), Vec::<serde_json::Value>::new());
+
+        let removed_paths = remove(directory_path, "odTrips", &write_lock).unwrap();
+        assert_eq!(removed_paths, vec![directory_path.join("odTrips.capnpbin"), manifest_path(directory_path, "odTrips")]);
+        assert!(read_items(directory_path, "odTrips", OdTripCollection.read_items().unwrap(), &json!({})).unwrap().is_none());
+
+    }
+
+    #[test]
+    fn swap_lock() {
+
+        let _ = fs::remove_dir_all("test/swap_lock");
+        fs::create_dir_all("test/swap_lock").unwrap();
+        let directory_path = Path::new("test/swap_lock");
+        let items_stream = OdTripCollection.items_stream().unwrap();
+        let write_lock = WriteLock { mode: WriteLockMode::Fail, timeout: Duration::from_secs(1) };
+
+        let mut writer = ShardsWriter::lock(directory_path, "odTrips", &items_stream, &write_lock).unwrap();
+        writer.write(od_trip_items(0..3), 2).unwrap();
+        writer.commit().unwrap();
+
+        // shards are not replaced while a reader of another process holds the swap lock:
+        let reader_lock = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(directory_path.join(".odTrips.capnpbin.count.lock")).unwrap();
+        reader_lock.lock_shared().unwrap();
+        let commit = thread::spawn(move || {
+            let items_stream = OdTripCollection.items_stream().unwrap();
+            let mut writer = ShardsWriter::lock(directory_path, "odTrips", &items_stream, &write_lock).unwrap();
+            writer.write(od_trip_items(3..4), 2).unwrap();
+            writer.commit().unwrap();
+        });
+        thread::sleep(Duration::from_millis(200));
+        assert!(!commit.is_finished());
+        assert_eq!(cache_files(directory_path), vec!["odTrips.capnpbin.0", "odTrips.capnpbin.1", "odTrips.capnpbin.count", "odTrips.manifest.json"]);
+        drop(reader_lock);
+        commit.join().unwrap();
+        assert_eq!(cache_files(directory_path), vec!["odTrips.capnpbin", "odTrips.manifest.json"]);
+        assert_eq!(read_integer_ids(directory_path), vec![json!(3)]);
+
+    }
+
+    #[test]
+    fn shard_files() {
+
+        let _ = fs::remove_dir_all("test/shard_files");
+        fs::create_dir_all("test/shard_files/staging").unwrap();
+        let directory_path = Path::new("test/shard_files");
+        let staging_directory_path = directory_path.join("staging");
+        let items_stream = OdTripCollection.items_stream().unwrap();
+        let write_lock = WriteLock { mode: WriteLockMode::Fail, timeout: Duration::from_secs(1) };
+
+        // a shard file is written every shard_size items:
+        let mut shard_files = ShardFilesWriter::new(&staging_directory_path, "odTrips", 2, &items_stream);
+        for item in od_trip_items(0..3) {
+            shard_files.push(item).unwrap();
+        }
+        assert_eq!(cache_files(&staging_directory_path), vec!["odTrips.0.capnpbin"]);
+        let shards = shard_files.finish().unwrap();
+        assert_eq!(shards.iter().map(|shard| (shard.file_name.as_str(), shard.count)).collect::<Vec<_>>(), vec![("odTrips.0.capnpbin", 2), ("odTrips.1.capnpbin", 1)]);
+
+        let mut writer = ShardsWriter::lock(directory_path, "odTrips", &items_stream, &write_lock).unwrap();
+        for shard in &shards {
+            writer.add_file(&staging_directory_path.join(&shard.file_name), shard.count).unwrap();
+        }
+        assert_eq!(writer.commit().unwrap().count(), 3);
+        assert_eq!(read_integer_ids(directory_path), (0..3).map(|i| json!(i)).collect::<Vec<_>>());
+
+        // the shard files of an unfinished writer are removed:
+        let mut shard_files = ShardFilesWriter::new(&staging_directory_path, "failed", 1, &items_stream);
+        shard_files.push(od_trip_items(0..1).remove(0)).unwrap();
+        drop(shard_files);
+        assert_eq!(cache_files(&staging_directory_path), vec!["odTrips.0.capnpbin", "odTrips.1.capnpbin"]);
+
+    }
+}
@@ -0,0 +1,116 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::io;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Interval between the checks of the active requests and jobs while draining
+const DRAIN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Time left to the server to send the last responses, which are written
+/// after their handler returns
+const RESPONSE_GRACE: Duration = Duration::from_millis(500);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Requests and jobs being handled
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Guard of a request or job, which the shutdown waits for
+pub struct Active;
+
+impl Active {
+    pub fn start() -> Active {
+        ACTIVE.fetch_add(1, Ordering::SeqCst);
+        Active
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        ACTIVE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Whether a shutdown signal was received: new requests are refused
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Wait up to timeout for the active requests and jobs to finish. Returns
+/// the number of requests and jobs still active.
+pub fn drain(timeout: Duration) -> usize {
+    let started = Instant::now();
+    loop {
+        let active = ACTIVE.load(Ordering::SeqCst);
+        if active == 0 || started.elapsed() >= timeout {
+            return active;
+        }
+        thread::sleep(DRAIN_INTERVAL);
+    }
+}
+
+/// On SIGTERM or SIGINT, refuse new requests, wait up to timeout for the
+/// active requests and jobs, then exit. A second signal exits at once.
+pub fn handle_signals(timeout: Duration) -> io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    thread::Builder::new().name(String::from("shutdown")).spawn(move || {
+        for signal in signals.forever() {
+            if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+                warn!("Received signal {} again, exiting without waiting for the active requests", signal);
+                process::exit(1);
+            }
+            info!("Received signal {}, shutting down after the active requests (at most {}s)...", signal, timeout.as_secs());
+            // keep receiving signals while draining:
+            thread::spawn(move || match drain(timeout) {
+                0 => {
+                    thread::sleep(RESPONSE_GRACE);
+                    info!("Shut down");
+                    process::exit(0);
+                },
+                active => {
+                    warn!("Shutdown timeout reached, exiting with {} active request(s) or job(s)", active);
+                    process::exit(1);
+                }
+            });
+        }
+    })?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+
+    #[test]
+    fn shutdown() {
+
+        // other tests can run jobs at the same time:
+        let active = Active::start();
+        let remaining = drain(Duration::from_millis(100));
+        assert!(remaining >= 1);
+
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(active);
+        });
+        assert_eq!(drain(Duration::from_secs(10)), 0);
+        handle.join().unwrap();
+        assert!(!is_shutting_down());
+
+    }
+}
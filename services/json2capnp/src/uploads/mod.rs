@@ -0,0 +1,313 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use crate::cache_files::WriteLock;
+use crate::collections::ItemsStream;
+use crate::errors::CacheError;
+use crate::shards::{Shard, ShardFilesWriter, ShardManifest, ShardsWriter};
+use crate::utils;
+
+/// Upload sessions not updated for this long are aborted when another
+/// session is opened
+const UPLOAD_SESSION_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Open upload sessions, by upload id
+static UPLOADS: Mutex<Option<HashMap<String, UploadSession>>> = Mutex::new(None);
+
+/// Chunked upload of a sharded collection. Each chunk is converted when it
+/// is received, in shard files of the .uploads/{upload_id} directory next to
+/// the collection cache. On commit, the shards of all the chunks, in chunk
+/// order, replace the shards of the collection (or are appended to them).
+struct UploadSession {
+    collection_name: String,
+    cache_file_name: String,
+    directory_path: PathBuf,
+    upload_directory_path: PathBuf,
+    append: bool,
+    chunks: BTreeMap<usize, Vec<Shard>>,
+    updated: Instant,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkSummary {
+    pub upload_id: String,
+    pub chunk: usize,
+    pub count: usize,
+    /// Number of chunks received so far
+    pub chunks: usize,
+}
+
+fn with_uploads<T>(uploads_fn: impl FnOnce(&mut HashMap<String, UploadSession>) -> T) -> T {
+    let mut uploads = UPLOADS.lock().unwrap_or_else(PoisonError::into_inner);
+    uploads_fn(uploads.get_or_insert_with(HashMap::new))
+}
+
+/// Remove the session of a collection from the open sessions
+fn take_session(collection_name: &str, upload_id: &str) -> Result<UploadSession, CacheError> {
+    with_uploads(|uploads| match uploads.get(upload_id) {
+        Some(session) if session.collection_name == collection_name => uploads.remove(upload_id),
+        _ => None
+    }).ok_or_else(|| CacheError::NotFound(format!("Upload {} of {} not found", upload_id, collection_name)))
+}
+
+/// Create a new directory in the .uploads directory of directory_path, for
+/// the shard files of an upload session or of a streamed write. Returns its
+/// id and path.
+pub fn create_upload_directory(directory_path: &Path) -> io::Result<(String, PathBuf)> {
+    let upload_id = utils::unique_id();
+    let upload_directory_path = directory_path.join(".uploads").join(&upload_id);
+    fs::create_dir_all(&upload_directory_path)?;
+    Ok((upload_id, upload_directory_path))
+}
+
+/// Remove the upload directories of directory_path and its subdirectories
+/// which are not used by an open session and were not changed for the
+/// session timeout: the sessions are only kept in memory, so the directories
+/// of the sessions of a previous run (or of interrupted streamed writes) are
+/// never removed otherwise. Returns the removed directories.
+pub fn remove_stale_upload_directories(directory_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut removed_paths = Vec::new();
+    for entry in fs::read_dir(directory_path)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        if entry.file_name() == ".uploads" {
+            removed_paths.extend(remove_stale_uploads(&entry.path())?);
+        } else {
+            removed_paths.extend(remove_stale_upload_directories(&entry.path())?);
+        }
+    }
+    Ok(removed_paths)
+}
+
+/// Remove the stale upload directories of an .uploads directory
+fn remove_stale_uploads(uploads_directory_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let open_directory_paths: Vec<PathBuf> = with_uploads(|uploads| uploads.values().map(|session| session.upload_directory_path.clone()).collect());
+    let mut removed_paths = Vec::new();
+    for entry in fs::read_dir(uploads_directory_path)? {
+        let path = entry?.path();
+        let stale = fs::metadata(&path)?.modified()?.elapsed().map(|elapsed| elapsed > UPLOAD_SESSION_TIMEOUT).unwrap_or(false);
+        if stale && !open_directory_paths.contains(&path) {
+            fs::remove_dir_all(&path)?;
+            removed_paths.push(path);
+        }
+    }
+    Ok(removed_paths)
+}
+
+/// Open an upload session for a collection cache in directory_path and
+/// return its upload id. The stale upload directories of directory_path are
+/// removed.
+pub fn open(collection_name: &str, cache_file_name: &str, directory_path: &Path, append: bool) -> Result<String, CacheError> {
+
+    let (upload_id, upload_directory_path) = create_upload_directory(directory_path)?;
+    if let Err(error) = remove_stale_uploads(&directory_path.join(".uploads")) {
+        warn!("Could not remove the stale uploads of {}: {}", directory_path.display(), error);
+    }
+
+    let expired_sessions: Vec<UploadSession> = with_uploads(|uploads| {
+        let expired_ids: Vec<String> = uploads.iter()
+            .filter(|(_, session)| session.updated.elapsed() > UPLOAD_SESSION_TIMEOUT)
+            .map(|(upload_id, _)| upload_id.clone())
+            .collect();
+        uploads.insert(upload_id.clone(), UploadSession {
+            collection_name: collection_name.to_string(),
+            cache_file_name: cache_file_name.to_string(),
+            directory_path: directory_path.to_path_buf(),
+            upload_directory_path,
+            append,
+            chunks: BTreeMap::new(),
+            updated: Instant::now(),
+        });
+        expired_ids.iter().filter_map(|upload_id| uploads.remove(upload_id)).collect()
+    });
+    for session in expired_sessions {
+        let _ = fs::remove_dir_all(&session.upload_directory_path);
+    }
+
+    Ok(upload_id)
+
+}
+
+/// Writer of the shard files of a chunk, in shard files of up to shard_size
+/// items. Each upload of a chunk has its own files, so a failed upload does
+/// not replace the files of the previous one.
+pub fn chunk_writer(collection_name: &str, upload_id: &str, chunk: usize, shard_size: usize, items_stream: &ItemsStream) -> Result<ShardFilesWriter, CacheError> {
+    let upload_directory_path = upload_directory_path(collection_name, upload_id)?;
+    Ok(ShardFilesWriter::new(&upload_directory_path, &format!("{}.{}", chunk, utils::unique_id()), shard_size, items_stream))
+}
+
+fn upload_directory_path(collection_name: &str, upload_id: &str) -> Result<PathBuf, CacheError> {
+    with_uploads(|uploads| match uploads.get(upload_id) {
+        Some(session) if session.collection_name == collection_name => Some(session.upload_directory_path.clone()),
+        _ => None
+    }).ok_or_else(|| CacheError::NotFound(format!("Upload {} of {} not found", upload_id, collection_name)))
+}
+
+/// Add the shard files of a chunk (see chunk_writer). A chunk uploaded again
+/// replaces the previous one. If the session was closed while the chunk was
+/// converted, only the files of this chunk are removed: a commit which fails
+/// puts its session back, with the files of its chunks.
+pub fn add_chunk(collection_name: &str, upload_id: &str, chunk: usize, shard_files: ShardFilesWriter) -> Result<ChunkSummary, CacheError> {
+
+    let upload_directory_path = shard_files.directory_path().to_path_buf();
+    let shards = shard_files.finish()?;
+    let count = shards.iter().map(|shard| shard.count).sum();
+    let file_names: Vec<String> = shards.iter().map(|shard| shard.file_name.clone()).collect();
+
+    let added = with_uploads(|uploads| match uploads.get_mut(upload_id) {
+        Some(session) if session.collection_name == collection_name => {
+            session.updated = Instant::now();
+            let previous_shards = session.chunks.insert(chunk, shards).unwrap_or_default();
+            Some((session.chunks.len(), previous_shards))
+        },
+        _ => None
+    });
+    let removed_file_names = match &added {
+        Some((_, previous_shards)) => previous_shards.iter().map(|shard| shard.file_name.clone()).collect(),
+        None => file_names
+    };
+    for file_name in removed_file_names {
+        let _ = fs::remove_file(upload_directory_path.join(file_name));
+    }
+    match added {
+        Some((chunks, _)) => Ok(ChunkSummary { upload_id: upload_id.to_string(), chunk, count, chunks }),
+        None => Err(CacheError::NotFound(format!("Upload {} of {} not found", upload_id, collection_name)))
+    }
+
+}
+
+/// Replace the shards of the collection by the shards of the chunks, or
+/// append them. The chunks must be numbered from 0 without gaps, and there
+/// must be expected_chunks of them if it is set. If the commit fails, the
+/// session stays open.
+pub fn commit(collection_name: &str, upload_id: &str, expected_chunks: Option<usize>, items_stream: &ItemsStream, config: &serde_json::Value) -> Result<ShardManifest, CacheError> {
+
+    let session = take_session(collection_name, upload_id)?;
+
+    let write_shards = || -> Result<ShardManifest, CacheError> {
+        if let Some(missing_chunk) = (0..session.chunks.len()).find(|chunk| !session.chunks.contains_key(chunk)) {
+            return Err(CacheError::InvalidPayload(format!("Chunk {} of upload {} is missing", missing_chunk, upload_id)));
+        }
+        match expected_chunks {
+            Some(expected_chunks) if expected_chunks != session.chunks.len() => {
+                return Err(CacheError::InvalidPayload(format!("Upload {} has {} chunks instead of {}", upload_id, session.chunks.len(), expected_chunks)));
+            },
+            _ => {}
+        }
+        let mut writer = ShardsWriter::lock(&session.directory_path, &session.cache_file_name, items_stream, &WriteLock::from_config(config))?;
+        if session.append {
+            writer.keep_current_shards(config)?;
+        }
+        for shard in session.chunks.values().flatten().filter(|shard| shard.count > 0) {
+            writer.add_file(&session.upload_directory_path.join(&shard.file_name), shard.count)?;
+        }
+        writer.commit()
+    };
+
+    match write_shards() {
+        Ok(manifest) => {
+            let _ = fs::remove_dir_all(&session.upload_directory_path);
+            Ok(manifest)
+        },
+        Err(error) => {
+            with_uploads(|uploads| uploads.insert(upload_id.to_string(), session));
+            Err(error)
+        }
+    }
+
+}
+
+/// Close an upload session without changing the collection
+pub fn abort(collection_name: &str, upload_id: &str) -> Result<(), CacheError> {
+    let session = take_session(collection_name, upload_id)?;
+    fs::remove_dir_all(&session.upload_directory_path)?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::collections::CacheCollection;
+    use crate::routers::od_trip_collection_router::OdTripCollection;
+    use crate::validation::Validator;
+    use pretty_assertions::{assert_eq};
+    use std::fs::File;
+    use std::time::SystemTime;
+
+    fn chunk_files(collection_name: &str, upload_id: &str, chunk: usize, items_count: usize) -> ShardFilesWriter {
+        let items_stream = OdTripCollection.items_stream().unwrap();
+        let mut shard_files = chunk_writer(collection_name, upload_id, chunk, 2, &items_stream).unwrap();
+        let mut validator = Validator::new();
+        for i in 0..items_count {
+            let od_trip = json!({ "id": format!("{:08x}-4f4c-4c36-9d6c-bb5cb5d5c4a1", i), "integer_id": i });
+            shard_files.push((items_stream.write_item)(&od_trip, validator.fields(&od_trip, "")).unwrap()).unwrap();
+        }
+        shard_files
+    }
+
+    fn upload_files(upload_directory_path: &Path) -> usize {
+        fs::read_dir(upload_directory_path).unwrap().count()
+    }
+
+    #[test]
+    fn chunk_of_closed_session() {
+
+        let _ = fs::remove_dir_all("test/closed_upload");
+        let directory_path = Path::new("test/closed_upload");
+        let upload_id = open("odTrips", "odTrips", directory_path, false).unwrap();
+        let upload_directory_path = directory_path.join(".uploads").join(&upload_id);
+        add_chunk("odTrips", &upload_id, 0, chunk_files("odTrips", &upload_id, 0, 3)).unwrap();
+        assert_eq!(upload_files(&upload_directory_path), 2);
+
+        // a chunk converted while the session is committed only removes its own files:
+        let shard_files = chunk_files("odTrips", &upload_id, 1, 1);
+        let session = take_session("odTrips", &upload_id).unwrap();
+        assert!(matches!(add_chunk("odTrips", &upload_id, 1, shard_files), Err(CacheError::NotFound(_))));
+        assert_eq!(upload_files(&upload_directory_path), 2);
+
+        with_uploads(|uploads| uploads.insert(upload_id.clone(), session));
+        abort("odTrips", &upload_id).unwrap();
+
+    }
+
+    #[test]
+    fn stale_upload_directories() {
+
+        let _ = fs::remove_dir_all("test/stale_uploads");
+        let directory_path = Path::new("test/stale_uploads");
+        let stale_directory_path = directory_path.join("dataSources/a1b2/.uploads/stale");
+        fs::create_dir_all(&stale_directory_path).unwrap();
+        fs::create_dir_all(directory_path.join(".uploads/recent")).unwrap();
+        let stale_time = SystemTime::now() - UPLOAD_SESSION_TIMEOUT - Duration::from_secs(60);
+        File::open(&stale_directory_path).unwrap().set_modified(stale_time).unwrap();
+
+        // the directory of an open session is kept, even if it was not changed for a while:
+        let upload_id = open("odTrips", "odTrips", directory_path, false).unwrap();
+        let upload_directory_path = directory_path.join(".uploads").join(&upload_id);
+        File::open(&upload_directory_path).unwrap().set_modified(stale_time).unwrap();
+
+        assert_eq!(remove_stale_upload_directories(directory_path).unwrap(), vec![stale_directory_path.clone()]);
+        assert!(directory_path.join(".uploads/recent").exists());
+        assert!(upload_directory_path.exists());
+        abort("odTrips", &upload_id).unwrap();
+
+    }
+}
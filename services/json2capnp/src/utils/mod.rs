@@ -7,6 +7,8 @@
  */
 
 use regex::Regex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /*pub fn string_or_null_to_empty_string(input: &std::string::String) -> std::string::String {
     if input == "null" {
@@ -16,14 +18,6 @@ use regex::Regex;
     }
 }*/
 
-pub fn json_null_to_empty_str(input: &serde_json::Value) -> &str {
-    if input.is_null() {
-        ""
-    } else {
-        input.as_str().unwrap()
-    }
-}
-
 /*pub fn empty_string_to_null(input: &std::string::String) -> std::string::String {
     if input == "" {
         String::from("null")
@@ -32,6 +26,14 @@ pub fn json_null_to_empty_str(input: &serde_json::Value) -> &str {
     }
 }*/
 
+static UNIQUE_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Id unique to this server, for upload sessions and jobs
+pub fn unique_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos()).unwrap_or(0);
+    format!("{:x}-{:x}-{:x}", nanos, std::process::id(), UNIQUE_ID_COUNTER.fetch_add(1, Ordering::SeqCst))
+}
+
 pub fn empty_str_to_json_null(input: &str) -> serde_json::Value {
     if input == "" {
         json!(null)
@@ -95,14 +97,6 @@ pub fn i8_to_json_boolean(input: i8) -> serde_json::Value {
     }
 }
 
-pub fn optional_string_json_null_to_empty_str(input: Option<&serde_json::Value>) -> &str {
-    &json_null_to_empty_str(input.unwrap_or(&json!(null)))
-}
-
-pub fn required_string(input: Option<&serde_json::Value>) -> &str {
-    &input.unwrap().as_str().unwrap()
-}
-
 pub fn time_str_to_seconds_since_midnight(time_str: &str) -> Option<u32> {
     let time_regex = Regex::new(r"(\d{2}):(\d{2}):?(\d{2})?").unwrap();
     if !time_regex.is_match(time_str)
@@ -115,15 +109,15 @@ pub fn time_str_to_seconds_since_midnight(time_str: &str) -> Option<u32> {
     for s in splitted_time {
         if i == 0 // hours
         {
-            seconds += 3600 * s.parse::<u32>().unwrap();
+            seconds += 3600 * s.parse::<u32>().ok()?;
         }
         else if i == 1 // minutes
         {
-            seconds += 60 * s.parse::<u32>().unwrap();
+            seconds += 60 * s.parse::<u32>().ok()?;
         }
         else if i == 2 // seconds (optional)
         {
-            seconds += s.parse::<u32>().unwrap();
+            seconds += s.parse::<u32>().ok()?;
         }
         i += 1;
     }
@@ -136,4 +130,15 @@ pub fn seconds_since_midnight_to_time_str(&seconds_since_midnight: &u32) -> Stri
     let seconds = seconds_since_midnight - hours * 3600 - minutes * 60;
     let time_string = if seconds == 0 { format!("{:02}:{:02}", hours, minutes) } else { format!("{:02}:{:02}:{:02}", hours, minutes, seconds) };
     time_string
-}
\ No newline at end of file
+}
+pub fn set_text_list(mut builder: capnp::text_list::Builder, values: &[&str]) {
+    for (i, value) in values.iter().enumerate() {
+        builder.set(i as u32, value);
+    }
+}
+
+pub fn set_primitive_list<T: capnp::private::layout::PrimitiveElement + Copy>(mut builder: capnp::primitive_list::Builder<T>, values: &[T]) {
+    for (i, value) in values.iter().enumerate() {
+        builder.set(i as u32, *value);
+    }
+}
@@ -0,0 +1,424 @@
+/*
+ * Copyright 2022 Polytechnique Montreal and contributors
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2 or any later version.
+ *
+ */
+
+use serde::Serialize;
+use serde_json::Value;
+use std::convert::TryFrom;
+use geojson::{Value as GeojsonValue};
+use crate::errors::CacheError;
+
+/// A problem with one field of the json payload, located with a json pointer
+/// (eg /odTrips/1532/origin_geography)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub pointer: String,
+    pub message: String,
+}
+
+/// Collects every validation error of a payload so they can all be returned
+/// at once instead of stopping at the first one. Invalid values are replaced
+/// by defaults so the conversion can go on.
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+/// Escape a key to be used as a json pointer token (RFC 6901)
+pub fn pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+pub fn child_pointer(pointer: &str, key: &str) -> String {
+    format!("{}/{}", pointer, pointer_token(key))
+}
+
+impl Validator {
+
+    pub fn new() -> Validator {
+        Validator { errors: Vec::new() }
+    }
+
+    pub fn error(&mut self, pointer: &str, message: &str) {
+        self.errors.push(FieldError { pointer: pointer.to_string(), message: message.to_string() });
+    }
+
+    pub fn into_result(self) -> Result<(), CacheError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(CacheError::Validation(self.errors))
+        }
+    }
+
+    /// Get a validator for the fields of an object
+    pub fn fields<'v, 'a>(&'v mut self, object: &'a Value, pointer: &str) -> Fields<'v, 'a> {
+        if !object.is_object() {
+            self.error(pointer, "must be an object");
+        }
+        Fields { validator: self, object, pointer: pointer.to_string() }
+    }
+
+    pub fn array<'a>(&mut self, value: &'a Value, pointer: &str) -> &'a [Value] {
+        match value.as_array() {
+            Some(array) => array,
+            None => {
+                self.error(pointer, "must be an array");
+                &[]
+            }
+        }
+    }
+
+    /// Get the features of a geojson FeatureCollection
+    pub fn features<'a>(&mut self, value: &'a Value, pointer: &str) -> &'a [Value] {
+        if value["type"].as_str() != Some("FeatureCollection") {
+            self.error(pointer, "must be a geojson FeatureCollection");
+            return &[];
+        }
+        self.array(&value["features"], &child_pointer(pointer, "features"))
+    }
+
+    pub fn string<'a>(&mut self, value: &'a Value, pointer: &str) -> &'a str {
+        match value {
+            Value::String(string) => string,
+            Value::Null => {
+                self.error(pointer, "is required");
+                ""
+            },
+            _ => {
+                self.error(pointer, "must be a string");
+                ""
+            }
+        }
+    }
+
+    pub fn optional_string<'a>(&mut self, value: &'a Value, pointer: &str) -> &'a str {
+        match value {
+            Value::Null => "",
+            _ => self.string(value, pointer)
+        }
+    }
+
+    /// Get an integer converted to a smaller integer type, reporting the
+    /// integers out of min..=max instead of wrapping them
+    fn integer_between<T: TryFrom<i64>>(&mut self, value: &Value, pointer: &str, min: i64, max: i64, default: T) -> T {
+        match value.as_i64() {
+            Some(integer) if (min..=max).contains(&integer) => T::try_from(integer).unwrap_or(default),
+            Some(_) => {
+                self.error(pointer, &format!("must be between {} and {}", min, max));
+                default
+            },
+            None => {
+                self.error(pointer, if value.is_null() { "is required" } else if min >= 0 { "must be a positive integer" } else { "must be an integer" });
+                default
+            }
+        }
+    }
+
+    pub fn i32_integer(&mut self, value: &Value, pointer: &str) -> i32 {
+        self.integer_between(value, pointer, i32::MIN.into(), i32::MAX.into(), -1)
+    }
+
+    pub fn u32_integer(&mut self, value: &Value, pointer: &str) -> u32 {
+        self.integer_between(value, pointer, 0, u32::MAX.into(), 0)
+    }
+
+    pub fn unsigned_integer(&mut self, value: &Value, pointer: &str) -> u64 {
+        match value.as_u64() {
+            Some(integer) => integer,
+            None => {
+                self.error(pointer, if value.is_null() { "is required" } else { "must be a positive integer" });
+                0
+            }
+        }
+    }
+
+    /// Get the coordinates of a geojson Point, multiplied by 1000000 and
+    /// rounded, as (longitude, latitude). Other geometry types give (-1, -1).
+    pub fn point(&mut self, value: &Value, pointer: &str) -> (i32, i32) {
+        match geojson::Geometry::from_json_value(value.clone()) {
+            Ok(geometry) => match geometry.value {
+                GeojsonValue::Point(point) if point.len() >= 2 => {
+                    ((point[0] * 1000000.0).round() as i32, (point[1] * 1000000.0).round() as i32)
+                },
+                GeojsonValue::Point(_) => {
+                    self.error(pointer, "point must have at least 2 coordinates");
+                    (-1, -1)
+                },
+                _ => (-1, -1)
+            },
+            Err(error) => {
+                self.error(pointer, &format!("must be a geojson geometry: {}", error));
+                (-1, -1)
+            }
+        }
+    }
+
+    /// Encode a geojson geometry to geobuf
+    pub fn geobuf(&mut self, value: &Value, pointer: &str) -> Vec<u8> {
+        use protobuf::Message;
+        if !value.is_object() {
+            self.error(pointer, "must be a geojson geometry");
+            return Vec::new();
+        }
+        let geojson_json = json!({
+            "type": "Feature",
+            "properties": {},
+            "geometry": value
+        });
+        let encoded = geobuf::encode::Encoder::encode(&geojson_json, 6, 2)
+            .map_err(|error| error.to_string())
+            .and_then(|data| data.write_to_bytes().map_err(|error| error.to_string()));
+        match encoded {
+            Ok(geobuf) => geobuf,
+            Err(error) => {
+                self.error(pointer, &format!("cannot be encoded: {}", error));
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn string_array<'a>(&mut self, value: &'a Value, pointer: &str) -> Vec<&'a str> {
+        self.array(value, pointer).iter().enumerate()
+            .map(|(i, item)| self.string(item, &format!("{}/{}", pointer, i)))
+            .collect()
+    }
+
+    pub fn i32_array(&mut self, value: &Value, pointer: &str) -> Vec<i32> {
+        self.array(value, pointer).iter().enumerate()
+            .map(|(i, item)| self.i32_integer(item, &format!("{}/{}", pointer, i)))
+            .collect()
+    }
+
+    /// Get the accessible nodes uuids with their travel times and distances,
+    /// stored as 3 parallel arrays in the data attribute of some objects (eg
+    /// originNodes, originNodesTravelTimes and originNodesDistances). Returns
+    /// None if the nodes array is not there. Travel times and distances are
+    /// stored as i16, so they must be between 0 and 32767.
+    pub fn accessible_nodes<'a>(&mut self, data: &'a Value, nodes_key: &str, pointer: &str) -> Option<(Vec<&'a str>, Vec<i16>, Vec<i16>)> {
+        if !data[nodes_key].is_array() {
+            return None;
+        }
+        let nodes_uuids = self.string_array(&data[nodes_key], &child_pointer(pointer, nodes_key));
+        let mut travel_times = Vec::with_capacity(nodes_uuids.len());
+        let mut distances = Vec::with_capacity(nodes_uuids.len());
+        let travel_times_key = format!("{}TravelTimes", nodes_key);
+        let distances_key = format!("{}Distances", nodes_key);
+        for j in 0..nodes_uuids.len() {
+            travel_times.push(self.integer_between(&data[&travel_times_key][j], &format!("{}/{}", child_pointer(pointer, &travel_times_key), j), 0, i16::MAX.into(), -1));
+            distances.push(self.integer_between(&data[&distances_key][j], &format!("{}/{}", child_pointer(pointer, &distances_key), j), 0, i16::MAX.into(), -1));
+        }
+        Some((nodes_uuids, travel_times, distances))
+    }
+
+}
+
+/// Move the validation errors under the from pointer (eg /line) to the to
+/// pointer (eg /lines/12), when a payload is converted as part of a bigger one
+pub fn rebase_pointers(error: CacheError, from: &str, to: &str) -> CacheError {
+    match error {
+        CacheError::Validation(errors) => CacheError::Validation(errors.into_iter().map(|error| {
+            match error.pointer.strip_prefix(from) {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') => FieldError { pointer: format!("{}{}", to, rest), message: error.message },
+                _ => error
+            }
+        }).collect()),
+        error => error
+    }
+}
+
+/// Validator for the fields of one json object
+pub struct Fields<'v, 'a> {
+    validator: &'v mut Validator,
+    object: &'a Value,
+    pointer: String,
+}
+
+impl<'v, 'a> Fields<'v, 'a> {
+
+    pub fn pointer(&self, key: &str) -> String {
+        child_pointer(&self.pointer, key)
+    }
+
+    pub fn get(&self, key: &str) -> &'a Value {
+        &self.object[key]
+    }
+
+    pub fn required_string(&mut self, key: &str) -> &'a str {
+        let pointer = self.pointer(key);
+        self.validator.string(&self.object[key], &pointer)
+    }
+
+    /// Uuids are used in file names, so they must be valid
+    pub fn required_uuid(&mut self, key: &str) -> &'a str {
+        let pointer = self.pointer(key);
+        let value = self.validator.string(&self.object[key], &pointer);
+        if !value.is_empty() && uuid::Uuid::parse_str(value).is_err() {
+            self.validator.error(&pointer, "must be a valid uuid");
+        }
+        value
+    }
+
+    /// Check that a string is a valid uuid, without reporting missing or
+    /// non-string values, which required_string reports
+    pub fn uuid_format(&mut self, key: &str) -> &'a str {
+        let value = self.object[key].as_str().unwrap_or("");
+        if !value.is_empty() && uuid::Uuid::parse_str(value).is_err() {
+            let pointer = self.pointer(key);
+            self.validator.error(&pointer, "must be a valid uuid");
+        }
+        value
+    }
+
+    /// Missing and null strings are empty strings
+    pub fn optional_string(&mut self, key: &str) -> &'a str {
+        let pointer = self.pointer(key);
+        self.validator.optional_string(&self.object[key], &pointer)
+    }
+
+    pub fn required_i32(&mut self, key: &str) -> i32 {
+        let pointer = self.pointer(key);
+        self.validator.i32_integer(&self.object[key], &pointer)
+    }
+
+    pub fn required_u32(&mut self, key: &str) -> u32 {
+        let pointer = self.pointer(key);
+        self.validator.u32_integer(&self.object[key], &pointer)
+    }
+
+    pub fn point(&mut self, key: &str) -> (i32, i32) {
+        let pointer = self.pointer(key);
+        self.validator.point(&self.object[key], &pointer)
+    }
+
+    /// Missing and null points are (-1, -1)
+    pub fn optional_point(&mut self, key: &str) -> (i32, i32) {
+        if self.object[key].is_null() {
+            return (-1, -1);
+        }
+        self.point(key)
+    }
+
+    /// Missing and null arrays are None
+    pub fn optional_string_array(&mut self, key: &str) -> Option<Vec<&'a str>> {
+        if self.object[key].is_null() {
+            return None;
+        }
+        let pointer = self.pointer(key);
+        Some(self.validator.string_array(&self.object[key], &pointer))
+    }
+
+    /// Missing and null arrays are None
+    pub fn optional_i32_array(&mut self, key: &str) -> Option<Vec<i32>> {
+        if self.object[key].is_null() {
+            return None;
+        }
+        let pointer = self.pointer(key);
+        Some(self.validator.i32_array(&self.object[key], &pointer))
+    }
+
+    pub fn accessible_nodes(&mut self, nodes_key: &str) -> Option<(Vec<&'a str>, Vec<i16>, Vec<i16>)> {
+        let pointer = self.pointer("data");
+        self.validator.accessible_nodes(&self.object["data"], nodes_key, &pointer)
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pretty_assertions::{assert_eq};
+
+    fn errors(validator: Validator) -> Vec<FieldError> {
+        match validator.into_result() {
+            Err(CacheError::Validation(errors)) => errors,
+            _ => vec![]
+        }
+    }
+
+    fn pointers(validator: Validator) -> Vec<String> {
+        errors(validator).into_iter().map(|error| error.pointer).collect()
+    }
+
+    #[test]
+    fn validator() {
+
+        let json = json!({
+            "odTrips": [
+                { "id": "a", "integer_id": 1, "origin_geography": { "type": "Point", "coordinates": [-73.5, 45.5] } },
+                { "id": 3, "integer_id": -1, "origin_geography": { "type": "Point" } },
+            ]
+        });
+
+        let mut validator = Validator::new();
+        let od_trips = validator.array(&json["odTrips"], "/odTrips");
+        assert_eq!(od_trips.len(), 2);
+
+        for (i, od_trip) in od_trips.iter().enumerate() {
+            let mut fields = validator.fields(od_trip, &format!("/odTrips/{}", i));
+            fields.required_string("id");
+            fields.optional_string("internal_id");
+            fields.required_u32("integer_id");
+            fields.point("origin_geography");
+            fields.optional_point("destination_geography");
+        }
+
+        assert_eq!(pointers(validator), vec!["/odTrips/1/id", "/odTrips/1/integer_id", "/odTrips/1/origin_geography"]);
+
+        let mut validator = Validator::new();
+        let (longitude, latitude) = validator.point(&json["odTrips"][0]["origin_geography"], "");
+        assert_eq!((longitude, latitude), (-73500000, 45500000));
+        assert!(validator.into_result().is_ok());
+
+        let mut validator = Validator::new();
+        validator.array(&json["persons"], "/persons");
+        validator.features(&json, "/nodes");
+        let errors = errors(validator);
+        assert_eq!(errors[0], FieldError { pointer: String::from("/persons"), message: String::from("must be an array") });
+        assert_eq!(errors[1].pointer, "/nodes");
+
+    }
+
+    #[test]
+    fn validator_accessible_nodes() {
+
+        let data = json!({
+            "originNodes": ["a", "b", 3],
+            "originNodesTravelTimes": [10, 20, 30],
+            "originNodesDistances": [100, "200"]
+        });
+
+        let mut validator = Validator::new();
+        let (nodes, travel_times, distances) = validator.accessible_nodes(&data, "originNodes", "/odTrips/0/data").unwrap();
+        assert_eq!(nodes, vec!["a", "b", ""]);
+        assert_eq!(travel_times, vec![10, 20, 30]);
+        assert_eq!(distances, vec![100, -1, -1]);
+
+        assert!(validator.accessible_nodes(&data, "destinationNodes", "/odTrips/0/data").is_none());
+        assert_eq!(pointers(validator), vec!["/odTrips/0/data/originNodes/2", "/odTrips/0/data/originNodesDistances/1", "/odTrips/0/data/originNodesDistances/2"]);
+
+        assert_eq!(child_pointer("/line/scheduleByServiceId", "a/b~c"), "/line/scheduleByServiceId/a~1b~0c");
+
+        // integers out of the range of their type are reported instead of wrapping:
+        let data = json!({ "originNodes": ["a"], "originNodesTravelTimes": [40000], "originNodesDistances": [-5] });
+        let mut validator = Validator::new();
+        let (_, travel_times, distances) = validator.accessible_nodes(&data, "originNodes", "/odTrips/0/data").unwrap();
+        assert_eq!((travel_times, distances), (vec![-1], vec![-1]));
+        assert_eq!(errors(validator), vec![
+            FieldError { pointer: String::from("/odTrips/0/data/originNodesTravelTimes/0"), message: String::from("must be between 0 and 32767") },
+            FieldError { pointer: String::from("/odTrips/0/data/originNodesDistances/0"), message: String::from("must be between 0 and 32767") }
+        ]);
+        let mut validator = Validator::new();
+        assert_eq!(validator.u32_integer(&json!(4294967296u64), "/integer_id"), 0);
+        assert_eq!(validator.i32_integer(&json!(-2147483649i64), "/segments/0"), -1);
+        assert_eq!(validator.u32_integer(&json!(4294967295u64), "/integer_id"), u32::MAX);
+        assert_eq!(errors(validator).into_iter().map(|error| error.message).collect::<Vec<_>>(), vec!["must be between 0 and 4294967295", "must be between -2147483648 and 2147483647"]);
+
+    }
+}